@@ -0,0 +1,355 @@
+use std::{
+    io::{Read, Write},
+    time::Duration,
+};
+
+use anyhow::Context;
+use base64::{Engine, prelude::BASE64_STANDARD};
+use futures_util::StreamExt;
+use serde::de::DeserializeOwned;
+
+use crate::engine::models::{
+    ExecutionEvent, ExecutionRecord, ExecutionStatus, FileEncoding, Language,
+};
+
+const USAGE: &str = "usage: ai run <file|-> [--language <name>] [--stdin <file|->] \
+[--profile <name>] [--priority <n>] [--url <url>] [--api-key <key>] [--quiet] [--json] \
+[-- <args>...]";
+
+// Exit codes for what is not the program's own exit code.
+const EXIT_USAGE: i32 = 2;
+const EXIT_ENGINE: i32 = 3;
+const EXIT_TIMED_OUT: i32 = 124;
+const EXIT_OUT_OF_MEMORY: i32 = 137;
+
+// How often the result is polled once the event stream has ended early.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Default, PartialEq)]
+struct Options {
+    // `-` reads the code from stdin.
+    file: String,
+    language: Option<String>,
+    stdin: Option<String>,
+    profile: Option<String>,
+    priority: Option<u8>,
+    args: Vec<String>,
+    url: String,
+    api_key: String,
+    quiet: bool,
+    json: bool,
+}
+
+// `ai run`: submits a local file, prints the execution's events to stderr
+// while it runs, then its output, and exits with the program's exit code.
+pub async fn run(args: &[String]) -> i32 {
+    let options = match parse(args) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{err}\n{USAGE}");
+            return EXIT_USAGE;
+        }
+    };
+    match execute(&options).await {
+        Ok(record) => report(&options, &record),
+        Err(err) => {
+            eprintln!("error: {err:#}");
+            EXIT_ENGINE
+        }
+    }
+}
+
+fn parse(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        url: std::env::var("ENGINE_URL").unwrap_or_else(|_| "http://127.0.0.1:8080".to_string()),
+        api_key: std::env::var("ENGINE_API_KEY").unwrap_or_else(|_| "dev-key".to_string()),
+        ..Default::default()
+    };
+    let mut args = args.iter();
+    let mut file = None;
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("{name} needs a value"))
+        };
+        match arg.as_str() {
+            "--language" | "-l" => options.language = Some(value(arg)?),
+            "--stdin" => options.stdin = Some(value(arg)?),
+            "--profile" => options.profile = Some(value(arg)?),
+            "--priority" => {
+                options.priority = Some(
+                    value(arg)?
+                        .parse()
+                        .map_err(|_| "--priority must be a number".to_string())?,
+                )
+            }
+            "--url" => options.url = value(arg)?,
+            "--api-key" => options.api_key = value(arg)?,
+            "--quiet" | "-q" => options.quiet = true,
+            "--json" => options.json = true,
+            "--" => {
+                options.args.extend(args.by_ref().cloned());
+            }
+            flag if flag.starts_with('-') && flag != "-" => {
+                return Err(format!("unknown option {flag}"));
+            }
+            path if file.is_none() => file = Some(path.to_string()),
+            extra => return Err(format!("unexpected argument {extra}")),
+        }
+    }
+    options.file = file.ok_or("no file given")?;
+    if options.language.is_none() {
+        let language = std::path::Path::new(&options.file)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(Language::from_extension)
+            .ok_or("cannot tell the language from the file name; pass --language")?;
+        options.language = Some(language.as_str().to_string());
+    }
+    options.url = options.url.trim_end_matches('/').to_string();
+    Ok(options)
+}
+
+fn read_input(path: &str) -> anyhow::Result<String> {
+    if path == "-" {
+        let mut input = String::new();
+        std::io::stdin().read_to_string(&mut input)?;
+        return Ok(input);
+    }
+    std::fs::read_to_string(path).with_context(|| format!("cannot read {path}"))
+}
+
+async fn execute(options: &Options) -> anyhow::Result<ExecutionRecord> {
+    let mut request = serde_json::json!({
+        "language": options.language,
+        "code": read_input(&options.file)?,
+        "args": options.args,
+        "profile": options.profile,
+        "priority": options.priority,
+    });
+    if let Some(stdin) = &options.stdin {
+        request["stdin"] = read_input(stdin)?.into();
+    }
+    let client = reqwest::Client::new();
+    let created: serde_json::Value = json(
+        client
+            .post(format!("{}/v1/executions", options.url))
+            .header("x-api-key", &options.api_key)
+            .header("content-type", "application/json")
+            .body(serde_json::to_vec(&request)?)
+            .send()
+            .await
+            .context("cannot reach the engine")?,
+    )
+    .await?;
+    let id = created["id"]
+        .as_str()
+        .context("engine returned no execution id")?
+        .to_string();
+    if !options.quiet {
+        eprintln!("execution {id}");
+    }
+
+    if let Err(err) = follow(&client, options, &id).await {
+        tracing::debug!(error = %format!("{err:#}"), "event stream ended early");
+    }
+    loop {
+        let record: ExecutionRecord = json(
+            client
+                .get(format!("{}/v1/executions/{id}/result", options.url))
+                .header("x-api-key", &options.api_key)
+                .send()
+                .await?,
+        )
+        .await?;
+        if !matches!(
+            record.status,
+            ExecutionStatus::Queued | ExecutionStatus::Running
+        ) {
+            return Ok(record);
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+// Prints events as they arrive until the stream ends with `finished`.
+async fn follow(client: &reqwest::Client, options: &Options, id: &str) -> anyhow::Result<()> {
+    let response = check(
+        client
+            .get(format!("{}/v1/executions/{id}/events", options.url))
+            .header("x-api-key", &options.api_key)
+            .send()
+            .await?,
+    )
+    .await?;
+    let mut body = response.bytes_stream();
+    let mut pending = Vec::new();
+    while let Some(chunk) = body.next().await {
+        pending.extend_from_slice(&chunk?);
+        while let Some(end) = pending.windows(2).position(|pair| pair == b"\n\n") {
+            let message: Vec<u8> = pending.drain(..end + 2).collect();
+            let Some(event) = parse_event(&String::from_utf8_lossy(&message)) else {
+                continue;
+            };
+            if !options.quiet {
+                eprintln!("[{}] {}", event.stage, event.message);
+            }
+        }
+    }
+    Ok(())
+}
+
+// The JSON `data:` of one server-sent event; keep-alive comments have none.
+fn parse_event(message: &str) -> Option<ExecutionEvent> {
+    let data: Vec<&str> = message
+        .lines()
+        .filter_map(|line| line.strip_prefix("data:"))
+        .map(str::trim_start)
+        .collect();
+    serde_json::from_str(&data.join("\n")).ok()
+}
+
+async fn check(response: reqwest::Response) -> anyhow::Result<reqwest::Response> {
+    if response.status().is_success() {
+        return Ok(response);
+    }
+    let status = response.status();
+    let body: serde_json::Value = response
+        .bytes()
+        .await
+        .ok()
+        .and_then(|body| serde_json::from_slice(&body).ok())
+        .unwrap_or_default();
+    match body["error"].as_str() {
+        Some(error) => anyhow::bail!("{status}: {error}"),
+        None => anyhow::bail!("{status}"),
+    }
+}
+
+async fn json<T: DeserializeOwned>(response: reqwest::Response) -> anyhow::Result<T> {
+    let body = check(response).await?.bytes().await?;
+    serde_json::from_slice(&body).context("unexpected response from the engine")
+}
+
+fn report(options: &Options, record: &ExecutionRecord) -> i32 {
+    if options.json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(record).unwrap_or_default()
+        );
+    } else if let Some(output) = &record.output {
+        for stage in [&output.install, &output.compile].into_iter().flatten() {
+            if !stage.success {
+                eprint!("{}{}", stage.stdout, stage.stderr);
+            }
+        }
+        write_stream(
+            &mut std::io::stdout(),
+            &output.stdout,
+            output.stdout_encoding,
+        );
+        write_stream(
+            &mut std::io::stderr(),
+            &output.stderr,
+            output.stderr_encoding,
+        );
+        for (index, case) in output.test_results.iter().enumerate() {
+            if let Some(passed) = case.passed {
+                eprintln!(
+                    "test case {}: {}",
+                    index + 1,
+                    if passed { "passed" } else { "failed" }
+                );
+            }
+        }
+    }
+    if let Some(error) = &record.error {
+        eprintln!("error: {error}");
+    }
+    exit_code(record)
+}
+
+fn write_stream(out: &mut dyn Write, text: &str, encoding: FileEncoding) {
+    let bytes = match encoding {
+        FileEncoding::Utf8 => text.as_bytes().to_vec(),
+        FileEncoding::Base64 => BASE64_STANDARD.decode(text).unwrap_or_default(),
+    };
+    let _ = out.write_all(&bytes);
+    let _ = out.flush();
+}
+
+// The program's exit code, made non-zero when a test case failed; fixed
+// codes when it did not run to completion.
+fn exit_code(record: &ExecutionRecord) -> i32 {
+    match record.status {
+        ExecutionStatus::TimedOut => EXIT_TIMED_OUT,
+        ExecutionStatus::OutOfMemory => EXIT_OUT_OF_MEMORY,
+        ExecutionStatus::Succeeded | ExecutionStatus::Failed => match &record.output {
+            Some(output) if output.exit_code != 0 => output.exit_code,
+            Some(output)
+                if output
+                    .test_results
+                    .iter()
+                    .any(|case| case.passed == Some(false)) =>
+            {
+                1
+            }
+            Some(_) if record.status == ExecutionStatus::Succeeded => 0,
+            _ => 1,
+        },
+        _ => EXIT_ENGINE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Options, parse, parse_event};
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_options_and_infers_the_language() {
+        let options = parse(&args(&[
+            "main.py",
+            "--stdin",
+            "in.txt",
+            "--priority",
+            "2",
+            "--url",
+            "http://engine:8080/",
+            "--",
+            "--verbose",
+            "x",
+        ]))
+        .unwrap();
+        assert_eq!(
+            options,
+            Options {
+                file: "main.py".into(),
+                language: Some("python".into()),
+                stdin: Some("in.txt".into()),
+                priority: Some(2),
+                args: vec!["--verbose".into(), "x".into()],
+                url: "http://engine:8080".into(),
+                api_key: options.api_key.clone(),
+                ..Default::default()
+            }
+        );
+        assert!(parse(&args(&["script"])).is_err());
+        assert_eq!(
+            parse(&args(&["-", "-l", "rust"]))
+                .unwrap()
+                .language
+                .as_deref(),
+            Some("rust")
+        );
+        assert!(parse(&args(&["a.py", "--bogus"])).is_err());
+
+        let event = parse_event("id: 3\nevent: worker\ndata: {\"ts_ms\":1,\"stage\":\"worker\",\"message\":\"claimed\"}\n\n").unwrap();
+        assert_eq!(event.stage, "worker");
+        assert!(parse_event(": keep-alive\n\n").is_none());
+    }
+}
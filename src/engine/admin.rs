@@ -0,0 +1,475 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use axum::{
+    Json, Router,
+    extract::State,
+    http::HeaderMap,
+    routing::{get, post},
+};
+use dashmap::DashMap;
+use serde::Serialize;
+use tokio::sync::{Notify, mpsc, watch};
+use uuid::Uuid;
+
+use crate::engine::{
+    api::{AppState, authenticate, constant_time_eq},
+    config::{SandboxBackendKind, Scope},
+    error::EngineError,
+    queue::QueuedJob,
+    quota::QuotaUsage,
+    store::now_ms,
+};
+
+// Chunks of input waiting to be written; senders wait while it is full.
+const STDIN_BUFFER: usize = 16;
+
+// Shared between the workers, the autoscalers and the admin API: which
+// execution each worker is running, whether workers may take new jobs off the
+// queue, how many workers each pool has and should have, and where input for
+// running interactive executions goes.
+#[derive(Debug)]
+pub struct WorkerControl {
+    running: DashMap<usize, RunningExecution>,
+    stdin: DashMap<Uuid, mpsc::Sender<Vec<u8>>>,
+    paused: watch::Sender<bool>,
+    pools: DashMap<String, Arc<PoolSize>>,
+    next_worker_id: AtomicUsize,
+}
+
+#[derive(Debug, Default)]
+pub struct PoolSize {
+    live: AtomicUsize,
+    target: AtomicUsize,
+    retire: Notify,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RunningExecution {
+    pub worker_id: usize,
+    pub pool: String,
+    pub execution_id: Uuid,
+    pub tenant_id: String,
+    pub started_at_ms: u64,
+    // When every run would have hit its timeout.
+    pub deadline_ms: u64,
+    pub heartbeat_ms: u64,
+    // Set once the watchdog has told the worker to give up on it.
+    pub killed: bool,
+    #[serde(skip)]
+    abort: Arc<Notify>,
+}
+
+impl WorkerControl {
+    pub fn new() -> Self {
+        Self {
+            running: DashMap::new(),
+            stdin: DashMap::new(),
+            paused: watch::Sender::new(false),
+            pools: DashMap::new(),
+            next_worker_id: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn pool(&self, name: &str) -> Arc<PoolSize> {
+        self.pools.entry(name.to_string()).or_default().clone()
+    }
+
+    // Worker ids are unique across pools.
+    pub fn next_worker_id(&self) -> usize {
+        self.next_worker_id.fetch_add(1, Ordering::SeqCst)
+    }
+
+    // Live and busy workers by pool.
+    pub fn usage(&self) -> BTreeMap<String, (usize, usize)> {
+        self.pools
+            .iter()
+            .map(|entry| (entry.key().clone(), (entry.live(), self.busy(entry.key()))))
+            .collect()
+    }
+
+    pub fn busy(&self, pool: &str) -> usize {
+        self.running
+            .iter()
+            .filter(|entry| entry.pool == pool)
+            .count()
+    }
+
+    // Resolves once the watchdog kills the execution.
+    pub fn started(&self, worker_id: usize, pool: &str, job: &QueuedJob) -> Arc<Notify> {
+        let runs = match &job.request.benchmark {
+            Some(benchmark) => u64::from(benchmark.runs + benchmark.warmup),
+            None => job.request.test_cases.len() as u64,
+        };
+        let now = now_ms();
+        let abort = Arc::new(Notify::new());
+        self.running.insert(
+            worker_id,
+            RunningExecution {
+                worker_id,
+                pool: pool.to_string(),
+                execution_id: job.id,
+                tenant_id: job.tenant_id.clone(),
+                started_at_ms: now,
+                deadline_ms: now + job.limits.timeout_ms * runs.max(1),
+                heartbeat_ms: now,
+                killed: false,
+                abort: abort.clone(),
+            },
+        );
+        abort
+    }
+
+    pub fn heartbeat(&self, worker_id: usize) {
+        if let Some(mut execution) = self.running.get_mut(&worker_id) {
+            execution.heartbeat_ms = now_ms();
+        }
+    }
+
+    // Executions more than `grace` past their deadline, or whose worker has
+    // not reported for that long, that have not been killed yet.
+    pub fn overdue(&self, grace: Duration) -> Vec<RunningExecution> {
+        let now = now_ms();
+        let grace = grace.as_millis() as u64;
+        self.running
+            .iter()
+            .filter(|execution| {
+                !execution.killed
+                    && (now > execution.deadline_ms + grace || now > execution.heartbeat_ms + grace)
+            })
+            .map(|execution| execution.value().clone())
+            .collect()
+    }
+
+    pub fn kill(&self, worker_id: usize) {
+        if let Some(mut execution) = self.running.get_mut(&worker_id) {
+            execution.killed = true;
+            execution.abort.notify_one();
+        }
+    }
+
+    // Also closes the execution's stdin, if it was interactive.
+    pub fn finished(&self, worker_id: usize) {
+        if let Some((_, execution)) = self.running.remove(&worker_id) {
+            self.stdin.remove(&execution.execution_id);
+        }
+    }
+
+    pub fn open_stdin(&self, execution_id: Uuid) -> mpsc::Receiver<Vec<u8>> {
+        let (sender, receiver) = mpsc::channel(STDIN_BUFFER);
+        self.stdin.insert(execution_id, sender);
+        receiver
+    }
+
+    // None unless the execution is interactive and running on this node.
+    pub fn stdin(&self, execution_id: Uuid) -> Option<mpsc::Sender<Vec<u8>>> {
+        self.stdin
+            .get(&execution_id)
+            .map(|sender| sender.value().clone())
+    }
+
+    pub fn close_stdin(&self, execution_id: Uuid) {
+        self.stdin.remove(&execution_id);
+    }
+
+    pub fn running(&self) -> Vec<RunningExecution> {
+        let mut running: Vec<_> = self
+            .running
+            .iter()
+            .map(|entry| entry.value().clone())
+            .collect();
+        running.sort_by_key(|execution| execution.worker_id);
+        running
+    }
+
+    // Jobs already running are unaffected; queued jobs wait until `resume`.
+    pub fn pause(&self) {
+        self.paused.send_replace(true);
+    }
+
+    pub fn resume(&self) {
+        self.paused.send_replace(false);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    pub async fn wait_until_resumed(&self) {
+        let mut paused = self.paused.subscribe();
+        // The sender lives as long as `self`, so this cannot fail.
+        let _ = paused.wait_for(|paused| !paused).await;
+    }
+}
+
+impl Default for WorkerControl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PoolSize {
+    pub fn live(&self) -> usize {
+        self.live.load(Ordering::SeqCst)
+    }
+
+    pub fn target(&self) -> usize {
+        self.target.load(Ordering::SeqCst)
+    }
+
+    // Idle workers above the new target are woken to exit; busy ones exit
+    // after their current job.
+    pub fn set_target(&self, target: usize) {
+        self.target.store(target, Ordering::SeqCst);
+        if self.live() > target {
+            self.retire.notify_waiters();
+        }
+    }
+
+    pub fn joined(&self) {
+        self.live.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn left(&self) {
+        self.live.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    // Claims one of the surplus worker slots, if there are any.
+    pub fn try_retire(&self) -> bool {
+        self.live
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |live| {
+                (live > self.target()).then(|| live - 1)
+            })
+            .is_ok()
+    }
+
+    pub async fn retire_requested(&self) {
+        self.retire.notified().await;
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct QueueReport {
+    paused: bool,
+    queued: usize,
+    queued_by_tenant: BTreeMap<String, usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct WorkersReport {
+    workers: usize,
+    busy: usize,
+    pools: BTreeMap<String, PoolReport>,
+    running: Vec<RunningExecution>,
+}
+
+#[derive(Debug, Serialize)]
+struct PoolReport {
+    workers: usize,
+    target: usize,
+    busy: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct SandboxReport {
+    default: SandboxBackendKind,
+    ready: bool,
+    detail: Option<String>,
+    backends: Vec<BackendReport>,
+}
+
+#[derive(Debug, Serialize)]
+struct BackendReport {
+    backend: SandboxBackendKind,
+    healthy: bool,
+    error: Option<String>,
+    // Set when the backend runs but some languages can't.
+    warning: Option<String>,
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/admin/queue", get(queue))
+        .route("/admin/queue/pause", post(pause))
+        .route("/admin/queue/resume", post(resume))
+        .route("/admin/workers", get(workers))
+        .route("/admin/sandbox", get(sandbox))
+        .route("/admin/quotas", get(quotas))
+}
+
+async fn queue(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<QueueReport>, EngineError> {
+    authorize_admin(&state, &headers)?;
+    Ok(Json(queue_report(&state)))
+}
+
+async fn pause(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<QueueReport>, EngineError> {
+    authorize_admin(&state, &headers)?;
+    state.workers.pause();
+    tracing::warn!("job dequeueing paused by admin");
+    Ok(Json(queue_report(&state)))
+}
+
+async fn resume(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<QueueReport>, EngineError> {
+    authorize_admin(&state, &headers)?;
+    state.workers.resume();
+    tracing::info!("job dequeueing resumed by admin");
+    Ok(Json(queue_report(&state)))
+}
+
+async fn workers(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<WorkersReport>, EngineError> {
+    authorize_admin(&state, &headers)?;
+    let running = state.workers.running();
+    let pools: BTreeMap<_, _> = state
+        .workers
+        .pools
+        .iter()
+        .map(|entry| {
+            let report = PoolReport {
+                workers: entry.live(),
+                target: entry.target(),
+                busy: state.workers.busy(entry.key()),
+            };
+            (entry.key().clone(), report)
+        })
+        .collect();
+    Ok(Json(WorkersReport {
+        workers: pools.values().map(|pool| pool.workers).sum(),
+        busy: running.len(),
+        pools,
+        running,
+    }))
+}
+
+async fn sandbox(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<SandboxReport>, EngineError> {
+    authorize_admin(&state, &headers)?;
+    let readiness = state.readiness.report();
+    let mut backends = Vec::new();
+    for (backend, sandbox) in state
+        .sandboxes
+        .iter()
+        .flat_map(|sandboxes| sandboxes.iter())
+    {
+        let health = sandbox.health().await;
+        let (error, warning) = match health {
+            Ok(warning) => (None, warning),
+            Err(err) => (Some(format!("{err:#}")), None),
+        };
+        backends.push(BackendReport {
+            backend,
+            healthy: error.is_none(),
+            error,
+            warning,
+        });
+    }
+    Ok(Json(SandboxReport {
+        default: state.config.sandbox_backend,
+        ready: readiness.ready,
+        detail: readiness.detail,
+        backends,
+    }))
+}
+
+// Tenants with an API key or a quota of their own, if they have a quota.
+async fn quotas(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<BTreeMap<String, QuotaUsage>>, EngineError> {
+    authorize_admin(&state, &headers)?;
+    let tenants: BTreeSet<&String> = state
+        .config
+        .api_keys
+        .values()
+        .map(|api_key| &api_key.tenant_id)
+        .chain(state.config.tenant_execution_quotas.keys())
+        .collect();
+    let mut report = BTreeMap::new();
+    for tenant in tenants {
+        if let Some(usage) = state.quotas.usage(tenant).await {
+            report.insert(tenant.clone(), usage);
+        }
+    }
+    Ok(Json(report))
+}
+
+fn queue_report(state: &AppState) -> QueueReport {
+    let queued_by_tenant = state.store.queued_by_tenant();
+    QueueReport {
+        paused: state.workers.is_paused(),
+        queued: queued_by_tenant.values().sum(),
+        queued_by_tenant,
+    }
+}
+
+// Admin routes take an ADMIN_API_KEYS key or an API key with the `admin`
+// scope, and are disabled (403) while neither exists.
+fn authorize_admin(state: &AppState, headers: &HeaderMap) -> Result<(), EngineError> {
+    let scoped_keys = state
+        .config
+        .api_keys
+        .values()
+        .any(|api_key| api_key.scopes.contains(&Scope::Admin));
+    if state.config.admin_api_keys.is_empty() && !scoped_keys {
+        return Err(EngineError::Forbidden);
+    }
+    let key = headers
+        .get("x-api-key")
+        .and_then(|value| value.to_str().ok())
+        .ok_or(EngineError::Unauthorized)?;
+    let known = state
+        .config
+        .admin_api_keys
+        .iter()
+        .any(|admin_key| constant_time_eq(admin_key.as_bytes(), key.as_bytes()));
+    if known {
+        Ok(())
+    } else {
+        authenticate(&state.config, headers, Scope::Admin).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use super::WorkerControl;
+
+    #[tokio::test]
+    async fn holds_workers_while_paused() {
+        let control = Arc::new(WorkerControl::new());
+        control.pause();
+        let waiting = tokio::spawn({
+            let control = control.clone();
+            async move { control.wait_until_resumed().await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiting.is_finished());
+
+        control.resume();
+        tokio::time::timeout(Duration::from_secs(1), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!control.is_paused());
+    }
+}
@@ -1,88 +1,637 @@
-use std::sync::Arc;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use axum::{
-    Json, Router,
-    extract::{Path, State},
+    Extension, Json, Router,
+    extract::{DefaultBodyLimit, Multipart, Path, Query, State},
     http::{HeaderMap, StatusCode},
-    routing::{get, post},
+    middleware,
+    response::sse::{Event, KeepAlive, Sse},
+    routing::{get, post, put},
+};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use futures_util::{Stream, stream};
+use serde::Deserialize;
+use tokio::sync::broadcast;
+use utoipa::{
+    IntoParams, Modify, OpenApi, ToSchema,
+    openapi::security::{ApiKey, ApiKeyValue, SecurityScheme},
 };
+use utoipa_swagger_ui::SwaggerUi;
 use uuid::Uuid;
 
 use crate::engine::{
-    config::EngineConfig,
-    error::EngineError,
-    metrics::MetricsRegistry,
+    admin::{self, WorkerControl},
+    archive::{self, ArchiveLimits},
+    audit::{AuditLog, AuditedExecution, audit_http},
+    capacity,
+    config::{EngineConfig, RateLimit, SandboxBackendKind, Scope},
+    detect, determinism,
+    error::{EngineError, ErrorBody},
+    grading,
+    health::{Readiness, ReadinessReport},
+    idempotency::{self, Claim, IdempotencyKeys},
+    metrics::{MetricsRegistry, Utilization},
     models::{
-        CreateExecutionResponse, ExecutionRecord, ExecutionRequest, ExecutionSummaryResponse,
+        BenchmarkOptions, CreateExecutionResponse, CreateSessionRequest, EvalRequest, EvalResult,
+        ExecutionEvent, ExecutionLimits, ExecutionMode, ExecutionRecord, ExecutionRequest,
+        ExecutionStatus, ExecutionSummaryResponse, ExecutionTemplate, FileEncoding, InputFile,
+        Language, ProjectArchive, PutSecretRequest, SecretInfo, SessionInfo, ShareLink,
+        ShareRequest, StdinInput, TemplateExecutionRequest, TemplateInfo, UsageReport,
+        relative_path,
     },
-    queue::{QueuedJob, Scheduler},
+    queue::{GPU_POOL, MAX_PRIORITY, QueuedJob, Scheduler},
+    quota::Quotas,
     rate_limit::TenantRateLimiter,
-    store::ExecutionStore,
+    result_cache::ResultCache,
+    sandbox::{EgressRule, LanguageSpec, SandboxRegistry, SessionSpec, image_allowed},
+    secrets::SecretStore,
+    session::SessionManager,
+    share,
+    stdin_upload::{self, StdinUpload},
+    store::{EventUpdate, ExecutionStore, now_ms},
+    templates::TemplateStore,
 };
 
+// Room for 8 MiB of input files after base64 plus the rest of the request.
+pub(crate) const SUBMIT_BODY_LIMIT: usize = 12 * 1024 * 1024;
+const STDIN_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[derive(Clone)]
 pub struct AppState {
-    config: EngineConfig,
-    store: Arc<ExecutionStore>,
+    pub(crate) config: EngineConfig,
+    pub(crate) store: Arc<ExecutionStore>,
     scheduler: Scheduler,
     metrics: Arc<MetricsRegistry>,
     rate_limiter: TenantRateLimiter,
+    read_rate_limiter: TenantRateLimiter,
+    idempotency_keys: IdempotencyKeys,
+    pub(crate) quotas: Quotas,
+    pub(crate) readiness: Arc<Readiness>,
+    // None on API-only nodes.
+    pub(crate) sandboxes: Option<SandboxRegistry>,
+    pub(crate) workers: Arc<WorkerControl>,
+    pub(crate) result_cache: ResultCache,
+    pub(crate) sessions: Arc<SessionManager>,
+    pub(crate) templates: Arc<TemplateStore>,
+    pub(crate) secrets: Arc<SecretStore>,
+    pub(crate) audit: AuditLog,
 }
 
-pub fn routes(
-    config: EngineConfig,
-    store: Arc<ExecutionStore>,
-    scheduler: Scheduler,
-    metrics_registry: Arc<MetricsRegistry>,
-) -> Router {
-    let rate_limiter =
-        TenantRateLimiter::new(config.rate_limit_per_minute, config.rate_limit_burst);
-    let state = AppState {
-        config,
-        store,
-        scheduler,
-        metrics: metrics_registry,
-        rate_limiter,
+impl AppState {
+    pub fn new(
+        config: EngineConfig,
+        store: Arc<ExecutionStore>,
+        scheduler: Scheduler,
+        metrics_registry: Arc<MetricsRegistry>,
+        readiness: Arc<Readiness>,
+        sandboxes: Option<SandboxRegistry>,
+        workers: Arc<WorkerControl>,
+    ) -> Self {
+        let rate_limiter = tenant_rate_limiter(
+            config.rate_limit_per_minute,
+            config.rate_limit_burst,
+            &config.tenant_rate_limits,
+        );
+        let read_rate_limiter = tenant_rate_limiter(
+            config.read_rate_limit_per_minute,
+            config.read_rate_limit_burst,
+            &config.tenant_read_rate_limits,
+        );
+        let idempotency_keys =
+            IdempotencyKeys::new(Duration::from_secs(config.idempotency_ttl_secs.max(1)));
+        let result_cache = ResultCache::new(Duration::from_secs(config.result_cache_ttl_secs));
+        let sessions = Arc::new(SessionManager::new(&config));
+        let quotas = Quotas::in_memory(&config);
+        Self {
+            config,
+            store,
+            scheduler,
+            metrics: metrics_registry,
+            rate_limiter,
+            read_rate_limiter,
+            idempotency_keys,
+            quotas,
+            readiness,
+            sandboxes,
+            workers,
+            result_cache,
+            sessions,
+            templates: Arc::new(TemplateStore::new()),
+            secrets: Arc::new(SecretStore::new()),
+            audit: AuditLog::disabled(),
+        }
+    }
+
+    pub fn with_templates(mut self, templates: Arc<TemplateStore>) -> Self {
+        self.templates = templates;
+        self
+    }
+
+    pub fn with_secrets(mut self, secrets: Arc<SecretStore>) -> Self {
+        self.secrets = secrets;
+        self
+    }
+
+    pub fn with_audit(mut self, audit: AuditLog) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    pub fn with_quotas(mut self, quotas: Quotas) -> Self {
+        self.quotas = quotas;
+        self
+    }
+}
+
+fn tenant_rate_limiter(
+    per_minute: u32,
+    burst: u32,
+    tenants: &HashMap<String, RateLimit>,
+) -> TenantRateLimiter {
+    tenants.iter().fold(
+        TenantRateLimiter::new(per_minute, burst),
+        |limiter, (tenant, limit)| limiter.with_tenant(tenant, limit.per_minute, limit.burst),
+    )
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Sandboxed Code Execution Engine"),
+    paths(
+        health,
+        ready,
+        metrics,
+        submit_execution,
+        submit_execution_upload,
+        list_executions,
+        get_execution,
+        get_result,
+        share_execution,
+        get_shared_result,
+        stream_events,
+        write_stdin,
+        usage,
+        create_session,
+        get_session,
+        eval_session,
+        suspend_session,
+        resume_session,
+        close_session,
+        list_templates,
+        get_template,
+        put_template,
+        delete_template,
+        submit_template_execution,
+        list_secrets,
+        put_secret,
+        delete_secret
+    ),
+    components(schemas(ErrorBody)),
+    modifiers(&ApiKeyAuth),
+    security(("api_key" = []))
+)]
+pub struct ApiDoc;
+
+struct ApiKeyAuth;
+
+impl Modify for ApiKeyAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        openapi
+            .components
+            .get_or_insert_default()
+            .add_security_scheme(
+                "api_key",
+                SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("x-api-key"))),
+            );
+    }
+}
+
+// Shape of the multipart upload body; only used for the OpenAPI document.
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct UploadForm {
+    // The JSON execution request.
+    request: String,
+    // Optional zip, tar or tar.gz project archive.
+    #[schema(format = Binary, content_media_type = "application/octet-stream")]
+    archive: Option<Vec<u8>>,
+    // Optional stdin too large for the JSON body, streamed to disk.
+    #[schema(format = Binary, content_media_type = "application/octet-stream")]
+    stdin: Option<Vec<u8>>,
+    // Any number of parts with a filename, written as input files.
+    #[schema(format = Binary, content_media_type = "application/octet-stream")]
+    file: Vec<u8>,
+}
+
+pub fn routes(state: AppState) -> Router {
+    let docs: Router<AppState> = if state.config.swagger_ui {
+        SwaggerUi::new("/docs")
+            .url("/openapi.json", ApiDoc::openapi())
+            .into()
+    } else {
+        Router::new().route("/openapi.json", get(|| async { Json(ApiDoc::openapi()) }))
     };
-    Router::new()
+    // Probes, metrics and docs are not audited.
+    let audited = middleware::from_fn_with_state(state.clone(), audit_http);
+    let ops = Router::new()
         .route("/healthz", get(health))
-        .route("/metrics", get(metrics))
-        .route("/v1/executions", post(submit_execution))
+        .route("/readyz", get(ready))
+        .route("/metrics", get(metrics));
+    if !state.config.role.serves_api() {
+        return ops
+            .merge(admin::routes().route_layer(audited))
+            .with_state(state);
+    }
+    let api = admin::routes()
+        .route(
+            "/v1/executions",
+            get(list_executions)
+                .post(submit_execution)
+                .layer(DefaultBodyLimit::max(SUBMIT_BODY_LIMIT)),
+        )
+        .route(
+            "/v1/executions/upload",
+            post(submit_execution_upload).layer(DefaultBodyLimit::max(
+                SUBMIT_BODY_LIMIT + state.config.stdin_upload_max_bytes as usize,
+            )),
+        )
         .route("/v1/executions/{id}", get(get_execution))
         .route("/v1/executions/{id}/result", get(get_result))
-        .with_state(state)
+        .route("/v1/executions/{id}/share", post(share_execution))
+        .route("/v1/shared/executions/{id}/result", get(get_shared_result))
+        .route("/v1/executions/{id}/events", get(stream_events))
+        .route("/v1/executions/{id}/stdin", post(write_stdin))
+        .route("/v1/usage", get(usage))
+        .route("/v1/sessions", post(create_session))
+        .route("/v1/sessions/{id}", get(get_session).delete(close_session))
+        .route("/v1/sessions/{id}/eval", post(eval_session))
+        .route("/v1/sessions/{id}/suspend", post(suspend_session))
+        .route("/v1/sessions/{id}/resume", post(resume_session))
+        .route("/v1/templates", get(list_templates))
+        .route(
+            "/v1/templates/{name}",
+            get(get_template)
+                .put(put_template)
+                .delete(delete_template)
+                .layer(DefaultBodyLimit::max(SUBMIT_BODY_LIMIT)),
+        )
+        .route(
+            "/v1/templates/{name}/executions",
+            post(submit_template_execution).layer(DefaultBodyLimit::max(SUBMIT_BODY_LIMIT)),
+        )
+        .route("/v1/secrets", get(list_secrets))
+        .route("/v1/secrets/{name}", put(put_secret).delete(delete_secret))
+        .route_layer(audited);
+    ops.merge(docs).merge(api).with_state(state)
 }
 
+#[utoipa::path(get, path = "/healthz", security(), responses((status = 200, body = Object)))]
 async fn health() -> Json<serde_json::Value> {
     Json(serde_json::json!({ "ok": true }))
 }
 
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    security(),
+    responses(
+        (status = 200, body = ReadinessReport),
+        (status = 503, description = "Sandbox images are still being pulled, or the default sandbox backend is down", body = ReadinessReport)
+    )
+)]
+async fn ready(State(state): State<AppState>) -> (StatusCode, Json<ReadinessReport>) {
+    let report = state.readiness.report();
+    let status = if report.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(report))
+}
+
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    security(),
+    responses((status = 200, description = "Prometheus text format", body = String, content_type = "text/plain"))
+)]
 async fn metrics(State(state): State<AppState>) -> (StatusCode, String) {
-    (StatusCode::OK, state.metrics.render_prometheus())
+    (StatusCode::OK, render_metrics(&state))
+}
+
+// Also what the push exporters send.
+pub(crate) fn render_metrics(state: &AppState) -> String {
+    let mut body = state.metrics.render_prometheus();
+    Utilization {
+        running: state.workers.running().len(),
+        workers: state.workers.usage(),
+        sandboxes: state
+            .sandboxes
+            .iter()
+            .flat_map(|sandboxes| sandboxes.active())
+            .map(|(kind, active)| (kind.as_str().to_string(), active))
+            .collect(),
+    }
+    .render(&mut body);
+    body
 }
 
+#[utoipa::path(
+    post,
+    path = "/v1/executions",
+    params(("Idempotency-Key" = Option<String>, Header, description = "Retries with the same key and body return the original execution")),
+    request_body = ExecutionRequest,
+    responses(
+        (status = 202, body = CreateExecutionResponse),
+        (status = 200, description = "Replay of an earlier submission with the same Idempotency-Key, or a finished execution answered from the result cache", body = CreateExecutionResponse),
+        (status = 400, body = ErrorBody),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 409, description = "Idempotency-Key reused for a different body", body = ErrorBody),
+        (status = 429, body = ErrorBody),
+        (status = 503, description = "Queue is full or too slow to admit more work", body = ErrorBody,
+            headers(("Retry-After" = u64, description = "Seconds until the queue is expected to admit the job")))
+    )
+)]
 async fn submit_execution(
     State(state): State<AppState>,
     headers: HeaderMap,
-    Json(mut request): Json<ExecutionRequest>,
+    Json(request): Json<serde_json::Value>,
+) -> Result<Created, EngineError> {
+    let request = detect::parse_request(request).map_err(EngineError::InvalidRequest)?;
+    enqueue_execution(&state, &headers, request)
+        .await
+        .map(audited)
+}
+
+// Multipart variant: a `request` part carries the JSON body, an `archive` part
+// a project archive (its entrypoint set in the body's `archive`), and every
+// other part with a filename becomes an input file.
+#[utoipa::path(
+    post,
+    path = "/v1/executions/upload",
+    params(("Idempotency-Key" = Option<String>, Header)),
+    request_body(content = UploadForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 202, body = CreateExecutionResponse),
+        (status = 400, body = ErrorBody),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 429, body = ErrorBody),
+        (status = 503, description = "Queue is full; see Retry-After", body = ErrorBody)
+    )
+)]
+async fn submit_execution_upload(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Result<Created, EngineError> {
+    let mut stdin = None;
+    let parsed = parse_upload(&state, &mut multipart, &mut stdin).await;
+    let stdin_path = stdin.as_ref().map(|upload| upload.path.clone());
+    let created = match parsed {
+        Ok(request) => enqueue_with_stdin(&state, &headers, request, stdin).await,
+        Err(err) => Err(err),
+    };
+    // Only a newly queued execution reads the upload.
+    if let Some(path) = stdin_path
+        && !matches!(&created, Ok((StatusCode::ACCEPTED, _)))
+    {
+        stdin_upload::discard(&path).await;
+    }
+    created.map(audited)
+}
+
+// The `stdin` part is spooled into `stdin` as it arrives, so the caller can
+// remove it whatever happens after.
+async fn parse_upload(
+    state: &AppState,
+    multipart: &mut Multipart,
+    stdin: &mut Option<StdinUpload>,
+) -> Result<ExecutionRequest, EngineError> {
+    let mut request: Option<ExecutionRequest> = None;
+    let mut project = None;
+    let mut files = Vec::new();
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|err| EngineError::InvalidRequest(err.to_string()))?
+    {
+        let field_name = field.name().unwrap_or_default().to_string();
+        if field_name == "stdin" {
+            if let Some(previous) = stdin.replace(stdin_upload::spool(&state.config, field).await?)
+            {
+                stdin_upload::discard(&previous.path).await;
+            }
+            continue;
+        }
+        if field_name == "request" {
+            let body = field
+                .bytes()
+                .await
+                .map_err(|err| EngineError::InvalidRequest(err.to_string()))?;
+            request = Some(
+                serde_json::from_slice(&body)
+                    .map_err(|err| err.to_string())
+                    .and_then(detect::parse_request)
+                    .map_err(|err| {
+                        EngineError::InvalidRequest(format!("invalid request part: {err}"))
+                    })?,
+            );
+            continue;
+        }
+        if field_name == "archive" {
+            let body = field
+                .bytes()
+                .await
+                .map_err(|err| EngineError::InvalidRequest(err.to_string()))?;
+            project = Some(BASE64_STANDARD.encode(body));
+            continue;
+        }
+        let name = field.file_name().map(str::to_string).unwrap_or(field_name);
+        let content = field
+            .bytes()
+            .await
+            .map_err(|err| EngineError::InvalidRequest(err.to_string()))?;
+        files.push(InputFile {
+            name,
+            content: BASE64_STANDARD.encode(content),
+            encoding: FileEncoding::Base64,
+        });
+    }
+
+    let mut request =
+        request.ok_or_else(|| EngineError::InvalidRequest("missing request part".to_string()))?;
+    request.files.extend(files);
+    if let Some(content) = project {
+        request
+            .archive
+            .get_or_insert_with(|| ProjectArchive {
+                content: String::new(),
+                entrypoint: None,
+            })
+            .content = content;
+    }
+    Ok(request)
+}
+
+// A submission's response, naming the new execution for the audit log.
+type Created = (
+    StatusCode,
+    Extension<AuditedExecution>,
+    Json<CreateExecutionResponse>,
+);
+
+fn audited((status, created): (StatusCode, Json<CreateExecutionResponse>)) -> Created {
+    (status, Extension(AuditedExecution(created.id)), created)
+}
+
+pub(crate) async fn enqueue_execution(
+    state: &AppState,
+    headers: &HeaderMap,
+    request: ExecutionRequest,
 ) -> Result<(StatusCode, Json<CreateExecutionResponse>), EngineError> {
-    let tenant_id = authenticate(&state.config, &headers)?;
-    enforce_rate_limit(&state, &tenant_id).await?;
+    enqueue_with_stdin(state, headers, request, None).await
+}
+
+// `stdin` replaces the request's inline stdin. The caller removes it unless
+// the execution was newly queued (202).
+async fn enqueue_with_stdin(
+    state: &AppState,
+    headers: &HeaderMap,
+    mut request: ExecutionRequest,
+    stdin: Option<StdinUpload>,
+) -> Result<(StatusCode, Json<CreateExecutionResponse>), EngineError> {
+    let tenant_id = authenticate(&state.config, headers, Scope::Submit)?;
+    let span = tracing::info_span!(
+        "execution",
+        execution_id = tracing::field::Empty,
+        tenant_id = %tenant_id,
+        language = request.language.as_str(),
+    );
+    // Not entered (it would be held across awaits); it closes when submit returns.
+    let _submit = tracing::info_span!(parent: &span, "execution.submit");
+    enforce_rate_limit(state, &tenant_id).await?;
+    let idempotency_key = idempotency_key(headers)?;
+    // Fingerprint the body as submitted, before any defaults are filled in.
+    let mut fingerprint = request_fingerprint(&request)?;
+    if let Some(upload) = &stdin {
+        let mut hasher = DefaultHasher::new();
+        (fingerprint, upload.hash).hash(&mut hasher);
+        fingerprint = hasher.finish();
+    }
 
+    unpack_archive(&state.config, &mut request)?;
     validate_request(&request)?;
+    validate_metadata(&state.config, &request.metadata)?;
+    if stdin.is_some()
+        && (!request.stdin.is_empty()
+            || !request.test_cases.is_empty()
+            || request
+                .benchmark
+                .as_ref()
+                .is_some_and(|benchmark| !benchmark.inputs.is_empty()))
+    {
+        return Err(EngineError::InvalidRequest(
+            "a stdin upload cannot be combined with stdin, test_cases or benchmark inputs"
+                .to_string(),
+        ));
+    }
     if request.allow_network && !state.config.network_allowed_tenants.contains(&tenant_id) {
         return Err(EngineError::Forbidden);
     }
+    if request.priority.is_none() {
+        request.priority = priority_header(headers)?;
+    }
+    if let Some(priority) = request.priority {
+        if priority > MAX_PRIORITY {
+            return Err(EngineError::InvalidRequest(format!(
+                "priority must be 0 to {MAX_PRIORITY}"
+            )));
+        }
+        if priority > state.config.max_priority(&tenant_id) {
+            return Err(EngineError::Forbidden);
+        }
+    }
+    let backend = resolve_backend(&state.config, &tenant_id, request.backend)?;
+    request.backend = Some(backend);
+    let docker = matches!(backend, SandboxBackendKind::Docker);
+    if !request.egress.is_empty() && !docker {
+        return Err(EngineError::InvalidRequest(
+            "egress rules require the docker backend".to_string(),
+        ));
+    }
+    if request.allow_network && state.config.network_proxy && !docker {
+        return Err(EngineError::InvalidRequest(
+            "network access goes through the egress proxy, which requires the docker backend"
+                .to_string(),
+        ));
+    }
+    if request.dependencies.is_some() && !state.config.may_install_dependencies(&tenant_id, backend)
+    {
+        return Err(EngineError::Forbidden);
+    }
+    if request.gpu {
+        if !state.scheduler.has_pool(GPU_POOL) || !docker {
+            return Err(EngineError::InvalidRequest(
+                "gpu execution is not enabled".to_string(),
+            ));
+        }
+        if !state.config.gpu_tenants.contains(&tenant_id) {
+            return Err(EngineError::Forbidden);
+        }
+    }
+    validate_egress(&state.config, &tenant_id, &request)?;
+    validate_version(&state.config, backend, &request)?;
+    validate_git(&state.config, &request)?;
+    let secrets = resolve_secrets(state, &tenant_id, &request)?;
+    determinism::apply(&state.config, &mut request).map_err(EngineError::InvalidRequest)?;
+    if matches!(
+        request.mode,
+        Some(ExecutionMode::Lint | ExecutionMode::Format | ExecutionMode::Test)
+    ) && state
+        .config
+        .tools
+        .command(&request.language, request.mode.as_ref())
+        .is_none()
+    {
+        return Err(EngineError::InvalidRequest(format!(
+            "no {} command is configured for {}",
+            match request.mode {
+                Some(ExecutionMode::Lint) => "lint",
+                Some(ExecutionMode::Format) => "format",
+                _ => "test",
+            },
+            request.language.as_str()
+        )));
+    }
+    if let Some(image) = &request.image {
+        if !docker {
+            return Err(EngineError::InvalidRequest(
+                "custom images require the docker backend".to_string(),
+            ));
+        }
+        if !image_allowed(&state.config.allowed_images, image) {
+            return Err(EngineError::Forbidden);
+        }
+    }
     if request.mode.is_none() {
         request.mode = Some(crate::engine::models::ExecutionMode::Human);
     }
 
-    let id = Uuid::new_v4();
-    let mut limits = request
-        .limits
-        .clone()
-        .unwrap_or_else(|| state.config.default_limits.clone())
-        .normalized();
+    let mut limits = resolve_limits(
+        &state.config,
+        &tenant_id,
+        &request.language,
+        request.limits.as_ref(),
+        request.profile.as_deref(),
+    )?
+    .normalized();
     if matches!(
         request.mode,
         Some(crate::engine::models::ExecutionMode::AgentOptimized)
@@ -90,6 +639,108 @@ async fn submit_execution(
         limits.timeout_ms = limits.timeout_ms.max(8_000);
         limits.max_output_bytes = limits.max_output_bytes.max(256 * 1024);
     }
+    if request.deterministic {
+        limits.cpu_cores = limits.cpu_cores.min(1.0);
+    }
+    if let Some(benchmark) = &request.benchmark {
+        validate_benchmark(&state.config, benchmark, &limits)?;
+    }
+    capacity::check(&state.config, &request, &limits).map_err(EngineError::InvalidRequest)?;
+
+    if let Some(key) = &idempotency_key {
+        match state.idempotency_keys.claim(&tenant_id, key, fingerprint) {
+            Claim::Reserved => {}
+            Claim::Existing(id) => {
+                let status = state
+                    .store
+                    .get(&id)
+                    .map(|record| record.status)
+                    .unwrap_or(ExecutionStatus::Queued);
+                return Ok((StatusCode::OK, Json(CreateExecutionResponse { id, status })));
+            }
+            Claim::InProgress => {
+                return Err(EngineError::Conflict(
+                    "a request with this idempotency key is still being processed".to_string(),
+                ));
+            }
+            Claim::Mismatch => {
+                return Err(EngineError::Conflict(
+                    "idempotency key was already used for a different request".to_string(),
+                ));
+            }
+        }
+    }
+    // Replays of an idempotency key are not counted again.
+    if let Err(err) = state.quotas.charge(&tenant_id).await {
+        if let Some(key) = &idempotency_key {
+            state.idempotency_keys.release(&tenant_id, key);
+        }
+        return Err(err);
+    }
+
+    let id = Uuid::new_v4();
+    span.record("execution_id", tracing::field::display(id));
+    if let Some(key) = &idempotency_key {
+        request.metadata.insert(
+            format!("{SYSTEM_METADATA_PREFIX}idempotency_key"),
+            key.clone(),
+        );
+    }
+    if request.language_detected {
+        request.metadata.insert(
+            format!("{SYSTEM_METADATA_PREFIX}detected_language"),
+            request.language.as_str().to_string(),
+        );
+    }
+    if let Some(upload) = &stdin {
+        request.metadata.insert(
+            format!("{SYSTEM_METADATA_PREFIX}stdin_bytes"),
+            upload.bytes.to_string(),
+        );
+    }
+
+    // A cached result could only have been redacted with other values, a
+    // git ref may have moved since, and benchmark timings are only good for
+    // the run that measured them. Uploaded stdin is not part of the key.
+    if request.cache
+        && stdin.is_none()
+        && secrets.is_empty()
+        && request.git.is_none()
+        && request.mode != Some(ExecutionMode::Benchmark)
+        && let Some(source) = state
+            .result_cache
+            .lookup(&state.store, &tenant_id, &request, &limits)
+    {
+        request.metadata.insert(
+            format!("{SYSTEM_METADATA_PREFIX}cached_from"),
+            source.id.to_string(),
+        );
+        let record = state
+            .store
+            .create_record(id, tenant_id.clone(), request, limits);
+        state.store.insert(record);
+        state.store.append_event(
+            id,
+            "cache_hit",
+            format!("result reused from execution {}", source.id),
+        );
+        state
+            .store
+            .mark_finished(id, source.status.clone(), source.output, source.error)
+            .await;
+        state.metrics.cache_hit();
+        if let Some(key) = &idempotency_key {
+            state.idempotency_keys.commit(&tenant_id, key, id);
+        }
+        return Ok((
+            StatusCode::OK,
+            Json(CreateExecutionResponse {
+                id,
+                status: source.status,
+            }),
+        ));
+    }
+
     let record: ExecutionRecord =
         state
             .store
@@ -100,61 +751,929 @@ async fn submit_execution(
         .scheduler
         .submit(QueuedJob {
             id,
-            tenant_id,
+            tenant_id: tenant_id.clone(),
             request,
             limits,
+            secrets,
+            stdin_file: stdin.map(|upload| upload.path),
+            enqueued_at: Instant::now(),
+            attempts: 0,
+            last_worker: None,
+            receipt: None,
+            queue_wait: tracing::info_span!(parent: &span, "execution.queue_wait"),
+            span: span.clone(),
         })
         .await
     {
         state.store.remove(&id);
+        state.quotas.refund(&tenant_id).await;
+        if let Some(key) = &idempotency_key {
+            state.idempotency_keys.release(&tenant_id, key);
+        }
         return Err(err);
     }
+    if let Some(key) = &idempotency_key {
+        state.idempotency_keys.commit(&tenant_id, key, id);
+    }
 
     Ok((
         StatusCode::ACCEPTED,
         Json(CreateExecutionResponse {
             id,
-            status: crate::engine::models::ExecutionStatus::Queued,
+            status: ExecutionStatus::Queued,
         }),
     ))
 }
 
+#[utoipa::path(
+    get,
+    path = "/v1/executions/{id}",
+    params(("id" = Uuid, Path)),
+    responses(
+        (status = 200, body = ExecutionSummaryResponse),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody)
+    )
+)]
 async fn get_execution(
     State(state): State<AppState>,
     headers: HeaderMap,
     Path(id): Path<Uuid>,
 ) -> Result<Json<ExecutionSummaryResponse>, EngineError> {
-    let tenant_id = authenticate(&state.config, &headers)?;
-    let record = load_for_tenant(&state, id, &tenant_id)?;
-
-    Ok(Json(ExecutionSummaryResponse {
-        id: record.id,
-        tenant_id: record.tenant_id,
-        status: record.status,
-        created_at_ms: record.created_at_ms,
-        started_at_ms: record.started_at_ms,
-        finished_at_ms: record.finished_at_ms,
-    }))
+    let tenant_id = authenticate_read(&state, &headers).await?;
+    let record = load_for_tenant(&state, id, &tenant_id).await?;
+    Ok(Json(record.into()))
+}
+
+pub(crate) const DEFAULT_LIST_LIMIT: u32 = 50;
+pub(crate) const MAX_LIST_LIMIT: u32 = 500;
+
+#[utoipa::path(
+    get,
+    path = "/v1/executions",
+    params(
+        ("status" = Option<ExecutionStatus>, Query, description = "Only executions in this status"),
+        ("limit" = Option<u32>, Query, description = "Defaults to 50, at most 500"),
+        ("offset" = Option<u32>, Query),
+        ("metadata.{key}" = Option<String>, Query,
+            description = "Only executions whose metadata has this key and value; repeat for several keys")
+    ),
+    responses(
+        (status = 200, body = Vec<ExecutionSummaryResponse>),
+        (status = 400, body = ErrorBody),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody)
+    )
+)]
+async fn list_executions(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<Vec<(String, String)>>,
+) -> Result<Json<Vec<ExecutionSummaryResponse>>, EngineError> {
+    let tenant_id = authenticate_read(&state, &headers).await?;
+    let invalid = |name: &str| EngineError::InvalidRequest(format!("invalid {name}"));
+    let mut status = None;
+    let mut limit = DEFAULT_LIST_LIMIT;
+    let mut offset = 0;
+    let mut metadata = BTreeMap::new();
+    for (name, value) in query {
+        match name.as_str() {
+            "status" => {
+                status = Some(
+                    serde_json::from_value::<ExecutionStatus>(serde_json::Value::String(value))
+                        .map_err(|_| invalid("status"))?,
+                )
+            }
+            "limit" => limit = value.parse::<u32>().map_err(|_| invalid("limit"))?,
+            "offset" => offset = value.parse::<usize>().map_err(|_| invalid("offset"))?,
+            _ => match name.strip_prefix("metadata.") {
+                Some(key) if !key.is_empty() => {
+                    metadata.insert(key.to_string(), value);
+                }
+                _ => {
+                    return Err(EngineError::InvalidRequest(format!(
+                        "unknown query parameter {name}"
+                    )));
+                }
+            },
+        }
+    }
+    let executions = state
+        .store
+        .list_for_tenant(&tenant_id, status.as_ref(), &metadata)
+        .into_iter()
+        .skip(offset)
+        .take(limit.clamp(1, MAX_LIST_LIMIT) as usize)
+        .map(ExecutionSummaryResponse::from)
+        .collect();
+    Ok(Json(executions))
 }
 
+#[utoipa::path(
+    get,
+    path = "/v1/executions/{id}/result",
+    params(("id" = Uuid, Path)),
+    responses(
+        (status = 200, body = ExecutionRecord),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody)
+    )
+)]
 async fn get_result(
     State(state): State<AppState>,
     headers: HeaderMap,
     Path(id): Path<Uuid>,
 ) -> Result<Json<ExecutionRecord>, EngineError> {
-    let tenant_id = authenticate(&state.config, &headers)?;
-    let record = load_for_tenant(&state, id, &tenant_id)?;
+    let tenant_id = authenticate_read(&state, &headers).await?;
+    let record = load_for_tenant(&state, id, &tenant_id).await?;
+    Ok(Json(record))
+}
+
+const DEFAULT_SHARE_TTL_SECS: u64 = 24 * 3600;
+
+#[utoipa::path(
+    post,
+    path = "/v1/executions/{id}/share",
+    params(("id" = Uuid, Path)),
+    request_body = ShareRequest,
+    responses(
+        (status = 200, body = ShareLink),
+        (status = 400, description = "Sharing is not enabled, or the TTL is out of range", body = ErrorBody),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody)
+    )
+)]
+async fn share_execution(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+    Json(request): Json<ShareRequest>,
+) -> Result<Json<ShareLink>, EngineError> {
+    let tenant_id = authenticate_read(&state, &headers).await?;
+    let key = state.config.share_link_key.as_deref().ok_or_else(|| {
+        EngineError::InvalidRequest("share links are not enabled on this engine".to_string())
+    })?;
+    let ttl_secs = request
+        .ttl_secs
+        .unwrap_or(DEFAULT_SHARE_TTL_SECS.min(state.config.share_link_max_ttl_secs));
+    if ttl_secs == 0 || ttl_secs > state.config.share_link_max_ttl_secs {
+        return Err(EngineError::InvalidRequest(format!(
+            "ttl_secs must be between 1 and {}",
+            state.config.share_link_max_ttl_secs
+        )));
+    }
+    load_for_tenant(&state, id, &tenant_id).await?;
+    let expires_at_ms = now_ms() + ttl_secs * 1000;
+    let signature = share::sign(key, id, &tenant_id, expires_at_ms, request.redact);
+    Ok(Json(ShareLink {
+        url: format!(
+            "{}/v1/shared/executions/{id}/result?expires={expires_at_ms}&redact={}&signature={signature}",
+            state
+                .config
+                .share_link_base_url
+                .as_deref()
+                .unwrap_or_default(),
+            request.redact
+        ),
+        expires_at_ms,
+    }))
+}
+
+// The query of a link minted by `share_execution`.
+#[derive(Debug, Deserialize, IntoParams)]
+struct SharedQuery {
+    // Unix milliseconds.
+    expires: u64,
+    #[serde(default)]
+    redact: bool,
+    signature: String,
+}
+
+// Counted against the sharing tenant's read rate limit.
+#[utoipa::path(
+    get,
+    path = "/v1/shared/executions/{id}/result",
+    security(),
+    params(("id" = Uuid, Path), SharedQuery),
+    responses(
+        (status = 200, body = ExecutionRecord),
+        (status = 403, description = "The link is expired or its signature does not match", body = ErrorBody),
+        (status = 404, body = ErrorBody),
+        (status = 429, body = ErrorBody)
+    )
+)]
+async fn get_shared_result(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<SharedQuery>,
+) -> Result<Json<ExecutionRecord>, EngineError> {
+    let key = state
+        .config
+        .share_link_key
+        .as_deref()
+        .ok_or(EngineError::NotFound)?;
+    let mut record = state.store.load(&id).await?.ok_or(EngineError::NotFound)?;
+    if !share::verify(
+        key,
+        &record,
+        query.expires,
+        query.redact,
+        &query.signature,
+        now_ms(),
+    ) {
+        return Err(EngineError::Forbidden);
+    }
+    if !state.read_rate_limiter.allow(&record.tenant_id).await {
+        return Err(EngineError::RateLimited);
+    }
+    if query.redact {
+        share::redact(&mut record);
+    }
     Ok(Json(record))
 }
 
-fn authenticate(config: &EngineConfig, headers: &HeaderMap) -> Result<String, EngineError> {
+// Input is only accepted while the execution runs, and only by the node
+// running it.
+#[utoipa::path(
+    post,
+    path = "/v1/executions/{id}/stdin",
+    params(("id" = Uuid, Path)),
+    request_body = StdinInput,
+    responses(
+        (status = 204, description = "Input written to the program's stdin"),
+        (status = 400, body = ErrorBody),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+        (status = 409, body = ErrorBody)
+    )
+)]
+async fn write_stdin(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+    Json(input): Json<StdinInput>,
+) -> Result<StatusCode, EngineError> {
+    let tenant_id = authenticate(&state.config, &headers, Scope::Submit)?;
+    let record = load_for_tenant(&state, id, &tenant_id).await?;
+    if !record.request.interactive {
+        return Err(EngineError::Conflict(
+            "execution is not interactive".to_string(),
+        ));
+    }
+    let data = input.decode().map_err(EngineError::InvalidRequest)?;
+    if data.len() > 256_000 {
+        return Err(EngineError::InvalidRequest("stdin too large".to_string()));
+    }
+    let Some(sender) = state.workers.stdin(id) else {
+        return Err(EngineError::Conflict(format!(
+            "execution is {} and not accepting input here",
+            record.status.as_str()
+        )));
+    };
+    // A program that stops reading would otherwise hold the request forever.
+    if !data.is_empty()
+        && sender
+            .send_timeout(data, STDIN_WRITE_TIMEOUT)
+            .await
+            .is_err()
+    {
+        return Err(EngineError::Conflict(
+            "execution is no longer reading input".to_string(),
+        ));
+    }
+    if input.close {
+        state.workers.close_stdin(id);
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Replays the recorded timeline, then follows new events until `finished`.
+#[utoipa::path(
+    get,
+    path = "/v1/executions/{id}/events",
+    params(("id" = Uuid, Path)),
+    responses(
+        (status = 200, description = "Server-sent events named by stage, with the event as JSON data", body = ExecutionEvent, content_type = "text/event-stream"),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody)
+    )
+)]
+async fn stream_events(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, EngineError> {
+    let tenant_id = authenticate_read(&state, &headers).await?;
+    // Subscribe before the snapshot so nothing falls between the two.
+    let updates = state.store.subscribe();
+    let record = load_for_tenant(&state, id, &tenant_id).await?;
+
+    let follow = EventFollow::new(state.store.clone(), record, updates);
+    let stream = stream::unfold(follow, |mut follow| async move {
+        let (seq, event) = follow.next().await?;
+        let sse = Event::default()
+            .id(seq.to_string())
+            .event(event.stage.clone())
+            .json_data(&event);
+        Some((sse, follow))
+    });
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+const DEFAULT_USAGE_WINDOW_MS: u64 = 24 * 60 * 60 * 1000;
+
+// Window bounds in unix milliseconds; defaults to the last 24 hours.
+#[derive(Debug, Deserialize, IntoParams)]
+struct UsageQuery {
+    // Inclusive start.
+    from_ms: Option<u64>,
+    // Exclusive end.
+    to_ms: Option<u64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/usage",
+    params(UsageQuery),
+    responses(
+        (status = 200, body = UsageReport),
+        (status = 400, body = ErrorBody),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody)
+    )
+)]
+async fn usage(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<UsageQuery>,
+) -> Result<Json<UsageReport>, EngineError> {
+    let tenant_id = authenticate_read(&state, &headers).await?;
+    let to_ms = query.to_ms.unwrap_or_else(|| now_ms() + 1);
+    let from_ms = query
+        .from_ms
+        .unwrap_or_else(|| to_ms.saturating_sub(DEFAULT_USAGE_WINDOW_MS));
+    if from_ms > to_ms {
+        return Err(EngineError::InvalidRequest(
+            "from_ms must not be after to_ms".to_string(),
+        ));
+    }
+    Ok(Json(state.store.usage_report(&tenant_id, from_ms, to_ms)))
+}
+
+// Sessions run on the node that created them, outside the queue.
+#[utoipa::path(
+    post,
+    path = "/v1/sessions",
+    request_body = CreateSessionRequest,
+    responses(
+        (status = 201, body = SessionInfo),
+        (status = 400, body = ErrorBody),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 409, body = ErrorBody),
+        (status = 429, body = ErrorBody)
+    )
+)]
+async fn create_session(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<CreateSessionRequest>,
+) -> Result<(StatusCode, Json<SessionInfo>), EngineError> {
+    let tenant_id = authenticate(&state.config, &headers, Scope::Submit)?;
+    enforce_rate_limit(&state, &tenant_id).await?;
+    let Some(sandboxes) = state
+        .sandboxes
+        .as_ref()
+        .filter(|_| state.sessions.enabled())
+    else {
+        return Err(EngineError::InvalidRequest(
+            "sessions are not enabled on this node".to_string(),
+        ));
+    };
+    validate_env(&request.env)?;
+    let backend = resolve_backend(&state.config, &tenant_id, request.backend)?;
+    let limits = resolve_limits(
+        &state.config,
+        &tenant_id,
+        &request.language,
+        request.limits.as_ref(),
+        request.profile.as_deref(),
+    )?
+    .normalized();
+    let sandbox = sandboxes.get(backend).ok_or_else(|| {
+        EngineError::InvalidRequest(format!(
+            "sandbox backend {} is not enabled",
+            backend.as_str()
+        ))
+    })?;
+    let session = state
+        .sessions
+        .open(
+            &tenant_id,
+            backend,
+            sandbox,
+            SessionSpec {
+                id: Uuid::new_v4(),
+                language: request.language,
+                limits,
+                env: request.env,
+            },
+        )
+        .await?;
+    Ok((StatusCode::CREATED, Json(session.info())))
+}
+
+// Includes the names defined in the session.
+#[utoipa::path(
+    get,
+    path = "/v1/sessions/{id}",
+    params(("id" = Uuid, Path)),
+    responses(
+        (status = 200, body = SessionInfo),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+        (status = 409, body = ErrorBody)
+    )
+)]
+async fn get_session(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+) -> Result<Json<SessionInfo>, EngineError> {
+    let tenant_id = authenticate_read(&state, &headers).await?;
+    let session = state.sessions.get(id, &tenant_id)?;
+    Ok(Json(state.sessions.inspect(&session).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/sessions/{id}/eval",
+    params(("id" = Uuid, Path)),
+    request_body = EvalRequest,
+    responses(
+        (status = 200, body = EvalResult),
+        (status = 400, body = ErrorBody),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+        (status = 429, body = ErrorBody)
+    )
+)]
+async fn eval_session(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+    Json(request): Json<EvalRequest>,
+) -> Result<Json<EvalResult>, EngineError> {
+    let tenant_id = authenticate(&state.config, &headers, Scope::Submit)?;
+    enforce_rate_limit(&state, &tenant_id).await?;
+    if request.code.len() > 250_000 {
+        return Err(EngineError::InvalidRequest("code too large".to_string()));
+    }
+    let session = state.sessions.get(id, &tenant_id)?;
+    Ok(Json(state.sessions.eval(&session, &request.code).await))
+}
+
+// Checkpoints the session's runtime and stops it (docker backend with
+// SESSION_CHECKPOINT); it no longer counts against SESSION_MAX. The next
+// evaluation restores it.
+#[utoipa::path(
+    post,
+    path = "/v1/sessions/{id}/suspend",
+    params(("id" = Uuid, Path)),
+    responses(
+        (status = 200, body = SessionInfo),
+        (status = 400, body = ErrorBody),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody)
+    )
+)]
+async fn suspend_session(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+) -> Result<Json<SessionInfo>, EngineError> {
+    let tenant_id = authenticate(&state.config, &headers, Scope::Submit)?;
+    let session = state.sessions.get(id, &tenant_id)?;
+    Ok(Json(state.sessions.suspend(&session).await?))
+}
+
+// Restores a suspended session ahead of its next evaluation.
+#[utoipa::path(
+    post,
+    path = "/v1/sessions/{id}/resume",
+    params(("id" = Uuid, Path)),
+    responses(
+        (status = 200, body = SessionInfo),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+        (status = 409, body = ErrorBody)
+    )
+)]
+async fn resume_session(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+) -> Result<Json<SessionInfo>, EngineError> {
+    let tenant_id = authenticate(&state.config, &headers, Scope::Submit)?;
+    let session = state.sessions.get(id, &tenant_id)?;
+    Ok(Json(state.sessions.resume(&session).await?))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/sessions/{id}",
+    params(("id" = Uuid, Path)),
+    responses(
+        (status = 204, description = "Session closed"),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody)
+    )
+)]
+async fn close_session(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, EngineError> {
+    let tenant_id = authenticate(&state.config, &headers, Scope::Submit)?;
+    let session = state.sessions.get(id, &tenant_id)?;
+    state.sessions.close(&session).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/templates",
+    responses(
+        (status = 200, body = Vec<TemplateInfo>),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody)
+    )
+)]
+async fn list_templates(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<TemplateInfo>>, EngineError> {
+    let tenant_id = authenticate_read(&state, &headers).await?;
+    Ok(Json(state.templates.list(&tenant_id)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/templates/{name}",
+    params(("name" = String, Path)),
+    responses(
+        (status = 200, body = TemplateInfo),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody)
+    )
+)]
+async fn get_template(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> Result<Json<TemplateInfo>, EngineError> {
+    let tenant_id = authenticate_read(&state, &headers).await?;
+    let info = state
+        .templates
+        .get(&tenant_id, &name)
+        .ok_or(EngineError::TemplateNotFound)?;
+    Ok(Json(info))
+}
+
+// Creates or replaces the template. It is checked like a submission, so a
+// template that could never run is rejected here rather than on use.
+#[utoipa::path(
+    put,
+    path = "/v1/templates/{name}",
+    params(("name" = String, Path)),
+    request_body = ExecutionTemplate,
+    responses(
+        (status = 200, body = TemplateInfo),
+        (status = 400, body = ErrorBody),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 409, description = "The tenant has TEMPLATE_MAX_PER_TENANT templates already", body = ErrorBody)
+    )
+)]
+async fn put_template(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+    Json(template): Json<ExecutionTemplate>,
+) -> Result<Json<TemplateInfo>, EngineError> {
+    let tenant_id = authenticate(&state.config, &headers, Scope::Submit)?;
+    validate_name("template", &name)?;
+    let sample = template.instantiate(TemplateExecutionRequest {
+        code: "template".to_string(),
+        stdin: String::new(),
+        args: None,
+        files: Vec::new(),
+        metadata: BTreeMap::new(),
+        priority: None,
+    });
+    validate_request(&sample)?;
+    let backend = resolve_backend(&state.config, &tenant_id, sample.backend)?;
+    validate_version(&state.config, backend, &sample)?;
+    resolve_limits(
+        &state.config,
+        &tenant_id,
+        &sample.language,
+        sample.limits.as_ref(),
+        sample.profile.as_deref(),
+    )?;
+    if state.templates.get(&tenant_id, &name).is_none()
+        && state.templates.count(&tenant_id) >= state.config.template_max_per_tenant
+    {
+        return Err(EngineError::Conflict(format!(
+            "template limit of {} reached",
+            state.config.template_max_per_tenant
+        )));
+    }
+    let info = state
+        .templates
+        .put(&tenant_id, &name, template)
+        .await
+        .map_err(|err| EngineError::Internal(err.to_string()))?;
+    Ok(Json(info))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/templates/{name}",
+    params(("name" = String, Path)),
+    responses(
+        (status = 204, description = "Template deleted"),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody)
+    )
+)]
+async fn delete_template(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> Result<StatusCode, EngineError> {
+    let tenant_id = authenticate(&state.config, &headers, Scope::Submit)?;
+    let removed = state
+        .templates
+        .remove(&tenant_id, &name)
+        .await
+        .map_err(|err| EngineError::Internal(err.to_string()))?;
+    if !removed {
+        return Err(EngineError::TemplateNotFound);
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/secrets",
+    responses(
+        (status = 200, body = Vec<SecretInfo>),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody)
+    )
+)]
+async fn list_secrets(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<SecretInfo>>, EngineError> {
+    let tenant_id = authenticate_read(&state, &headers).await?;
+    Ok(Json(state.secrets.list(&tenant_id)))
+}
+
+// Creates or replaces the secret. Values are write-only: no endpoint returns
+// them, and runs that use them have them redacted from their output.
+#[utoipa::path(
+    put,
+    path = "/v1/secrets/{name}",
+    params(("name" = String, Path)),
+    request_body = PutSecretRequest,
+    responses(
+        (status = 200, body = SecretInfo),
+        (status = 400, description = "Invalid secret, or ENCRYPTION_KEYS is not set", body = ErrorBody),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 409, description = "The tenant has SECRET_MAX_PER_TENANT secrets already", body = ErrorBody)
+    )
+)]
+async fn put_secret(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+    Json(secret): Json<PutSecretRequest>,
+) -> Result<Json<SecretInfo>, EngineError> {
+    let tenant_id = authenticate(&state.config, &headers, Scope::Submit)?;
+    if !state.secrets.enabled() {
+        return Err(EngineError::InvalidRequest(
+            "secrets need ENCRYPTION_KEYS to be set".to_string(),
+        ));
+    }
+    validate_name("secret", &name)?;
+    if secret.value.is_empty() || secret.value.len() > 16 * 1024 || secret.value.contains('\0') {
+        return Err(EngineError::InvalidRequest(
+            "secret values are 1 byte to 16 KiB, without NUL".to_string(),
+        ));
+    }
+    if !state.secrets.contains(&tenant_id, &name)
+        && state.secrets.count(&tenant_id) >= state.config.secret_max_per_tenant
+    {
+        return Err(EngineError::Conflict(format!(
+            "secret limit of {} reached",
+            state.config.secret_max_per_tenant
+        )));
+    }
+    let info = state
+        .secrets
+        .put(&tenant_id, &name, &secret.value)
+        .await
+        .map_err(|err| EngineError::Internal(err.to_string()))?;
+    Ok(Json(info))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/secrets/{name}",
+    params(("name" = String, Path)),
+    responses(
+        (status = 204, description = "Secret deleted"),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody)
+    )
+)]
+async fn delete_secret(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> Result<StatusCode, EngineError> {
+    let tenant_id = authenticate(&state.config, &headers, Scope::Submit)?;
+    let removed = state
+        .secrets
+        .remove(&tenant_id, &name)
+        .await
+        .map_err(|err| EngineError::Internal(err.to_string()))?;
+    if !removed {
+        return Err(EngineError::SecretNotFound);
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Submits the template with this request's code and input; otherwise the
+// same as `POST /v1/executions`.
+#[utoipa::path(
+    post,
+    path = "/v1/templates/{name}/executions",
+    params(
+        ("name" = String, Path),
+        ("Idempotency-Key" = Option<String>, Header)
+    ),
+    request_body = TemplateExecutionRequest,
+    responses(
+        (status = 202, body = CreateExecutionResponse),
+        (status = 200, description = "Idempotent replay or result cache hit", body = CreateExecutionResponse),
+        (status = 400, body = ErrorBody),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+        (status = 409, body = ErrorBody),
+        (status = 429, body = ErrorBody),
+        (status = 503, description = "Queue is full; see Retry-After", body = ErrorBody)
+    )
+)]
+async fn submit_template_execution(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+    Json(submission): Json<TemplateExecutionRequest>,
+) -> Result<Created, EngineError> {
+    let tenant_id = authenticate(&state.config, &headers, Scope::Submit)?;
+    let info = state
+        .templates
+        .get(&tenant_id, &name)
+        .ok_or(EngineError::TemplateNotFound)?;
+    let request = info.template.instantiate(submission);
+    enqueue_execution(&state, &headers, request)
+        .await
+        .map(audited)
+}
+
+pub(crate) struct EventFollow {
+    store: Arc<ExecutionStore>,
+    id: Uuid,
+    backlog: VecDeque<ExecutionEvent>,
+    next_seq: usize,
+    updates: broadcast::Receiver<EventUpdate>,
+    done: bool,
+}
+
+impl EventFollow {
+    // `updates` must have been subscribed before `record` was loaded.
+    pub(crate) fn new(
+        store: Arc<ExecutionStore>,
+        record: ExecutionRecord,
+        updates: broadcast::Receiver<EventUpdate>,
+    ) -> Self {
+        Self {
+            store,
+            id: record.id,
+            backlog: record.events.into(),
+            next_seq: 0,
+            updates,
+            done: false,
+        }
+    }
+
+    pub(crate) async fn next(&mut self) -> Option<(usize, ExecutionEvent)> {
+        loop {
+            if let Some(event) = self.backlog.pop_front() {
+                let seq = self.next_seq;
+                self.next_seq += 1;
+                self.done = event.stage == "finished";
+                return Some((seq, event));
+            }
+            if self.done {
+                return None;
+            }
+            match self.updates.recv().await {
+                Ok(update) if update.id != self.id || update.seq < self.next_seq => {}
+                Ok(update) if update.seq == self.next_seq => self.backlog.push_back(update.event),
+                // Missed updates: catch up from the record itself.
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {
+                    let record = self.store.get(&self.id)?;
+                    self.backlog = record.events.into_iter().skip(self.next_seq).collect();
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+fn idempotency_key(headers: &HeaderMap) -> Result<Option<String>, EngineError> {
+    let Some(value) = headers.get("idempotency-key") else {
+        return Ok(None);
+    };
+    let key = value
+        .to_str()
+        .ok()
+        .filter(|key| !key.is_empty() && key.len() <= idempotency::MAX_KEY_LEN)
+        .ok_or_else(|| {
+            EngineError::InvalidRequest(format!(
+                "Idempotency-Key must be 1 to {} visible ASCII characters",
+                idempotency::MAX_KEY_LEN
+            ))
+        })?;
+    Ok(Some(key.to_string()))
+}
+
+fn priority_header(headers: &HeaderMap) -> Result<Option<u8>, EngineError> {
+    let Some(value) = headers.get("x-priority") else {
+        return Ok(None);
+    };
+    value
+        .to_str()
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .map(Some)
+        .ok_or_else(|| {
+            EngineError::InvalidRequest(format!("X-Priority must be 0 to {MAX_PRIORITY}"))
+        })
+}
+
+fn request_fingerprint(request: &ExecutionRequest) -> Result<u64, EngineError> {
+    let body = serde_json::to_vec(request).map_err(|err| EngineError::Internal(err.to_string()))?;
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    Ok(hasher.finish())
+}
+
+// The key's tenant; a known key without `scope` is forbidden (403).
+pub(crate) fn authenticate(
+    config: &EngineConfig,
+    headers: &HeaderMap,
+    scope: Scope,
+) -> Result<String, EngineError> {
     let key = headers
         .get("x-api-key")
         .and_then(|v| v.to_str().ok())
         .ok_or(EngineError::Unauthorized)?;
-    for (candidate_key, tenant_id) in &config.api_keys {
+    for (candidate_key, api_key) in &config.api_keys {
         if constant_time_eq(key.as_bytes(), candidate_key.as_bytes()) {
-            return Ok(tenant_id.clone());
+            if !api_key.scopes.contains(&scope) {
+                return Err(EngineError::Forbidden);
+            }
+            return Ok(api_key.tenant_id.clone());
         }
     }
     Err(EngineError::Unauthorized)
@@ -167,9 +1686,162 @@ async fn enforce_rate_limit(state: &AppState, tenant_id: &str) -> Result<(), Eng
     Ok(())
 }
 
+// `authenticate` for read endpoints, which have their own rate limit so
+// polling does not use up the submission budget.
+pub(crate) async fn authenticate_read(
+    state: &AppState,
+    headers: &HeaderMap,
+) -> Result<String, EngineError> {
+    let tenant_id = authenticate(&state.config, headers, Scope::Read)?;
+    if !state.read_rate_limiter.allow(&tenant_id).await {
+        return Err(EngineError::RateLimited);
+    }
+    Ok(tenant_id)
+}
+
+// The request's limits, else its profile's, else the language's defaults;
+// capped at the language's ceiling.
+fn resolve_limits(
+    config: &EngineConfig,
+    tenant_id: &str,
+    language: &Language,
+    limits: Option<&ExecutionLimits>,
+    profile: Option<&str>,
+) -> Result<ExecutionLimits, EngineError> {
+    let Some(profile) = profile else {
+        // Raw limits would get around a tenant's profile allowlist.
+        if limits.is_some() && config.tenant_profiles.contains_key(tenant_id) {
+            return Err(EngineError::Forbidden);
+        }
+        let limits = limits
+            .cloned()
+            .unwrap_or_else(|| config.default_limits_for(language).clone());
+        return Ok(config.cap_limits(language, limits));
+    };
+    if limits.is_some() {
+        return Err(EngineError::InvalidRequest(
+            "specify either limits or profile, not both".to_string(),
+        ));
+    }
+    let limits = config
+        .limit_profiles
+        .get(profile)
+        .ok_or_else(|| EngineError::InvalidRequest(format!("unknown limit profile: {profile}")))?;
+    if !config.profile_allowed(tenant_id, profile) {
+        return Err(EngineError::Forbidden);
+    }
+    Ok(config.cap_limits(language, limits.clone()))
+}
+
+// The requested backend, else the tenant's, else the default.
+fn resolve_backend(
+    config: &EngineConfig,
+    tenant_id: &str,
+    requested: Option<SandboxBackendKind>,
+) -> Result<SandboxBackendKind, EngineError> {
+    let backend = requested
+        .or_else(|| config.tenant_backends.get(tenant_id).copied())
+        .unwrap_or(config.sandbox_backend);
+    if !config.sandbox_backends.contains(&backend) {
+        return Err(EngineError::InvalidRequest(format!(
+            "sandbox backend {} is not enabled",
+            backend.as_str()
+        )));
+    }
+    Ok(backend)
+}
+
+// Every run may use the whole timeout, so the worst case is what counts
+// against BENCHMARK_MAX_TOTAL_MS.
+fn validate_benchmark(
+    config: &EngineConfig,
+    benchmark: &BenchmarkOptions,
+    limits: &ExecutionLimits,
+) -> Result<(), EngineError> {
+    if benchmark.runs == 0 || benchmark.runs > config.benchmark_max_runs {
+        return Err(EngineError::InvalidRequest(format!(
+            "benchmark runs must be between 1 and {}",
+            config.benchmark_max_runs
+        )));
+    }
+    if benchmark.warmup > benchmark.runs {
+        return Err(EngineError::InvalidRequest(
+            "benchmark warmup cannot exceed runs".to_string(),
+        ));
+    }
+    if benchmark.inputs.len() > 128 || benchmark.inputs.iter().any(|input| input.len() > 64_000) {
+        return Err(EngineError::InvalidRequest(
+            "benchmark inputs too large; max is 128 of 64000 bytes".to_string(),
+        ));
+    }
+    let worst_case = u64::from(benchmark.runs + benchmark.warmup) * limits.timeout_ms;
+    if worst_case > config.benchmark_max_total_ms {
+        return Err(EngineError::InvalidRequest(format!(
+            "benchmark could run for {worst_case} ms; max is {} ms (runs + warmup times timeout_ms)",
+            config.benchmark_max_total_ms
+        )));
+    }
+    Ok(())
+}
+
+// Metadata keys under this prefix are written by the engine only.
+pub(crate) const SYSTEM_METADATA_PREFIX: &str = "system.";
+
+// Metadata is for labels to search by, not a place to store payloads.
+fn validate_metadata(
+    config: &EngineConfig,
+    metadata: &BTreeMap<String, String>,
+) -> Result<(), EngineError> {
+    if metadata.len() > config.metadata_max_keys {
+        return Err(EngineError::InvalidRequest(format!(
+            "too many metadata keys; max is {}",
+            config.metadata_max_keys
+        )));
+    }
+    let mut total = 0;
+    for (key, value) in metadata {
+        if key.is_empty() || key.len() > config.metadata_max_key_bytes {
+            return Err(EngineError::InvalidRequest(format!(
+                "metadata keys must be 1 to {} bytes",
+                config.metadata_max_key_bytes
+            )));
+        }
+        if key.starts_with(SYSTEM_METADATA_PREFIX) {
+            return Err(EngineError::InvalidRequest(format!(
+                "metadata key {key} uses the reserved {SYSTEM_METADATA_PREFIX} prefix"
+            )));
+        }
+        if value.len() > config.metadata_max_value_bytes {
+            return Err(EngineError::InvalidRequest(format!(
+                "metadata value of {key} is too large; max is {} bytes",
+                config.metadata_max_value_bytes
+            )));
+        }
+        total += key.len() + value.len();
+    }
+    if total > config.metadata_max_bytes {
+        return Err(EngineError::InvalidRequest(format!(
+            "metadata too large; max is {} bytes",
+            config.metadata_max_bytes
+        )));
+    }
+    Ok(())
+}
+
 fn validate_request(request: &ExecutionRequest) -> Result<(), EngineError> {
-    if request.code.trim().is_empty() {
-        return Err(EngineError::InvalidRequest("code is empty".to_string()));
+    match (&request.git, request.code.trim().is_empty()) {
+        (None, true) => return Err(EngineError::InvalidRequest("code is empty".to_string())),
+        (Some(_), false) => {
+            return Err(EngineError::InvalidRequest(
+                "specify either code or git, not both".to_string(),
+            ));
+        }
+        _ => {}
+    }
+    if request.git.is_some() && request.archive.is_some() {
+        return Err(EngineError::InvalidRequest(
+            "specify either git or archive, not both".to_string(),
+        ));
     }
     if request.code.len() > 250_000 {
         return Err(EngineError::InvalidRequest("code too large".to_string()));
@@ -187,36 +1859,391 @@ fn validate_request(request: &ExecutionRequest) -> Result<(), EngineError> {
             "too many test cases; max is 128".to_string(),
         ));
     }
+    if matches!(
+        request.mode,
+        Some(
+            ExecutionMode::Check
+                | ExecutionMode::Lint
+                | ExecutionMode::Format
+                | ExecutionMode::Test
+                | ExecutionMode::Benchmark
+        )
+    ) && (request.interactive || !request.test_cases.is_empty())
+    {
+        return Err(EngineError::InvalidRequest(
+            "check, lint, format, test and benchmark executions cannot be interactive or have test cases"
+                .to_string(),
+        ));
+    }
+    if request.seed.is_some() && !request.deterministic {
+        return Err(EngineError::InvalidRequest(
+            "seed requires deterministic".to_string(),
+        ));
+    }
+    if request.benchmark.is_some() && request.mode != Some(ExecutionMode::Benchmark) {
+        return Err(EngineError::InvalidRequest(
+            "benchmark options require mode benchmark".to_string(),
+        ));
+    }
+    if request.interactive && !request.test_cases.is_empty() {
+        return Err(EngineError::InvalidRequest(
+            "interactive executions cannot have test cases".to_string(),
+        ));
+    }
     for case in &request.test_cases {
         if case.stdin.len() > 64_000 {
             return Err(EngineError::InvalidRequest(
                 "test case stdin too large".to_string(),
             ));
         }
+        grading::validate_case(case).map_err(EngineError::InvalidRequest)?;
     }
-    if let Some(limits) = &request.limits {
-        if limits.timeout_ms == 0 || limits.memory_mb == 0 || limits.max_output_bytes == 0 {
+    if let Some(manifest) = &request.dependencies {
+        if LanguageSpec::for_language(&request.language)
+            .dependencies
+            .is_none()
+        {
             return Err(EngineError::InvalidRequest(
-                "limits must be greater than zero".to_string(),
+                "dependency manifests are not supported for this language".to_string(),
             ));
         }
+        if manifest.len() > 64_000 {
+            return Err(EngineError::InvalidRequest(
+                "dependency manifest too large".to_string(),
+            ));
+        }
+    }
+    validate_env(&request.env)?;
+    validate_env(&request.secrets)?;
+    if let Some(name) = request
+        .env
+        .keys()
+        .find(|name| request.secrets.contains_key(*name))
+    {
+        return Err(EngineError::InvalidRequest(format!(
+            "environment variable {name} is set both in env and secrets"
+        )));
+    }
+    validate_files(request)?;
+    if let Some(limits) = &request.limits
+        && (limits.timeout_ms == 0 || limits.memory_mb == 0 || limits.max_output_bytes == 0)
+    {
+        return Err(EngineError::InvalidRequest(
+            "limits must be greater than zero".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn validate_version(
+    config: &EngineConfig,
+    backend: SandboxBackendKind,
+    request: &ExecutionRequest,
+) -> Result<(), EngineError> {
+    let Some(version) = &request.version else {
+        return Ok(());
+    };
+    if request.image.is_some() {
+        return Err(EngineError::InvalidRequest(
+            "specify either image or version, not both".to_string(),
+        ));
+    }
+    let versions = config.runtime_versions(backend, &request.language);
+    if versions.is_some_and(|versions| versions.contains_key(version)) {
+        return Ok(());
+    }
+    let available: Vec<&str> = versions
+        .into_iter()
+        .flat_map(|versions| versions.keys().map(String::as_str))
+        .collect();
+    Err(EngineError::InvalidRequest(format!(
+        "unknown {} version {version} on the {} backend; available: [{}]",
+        request.language.as_str(),
+        backend.as_str(),
+        available.join(", ")
+    )))
+}
+
+// Checks the archive and, with an entrypoint, takes the program source (and
+// the language's manifest, unless the request has dependencies) from it.
+fn unpack_archive(
+    config: &EngineConfig,
+    request: &mut ExecutionRequest,
+) -> Result<(), EngineError> {
+    let Some(project) = &request.archive else {
+        return Ok(());
+    };
+    if project.content.is_empty() {
+        return Err(EngineError::InvalidRequest("archive is empty".to_string()));
+    }
+    let files = archive::extract_base64(&project.content, ArchiveLimits::from_config(config))
+        .map_err(EngineError::InvalidRequest)?;
+    let lang = LanguageSpec::for_language(&request.language);
+    let entrypoint = project.entrypoint.clone();
+    if let Some(path) = lang
+        .reserved_names()
+        .into_iter()
+        .find(|name| archive::find(&files, name).is_some() && entrypoint.as_deref() != Some(*name))
+    {
+        return Err(EngineError::InvalidRequest(format!(
+            "archive file {path} would overwrite the program source"
+        )));
+    }
+    let Some(entrypoint) = entrypoint else {
+        return Ok(());
+    };
+    if !request.code.trim().is_empty() {
+        return Err(EngineError::InvalidRequest(
+            "specify either code or an archive entrypoint, not both".to_string(),
+        ));
+    }
+    let source = archive::find(&files, &entrypoint).ok_or_else(|| {
+        EngineError::InvalidRequest(format!("entrypoint {entrypoint} is not in the archive"))
+    })?;
+    request.code = String::from_utf8(source.to_vec()).map_err(|_| {
+        EngineError::InvalidRequest(format!("entrypoint {entrypoint} is not UTF-8"))
+    })?;
+    if request.dependencies.is_none()
+        && let Some(manifest) = lang.dependencies.as_ref().map(|deps| deps.manifest)
+        && let Some(bytes) = archive::find(&files, manifest)
+    {
+        request.dependencies = String::from_utf8(bytes.to_vec()).ok();
     }
     Ok(())
 }
 
-fn load_for_tenant(
+fn validate_git(config: &EngineConfig, request: &ExecutionRequest) -> Result<(), EngineError> {
+    let Some(git) = &request.git else {
+        return Ok(());
+    };
+    let host = git
+        .url
+        .strip_prefix("https://")
+        .and_then(|rest| rest.split(['/', '?', '#']).next())
+        .filter(|authority| !authority.contains('@'))
+        .map(|authority| authority.split(':').next().unwrap_or(authority))
+        .ok_or_else(|| {
+            EngineError::InvalidRequest("git url must be https, without credentials".to_string())
+        })?;
+    if config.git_allowed_hosts.is_empty() {
+        return Err(EngineError::InvalidRequest(
+            "git sources are not enabled on this engine".to_string(),
+        ));
+    }
+    if !config
+        .git_allowed_hosts
+        .contains(&host.to_ascii_lowercase())
+    {
+        return Err(EngineError::Forbidden);
+    }
+    let valid_ref = git.reference.as_deref().is_none_or(|reference| {
+        !reference.is_empty()
+            && !reference.starts_with('-')
+            && !reference.contains("..")
+            && reference
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '/'))
+    });
+    if !valid_ref {
+        return Err(EngineError::InvalidRequest("invalid git ref".to_string()));
+    }
+    if relative_path(&git.entrypoint).is_none()
+        || git
+            .subdir
+            .as_deref()
+            .is_some_and(|subdir| relative_path(subdir).is_none())
+    {
+        return Err(EngineError::InvalidRequest(
+            "git subdir and entrypoint must be relative paths inside the repository".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+// Template and secret names; `kind` names which in the error.
+fn validate_name(kind: &str, name: &str) -> Result<(), EngineError> {
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid {
+        return Err(EngineError::InvalidRequest(format!(
+            "{kind} names are 1 to 64 letters, digits, '-', '_' or '.'"
+        )));
+    }
+    Ok(())
+}
+
+// The values of the request's secrets by environment variable.
+fn resolve_secrets(
+    state: &AppState,
+    tenant_id: &str,
+    request: &ExecutionRequest,
+) -> Result<BTreeMap<String, String>, EngineError> {
+    let mut resolved = BTreeMap::new();
+    for (var, name) in &request.secrets {
+        let value = state
+            .secrets
+            .reveal(tenant_id, name)?
+            .ok_or_else(|| EngineError::InvalidRequest(format!("unknown secret: {name}")))?;
+        resolved.insert(var.clone(), value);
+    }
+    Ok(resolved)
+}
+
+fn validate_egress(
+    config: &EngineConfig,
+    tenant_id: &str,
+    request: &ExecutionRequest,
+) -> Result<(), EngineError> {
+    if request.egress.is_empty() {
+        return Ok(());
+    }
+    if request.allow_network {
+        return Err(EngineError::InvalidRequest(
+            "egress rules cannot be combined with allow_network".to_string(),
+        ));
+    }
+    if !config.egress_enabled() {
+        return Err(EngineError::InvalidRequest(
+            "egress policies are not enabled on this engine".to_string(),
+        ));
+    }
+    if request.egress.len() > 32 {
+        return Err(EngineError::InvalidRequest(
+            "too many egress rules; max is 32".to_string(),
+        ));
+    }
+    for raw in &request.egress {
+        let rule: EgressRule = raw.parse().map_err(EngineError::InvalidRequest)?;
+        if !config.egress_allowed(tenant_id, &rule) {
+            return Err(EngineError::Forbidden);
+        }
+    }
+    Ok(())
+}
+
+fn validate_env(env: &BTreeMap<String, String>) -> Result<(), EngineError> {
+    if env.len() > 64 {
+        return Err(EngineError::InvalidRequest(
+            "too many environment variables; max is 64".to_string(),
+        ));
+    }
+    let mut total = 0usize;
+    for (name, value) in env {
+        let valid_name = name
+            .chars()
+            .next()
+            .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid_name || value.contains('\0') {
+            return Err(EngineError::InvalidRequest(format!(
+                "invalid environment variable: {name}"
+            )));
+        }
+        if env_denied(name) {
+            return Err(EngineError::InvalidRequest(format!(
+                "environment variable {name} cannot be overridden"
+            )));
+        }
+        total += name.len() + value.len();
+    }
+    if total > 32 * 1024 {
+        return Err(EngineError::InvalidRequest(
+            "environment too large; max is 32 KiB".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+// Variables that change how a shell or runtime loads code, or that the
+// sandbox sets itself.
+fn env_denied(name: &str) -> bool {
+    const DENIED: &[&str] = &[
+        "PATH",
+        "HOME",
+        "BASH_ENV",
+        "ENV",
+        "PYTHONPATH",
+        "PYTHONHOME",
+        "PYTHONSTARTUP",
+        "NODE_PATH",
+        "NODE_OPTIONS",
+        "JAVA_TOOL_OPTIONS",
+        "_JAVA_OPTIONS",
+        "JDK_JAVA_OPTIONS",
+        "JVM_OPTS",
+        "RUBYOPT",
+        "RUBYLIB",
+        "PERL5OPT",
+        "PERL5LIB",
+        "PHPRC",
+        "PHP_INI_SCAN_DIR",
+        "GOFLAGS",
+        "DOTNET_STARTUP_HOOKS",
+        "WORKSPACE",
+        "DEPS",
+        "OUT",
+    ];
+    let upper = name.to_ascii_uppercase();
+    DENIED.contains(&upper.as_str()) || upper.starts_with("LD_") || upper.starts_with("DYLD_")
+}
+
+fn validate_files(request: &ExecutionRequest) -> Result<(), EngineError> {
+    if request.files.len() > 32 {
+        return Err(EngineError::InvalidRequest(
+            "too many input files; max is 32".to_string(),
+        ));
+    }
+    let reserved = LanguageSpec::for_language(&request.language).reserved_names();
+    let mut total = 0usize;
+    let mut seen = std::collections::HashSet::new();
+    for file in &request.files {
+        let Some(path) = file.relative_path() else {
+            return Err(EngineError::InvalidRequest(format!(
+                "invalid input file name: {}",
+                file.name
+            )));
+        };
+        if reserved
+            .iter()
+            .any(|name| path == std::path::Path::new(name))
+        {
+            return Err(EngineError::InvalidRequest(format!(
+                "input file {} would overwrite the program source",
+                file.name
+            )));
+        }
+        if !seen.insert(path) {
+            return Err(EngineError::InvalidRequest(format!(
+                "duplicate input file: {}",
+                file.name
+            )));
+        }
+        total += file.decode().map_err(EngineError::InvalidRequest)?.len();
+    }
+    if total > 8 * 1024 * 1024 {
+        return Err(EngineError::InvalidRequest(
+            "input files too large; max is 8 MiB in total".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+pub(crate) async fn load_for_tenant(
     state: &AppState,
     id: Uuid,
     tenant_id: &str,
 ) -> Result<ExecutionRecord, EngineError> {
-    let record = state.store.get(&id).ok_or(EngineError::NotFound)?;
+    let record = state.store.load(&id).await?.ok_or(EngineError::NotFound)?;
     if record.tenant_id != tenant_id {
         return Err(EngineError::Forbidden);
     }
     Ok(record)
 }
 
-fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
     if a.len() != b.len() {
         return false;
     }
@@ -229,7 +2256,12 @@ fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
 
 #[cfg(test)]
 mod tests {
-    use super::constant_time_eq;
+    use utoipa::OpenApi;
+
+    use std::collections::BTreeMap;
+
+    use super::{ApiDoc, constant_time_eq, env_denied, resolve_limits, validate_metadata};
+    use crate::engine::{config::EngineConfig, models::Language};
 
     #[test]
     fn compares_equal_and_non_equal_keys() {
@@ -237,4 +2269,98 @@ mod tests {
         assert!(!constant_time_eq(b"abc123", b"abc124"));
         assert!(!constant_time_eq(b"abc123", b"abc1234"));
     }
+
+    #[test]
+    fn caps_metadata_and_reserves_the_system_prefix() {
+        let mut config = EngineConfig::from_env();
+        config.metadata_max_keys = 2;
+        config.metadata_max_value_bytes = 8;
+        config.metadata_max_bytes = 20;
+        let metadata = |pairs: &[(&str, &str)]| -> BTreeMap<String, String> {
+            pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        };
+        assert!(validate_metadata(&config, &metadata(&[("assignment_id", "42")])).is_ok());
+        assert!(
+            validate_metadata(&config, &metadata(&[("a", "1"), ("b", "2"), ("c", "3")])).is_err()
+        );
+        assert!(validate_metadata(&config, &metadata(&[("a", "123456789")])).is_err());
+        assert!(
+            validate_metadata(
+                &config,
+                &metadata(&[("user_id", "12345678"), ("course", "1")])
+            )
+            .is_err()
+        );
+        assert!(validate_metadata(&config, &metadata(&[("", "1")])).is_err());
+        assert!(validate_metadata(&config, &metadata(&[("system.cached_from", "x")])).is_err());
+    }
+
+    #[test]
+    fn keeps_allowlisted_tenants_to_their_profiles() {
+        let mut config = EngineConfig::from_env();
+        config
+            .tenant_profiles
+            .insert("t".to_string(), ["small".to_string()].into());
+        let raw = config.default_limits.clone();
+        let resolve = |tenant, limits, profile| {
+            resolve_limits(&config, tenant, &Language::Python, limits, profile)
+        };
+        assert!(resolve("t", Some(&raw), None).is_err());
+        assert!(resolve("t", None, Some("small")).is_ok());
+        assert!(resolve("t", None, Some("large")).is_err());
+        assert!(resolve("t", None, None).is_ok());
+        assert!(resolve("other", Some(&raw), None).is_ok());
+    }
+
+    #[test]
+    fn denies_loader_and_sandbox_variables() {
+        assert!(env_denied("LD_PRELOAD"));
+        assert!(env_denied("node_options"));
+        assert!(env_denied("DEPS"));
+        for name in [
+            "BASH_ENV",
+            "env",
+            "RUBYOPT",
+            "PERL5OPT",
+            "GOFLAGS",
+            "_JAVA_OPTIONS",
+            "HOME",
+        ] {
+            assert!(env_denied(name), "{name}");
+        }
+        assert!(!env_denied("APP_MODE"));
+    }
+
+    #[test]
+    fn documents_every_execution_route() {
+        let doc = ApiDoc::openapi();
+        for path in [
+            "/v1/executions",
+            "/v1/executions/upload",
+            "/v1/executions/{id}",
+            "/v1/executions/{id}/result",
+            "/v1/executions/{id}/events",
+            "/v1/executions/{id}/stdin",
+            "/v1/executions/{id}/share",
+            "/v1/shared/executions/{id}/result",
+            "/v1/usage",
+            "/v1/sessions",
+            "/v1/sessions/{id}",
+            "/v1/sessions/{id}/eval",
+            "/v1/sessions/{id}/suspend",
+            "/v1/templates",
+            "/v1/templates/{name}",
+            "/v1/templates/{name}/executions",
+            "/v1/secrets",
+            "/v1/secrets/{name}",
+        ] {
+            assert!(doc.paths.paths.contains_key(path), "{path} missing");
+        }
+        let schemas = doc.components.unwrap().schemas;
+        assert!(schemas.contains_key("ExecutionRequest"));
+        assert!(schemas.contains_key("ExecutionRecord"));
+    }
 }
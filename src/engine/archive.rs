@@ -0,0 +1,193 @@
+use std::{
+    io::{Cursor, Read},
+    path::{Path, PathBuf},
+};
+
+use base64::{Engine, prelude::BASE64_STANDARD};
+
+use crate::engine::{config::EngineConfig, models::relative_path};
+
+// Extraction never goes past these, whatever ARCHIVE_MAX_BYTES and
+// ARCHIVE_MAX_FILES say; the sandboxes extract with them.
+pub const CEILING: ArchiveLimits = ArchiveLimits {
+    max_bytes: 256 * 1024 * 1024,
+    max_files: 10_000,
+};
+
+// Caps on the extracted tree, counted before anything is written.
+#[derive(Debug, Clone, Copy)]
+pub struct ArchiveLimits {
+    pub max_bytes: u64,
+    pub max_files: usize,
+}
+
+impl ArchiveLimits {
+    pub fn from_config(config: &EngineConfig) -> Self {
+        Self {
+            max_bytes: config.archive_max_bytes.min(CEILING.max_bytes),
+            max_files: config.archive_max_files.min(CEILING.max_files),
+        }
+    }
+}
+
+// The regular files of a zip, tar or gzipped tar archive (told apart by
+// their magic bytes), as relative paths. Entries that would land outside
+// the workspace are rejected; directories, links and devices are skipped.
+pub fn extract(data: &[u8], limits: ArchiveLimits) -> Result<Vec<(PathBuf, Vec<u8>)>, String> {
+    let mut tree = Tree {
+        files: Vec::new(),
+        total: 0,
+        limits,
+    };
+    if data.starts_with(b"PK\x03\x04") || data.starts_with(b"PK\x05\x06") {
+        read_zip(data, &mut tree)?;
+    } else if data.starts_with(&[0x1f, 0x8b]) {
+        read_tar(flate2::read::GzDecoder::new(data), &mut tree)?;
+    } else {
+        read_tar(data, &mut tree)?;
+    }
+    Ok(tree.files)
+}
+
+// `extract` for a base64-encoded archive, as carried in requests.
+pub fn extract_base64(
+    content: &str,
+    limits: ArchiveLimits,
+) -> Result<Vec<(PathBuf, Vec<u8>)>, String> {
+    let data = BASE64_STANDARD
+        .decode(content.trim())
+        .map_err(|err| format!("archive is not valid base64: {err}"))?;
+    extract(&data, limits)
+}
+
+struct Tree {
+    files: Vec<(PathBuf, Vec<u8>)>,
+    total: u64,
+    limits: ArchiveLimits,
+}
+
+impl Tree {
+    // Reads at most one byte past the remaining budget, so a decompression
+    // bomb is stopped as soon as it overshoots.
+    fn add(&mut self, name: &str, reader: impl Read) -> Result<(), String> {
+        let path = relative_path(name.trim_start_matches("./"))
+            .ok_or_else(|| format!("archive entry {name} is outside the workspace"))?
+            .to_path_buf();
+        if self.files.len() >= self.limits.max_files {
+            return Err(format!(
+                "archive has more than {} files",
+                self.limits.max_files
+            ));
+        }
+        let remaining = self.limits.max_bytes - self.total;
+        let mut bytes = Vec::new();
+        reader
+            .take(remaining + 1)
+            .read_to_end(&mut bytes)
+            .map_err(|err| format!("failed to read archive entry {name}: {err}"))?;
+        if bytes.len() as u64 > remaining {
+            return Err(format!(
+                "archive is larger than {} bytes extracted",
+                self.limits.max_bytes
+            ));
+        }
+        self.total += bytes.len() as u64;
+        self.files.push((path, bytes));
+        Ok(())
+    }
+}
+
+fn read_zip(data: &[u8], tree: &mut Tree) -> Result<(), String> {
+    let mut archive =
+        zip::ZipArchive::new(Cursor::new(data)).map_err(|err| format!("invalid zip: {err}"))?;
+    for index in 0..archive.len() {
+        let file = archive
+            .by_index(index)
+            .map_err(|err| format!("invalid zip: {err}"))?;
+        if file.is_dir() || file.is_symlink() {
+            continue;
+        }
+        let name = file.name().to_string();
+        tree.add(&name, file)?;
+    }
+    Ok(())
+}
+
+fn read_tar(reader: impl Read, tree: &mut Tree) -> Result<(), String> {
+    let mut archive = tar::Archive::new(reader);
+    let entries = archive
+        .entries()
+        .map_err(|err| format!("invalid tar: {err}"))?;
+    for entry in entries {
+        let entry = entry.map_err(|err| format!("invalid tar: {err}"))?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry
+            .path()
+            .map_err(|err| format!("invalid tar: {err}"))?
+            .to_string_lossy()
+            .to_string();
+        tree.add(&name, entry)?;
+    }
+    Ok(())
+}
+
+// The file at `path` in an extracted tree.
+pub fn find<'a>(files: &'a [(PathBuf, Vec<u8>)], path: &str) -> Option<&'a [u8]> {
+    files
+        .iter()
+        .find(|(name, _)| name == Path::new(path))
+        .map(|(_, bytes)| bytes.as_slice())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::{ArchiveLimits, extract};
+
+    const LIMITS: ArchiveLimits = ArchiveLimits {
+        max_bytes: 1024,
+        max_files: 4,
+    };
+
+    fn tar_gz(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, data) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            // Written raw so `../` names survive into the archive.
+            header.as_gnu_mut().unwrap().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_cksum();
+            builder.append(&header, *data).unwrap();
+        }
+        let tar = builder.into_inner().unwrap();
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        gz.write_all(&tar).unwrap();
+        gz.finish().unwrap()
+    }
+
+    #[test]
+    fn extracts_zip_and_tar_within_the_workspace() {
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        zip.add_directory("src/", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        zip.start_file("src/util.py", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(b"X = 1").unwrap();
+        let zip = zip.finish().unwrap().into_inner();
+        let files = extract(&zip, LIMITS).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].0, std::path::Path::new("src/util.py"));
+        assert_eq!(files[0].1, b"X = 1");
+
+        let files = extract(&tar_gz(&[("./app/main.py", b"print(1)")]), LIMITS).unwrap();
+        assert_eq!(files[0].0, std::path::Path::new("app/main.py"));
+
+        assert!(extract(&tar_gz(&[("../evil", b"x")]), LIMITS).is_err());
+        assert!(extract(&tar_gz(&[("/etc/evil", b"x")]), LIMITS).is_err());
+        assert!(extract(&tar_gz(&[("big", &[0u8; 2048])]), LIMITS).is_err());
+    }
+}
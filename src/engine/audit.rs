@@ -0,0 +1,280 @@
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use axum::{
+    extract::{ConnectInfo, MatchedPath, Request, State},
+    http::HeaderMap,
+    middleware::Next,
+    response::Response,
+};
+use serde::Serialize;
+use tokio::{io::AsyncWriteExt, sync::mpsc};
+use uuid::Uuid;
+
+use crate::engine::{api::AppState, config::EngineConfig, store::now_ms};
+
+const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+
+// One API call. `key_id` is a fingerprint of the presented API key, also for
+// keys that were rejected; `tenant_id` is set when the key is known.
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEntry {
+    pub ts_ms: u64,
+    pub protocol: &'static str,
+    pub method: String,
+    pub endpoint: String,
+    pub tenant_id: Option<String>,
+    pub key_id: Option<String>,
+    pub execution_id: Option<Uuid>,
+    // The HTTP status code, or the gRPC status code name.
+    pub status: String,
+    pub ok: bool,
+    pub ip: Option<String>,
+    pub forwarded_for: Option<String>,
+}
+
+// Set on a response to name the execution a call created.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct AuditedExecution(pub Uuid);
+
+// Append-only audit trail of API calls, kept apart from execution events: one
+// JSON line per call in a file per UTC day, written in the background. Files
+// older than the retention are deleted when a new day starts.
+#[derive(Debug, Clone, Default)]
+pub struct AuditLog {
+    sender: Option<mpsc::UnboundedSender<AuditEntry>>,
+}
+
+impl AuditLog {
+    // Records nothing.
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    pub fn spawn(dir: PathBuf, retention_days: u64) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(write_entries(dir, retention_days.max(1), receiver));
+        Ok(Self {
+            sender: Some(sender),
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.sender.is_some()
+    }
+
+    pub fn record(&self, entry: AuditEntry) {
+        if let Some(sender) = &self.sender {
+            // The writer only stops with the runtime.
+            let _ = sender.send(entry);
+        }
+    }
+
+    // The caller-identifying fields for a call made with `headers`.
+    pub fn entry(
+        config: &EngineConfig,
+        headers: &HeaderMap,
+        protocol: &'static str,
+        method: impl Into<String>,
+        endpoint: impl Into<String>,
+        ip: Option<SocketAddr>,
+    ) -> AuditEntry {
+        let key = headers
+            .get("x-api-key")
+            .and_then(|value| value.to_str().ok());
+        AuditEntry {
+            ts_ms: now_ms(),
+            protocol,
+            method: method.into(),
+            endpoint: endpoint.into(),
+            tenant_id: key
+                .and_then(|key| config.api_keys.get(key))
+                .map(|api_key| api_key.tenant_id.clone()),
+            key_id: key.map(key_id),
+            execution_id: None,
+            status: String::new(),
+            ok: false,
+            ip: ip.map(|addr| addr.ip().to_string()),
+            forwarded_for: headers
+                .get("x-forwarded-for")
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string),
+        }
+    }
+}
+
+// Route layer for the REST API; the execution id comes from the `{id}` of
+// execution routes or from the response of a submission.
+pub(crate) async fn audit_http(
+    State(state): State<AppState>,
+    matched: Option<MatchedPath>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !state.audit.enabled() {
+        return next.run(request).await;
+    }
+    let endpoint = matched.map_or_else(
+        || request.uri().path().to_string(),
+        |matched| matched.as_str().to_string(),
+    );
+    let path_execution = endpoint
+        .starts_with("/v1/executions/{id}")
+        .then(|| request.uri().path().split('/').nth(3))
+        .flatten()
+        .and_then(|id| Uuid::parse_str(id).ok());
+    let mut entry = AuditLog::entry(
+        &state.config,
+        request.headers(),
+        "http",
+        request.method().as_str(),
+        endpoint,
+        request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| *addr),
+    );
+    let response = next.run(request).await;
+    entry.execution_id = response
+        .extensions()
+        .get::<AuditedExecution>()
+        .map(|AuditedExecution(id)| *id)
+        .or(path_execution);
+    entry.status = response.status().as_u16().to_string();
+    entry.ok = response.status().is_success();
+    state.audit.record(entry);
+    response
+}
+
+async fn write_entries(
+    dir: PathBuf,
+    retention_days: u64,
+    mut receiver: mpsc::UnboundedReceiver<AuditEntry>,
+) {
+    let mut current: Option<(u64, tokio::fs::File)> = None;
+    while let Some(entry) = receiver.recv().await {
+        let day = entry.ts_ms / DAY_MS;
+        if current.as_ref().is_none_or(|(open, _)| *open != day) {
+            prune(&dir, day.saturating_sub(retention_days)).await;
+            let path = dir.join(file_name(day));
+            let mut options = tokio::fs::OpenOptions::new();
+            options.create(true).append(true);
+            match options.open(&path).await {
+                Ok(file) => current = Some((day, file)),
+                Err(err) => {
+                    tracing::error!(path = %path.display(), error = %err, "failed to open audit log");
+                    current = None;
+                    continue;
+                }
+            }
+        }
+        let Some((_, file)) = &mut current else {
+            continue;
+        };
+        let Ok(mut line) = serde_json::to_vec(&entry) else {
+            continue;
+        };
+        line.push(b'\n');
+        if let Err(err) = file.write_all(&line).await {
+            tracing::error!(error = %err, "failed to write audit log");
+        }
+    }
+}
+
+// Deletes the day files before `oldest_day`. ISO dates sort like the days.
+async fn prune(dir: &Path, oldest_day: u64) {
+    let oldest = file_name(oldest_day);
+    let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+        return;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with("audit-") && name.ends_with(".jsonl") && name < oldest {
+            let _ = tokio::fs::remove_file(entry.path()).await;
+        }
+    }
+}
+
+fn file_name(day: u64) -> String {
+    let (year, month, day) = civil_date(day as i64);
+    format!("audit-{year:04}-{month:02}-{day:02}.jsonl")
+}
+
+// Days since the Unix epoch to a proleptic Gregorian date.
+pub(crate) fn civil_date(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+// 64-bit FNV-1a: stable across builds, and short enough to name a key in an
+// investigation without storing it.
+fn key_id(key: &str) -> String {
+    let hash = key.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+    format!("{hash:016x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::http::HeaderMap;
+
+    use super::{AuditLog, DAY_MS, file_name};
+    use crate::engine::config::{ApiKey, EngineConfig, Scope};
+
+    #[tokio::test]
+    async fn appends_per_day_and_prunes_expired_files() {
+        let dir = std::env::temp_dir().join(format!("audit-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let expired = dir.join(file_name(0));
+        std::fs::write(&expired, "{}\n").unwrap();
+        assert_eq!(file_name(0), "audit-1970-01-01.jsonl");
+
+        let mut config = EngineConfig::from_env();
+        config.api_keys.insert(
+            "secret".to_string(),
+            ApiKey {
+                tenant_id: "acme".to_string(),
+                scopes: [Scope::Read].into(),
+            },
+        );
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", "secret".parse().unwrap());
+        let log = AuditLog::spawn(dir.clone(), 30).unwrap();
+        for status in ["202", "404"] {
+            let mut entry = AuditLog::entry(&config, &headers, "http", "GET", "/v1/usage", None);
+            entry.status = status.to_string();
+            log.record(entry);
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        assert!(!expired.exists());
+        let today = dir.join(file_name(super::now_ms() / DAY_MS));
+        let lines: Vec<serde_json::Value> = std::fs::read_to_string(today)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["tenant_id"], "acme");
+        assert_eq!(lines[1]["status"], "404");
+        assert_eq!(lines[0]["key_id"].as_str().unwrap().len(), 16);
+        assert_ne!(lines[0]["key_id"], "secret");
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,160 @@
+use std::sync::Arc;
+
+use tokio::sync::Semaphore;
+use tracing::Instrument;
+
+use crate::engine::{
+    models::{BenchmarkOptions, BenchmarkReport, BenchmarkSample, Distribution},
+    sandbox::{EventSink, RunSpec, SandboxBackend, SandboxResult},
+};
+
+// Runs the program `warmup + runs` times, one after another so the runs
+// never compete with each other, each holding a run slot. Run `i` reads
+// `inputs[i % inputs.len()]`, or the request's stdin. The first run that
+// does not exit 0 ends the benchmark and is returned as the result;
+// otherwise the last run is.
+pub async fn execute(
+    base: RunSpec,
+    sandbox: Arc<dyn SandboxBackend>,
+    run_slots: Arc<Semaphore>,
+) -> anyhow::Result<(SandboxResult, BenchmarkReport)> {
+    let options = base.request.benchmark.clone().unwrap_or_default();
+    let total = options.warmup + options.runs;
+    let run_events = {
+        let events = base.events.clone();
+        EventSink::new(move |stage, message| {
+            if stage != "running" {
+                events.emit(stage, message);
+            }
+        })
+    };
+    let mut samples = Vec::with_capacity(total as usize);
+    let mut last = None;
+    for index in 0..total {
+        let mut spec = base.clone();
+        if !options.inputs.is_empty() {
+            spec.request.stdin = options.inputs[index as usize % options.inputs.len()].clone();
+        }
+        spec.events = run_events.clone();
+        let out = {
+            let _slot = run_slots.acquire().await?;
+            sandbox
+                .execute(spec)
+                .instrument(tracing::info_span!("execution.benchmark_run", index))
+                .await?
+        };
+        let warmup = index < options.warmup;
+        base.events.emit(
+            "benchmark_run",
+            format!(
+                "{} {} finished in {} ms",
+                if warmup { "warmup" } else { "run" },
+                if warmup {
+                    index + 1
+                } else {
+                    index - options.warmup + 1
+                },
+                out.duration_ms
+            ),
+        );
+        samples.push(BenchmarkSample {
+            warmup,
+            duration_ms: out.duration_ms,
+            usage: out.usage.clone(),
+            exit_code: out.exit_code,
+        });
+        let failed = out.exit_code != 0 || out.timed_out || out.oom_killed;
+        last = Some(out);
+        if failed {
+            break;
+        }
+    }
+    let result = last.ok_or_else(|| anyhow::anyhow!("benchmark made no runs"))?;
+    Ok((result, summarize(&options, samples)))
+}
+
+pub fn summarize(options: &BenchmarkOptions, samples: Vec<BenchmarkSample>) -> BenchmarkReport {
+    let measured: Vec<&BenchmarkSample> = samples.iter().filter(|sample| !sample.warmup).collect();
+    let durations: Vec<u64> = measured
+        .iter()
+        .map(|sample| sample.duration_ms as u64)
+        .collect();
+    let memory: Vec<u64> = measured
+        .iter()
+        .filter_map(|sample| sample.usage.peak_memory_bytes)
+        .collect();
+    BenchmarkReport {
+        runs: measured.len(),
+        completed: measured.len() == options.runs as usize
+            && measured.iter().all(|sample| sample.exit_code == 0),
+        duration_ms: distribution(durations),
+        peak_memory_bytes: distribution(memory),
+        samples,
+    }
+}
+
+// Nearest-rank percentiles.
+fn distribution(mut values: Vec<u64>) -> Option<Distribution> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_unstable();
+    let rank = |percentile: usize| {
+        let index = (percentile * values.len()).div_ceil(100).max(1) - 1;
+        values[index]
+    };
+    Some(Distribution {
+        min: values[0],
+        median: rank(50),
+        p95: rank(95),
+        max: values[values.len() - 1],
+        mean: values.iter().sum::<u64>() as f64 / values.len() as f64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::summarize;
+    use crate::engine::models::{BenchmarkOptions, BenchmarkSample, ResourceUsage};
+
+    fn sample(warmup: bool, duration_ms: u128, memory: u64) -> BenchmarkSample {
+        BenchmarkSample {
+            warmup,
+            duration_ms,
+            usage: ResourceUsage {
+                peak_memory_bytes: Some(memory),
+                ..ResourceUsage::default()
+            },
+            exit_code: 0,
+        }
+    }
+
+    #[test]
+    fn summarizes_measured_runs_only() {
+        let options = BenchmarkOptions {
+            runs: 20,
+            warmup: 1,
+            inputs: Vec::new(),
+        };
+        let mut samples = vec![sample(true, 5000, 1)];
+        samples.extend((1..=20).map(|ms| sample(false, ms, ms as u64 * 10)));
+        let report = summarize(&options, samples);
+        assert_eq!(report.runs, 20);
+        assert!(report.completed);
+        let durations = report.duration_ms.unwrap();
+        assert_eq!(
+            (
+                durations.min,
+                durations.median,
+                durations.p95,
+                durations.max
+            ),
+            (1, 10, 19, 20)
+        );
+        assert_eq!(durations.mean, 10.5);
+        assert_eq!(report.peak_memory_bytes.unwrap().max, 200);
+
+        let short = summarize(&options, vec![sample(false, 3, 1)]);
+        assert!(!short.completed);
+    }
+}
@@ -0,0 +1,250 @@
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::engine::{
+    config::EngineConfig,
+    models::{ExecutionLimits, ExecutionMode, ExecutionRequest},
+};
+
+// The sandbox CPU and memory one job holds while it runs: its limits times
+// the runs it has going at once (test cases run side by side).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Demand {
+    pub cpu_cores: f64,
+    pub memory_mb: u64,
+}
+
+impl Demand {
+    pub fn of(
+        request: &ExecutionRequest,
+        limits: &ExecutionLimits,
+        test_case_parallelism: usize,
+    ) -> Self {
+        let concurrent = if request.mode == Some(ExecutionMode::Benchmark) {
+            1
+        } else {
+            request
+                .test_cases
+                .len()
+                .clamp(1, test_case_parallelism.max(1))
+        };
+        Self {
+            cpu_cores: f64::from(limits.cpu_cores) * concurrent as f64,
+            memory_mb: limits.memory_mb * concurrent as u64,
+        }
+    }
+
+    fn cpu_millis(&self) -> u32 {
+        (self.cpu_cores * 1000.0).ceil() as u32
+    }
+}
+
+// What a worker node hands out to the jobs it runs at once
+// (WORKER_NODE_CPU_CORES / WORKER_NODE_MEMORY_MB). A job waits in the queue
+// until its demand is free; jobs that need more than the whole node are
+// turned away on submit.
+#[derive(Clone)]
+pub struct Capacity {
+    total: Demand,
+    cpu_millis: Arc<Semaphore>,
+    memory_mb: Arc<Semaphore>,
+}
+
+// Held while the job runs.
+pub struct Reservation {
+    _cpu: Option<OwnedSemaphorePermit>,
+    _memory: Option<OwnedSemaphorePermit>,
+}
+
+impl Capacity {
+    // None unless either limit is set; an unset one is unlimited.
+    pub fn from_config(config: &EngineConfig) -> Option<Self> {
+        let total = node_capacity(config)?;
+        Some(Self {
+            total,
+            cpu_millis: Arc::new(Semaphore::new(total.cpu_millis() as usize)),
+            memory_mb: Arc::new(Semaphore::new(total.memory_mb as usize)),
+        })
+    }
+
+    // A demand beyond the node is cut down to all of it, so a job accepted
+    // under other settings still runs, alone.
+    pub fn try_reserve(&self, demand: Demand) -> Option<Reservation> {
+        let (cpu, memory) = self.permits(demand);
+        Some(Reservation {
+            _cpu: try_acquire(&self.cpu_millis, cpu)?,
+            _memory: try_acquire(&self.memory_mb, memory)?,
+        })
+    }
+
+    // Waits in line: a large job is not overtaken by smaller ones that
+    // arrive after it.
+    pub async fn reserve(&self, demand: Demand) -> Reservation {
+        let (cpu, memory) = self.permits(demand);
+        Reservation {
+            _cpu: acquire(&self.cpu_millis, cpu).await,
+            _memory: acquire(&self.memory_mb, memory).await,
+        }
+    }
+
+    fn permits(&self, demand: Demand) -> (u32, u32) {
+        (
+            demand.cpu_millis().min(self.total.cpu_millis()),
+            demand.memory_mb.min(self.total.memory_mb) as u32,
+        )
+    }
+}
+
+// Unlimited resources come out as zero permits and take none.
+fn try_acquire(semaphore: &Arc<Semaphore>, permits: u32) -> Option<Option<OwnedSemaphorePermit>> {
+    if permits == 0 {
+        return Some(None);
+    }
+    semaphore
+        .clone()
+        .try_acquire_many_owned(permits)
+        .ok()
+        .map(Some)
+}
+
+async fn acquire(semaphore: &Arc<Semaphore>, permits: u32) -> Option<OwnedSemaphorePermit> {
+    if permits == 0 {
+        return None;
+    }
+    semaphore.clone().acquire_many_owned(permits).await.ok()
+}
+
+// Test-case runs one tenant may have going at once across all of its
+// executions (TENANT_TEST_CASE_CONCURRENCY), shared by every pool on the
+// node. A limit of zero is unlimited.
+#[derive(Clone)]
+pub struct TenantSlots {
+    limit: usize,
+    tenants: Arc<DashMap<String, Arc<Semaphore>>>,
+}
+
+impl TenantSlots {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            tenants: Arc::new(DashMap::new()),
+        }
+    }
+
+    // Held for one test-case run; None when unlimited.
+    pub async fn acquire(&self, tenant_id: &str) -> Option<OwnedSemaphorePermit> {
+        if self.limit == 0 {
+            return None;
+        }
+        let slots = self
+            .tenants
+            .entry(tenant_id.to_string())
+            .or_insert_with(|| Arc::new(Semaphore::new(self.limit)))
+            .clone();
+        slots.acquire_owned().await.ok()
+    }
+}
+
+// A limit of zero is unlimited.
+fn node_capacity(config: &EngineConfig) -> Option<Demand> {
+    let total = Demand {
+        cpu_cores: config.worker_node_cpu_cores,
+        memory_mb: config.worker_node_memory_mb,
+    };
+    (total.cpu_cores > 0.0 || total.memory_mb > 0).then_some(total)
+}
+
+// Rejects a submission no worker node could ever run.
+pub fn check(
+    config: &EngineConfig,
+    request: &ExecutionRequest,
+    limits: &ExecutionLimits,
+) -> Result<(), String> {
+    let Some(total) = node_capacity(config) else {
+        return Ok(());
+    };
+    let demand = Demand::of(request, limits, config.test_case_parallelism);
+    if total.cpu_cores > 0.0 && demand.cpu_cores > total.cpu_cores {
+        return Err(format!(
+            "needs {} cpu cores at once (cpu_cores times concurrent test cases); worker nodes have {}",
+            demand.cpu_cores, total.cpu_cores
+        ));
+    }
+    if total.memory_mb > 0 && demand.memory_mb > total.memory_mb {
+        return Err(format!(
+            "needs {} MB of memory at once (memory_mb times concurrent test cases); worker nodes have {}",
+            demand.memory_mb, total.memory_mb
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Capacity, Demand, TenantSlots, check};
+    use crate::engine::{
+        config::EngineConfig,
+        models::{ExecutionLimits, ExecutionRequest},
+    };
+
+    #[tokio::test]
+    async fn rejects_what_no_node_fits_and_queues_what_is_busy() {
+        let mut config = EngineConfig::from_env();
+        config.worker_node_cpu_cores = 4.0;
+        config.worker_node_memory_mb = 0;
+        config.test_case_parallelism = 4;
+        let mut request: ExecutionRequest = serde_json::from_value(serde_json::json!({
+            "language": "python",
+            "code": "print(input())",
+            "test_cases": [{ "stdin": "1" }, { "stdin": "2" }, { "stdin": "3" }],
+        }))
+        .unwrap();
+        let mut limits = ExecutionLimits {
+            cpu_cores: 1.0,
+            ..ExecutionLimits::MAX
+        };
+        assert!(check(&config, &request, &limits).is_ok());
+        limits.cpu_cores = 2.0;
+        assert!(check(&config, &request, &limits).is_err());
+        request.test_cases.truncate(1);
+        assert!(check(&config, &request, &limits).is_ok());
+
+        let capacity = Capacity::from_config(&config).unwrap();
+        let half = Demand {
+            cpu_cores: 2.0,
+            memory_mb: 512,
+        };
+        let first = capacity.try_reserve(half).unwrap();
+        let _second = capacity.reserve(half).await;
+        assert!(capacity.try_reserve(half).is_none());
+        drop(first);
+        assert!(capacity.try_reserve(half).is_some());
+        // More than the node waits for all of it rather than forever.
+        assert!(
+            capacity
+                .try_reserve(Demand {
+                    cpu_cores: 16.0,
+                    memory_mb: 0
+                })
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn caps_test_case_runs_per_tenant() {
+        let slots = TenantSlots::new(2);
+        let first = slots.acquire("acme").await;
+        let _second = slots.acquire("acme").await;
+        assert!(first.is_some());
+        let third = slots.acquire("acme");
+        tokio::pin!(third);
+        assert!(futures_util::poll!(third.as_mut()).is_pending());
+        // Other tenants have slots of their own.
+        assert!(slots.acquire("beta").await.is_some());
+        drop(first);
+        assert!(third.await.is_some());
+        assert!(TenantSlots::new(0).acquire("acme").await.is_none());
+    }
+}
@@ -1,58 +1,705 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     env,
     net::SocketAddr,
     path::PathBuf,
     str::FromStr,
 };
 
-use crate::engine::models::ExecutionLimits;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::engine::{
+    metrics::DEFAULT_TENANT_LABELS,
+    models::{ExecutionLimits, ExecutionMode, ExecutionOutput, Language},
+    sandbox::{EgressRule, LanguageOverrides},
+};
 
 #[derive(Debug, Clone)]
 pub struct EngineConfig {
+    pub role: EngineRole,
     pub bind_addr: SocketAddr,
-    pub worker_count: usize,
+    pub grpc_bind_addr: Option<SocketAddr>,
+    pub swagger_ui: bool,
+    pub worker_min: usize,
+    pub worker_max: usize,
+    pub worker_target_wait_ms: u64,
+    pub worker_scale_interval_ms: u64,
+    pub worker_heartbeat_interval_ms: u64,
+    pub watchdog_interval_ms: u64,
+    // 0 disables the watchdog.
+    pub stuck_execution_grace_ms: u64,
     pub queue_capacity: usize,
+    pub queue_max_depth: usize,
+    pub queue_max_wait_ms: u64,
+    pub queue_backend: QueueBackendKind,
+    pub redis_url: String,
+    pub redis_queue_prefix: String,
+    pub queue_lease_ms: u64,
+    pub max_concurrent_runs: usize,
+    pub test_case_parallelism: usize,
+    // Test-case runs one tenant may have in flight across its executions.
+    pub tenant_test_case_concurrency: usize,
+    // What one worker node's sandboxes may use at once; 0 is unlimited.
+    pub worker_node_cpu_cores: f64,
+    pub worker_node_memory_mb: u64,
+    pub infra_retry_limit: u32,
+    pub language_pools: Vec<LanguagePool>,
+    pub gpu_workers: usize,
+    pub gpu_max_concurrent_runs: usize,
+    pub gpu_devices: String,
+    pub gpu_tenants: HashSet<String>,
     pub sandbox_backend: SandboxBackendKind,
+    pub sandbox_backends: Vec<SandboxBackendKind>,
+    pub tenant_backends: HashMap<String, SandboxBackendKind>,
+    // Highest queue priority a tenant may ask for.
+    pub default_max_priority: u8,
+    pub tenant_max_priority: HashMap<String, u8>,
+    // Backends in the order a job falls back through when one fails.
+    pub sandbox_failover: Vec<SandboxBackendKind>,
+    // How often /readyz's view of the sandbox backends is refreshed.
+    pub health_check_interval_ms: u64,
+    pub container_runtime: String,
+    pub container_rootless: bool,
+    pub container_host: Option<String>,
+    pub seccomp_profile: Option<String>,
+    pub apparmor_profile: Option<String>,
+    // libfaketime, preloaded into deterministic runs to fake their clocks.
+    pub faketime_library: Option<String>,
+    // The CPU deterministic runs are pinned to.
+    pub deterministic_cpu: usize,
+    // A delegated cgroup v2 directory the process backend limits runs in.
+    pub process_cgroup_root: Option<PathBuf>,
+    // The user (name or uid:gid) process-backend programs run as, the root
+    // they are chrooted into and their PATH; see sandbox::isolation.
+    pub process_run_user: Option<String>,
+    pub process_chroot: Option<PathBuf>,
+    pub process_path: String,
+    pub selinux_labels: Vec<String>,
+    pub language_images: HashMap<Language, String>,
+    // Runtime versions requests may pin: images for the docker backend,
+    // interpreter or compiler commands for the process backend.
+    pub language_versions: HashMap<Language, BTreeMap<String, String>>,
+    pub language_version_commands: HashMap<Language, BTreeMap<String, String>>,
+    pub language_overrides: HashMap<Language, LanguageOverrides>,
+    pub tools: ToolCommands,
+    pub registry_auth: HashMap<String, RegistryAuth>,
+    pub prepull_images: bool,
+    pub allowed_images: Vec<String>,
+    // Hosts requests may fetch code from; empty disables `git` sources.
+    pub git_allowed_hosts: HashSet<String>,
+    pub git_fetch_timeout_ms: u64,
+    pub git_max_bytes: u64,
+    pub git_max_files: usize,
+    pub archive_max_bytes: u64,
+    // Where `stdin` parts of multipart submissions are spooled; must be
+    // shared with the workers when they run on other hosts.
+    pub stdin_upload_dir: PathBuf,
+    pub stdin_upload_max_bytes: u64,
+    pub archive_max_files: usize,
+    pub benchmark_max_runs: u32,
+    // Cap on (runs + warmup) * timeout_ms for one benchmark execution.
+    pub benchmark_max_total_ms: u64,
+    // Caps on a request's free-form metadata.
+    pub metadata_max_keys: usize,
+    pub metadata_max_key_bytes: usize,
+    pub metadata_max_value_bytes: usize,
+    pub metadata_max_bytes: usize,
+    pub dependency_cache_dir: PathBuf,
+    pub compile_cache_dir: PathBuf,
+    // Bounds on the process backend's compiled programs, evicted LRU.
+    pub process_compile_cache_max_entries: usize,
+    pub process_compile_cache_max_bytes: u64,
     pub default_limits: ExecutionLimits,
-    pub api_keys: HashMap<String, String>,
+    pub limit_profiles: HashMap<String, ExecutionLimits>,
+    // Used in place of `default_limits` for requests in the language.
+    pub language_limits: HashMap<Language, ExecutionLimits>,
+    // The most a request in the language gets, whatever it asks for.
+    pub language_limit_ceilings: HashMap<Language, ExecutionLimits>,
+    pub tenant_profiles: HashMap<String, HashSet<String>>,
+    pub api_keys: HashMap<String, ApiKey>,
+    pub admin_api_keys: HashSet<String>,
     pub rate_limit_per_minute: u32,
     pub rate_limit_burst: u32,
+    pub tenant_rate_limits: HashMap<String, RateLimit>,
+    // Executions per UTC day and month; 0 is unlimited.
+    pub execution_quota: Quota,
+    pub tenant_execution_quotas: HashMap<String, Quota>,
+    // For read endpoints (polling, listing, event streams), counted apart
+    // from submissions.
+    pub read_rate_limit_per_minute: u32,
+    pub read_rate_limit_burst: u32,
+    pub tenant_read_rate_limits: HashMap<String, RateLimit>,
+    pub idempotency_ttl_secs: u64,
+    pub result_cache_ttl_secs: u64,
+    // REPL sessions: 0 for `session_max` disables them.
+    pub session_max: usize,
+    pub session_max_per_tenant: usize,
+    pub session_idle_timeout_ms: u64,
+    // Idle docker sessions are checkpointed (CRIU) and stopped instead of
+    // closed, then closed once suspended for `session_suspended_ttl_ms`.
+    pub session_checkpoint: bool,
+    pub session_suspended_ttl_ms: u64,
+    pub cost_weights: CostWeights,
+    pub templates_path: Option<PathBuf>,
+    pub template_max_per_tenant: usize,
+    // Tenant secrets are sealed with the record cipher, so they need
+    // `encryption_keys`.
+    pub secrets_path: Option<PathBuf>,
+    pub secret_max_per_tenant: usize,
     pub network_allowed_tenants: HashSet<String>,
+    pub tenant_egress: HashMap<String, Vec<EgressRule>>,
+    pub egress_network: Option<String>,
+    pub egress_proxy_addr: SocketAddr,
+    pub egress_proxy_url: Option<String>,
+    // `allow_network` docker runs go out through the egress proxy too,
+    // limited to the tenant's allowlist (any destination without one).
+    pub network_proxy: bool,
+    pub tenant_network_allowlist: HashMap<String, Vec<EgressRule>>,
+    // Bytes per second through the egress proxy per run; zero is unlimited.
+    pub default_network_bandwidth: u64,
+    pub tenant_network_bandwidth: HashMap<String, u64>,
     pub persistence_path: Option<PathBuf>,
+    pub persist_compact_interval_secs: u64,
+    pub persist_keep_segments: usize,
+    // Finished records older than this are dropped from memory and the
+    // persistence file; zero keeps them forever.
+    pub record_retention_secs: u64,
+    // S3-compatible bucket for large output and archived records; unset
+    // keeps everything in memory and the persistence file.
+    pub object_store_bucket: Option<String>,
+    pub object_store_endpoint: Option<String>,
+    pub object_store_region: String,
+    pub object_store_prefix: String,
+    pub object_store_access_key_id: Option<String>,
+    pub object_store_secret_access_key: Option<String>,
+    pub object_store_offload_bytes: usize,
+    pub object_store_timeout_ms: u64,
+    // Keys sealing persisted and queued executions; empty leaves them in
+    // plaintext.
+    pub encryption_keys: String,
+    pub encryption_keys_file: Option<PathBuf>,
+    pub audit_log_dir: Option<PathBuf>,
+    pub audit_retention_days: u64,
+    // Signs shared result links; sharing is off without it.
+    pub share_link_key: Option<String>,
+    pub share_link_max_ttl_secs: u64,
+    // Prefix of shared links, e.g. `https://engine.example.com`; unset gives
+    // a path.
+    pub share_link_base_url: Option<String>,
     pub log_level: String,
+    pub metrics_tenant_labels: usize,
+    // Push export of /metrics; each target is off unless set.
+    pub metrics_push_interval_ms: u64,
+    pub statsd_addr: Option<String>,
+    pub statsd_prefix: String,
+    pub metrics_remote_write_url: Option<String>,
+    pub metrics_remote_write_token: Option<String>,
+    pub metrics_instance: String,
+    pub otlp_endpoint: Option<String>,
+    pub otel_service_name: String,
 }
 
 impl EngineConfig {
     pub fn from_env() -> Self {
+        let worker_count = env_parse("WORKER_COUNT", 4usize);
+        let worker_min = env_parse("WORKER_MIN", worker_count).max(1);
+        let worker_max = env_parse("WORKER_MAX", worker_count).max(worker_min);
+        let queue_capacity = env_parse("QUEUE_CAPACITY", 1024usize);
+        let gpu_workers = env_parse("GPU_WORKERS", 0usize);
+        let rate_limit_burst = env_parse("RATE_LIMIT_BURST", 20u32);
+        let read_rate_limit_burst = env_parse("READ_RATE_LIMIT_BURST", 100u32);
+        let sandbox_backend = env_parse("SANDBOX_BACKEND", SandboxBackendKind::Docker);
+        let default_limits = ExecutionLimits {
+            cpu_cores: env_parse("DEFAULT_CPU_CORES", 0.5),
+            memory_mb: env_parse("DEFAULT_MEMORY_MB", 256),
+            timeout_ms: env_parse("DEFAULT_TIMEOUT_MS", 3000),
+            max_processes: env_parse("DEFAULT_MAX_PROCESSES", 32),
+            max_file_size_bytes: env_parse("DEFAULT_MAX_FILE_SIZE_BYTES", 1024 * 1024),
+            max_output_bytes: env_parse("DEFAULT_MAX_OUTPUT_BYTES", 64 * 1024),
+        };
         Self {
-            bind_addr: env_parse("BIND_ADDR", "0.0.0.0:8080"),
-            worker_count: env_parse("WORKER_COUNT", 4usize),
-            queue_capacity: env_parse("QUEUE_CAPACITY", 1024usize),
-            sandbox_backend: env_parse("SANDBOX_BACKEND", SandboxBackendKind::Docker),
-            default_limits: ExecutionLimits {
-                cpu_cores: env_parse("DEFAULT_CPU_CORES", 0.5),
-                memory_mb: env_parse("DEFAULT_MEMORY_MB", 256),
-                timeout_ms: env_parse("DEFAULT_TIMEOUT_MS", 3000),
-                max_processes: env_parse("DEFAULT_MAX_PROCESSES", 32),
-                max_file_size_bytes: env_parse("DEFAULT_MAX_FILE_SIZE_BYTES", 1024 * 1024),
-                max_output_bytes: env_parse("DEFAULT_MAX_OUTPUT_BYTES", 64 * 1024),
+            role: env_parse("ENGINE_ROLE", EngineRole::All),
+            bind_addr: env_parse("BIND_ADDR", SocketAddr::from(([0, 0, 0, 0], 8080))),
+            grpc_bind_addr: env::var("GRPC_BIND_ADDR")
+                .ok()
+                .and_then(|addr| addr.parse().ok()),
+            swagger_ui: env_parse("SWAGGER_UI", false),
+            worker_min,
+            worker_max,
+            worker_target_wait_ms: env_parse("WORKER_TARGET_WAIT_MS", 2000u64),
+            worker_scale_interval_ms: env_parse("WORKER_SCALE_INTERVAL_MS", 1000u64),
+            worker_heartbeat_interval_ms: env_parse("WORKER_HEARTBEAT_INTERVAL_MS", 1000u64),
+            watchdog_interval_ms: env_parse("WATCHDOG_INTERVAL_MS", 10_000u64),
+            stuck_execution_grace_ms: env_parse("STUCK_EXECUTION_GRACE_MS", 300_000u64),
+            queue_capacity,
+            queue_max_depth: env_parse("QUEUE_MAX_DEPTH", queue_capacity),
+            queue_backend: env_parse("QUEUE_BACKEND", QueueBackendKind::Memory),
+            redis_url: env::var("REDIS_URL")
+                .unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string()),
+            redis_queue_prefix: env::var("REDIS_QUEUE_PREFIX")
+                .unwrap_or_else(|_| "engine:queue".to_string()),
+            // REDIS_CLAIM_IDLE_MS is its name from before leases were renewed.
+            queue_lease_ms: env_parse(
+                "QUEUE_LEASE_MS",
+                env_parse("REDIS_CLAIM_IDLE_MS", 60_000u64),
+            ),
+            queue_max_wait_ms: env_parse("QUEUE_MAX_WAIT_MS", 0u64),
+            max_concurrent_runs: env_parse("MAX_CONCURRENT_RUNS", worker_max),
+            test_case_parallelism: env_parse("TEST_CASE_PARALLELISM", 4usize),
+            tenant_test_case_concurrency: env_parse("TENANT_TEST_CASE_CONCURRENCY", 8usize),
+            worker_node_cpu_cores: env_parse("WORKER_NODE_CPU_CORES", 0f64),
+            worker_node_memory_mb: env_parse("WORKER_NODE_MEMORY_MB", 0u64),
+            infra_retry_limit: env_parse("INFRA_RETRY_LIMIT", 2u32),
+            language_pools: parse_language_pools(&env::var("LANGUAGE_POOLS").unwrap_or_default()),
+            gpu_workers,
+            gpu_max_concurrent_runs: env_parse("GPU_MAX_CONCURRENT_RUNS", gpu_workers),
+            gpu_devices: env::var("GPU_DEVICES").unwrap_or_else(|_| "all".to_string()),
+            gpu_tenants: parse_list(&env::var("GPU_TENANTS").unwrap_or_default()),
+            sandbox_backend,
+            sandbox_backends: parse_backends(
+                &env::var("SANDBOX_BACKENDS").unwrap_or_default(),
+                sandbox_backend,
+            ),
+            tenant_backends: parse_pairs(&env::var("TENANT_BACKENDS").unwrap_or_default())
+                .filter_map(|(tenant, backend)| Some((tenant.to_string(), backend.parse().ok()?)))
+                .collect(),
+            default_max_priority: env_parse("DEFAULT_MAX_PRIORITY", 0u8),
+            tenant_max_priority: parse_pairs(&env::var("TENANT_MAX_PRIORITY").unwrap_or_default())
+                .filter_map(|(tenant, max)| Some((tenant.to_string(), max.parse().ok()?)))
+                .collect(),
+            sandbox_failover: parse_failover(&env::var("SANDBOX_FAILOVER").unwrap_or_default()),
+            health_check_interval_ms: env_parse("HEALTH_CHECK_INTERVAL_MS", 10_000u64).max(100),
+            container_runtime: env::var("CONTAINER_RUNTIME")
+                .unwrap_or_else(|_| "docker".to_string()),
+            container_rootless: env_parse("CONTAINER_ROOTLESS", false),
+            container_host: env::var("CONTAINER_HOST").ok(),
+            seccomp_profile: env::var("SECCOMP_PROFILE").ok(),
+            faketime_library: env::var("FAKETIME_LIBRARY").ok(),
+            deterministic_cpu: env_parse("DETERMINISTIC_CPU", 0usize),
+            process_cgroup_root: env::var("PROCESS_CGROUP_ROOT").ok().map(PathBuf::from),
+            process_run_user: env::var("PROCESS_RUN_USER").ok(),
+            process_chroot: env::var("PROCESS_CHROOT").ok().map(PathBuf::from),
+            process_path: env::var("PROCESS_PATH")
+                .unwrap_or_else(|_| crate::engine::sandbox::DEFAULT_PROCESS_PATH.to_string()),
+            apparmor_profile: env::var("APPARMOR_PROFILE").ok(),
+            selinux_labels: parse_list(&env::var("SELINUX_LABELS").unwrap_or_default())
+                .into_iter()
+                .collect(),
+            language_images: parse_language_images(
+                &env::var("LANGUAGE_IMAGES").unwrap_or_default(),
+            ),
+            language_versions: parse_language_versions(
+                &env::var("LANGUAGE_VERSIONS").unwrap_or_default(),
+            ),
+            language_overrides: LanguageOverrides::parse(parse_pairs(
+                &env::var("LANGUAGE_COMMANDS").unwrap_or_default(),
+            )),
+            language_version_commands: parse_language_versions(
+                &env::var("LANGUAGE_VERSION_COMMANDS").unwrap_or_default(),
+            ),
+            tools: ToolCommands {
+                lint: parse_tool_commands(
+                    &env::var("LINT_COMMANDS").unwrap_or_default(),
+                    DEFAULT_LINT_COMMANDS,
+                ),
+                format: parse_tool_commands(
+                    &env::var("FORMAT_COMMANDS").unwrap_or_default(),
+                    DEFAULT_FORMAT_COMMANDS,
+                ),
+                test: parse_tool_commands(
+                    &env::var("TEST_COMMANDS").unwrap_or_default(),
+                    DEFAULT_TEST_COMMANDS,
+                ),
+                images: parse_language_images(&env::var("TOOL_IMAGES").unwrap_or_default()),
             },
+            registry_auth: parse_registry_auth(&env::var("REGISTRY_AUTH").unwrap_or_default()),
+            prepull_images: env_parse("PREPULL_IMAGES", true),
+            allowed_images: parse_list(&env::var("ALLOWED_IMAGES").unwrap_or_default())
+                .into_iter()
+                .collect(),
+            git_allowed_hosts: parse_list(&env::var("GIT_ALLOWED_HOSTS").unwrap_or_default()),
+            git_fetch_timeout_ms: env_parse("GIT_FETCH_TIMEOUT_MS", 30_000u64),
+            git_max_bytes: env_parse("GIT_MAX_BYTES", 32 * 1024 * 1024u64),
+            git_max_files: env_parse("GIT_MAX_FILES", 1000usize),
+            archive_max_bytes: env_parse("ARCHIVE_MAX_BYTES", 64 * 1024 * 1024u64),
+            stdin_upload_dir: env::var("STDIN_UPLOAD_DIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(|_| env::temp_dir().join("sandbox-stdin")),
+            stdin_upload_max_bytes: env_parse("STDIN_UPLOAD_MAX_BYTES", 64 * 1024 * 1024u64),
+            archive_max_files: env_parse("ARCHIVE_MAX_FILES", 2000usize),
+            benchmark_max_runs: env_parse("BENCHMARK_MAX_RUNS", 100u32),
+            benchmark_max_total_ms: env_parse("BENCHMARK_MAX_TOTAL_MS", 120_000u64),
+            metadata_max_keys: env_parse("METADATA_MAX_KEYS", 32usize),
+            metadata_max_key_bytes: env_parse("METADATA_MAX_KEY_BYTES", 128usize),
+            metadata_max_value_bytes: env_parse("METADATA_MAX_VALUE_BYTES", 1024usize),
+            metadata_max_bytes: env_parse("METADATA_MAX_BYTES", 8192usize),
+            dependency_cache_dir: env::var("DEPENDENCY_CACHE_DIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(|_| env::temp_dir().join("sandbox-deps")),
+            compile_cache_dir: env::var("COMPILE_CACHE_DIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(|_| env::temp_dir().join("sandbox-artifacts")),
+            process_compile_cache_max_entries: env_parse(
+                "PROCESS_COMPILE_CACHE_MAX_ENTRIES",
+                256usize,
+            ),
+            process_compile_cache_max_bytes: env_parse(
+                "PROCESS_COMPILE_CACHE_MAX_BYTES",
+                1024 * 1024 * 1024u64,
+            ),
+            limit_profiles: parse_limit_profiles(
+                &env::var("LIMIT_PROFILES").unwrap_or_else(|_| DEFAULT_LIMIT_PROFILES.to_string()),
+                &default_limits,
+            ),
+            tenant_profiles: parse_tenant_profiles(
+                &env::var("TENANT_PROFILES").unwrap_or_default(),
+            ),
+            language_limits: parse_language_limits(
+                &env::var("LANGUAGE_LIMITS").unwrap_or_default(),
+                &default_limits,
+            ),
+            language_limit_ceilings: parse_language_limits(
+                &env::var("LANGUAGE_LIMIT_CEILINGS").unwrap_or_default(),
+                &ExecutionLimits::MAX,
+            ),
+            default_limits,
             api_keys: parse_api_keys(
                 &env::var("API_KEYS").unwrap_or_else(|_| "default:dev-key".to_string()),
             ),
+            admin_api_keys: parse_list(&env::var("ADMIN_API_KEYS").unwrap_or_default()),
             rate_limit_per_minute: env_parse("RATE_LIMIT_PER_MINUTE", 120u32),
-            rate_limit_burst: env_parse("RATE_LIMIT_BURST", 20u32),
+            rate_limit_burst,
+            tenant_rate_limits: parse_tenant_rate_limits(
+                &env::var("TENANT_RATE_LIMITS").unwrap_or_default(),
+                rate_limit_burst,
+            ),
+            execution_quota: Quota {
+                daily: env_parse("DAILY_EXECUTION_QUOTA", 0u64),
+                monthly: env_parse("MONTHLY_EXECUTION_QUOTA", 0u64),
+            },
+            tenant_execution_quotas: parse_tenant_quotas(
+                &env::var("TENANT_EXECUTION_QUOTAS").unwrap_or_default(),
+            ),
+            read_rate_limit_per_minute: env_parse("READ_RATE_LIMIT_PER_MINUTE", 600u32),
+            read_rate_limit_burst,
+            tenant_read_rate_limits: parse_tenant_rate_limits(
+                &env::var("TENANT_READ_RATE_LIMITS").unwrap_or_default(),
+                read_rate_limit_burst,
+            ),
+            idempotency_ttl_secs: env_parse("IDEMPOTENCY_TTL_SECS", 24 * 60 * 60u64),
+            result_cache_ttl_secs: env_parse("RESULT_CACHE_TTL_SECS", 60 * 60u64),
+            session_max: env_parse("SESSION_MAX", 32usize),
+            session_max_per_tenant: env_parse("SESSION_MAX_PER_TENANT", 4usize),
+            session_idle_timeout_ms: env_parse("SESSION_IDLE_TIMEOUT_MS", 10 * 60 * 1000u64),
+            session_checkpoint: env_parse("SESSION_CHECKPOINT", false),
+            session_suspended_ttl_ms: env_parse("SESSION_SUSPENDED_TTL_MS", 24 * 60 * 60 * 1000u64),
+            cost_weights: CostWeights {
+                cpu_second: env_parse("COST_CPU_SECOND", 1.0),
+                memory_gb_second: env_parse("COST_MEMORY_GB_SECOND", 1.0),
+                duration_second: env_parse("COST_DURATION_SECOND", 1.0),
+            },
+            templates_path: env::var("TEMPLATES_PATH").ok().map(PathBuf::from),
+            template_max_per_tenant: env_parse("TEMPLATE_MAX_PER_TENANT", 100usize),
+            secrets_path: env::var("SECRETS_PATH").ok().map(PathBuf::from),
+            secret_max_per_tenant: env_parse("SECRET_MAX_PER_TENANT", 100usize),
             network_allowed_tenants: parse_list(
                 &env::var("NETWORK_ALLOWED_TENANTS").unwrap_or_default(),
             ),
+            tenant_egress: parse_tenant_egress(&env::var("TENANT_EGRESS").unwrap_or_default()),
+            egress_network: env::var("EGRESS_NETWORK").ok(),
+            egress_proxy_addr: env_parse(
+                "EGRESS_PROXY_ADDR",
+                SocketAddr::from(([0, 0, 0, 0], 3128)),
+            ),
+            egress_proxy_url: env::var("EGRESS_PROXY_URL").ok(),
+            network_proxy: env_parse("NETWORK_PROXY", false),
+            tenant_network_allowlist: parse_tenant_egress(
+                &env::var("TENANT_NETWORK_ALLOWLIST").unwrap_or_default(),
+            ),
+            default_network_bandwidth: env_parse("NETWORK_BANDWIDTH_BYTES_PER_SEC", 0u64),
+            tenant_network_bandwidth: parse_pairs(
+                &env::var("TENANT_NETWORK_BANDWIDTH").unwrap_or_default(),
+            )
+            .filter_map(|(tenant, rate)| Some((tenant.to_string(), rate.parse().ok()?)))
+            .collect(),
             persistence_path: env::var("PERSIST_RESULTS_PATH").ok().map(PathBuf::from),
+            persist_compact_interval_secs: env_parse("PERSIST_COMPACT_INTERVAL_SECS", 3600u64),
+            persist_keep_segments: env_parse("PERSIST_KEEP_SEGMENTS", 1usize),
+            record_retention_secs: env_parse("RECORD_RETENTION_SECS", 0u64),
+            object_store_bucket: env::var("OBJECT_STORE_BUCKET").ok(),
+            object_store_endpoint: env::var("OBJECT_STORE_ENDPOINT").ok(),
+            object_store_region: env::var("OBJECT_STORE_REGION")
+                .unwrap_or_else(|_| "us-east-1".to_string()),
+            object_store_prefix: env::var("OBJECT_STORE_PREFIX").unwrap_or_default(),
+            object_store_access_key_id: env::var("OBJECT_STORE_ACCESS_KEY_ID")
+                .or_else(|_| env::var("AWS_ACCESS_KEY_ID"))
+                .ok(),
+            object_store_secret_access_key: env::var("OBJECT_STORE_SECRET_ACCESS_KEY")
+                .or_else(|_| env::var("AWS_SECRET_ACCESS_KEY"))
+                .ok(),
+            object_store_offload_bytes: env_parse("OBJECT_STORE_OFFLOAD_BYTES", 65_536usize),
+            object_store_timeout_ms: env_parse("OBJECT_STORE_TIMEOUT_MS", 10_000u64),
+            encryption_keys: env::var("ENCRYPTION_KEYS").unwrap_or_default(),
+            encryption_keys_file: env::var("ENCRYPTION_KEYS_FILE").ok().map(PathBuf::from),
+            audit_log_dir: env::var("AUDIT_LOG_DIR").ok().map(PathBuf::from),
+            audit_retention_days: env_parse("AUDIT_RETENTION_DAYS", 90u64),
+            share_link_key: env::var("SHARE_LINK_KEY")
+                .ok()
+                .filter(|key| !key.is_empty()),
+            share_link_max_ttl_secs: env_parse("SHARE_LINK_MAX_TTL_SECS", 7 * 24 * 3600u64),
+            share_link_base_url: env::var("SHARE_LINK_BASE_URL")
+                .ok()
+                .map(|url| url.trim_end_matches('/').to_string()),
             log_level: env::var("LOG_LEVEL").unwrap_or_else(|_| "info".to_string()),
+            metrics_tenant_labels: env_parse("METRICS_TENANT_LABELS", DEFAULT_TENANT_LABELS),
+            metrics_push_interval_ms: env_parse("METRICS_PUSH_INTERVAL_MS", 10_000u64),
+            statsd_addr: env::var("STATSD_ADDR").ok().filter(|addr| !addr.is_empty()),
+            statsd_prefix: env::var("STATSD_PREFIX").unwrap_or_default(),
+            metrics_remote_write_url: env::var("METRICS_REMOTE_WRITE_URL")
+                .ok()
+                .filter(|url| !url.is_empty()),
+            metrics_remote_write_token: env::var("METRICS_REMOTE_WRITE_TOKEN").ok(),
+            metrics_instance: env::var("METRICS_INSTANCE")
+                .or_else(|_| env::var("HOSTNAME"))
+                .unwrap_or_else(|_| "engine".to_string()),
+            otlp_endpoint: env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok(),
+            otel_service_name: env::var("OTEL_SERVICE_NAME")
+                .unwrap_or_else(|_| "sandbox-engine".to_string()),
+        }
+    }
+}
+
+impl EngineConfig {
+    pub fn egress_enabled(&self) -> bool {
+        self.sandbox_backends.contains(&SandboxBackendKind::Docker)
+            && self.egress_network.is_some()
+            && self.egress_proxy_url.is_some()
+    }
+
+    // Tenants with full network access may reach any destination; others only
+    // those covered by their TENANT_EGRESS entry.
+    pub fn egress_allowed(&self, tenant_id: &str, rule: &EgressRule) -> bool {
+        self.network_allowed_tenants.contains(tenant_id)
+            || self
+                .tenant_egress
+                .get(tenant_id)
+                .is_some_and(|allowed| allowed.iter().any(|entry| entry.covers(rule)))
+    }
+
+    // Installs fetch from package registries, so they need network access:
+    // full, or a TENANT_EGRESS grant used through the proxy. The process
+    // backend has no proxy, so there only full access counts, and not under
+    // NETWORK_PROXY.
+    pub fn may_install_dependencies(&self, tenant_id: &str, backend: SandboxBackendKind) -> bool {
+        let network = self.network_allowed_tenants.contains(tenant_id);
+        match backend {
+            SandboxBackendKind::Process => network && !self.network_proxy,
+            SandboxBackendKind::Docker => {
+                network
+                    || (self.egress_enabled()
+                        && self
+                            .tenant_egress
+                            .get(tenant_id)
+                            .is_some_and(|rules| !rules.is_empty()))
+            }
+        }
+    }
+
+    // The versions a request may pin for `language` on `backend`.
+    pub fn runtime_versions(
+        &self,
+        backend: SandboxBackendKind,
+        language: &Language,
+    ) -> Option<&BTreeMap<String, String>> {
+        match backend {
+            SandboxBackendKind::Docker => self.language_versions.get(language),
+            SandboxBackendKind::Process => self.language_version_commands.get(language),
+        }
+    }
+
+    pub fn default_limits_for(&self, language: &Language) -> &ExecutionLimits {
+        self.language_limits
+            .get(language)
+            .unwrap_or(&self.default_limits)
+    }
+
+    pub fn cap_limits(&self, language: &Language, limits: ExecutionLimits) -> ExecutionLimits {
+        match self.language_limit_ceilings.get(language) {
+            Some(ceiling) => limits.capped(ceiling),
+            None => limits,
         }
     }
+
+    // Tenants without an entry in TENANT_PROFILES may use every profile.
+    pub fn profile_allowed(&self, tenant_id: &str, profile: &str) -> bool {
+        self.tenant_profiles
+            .get(tenant_id)
+            .is_none_or(|allowed| allowed.contains(profile))
+    }
+
+    pub fn max_priority(&self, tenant_id: &str) -> u8 {
+        self.tenant_max_priority
+            .get(tenant_id)
+            .copied()
+            .unwrap_or(self.default_max_priority)
+    }
 }
 
-#[derive(Debug, Clone, Copy, Default)]
+// Only for tools the default images ship with.
+const DEFAULT_LINT_COMMANDS: &[(Language, &str)] = &[
+    (Language::Go, "GOCACHE=/tmp/go HOME=/tmp go vet main.go"),
+    (
+        Language::TypeScript,
+        "DENO_DIR=/tmp/deno deno lint --compact main.ts",
+    ),
+];
+const DEFAULT_FORMAT_COMMANDS: &[(Language, &str)] = &[
+    (Language::Go, "gofmt main.go"),
+    (
+        Language::TypeScript,
+        "DENO_DIR=/tmp/deno deno fmt - < main.ts",
+    ),
+];
+
+const DEFAULT_TEST_COMMANDS: &[(Language, &str)] = &[(
+    Language::TypeScript,
+    "DENO_DIR=/tmp/deno deno test --allow-read --reporter=junit",
+)];
+
+// Linter, formatter and test runner commands per language, run with `sh -c` in the
+// working directory next to the source. `images` are docker images with the
+// tools installed, used instead of the language image.
+#[derive(Debug, Clone, Default)]
+pub struct ToolCommands {
+    pub lint: HashMap<Language, String>,
+    pub format: HashMap<Language, String>,
+    pub test: HashMap<Language, String>,
+    pub images: HashMap<Language, String>,
+}
+
+impl ToolCommands {
+    // The command that replaces the program for `mode`, if it is a tool mode.
+    pub fn command(&self, language: &Language, mode: Option<&ExecutionMode>) -> Option<&str> {
+        let commands = match mode {
+            Some(ExecutionMode::Lint) => &self.lint,
+            Some(ExecutionMode::Format) => &self.format,
+            Some(ExecutionMode::Test) => &self.test,
+            _ => return None,
+        };
+        commands.get(language).map(String::as_str)
+    }
+}
+
+// Prices for an execution's resource use: per second of CPU time, per
+// GB-second of reserved memory and per second of run time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CostWeights {
+    pub cpu_second: f64,
+    pub memory_gb_second: f64,
+    pub duration_second: f64,
+}
+
+impl CostWeights {
+    // Summed over every run (each test case). Backends that don't measure
+    // CPU time are charged the reserved cores for the whole run.
+    pub fn cost(&self, output: &ExecutionOutput, limits: &ExecutionLimits) -> f64 {
+        output
+            .runs()
+            .into_iter()
+            .map(|(usage, duration_ms)| {
+                let seconds = duration_ms as f64 / 1000.0;
+                let cpu_seconds = match (usage.user_cpu_ms, usage.system_cpu_ms) {
+                    (None, None) => f64::from(limits.cpu_cores) * seconds,
+                    (user, system) => (user.unwrap_or(0) + system.unwrap_or(0)) as f64 / 1000.0,
+                };
+                let memory_gb_seconds = limits.memory_mb as f64 / 1024.0 * seconds;
+                self.cpu_second * cpu_seconds
+                    + self.memory_gb_second * memory_gb_seconds
+                    + self.duration_second * seconds
+            })
+            .sum()
+    }
+}
+
+const DEFAULT_LIMIT_PROFILES: &str = "small=0.5:256:3000,medium=1:1024:10000,large=2:4096:30000";
+
+// A fixed-size worker pool that runs only the listed languages.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LanguagePool {
+    pub name: String,
+    pub languages: Vec<Language>,
+    pub workers: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiKey {
+    pub tenant_id: String,
+    pub scopes: HashSet<Scope>,
+}
+
+// `submit` enqueues and cancels executions, `read` fetches them and usage,
+// `admin` opens the admin endpoints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Scope {
+    Submit,
+    Read,
+    Admin,
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "submit" => Ok(Self::Submit),
+            "read" => Ok(Self::Read),
+            "admin" => Ok(Self::Admin),
+            _ => Err(format!("unknown api key scope: {s}")),
+        }
+    }
+}
+
+// Requests per minute and burst size for one tenant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub per_minute: u32,
+    pub burst: u32,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quota {
+    pub daily: u64,
+    pub monthly: u64,
+}
+
+impl Quota {
+    pub fn is_limited(&self) -> bool {
+        self.daily > 0 || self.monthly > 0
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RegistryAuth {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+    ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
 pub enum SandboxBackendKind {
     #[default]
     Docker,
@@ -71,23 +718,315 @@ impl FromStr for SandboxBackendKind {
     }
 }
 
-fn parse_api_keys(input: &str) -> HashMap<String, String> {
+// `api` nodes accept and serve executions, `worker` nodes run them; both
+// share the Redis queue and replicate execution records through Redis.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EngineRole {
+    #[default]
+    All,
+    Api,
+    Worker,
+}
+
+impl FromStr for EngineRole {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "all" => Ok(Self::All),
+            "api" => Ok(Self::Api),
+            "worker" => Ok(Self::Worker),
+            _ => Err(format!("unsupported engine role: {s}")),
+        }
+    }
+}
+
+impl EngineRole {
+    pub fn serves_api(&self) -> bool {
+        !matches!(self, Self::Worker)
+    }
+
+    pub fn runs_workers(&self) -> bool {
+        !matches!(self, Self::Api)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QueueBackendKind {
+    #[default]
+    Memory,
+    Redis,
+}
+
+impl FromStr for QueueBackendKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "memory" => Ok(Self::Memory),
+            "redis" => Ok(Self::Redis),
+            _ => Err(format!("unsupported queue backend: {s}")),
+        }
+    }
+}
+
+impl SandboxBackendKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Docker => "docker",
+            Self::Process => "process",
+        }
+    }
+}
+
+// `tenant:key` or `tenant:key:read|submit`; keys without scopes may submit
+// and read. Entries with an unknown scope are skipped.
+fn parse_api_keys(input: &str) -> HashMap<String, ApiKey> {
     let mut keys = HashMap::new();
     for raw in input.split(',') {
         let entry = raw.trim();
         if entry.is_empty() {
             continue;
         }
-        if let Some((tenant, key)) = entry.split_once(':') {
-            keys.insert(key.to_string(), tenant.to_string());
-        }
+        let mut parts = entry.splitn(3, ':');
+        let (Some(tenant), Some(key)) = (parts.next(), parts.next()) else {
+            continue;
+        };
+        let scopes = match parts.next() {
+            Some(scopes) => match scopes
+                .split('|')
+                .map(|scope| scope.trim().parse())
+                .collect()
+            {
+                Ok(scopes) => scopes,
+                Err(_) => continue,
+            },
+            None => HashSet::from([Scope::Submit, Scope::Read]),
+        };
+        keys.insert(
+            key.to_string(),
+            ApiKey {
+                tenant_id: tenant.to_string(),
+                scopes,
+            },
+        );
     }
     if keys.is_empty() {
-        keys.insert("dev-key".to_string(), "default".to_string());
+        keys.insert(
+            "dev-key".to_string(),
+            ApiKey {
+                tenant_id: "default".to_string(),
+                scopes: HashSet::from([Scope::Submit, Scope::Read]),
+            },
+        );
     }
     keys
 }
 
+fn parse_language_images(input: &str) -> HashMap<Language, String> {
+    parse_pairs(input)
+        .filter_map(|(language, image)| Some((language.parse().ok()?, image.to_string())))
+        .collect()
+}
+
+// `python@3.11=python:3.11-alpine,javascript@20=node:20-alpine`; entries for
+// unknown languages are skipped.
+fn parse_language_versions(input: &str) -> HashMap<Language, BTreeMap<String, String>> {
+    let mut versions: HashMap<Language, BTreeMap<String, String>> = HashMap::new();
+    for (key, target) in parse_pairs(input) {
+        let Some((language, version)) = key.split_once('@') else {
+            continue;
+        };
+        let (Ok(language), version) = (language.parse::<Language>(), version.trim()) else {
+            continue;
+        };
+        if !version.is_empty() {
+            versions
+                .entry(language)
+                .or_default()
+                .insert(version.to_string(), target.to_string());
+        }
+    }
+    versions
+}
+
+// `python=ruff check --output-format=concise main.py`, on top of `defaults`.
+fn parse_tool_commands(input: &str, defaults: &[(Language, &str)]) -> HashMap<Language, String> {
+    let mut commands: HashMap<Language, String> = defaults
+        .iter()
+        .map(|(language, command)| (language.clone(), command.to_string()))
+        .collect();
+    commands.extend(parse_language_images(input));
+    commands
+}
+
+fn parse_registry_auth(input: &str) -> HashMap<String, RegistryAuth> {
+    parse_pairs(input)
+        .filter_map(|(registry, credentials)| {
+            let (username, password) = credentials.split_once(':')?;
+            Some((
+                registry.to_string(),
+                RegistryAuth {
+                    username: username.to_string(),
+                    password: password.to_string(),
+                },
+            ))
+        })
+        .collect()
+}
+
+// `name=cpu_cores:memory_mb:timeout_ms`; the remaining limits come from the
+// defaults.
+fn parse_limit_profiles(
+    input: &str,
+    defaults: &ExecutionLimits,
+) -> HashMap<String, ExecutionLimits> {
+    parse_pairs(input)
+        .filter_map(|(name, spec)| {
+            let mut fields = spec.split(':').map(str::trim);
+            let limits = ExecutionLimits {
+                cpu_cores: fields.next()?.parse().ok()?,
+                memory_mb: fields.next()?.parse().ok()?,
+                timeout_ms: fields.next()?.parse().ok()?,
+                ..defaults.clone()
+            };
+            fields
+                .next()
+                .is_none()
+                .then(|| (name.to_string(), limits.normalized()))
+        })
+        .collect()
+}
+
+// `rust=1:1024:10000,java=1:1024:8000`, in the profile format; the
+// remaining limits come from `defaults`.
+fn parse_language_limits(
+    input: &str,
+    defaults: &ExecutionLimits,
+) -> HashMap<Language, ExecutionLimits> {
+    parse_limit_profiles(input, defaults)
+        .into_iter()
+        .filter_map(|(language, limits)| Some((language.parse().ok()?, limits)))
+        .collect()
+}
+
+// `tenant=small|medium,tenant2=large`.
+fn parse_tenant_profiles(input: &str) -> HashMap<String, HashSet<String>> {
+    parse_pairs(input)
+        .map(|(tenant, profiles)| {
+            let profiles = profiles
+                .split('|')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(str::to_string)
+                .collect();
+            (tenant.to_string(), profiles)
+        })
+        .collect()
+}
+
+// `compiled=rust|cpp|go:2,scripts=python|javascript:6`. The built-in pool
+// names are reserved and a language belongs to the first pool listing it.
+fn parse_language_pools(input: &str) -> Vec<LanguagePool> {
+    let mut claimed = HashSet::new();
+    parse_pairs(input)
+        .filter(|(name, _)| !matches!(*name, "default" | "gpu"))
+        .filter_map(|(name, spec)| {
+            let (languages, workers) = spec.rsplit_once(':')?;
+            let workers: usize = workers.trim().parse().ok().filter(|n| *n > 0)?;
+            let languages: Vec<Language> = languages
+                .split('|')
+                .filter_map(|language| language.parse::<Language>().ok())
+                .filter(|language| claimed.insert(language.clone()))
+                .collect();
+            (!languages.is_empty()).then(|| LanguagePool {
+                name: name.to_string(),
+                languages,
+                workers,
+            })
+        })
+        .collect()
+}
+
+// `tenant=pypi.org|*.pythonhosted.org:443,tenant2=registry.npmjs.org:443`.
+// `trusted=600:100,ci=300`; the burst defaults to `default_burst`.
+fn parse_tenant_rate_limits(input: &str, default_burst: u32) -> HashMap<String, RateLimit> {
+    parse_pairs(input)
+        .filter_map(|(tenant, limit)| {
+            let (per_minute, burst) = match limit.split_once(':') {
+                Some((per_minute, burst)) => (per_minute, burst.trim().parse().ok()?),
+                None => (limit, default_burst),
+            };
+            let limit = RateLimit {
+                per_minute: per_minute.trim().parse().ok()?,
+                burst,
+            };
+            Some((tenant.to_string(), limit))
+        })
+        .collect()
+}
+
+// `tenant=daily/monthly`; either side may be empty or 0 for no limit.
+fn parse_tenant_quotas(input: &str) -> HashMap<String, Quota> {
+    parse_pairs(input)
+        .filter_map(|(tenant, quota)| {
+            let (daily, monthly) = quota.split_once('/').unwrap_or((quota, ""));
+            let count = |value: &str| match value.trim() {
+                "" => Some(0),
+                value => value.parse().ok(),
+            };
+            let quota = Quota {
+                daily: count(daily)?,
+                monthly: count(monthly)?,
+            };
+            Some((tenant.to_string(), quota))
+        })
+        .collect()
+}
+
+// The default backend is always enabled; unknown names are skipped.
+fn parse_backends(input: &str, default: SandboxBackendKind) -> Vec<SandboxBackendKind> {
+    let mut backends: Vec<_> = input
+        .split(',')
+        .filter_map(|name| name.trim().parse().ok())
+        .chain([default])
+        .collect();
+    backends.sort();
+    backends.dedup();
+    backends
+}
+
+// Keeps the given order, unlike `parse_backends`.
+fn parse_failover(input: &str) -> Vec<SandboxBackendKind> {
+    let mut chain: Vec<SandboxBackendKind> = Vec::new();
+    for kind in input.split(',').filter_map(|name| name.trim().parse().ok()) {
+        if !chain.contains(&kind) {
+            chain.push(kind);
+        }
+    }
+    chain
+}
+
+fn parse_tenant_egress(input: &str) -> HashMap<String, Vec<EgressRule>> {
+    parse_pairs(input)
+        .map(|(tenant, rules)| {
+            let rules = rules
+                .split('|')
+                .filter_map(|rule| rule.parse().ok())
+                .collect();
+            (tenant.to_string(), rules)
+        })
+        .collect()
+}
+
+fn parse_pairs(input: &str) -> impl Iterator<Item = (&str, &str)> {
+    input.split(',').filter_map(|raw| {
+        let (key, value) = raw.trim().split_once('=')?;
+        let (key, value) = (key.trim(), value.trim());
+        (!key.is_empty() && !value.is_empty()).then_some((key, value))
+    })
+}
+
 fn parse_list(input: &str) -> HashSet<String> {
     input
         .split(',')
@@ -111,3 +1050,179 @@ where
         .and_then(|raw| raw.parse().ok())
         .unwrap_or(default)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        CostWeights, DEFAULT_FORMAT_COMMANDS, DEFAULT_LIMIT_PROFILES, EngineConfig, RateLimit,
+        SandboxBackendKind, Scope, parse_api_keys, parse_backends, parse_language_limits,
+        parse_language_pools, parse_language_versions, parse_limit_profiles, parse_tenant_egress,
+        parse_tenant_profiles, parse_tenant_rate_limits, parse_tool_commands,
+    };
+    use crate::engine::models::{ExecutionLimits, Language};
+
+    #[test]
+    fn weighs_cost_per_run() {
+        let weights = CostWeights {
+            cpu_second: 2.0,
+            memory_gb_second: 1.0,
+            duration_second: 0.5,
+        };
+        let limits = ExecutionLimits {
+            cpu_cores: 2.0,
+            memory_mb: 512,
+            timeout_ms: 1000,
+            max_processes: 8,
+            max_file_size_bytes: 1024,
+            max_output_bytes: 1024,
+        };
+        let case = |cpu: serde_json::Value| {
+            serde_json::json!({
+                "stdin": "", "stdout": "", "stderr": "", "passed": true, "exit_code": 0,
+                "duration_ms": 2000,
+                "usage": { "user_cpu_ms": cpu, "stdout_bytes": 0, "stderr_bytes": 0 },
+            })
+        };
+        // Measured: 1.5 cpu-s * 2 + 0.5 GB * 2 s + 2 s * 0.5 = 5; unmeasured
+        // charges 2 cores * 2 s instead: 8 + 1 + 1 = 10.
+        let output = serde_json::from_value(serde_json::json!({
+            "stdout": "", "stderr": "", "exit_code": 0, "duration_ms": 4000,
+            "sandbox_backend": "process",
+            "test_results": [case(1500.into()), case(serde_json::Value::Null)],
+        }))
+        .unwrap();
+        assert_eq!(weights.cost(&output, &limits), 15.0);
+    }
+
+    #[test]
+    fn installs_dependencies_only_with_network_access() {
+        let mut config = EngineConfig::from_env();
+        config.sandbox_backends = vec![SandboxBackendKind::Docker];
+        config.network_allowed_tenants = ["net".to_string()].into();
+        config.tenant_egress = parse_tenant_egress("pkg=pypi.org:443");
+        let docker = SandboxBackendKind::Docker;
+        assert!(config.may_install_dependencies("net", docker));
+        // A grant is only usable through the proxy.
+        assert!(!config.may_install_dependencies("pkg", docker));
+        config.egress_network = Some("sandbox-egress".to_string());
+        config.egress_proxy_url = Some("http://proxy:3128".to_string());
+        assert!(config.may_install_dependencies("pkg", docker));
+        assert!(!config.may_install_dependencies("other", docker));
+
+        let process = SandboxBackendKind::Process;
+        assert!(config.may_install_dependencies("net", process));
+        assert!(!config.may_install_dependencies("pkg", process));
+        config.network_proxy = true;
+        assert!(!config.may_install_dependencies("net", process));
+        assert!(config.may_install_dependencies("net", docker));
+    }
+
+    #[test]
+    fn parses_limit_profiles_and_tenant_allowlists() {
+        let defaults = ExecutionLimits {
+            cpu_cores: 0.5,
+            memory_mb: 256,
+            timeout_ms: 3000,
+            max_processes: 32,
+            max_file_size_bytes: 1024 * 1024,
+            max_output_bytes: 64 * 1024,
+        };
+        let profiles = parse_limit_profiles(DEFAULT_LIMIT_PROFILES, &defaults);
+        assert_eq!(profiles.len(), 3);
+        let large = &profiles["large"];
+        assert_eq!(
+            (large.cpu_cores, large.memory_mb, large.timeout_ms),
+            (2.0, 4096, 30_000)
+        );
+        assert_eq!(large.max_processes, 32);
+        assert!(parse_limit_profiles("bad=1:2,worse=x:1:1", &defaults).is_empty());
+
+        let languages = parse_language_limits("rust=1:1024:10000,cobol=1:1:1", &defaults);
+        assert_eq!(languages.len(), 1);
+        assert_eq!(languages[&Language::Rust].memory_mb, 1024);
+        let ceilings = parse_language_limits("python=1:512:5000", &ExecutionLimits::MAX);
+        let capped = profiles["large"]
+            .clone()
+            .capped(&ceilings[&Language::Python]);
+        assert_eq!(
+            (capped.cpu_cores, capped.memory_mb, capped.timeout_ms),
+            (1.0, 512, 5000)
+        );
+        assert_eq!(capped.max_processes, 32);
+
+        let tenants = parse_tenant_profiles("acme=small|medium, free=small");
+        assert!(tenants["acme"].contains("medium"));
+        assert!(!tenants["free"].contains("large"));
+    }
+
+    #[test]
+    fn parses_language_pools() {
+        let pools = parse_language_pools("compiled=rust|cpp:2,scripts=python|rust:6,gpu=python:1");
+        assert_eq!(pools.len(), 2);
+        assert_eq!(pools[0].languages, [Language::Rust, Language::Cpp]);
+        assert_eq!(pools[0].workers, 2);
+        // rust already belongs to `compiled`.
+        assert_eq!(pools[1].languages, [Language::Python]);
+        assert!(parse_language_pools("empty=cobol:2,idle=go:0").is_empty());
+    }
+
+    #[test]
+    fn always_enables_the_default_backend() {
+        use SandboxBackendKind::{Docker, Process};
+        assert_eq!(parse_backends("", Process), [Process]);
+        assert_eq!(
+            parse_backends("process, wasm,docker", Docker),
+            [Docker, Process]
+        );
+    }
+
+    #[test]
+    fn parses_api_key_scopes() {
+        let keys = parse_api_keys("acme:k1, acme:k2:read, ops:k3:read|admin, bad:k4:write");
+        assert_eq!(keys.len(), 3);
+        assert!(keys["k1"].scopes.contains(&Scope::Submit));
+        assert!(!keys["k1"].scopes.contains(&Scope::Admin));
+        assert_eq!(keys["k2"].scopes.len(), 1);
+        assert_eq!(keys["k3"].tenant_id, "ops");
+        assert!(keys["k3"].scopes.contains(&Scope::Admin));
+    }
+
+    #[test]
+    fn parses_tenant_rate_limits() {
+        let limits = parse_tenant_rate_limits("trusted=600:100, ci=300, bad=x:1", 20);
+        assert_eq!(limits.len(), 2);
+        assert_eq!(
+            limits["trusted"],
+            RateLimit {
+                per_minute: 600,
+                burst: 100
+            }
+        );
+        assert_eq!(limits["ci"].burst, 20);
+    }
+
+    #[test]
+    fn parses_language_version_matrix() {
+        let versions = parse_language_versions(
+            "python@3.11=python:3.11-alpine, python@3.12=python:3.12-alpine,node=x,cobol@1=y",
+        );
+        assert_eq!(versions.len(), 1);
+        let python = &versions[&Language::Python];
+        assert_eq!(python["3.11"], "python:3.11-alpine");
+        assert_eq!(python.keys().collect::<Vec<_>>(), ["3.11", "3.12"]);
+    }
+
+    #[test]
+    fn overrides_default_tool_commands() {
+        let commands = parse_tool_commands(
+            "python=ruff format --no-cache - < main.py, go=gofumpt main.go",
+            DEFAULT_FORMAT_COMMANDS,
+        );
+        assert_eq!(
+            commands[&Language::Python],
+            "ruff format --no-cache - < main.py"
+        );
+        assert_eq!(commands[&Language::Go], "gofumpt main.go");
+        assert!(commands.contains_key(&Language::TypeScript));
+    }
+}
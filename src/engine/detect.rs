@@ -0,0 +1,191 @@
+use std::path::Path;
+
+use crate::engine::models::{ExecutionRequest, Language};
+
+// Parses a submission's JSON, where `"language": "auto"` is replaced with
+// the language detected from `filename` (else the git or archive
+// entrypoint) and the code.
+pub fn parse_request(mut value: serde_json::Value) -> Result<ExecutionRequest, String> {
+    let auto = value
+        .get("language")
+        .and_then(|language| language.as_str())
+        .is_some_and(|language| language.eq_ignore_ascii_case("auto"));
+    if auto {
+        let hint = ["/filename", "/git/entrypoint", "/archive/entrypoint"]
+            .iter()
+            .find_map(|pointer| value.pointer(pointer).and_then(|hint| hint.as_str()));
+        let code = value.get("code").and_then(|code| code.as_str());
+        let language = detect(hint, code.unwrap_or_default())
+            .ok_or("cannot detect the language; set `language` or `filename`")?;
+        value["language"] = language.as_str().into();
+    }
+    let mut request: ExecutionRequest =
+        serde_json::from_value(value).map_err(|err| err.to_string())?;
+    request.language_detected = auto;
+    Ok(request)
+}
+
+// The file name's extension decides when it is known, then a shebang, then
+// markers only one of the languages' syntax has.
+pub fn detect(filename: Option<&str>, code: &str) -> Option<Language> {
+    if let Some(language) = filename
+        .and_then(|name| Path::new(name).extension())
+        .and_then(|extension| extension.to_str())
+        .and_then(Language::from_extension)
+    {
+        return Some(language);
+    }
+    let code = code.trim_start_matches('\u{feff}');
+    if let Some(shebang) = code.lines().next().and_then(|line| line.strip_prefix("#!")) {
+        return from_shebang(shebang);
+    }
+    from_syntax(code)
+}
+
+fn from_shebang(shebang: &str) -> Option<Language> {
+    // `/usr/bin/env -S deno run` names the interpreter after env's flags.
+    let interpreter = shebang
+        .split_whitespace()
+        .map(|word| word.rsplit('/').next().unwrap_or(word))
+        .find(|word| *word != "env" && !word.starts_with('-'))?;
+    let name = interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    Some(match name {
+        "python" | "pypy" => Language::Python,
+        "node" | "nodejs" => Language::JavaScript,
+        "deno" | "ts-node" | "tsx" | "bun" => Language::TypeScript,
+        "ruby" => Language::Ruby,
+        "php" => Language::Php,
+        "kotlin" => Language::Kotlin,
+        _ => return None,
+    })
+}
+
+fn from_syntax(code: &str) -> Option<Language> {
+    let has = |marker: &str| code.contains(marker);
+    let line_starts = |prefix: &str| {
+        code.lines()
+            .any(|line| line.trim_start().starts_with(prefix))
+    };
+
+    if has("<?php") {
+        return Some(Language::Php);
+    }
+    if line_starts("package main") || has("func main()") {
+        return Some(Language::Go);
+    }
+    if has("fn main(") || has("println!(") || line_starts("use std::") {
+        return Some(Language::Rust);
+    }
+    if has("fun main(") {
+        return Some(Language::Kotlin);
+    }
+    if line_starts("using System") || has("Console.Write") || has("static void Main(") {
+        return Some(Language::CSharp);
+    }
+    if has("public static void main") || has("System.out.print") {
+        return Some(Language::Java);
+    }
+    if line_starts("#include") {
+        let cpp = [
+            "std::",
+            "<iostream>",
+            "<vector>",
+            "<string>",
+            "using namespace",
+        ];
+        return Some(if cpp.iter().any(|marker| has(marker)) {
+            Language::Cpp
+        } else {
+            Language::C
+        });
+    }
+    // Python blocks end their header lines with `:`; Ruby's close with `end`.
+    let python_block = code.lines().any(|line| {
+        let line = line.trim();
+        ["def ", "class ", "if ", "for ", "while "]
+            .iter()
+            .any(|keyword| line.starts_with(keyword))
+            && line.ends_with(':')
+    });
+    if python_block || (line_starts("import ") && !has(";")) || has("print(f\"") {
+        return Some(Language::Python);
+    }
+    if line_starts("puts ")
+        || line_starts("require '")
+        || (line_starts("end") && line_starts("def "))
+    {
+        return Some(Language::Ruby);
+    }
+    if has("console.log") || has("require(") || has("=>") || line_starts("function ") {
+        let typed = [": string", ": number", ": boolean", ": void", "interface "];
+        return Some(if typed.iter().any(|marker| has(marker)) {
+            Language::TypeScript
+        } else {
+            Language::JavaScript
+        });
+    }
+    if line_starts("print(") {
+        return Some(Language::Python);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{detect, parse_request};
+    use crate::engine::models::Language;
+
+    #[test]
+    fn detects_from_filename_shebang_and_syntax() {
+        assert_eq!(detect(Some("src/app.kt"), ""), Some(Language::Kotlin));
+        assert_eq!(
+            detect(Some("script"), "#!/usr/bin/env python3\nprint(1)"),
+            Some(Language::Python)
+        );
+        assert_eq!(
+            detect(None, "#!/usr/bin/env -S deno run\nconsole.log(1)"),
+            Some(Language::TypeScript)
+        );
+        let samples = [
+            ("package main\n\nfunc main() {}", Language::Go),
+            ("fn main() {\n    println!(\"hi\");\n}", Language::Rust),
+            ("#include <stdio.h>\nint main() { return 0; }", Language::C),
+            (
+                "#include <iostream>\nint main() { std::cout << 1; }",
+                Language::Cpp,
+            ),
+            (
+                "public class Main { public static void main(String[] a) {} }",
+                Language::Java,
+            ),
+            ("using System;\nConsole.WriteLine(1);", Language::CSharp),
+            ("def main():\n    print('hi')", Language::Python),
+            ("def main\n  puts 'hi'\nend", Language::Ruby),
+            (
+                "const add = (a: number, b: number) => a + b;",
+                Language::TypeScript,
+            ),
+            ("console.log('hi')", Language::JavaScript),
+            ("<?php echo 1;", Language::Php),
+        ];
+        for (code, language) in samples {
+            assert_eq!(detect(None, code), Some(language), "{code}");
+        }
+        assert_eq!(detect(None, "hello"), None);
+
+        let request = parse_request(serde_json::json!({
+            "language": "auto",
+            "code": "print(1)",
+            "filename": "main.rb",
+        }))
+        .unwrap();
+        assert_eq!(request.language, Language::Ruby);
+        assert!(request.language_detected);
+        assert!(parse_request(serde_json::json!({ "language": "auto", "code": "?" })).is_err());
+        assert!(
+            !parse_request(serde_json::json!({ "language": "go", "code": "" }))
+                .unwrap()
+                .language_detected
+        );
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::BTreeMap;
+
+use crate::engine::{config::EngineConfig, models::ExecutionRequest};
+
+// 2024-01-01T00:00:00Z, the clock every deterministic run starts at.
+pub const START_TIME_SECS: u64 = 1_704_067_200;
+
+// Variables a deterministic run always gets; requests cannot set them.
+pub const FIXED_ENV: &[&str] = &[
+    "LANG",
+    "LC_ALL",
+    "TZ",
+    "SOURCE_DATE_EPOCH",
+    "SANDBOX_START_TIME_MS",
+    "SANDBOX_SEED",
+    "PYTHONHASHSEED",
+    "FAKETIME",
+    "FAKETIME_DONT_RESET",
+];
+
+// The environment that pins a deterministic run: C.UTF-8 collation and
+// formatting, UTC, a fixed start time, and the seed as `SANDBOX_SEED` (the
+// variable programs seed their PRNG from) and `PYTHONHASHSEED`. With
+// FAKETIME_LIBRARY, libfaketime is preloaded so wall and monotonic clocks
+// start at the fixed time too.
+pub fn env(config: &EngineConfig, seed: u64) -> BTreeMap<String, String> {
+    let mut env = BTreeMap::from([
+        ("LANG".to_string(), "C.UTF-8".to_string()),
+        ("LC_ALL".to_string(), "C.UTF-8".to_string()),
+        ("TZ".to_string(), "UTC".to_string()),
+        ("SOURCE_DATE_EPOCH".to_string(), START_TIME_SECS.to_string()),
+        (
+            "SANDBOX_START_TIME_MS".to_string(),
+            (START_TIME_SECS * 1000).to_string(),
+        ),
+        ("SANDBOX_SEED".to_string(), seed.to_string()),
+        // Python only accepts 0..=4294967295.
+        (
+            "PYTHONHASHSEED".to_string(),
+            (seed % (u64::from(u32::MAX) + 1)).to_string(),
+        ),
+    ]);
+    if let Some(library) = &config.faketime_library {
+        env.insert("LD_PRELOAD".to_string(), library.clone());
+        env.insert("FAKETIME".to_string(), format!("@{START_TIME_SECS}"));
+        // Time moves forward from the fixed start instead of standing still.
+        env.insert("FAKETIME_DONT_RESET".to_string(), "1".to_string());
+    }
+    env
+}
+
+// Fills in the seed and the fixed environment of a deterministic request.
+// Fails with the name of a variable the request tried to set itself.
+pub fn apply(config: &EngineConfig, request: &mut ExecutionRequest) -> Result<(), String> {
+    if !request.deterministic {
+        return Ok(());
+    }
+    if let Some(name) = request
+        .env
+        .keys()
+        .chain(request.secrets.keys())
+        .find(|name| FIXED_ENV.contains(&name.as_str()))
+    {
+        return Err(format!(
+            "environment variable {name} is fixed for deterministic executions"
+        ));
+    }
+    let seed = *request.seed.get_or_insert(0);
+    request.env.extend(env(config, seed));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::apply;
+    use crate::engine::{config::EngineConfig, models::ExecutionRequest};
+
+    #[test]
+    fn pins_the_environment_of_deterministic_requests() {
+        let mut config = EngineConfig::from_env();
+        config.faketime_library = None;
+        let mut request: ExecutionRequest = serde_json::from_value(serde_json::json!({
+            "language": "python", "code": "print(1)", "deterministic": true, "seed": 42,
+            "env": {"MODE": "grade"},
+        }))
+        .unwrap();
+        apply(&config, &mut request).unwrap();
+        assert_eq!(request.env["SANDBOX_SEED"], "42");
+        assert_eq!(request.env["TZ"], "UTC");
+        assert_eq!(request.env["MODE"], "grade");
+        assert!(!request.env.contains_key("LD_PRELOAD"));
+
+        config.faketime_library = Some("/usr/lib/faketime/libfaketime.so.1".to_string());
+        let mut request: ExecutionRequest = serde_json::from_value(serde_json::json!({
+            "language": "python", "code": "print(1)", "deterministic": true,
+        }))
+        .unwrap();
+        apply(&config, &mut request).unwrap();
+        assert_eq!(request.seed, Some(0));
+        assert_eq!(request.env["FAKETIME"], "@1704067200");
+
+        let mut request: ExecutionRequest = serde_json::from_value(serde_json::json!({
+            "language": "python", "code": "print(1)", "deterministic": true,
+            "env": {"TZ": "Europe/Paris"},
+        }))
+        .unwrap();
+        assert!(apply(&config, &mut request).is_err());
+    }
+}
@@ -0,0 +1,379 @@
+use std::sync::LazyLock;
+
+use regex::{Captures, Regex};
+
+use crate::engine::models::{
+    Diagnostic, DiagnosticSeverity, DiagnosticStage, ExecutionOutput, FailureClass, FileEncoding,
+    TerminationReason,
+};
+
+// Per-stream budget for logs returned to agents, split between head and tail.
+const AGENT_LOG_BYTES: usize = 4 * 1024;
+const MAX_DIAGNOSTICS: usize = 32;
+// rustc/cargo summary lines that carry no diagnostic of their own.
+const RUST_NOISE: [&str; 3] = [
+    "aborting due to",
+    "could not compile",
+    "run with `RUST_BACKTRACE",
+];
+
+// `file:line[:col]: [severity:] message` (gcc, clang, go, javac, kotlinc).
+static COLON_STYLE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(?P<file>[^\s:()]+\.\w+):(?P<line>\d+):(?:(?P<col>\d+):)?\s*(?:(?P<sev>fatal error|error|warning|note):\s*)?(?P<msg>.+)$").unwrap()
+});
+// `file(line,col): severity CODE: message` (tsc, csc).
+static PAREN_STYLE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(?P<file>[^\s()]+\.\w+)\((?P<line>\d+),(?P<col>\d+)\):\s*(?P<sev>error|warning|info)\s+(?P<msg>.+)$").unwrap()
+});
+// rustc prints the message first and the location on a following `-->` line.
+static RUST_HEADER: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(?P<sev>error|warning|note)(?:\[\w+\])?: (?P<msg>.+)$").unwrap()
+});
+static RUST_LOCATION: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s*--> (?P<file>[^:]+):(?P<line>\d+):(?P<col>\d+)$").unwrap());
+static RUST_PANIC: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^thread '.*' panicked at (?P<file>[^:]+):(?P<line>\d+):(?P<col>\d+):$").unwrap()
+});
+// Python frames precede the exception line; the last one is where it was raised.
+static PYTHON_FRAME: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"^\s*File "(?P<file>[^"]+)", line (?P<line>\d+)"#).unwrap());
+// Java/JVM, node and go frames follow the exception line.
+static STACK_FRAME: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^\s*(?:at (?:.*\()?)?(?P<file>[^\s():]+\.\w+):(?P<line>\d+)(?::(?P<col>\d+))?(?:\)| \+0x\w+)?$").unwrap()
+});
+static EXCEPTION: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(?:Exception in thread \S+ |panic: )?(?P<msg>[\w.$]*(?:Error|Exception|Exit|Interrupt)\b.*|runtime error: .+)$").unwrap()
+});
+
+// Adds the machine-oriented view for `agent_optimized` executions: a failure
+// class, parsed diagnostics and head/tail-truncated logs.
+pub fn apply_agent_view(output: &mut ExecutionOutput) {
+    output.failure = classify(output);
+
+    let mut diagnostics = Vec::new();
+    if let Some(compile) = &output.compile {
+        diagnostics.extend(parse(DiagnosticStage::Compile, &compile.stderr));
+        diagnostics.extend(parse(DiagnosticStage::Compile, &compile.stdout));
+    }
+    let runtime_stderr = output
+        .test_results
+        .iter()
+        .find(|case| case.passed != Some(true) && !case.stderr.is_empty())
+        .map_or(&output.stderr, |case| &case.stderr);
+    diagnostics.extend(parse(DiagnosticStage::Runtime, runtime_stderr));
+    diagnostics.truncate(MAX_DIAGNOSTICS);
+    output.diagnostics = diagnostics;
+
+    // Base64 output cannot be cut without breaking it.
+    if output.stdout_encoding == FileEncoding::Utf8 {
+        compact_log(&mut output.stdout);
+    }
+    if output.stderr_encoding == FileEncoding::Utf8 {
+        compact_log(&mut output.stderr);
+    }
+    for stage in [&mut output.install, &mut output.compile]
+        .into_iter()
+        .flatten()
+    {
+        compact_log(&mut stage.stdout);
+        compact_log(&mut stage.stderr);
+    }
+    for case in &mut output.test_results {
+        if case.stdout_encoding == FileEncoding::Utf8 {
+            compact_log(&mut case.stdout);
+        }
+        if case.stderr_encoding == FileEncoding::Utf8 {
+            compact_log(&mut case.stderr);
+        }
+    }
+}
+
+// `check` executions only report what the compile or syntax check found.
+pub fn apply_check_view(output: &mut ExecutionOutput) {
+    output.failure = classify(output);
+    if let Some(compile) = &output.compile {
+        let mut diagnostics = parse(DiagnosticStage::Compile, &compile.stderr);
+        diagnostics.extend(parse(DiagnosticStage::Compile, &compile.stdout));
+        diagnostics.truncate(MAX_DIAGNOSTICS);
+        output.diagnostics = diagnostics;
+    }
+}
+
+// Findings reported by a `lint` execution's linter.
+pub fn apply_lint_view(output: &mut ExecutionOutput) {
+    let mut diagnostics = parse(DiagnosticStage::Lint, &output.stdout);
+    diagnostics.extend(parse(DiagnosticStage::Lint, &output.stderr));
+    diagnostics.truncate(MAX_DIAGNOSTICS);
+    output.diagnostics = diagnostics;
+}
+
+pub fn classify(output: &ExecutionOutput) -> Option<FailureClass> {
+    if output.test_results.is_empty() {
+        return failure_for(output.termination_reason, output.exit_code);
+    }
+    let failing = || {
+        output
+            .test_results
+            .iter()
+            .filter(|case| case.passed != Some(true))
+    };
+    failing()
+        .find_map(|case| failure_for(case.termination_reason, case.exit_code))
+        .or_else(|| {
+            failing()
+                .any(|case| case.passed == Some(false))
+                .then_some(FailureClass::WrongAnswer)
+        })
+}
+
+fn failure_for(reason: TerminationReason, exit_code: i32) -> Option<FailureClass> {
+    match reason {
+        TerminationReason::InstallFailed => Some(FailureClass::DependencyError),
+        TerminationReason::CompileFailed => Some(FailureClass::CompileError),
+        TerminationReason::TimedOut => Some(FailureClass::Timeout),
+        TerminationReason::OutOfMemory => Some(FailureClass::MemoryLimit),
+        TerminationReason::Signaled => Some(FailureClass::Crash),
+        TerminationReason::Exited if exit_code != 0 => Some(FailureClass::RuntimeError),
+        TerminationReason::Exited => None,
+    }
+}
+
+pub fn parse(stage: DiagnosticStage, text: &str) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    // A message still waiting for the location line that follows it.
+    let mut pending: Option<Diagnostic> = None;
+    let mut last_frame: Option<Captures> = None;
+    let mut panic_at: Option<Captures> = None;
+
+    for line in text.lines() {
+        if let Some(location) = panic_at.take() {
+            let mut diagnostic = diagnostic(stage, None, line.trim());
+            locate(&mut diagnostic, &location);
+            diagnostics.push(diagnostic);
+        } else if let Some(caps) = COLON_STYLE
+            .captures(line)
+            .or_else(|| PAREN_STYLE.captures(line))
+        {
+            let mut diagnostic =
+                diagnostic(stage, caps.name("sev").map(|m| m.as_str()), &caps["msg"]);
+            locate(&mut diagnostic, &caps);
+            diagnostics.push(diagnostic);
+        } else if let Some(caps) = RUST_HEADER.captures(line) {
+            diagnostics.extend(pending.take());
+            if !RUST_NOISE
+                .iter()
+                .any(|noise| caps["msg"].starts_with(noise))
+            {
+                pending = Some(diagnostic(stage, Some(&caps["sev"]), &caps["msg"]));
+            }
+        } else if let Some(caps) = RUST_LOCATION.captures(line) {
+            if let Some(mut diagnostic) = pending.take() {
+                locate(&mut diagnostic, &caps);
+                diagnostics.push(diagnostic);
+            }
+        } else if let Some(caps) = RUST_PANIC.captures(line) {
+            diagnostics.extend(pending.take());
+            panic_at = Some(caps);
+        } else if let Some(caps) = PYTHON_FRAME.captures(line) {
+            last_frame = Some(caps);
+        } else if let Some(caps) = STACK_FRAME.captures(line) {
+            if let Some(mut diagnostic) = pending.take() {
+                locate(&mut diagnostic, &caps);
+                diagnostics.push(diagnostic);
+            }
+        } else if let Some(caps) = EXCEPTION.captures(line) {
+            diagnostics.extend(pending.take());
+            let mut diagnostic = diagnostic(stage, None, &caps["msg"]);
+            match last_frame.take() {
+                Some(frame) => {
+                    locate(&mut diagnostic, &frame);
+                    diagnostics.push(diagnostic);
+                }
+                None => pending = Some(diagnostic),
+            }
+        }
+    }
+    diagnostics.extend(pending);
+    diagnostics
+}
+
+fn diagnostic(stage: DiagnosticStage, severity: Option<&str>, message: &str) -> Diagnostic {
+    let severity = match severity {
+        Some("warning") => DiagnosticSeverity::Warning,
+        Some("note" | "info") => DiagnosticSeverity::Note,
+        _ => DiagnosticSeverity::Error,
+    };
+    Diagnostic {
+        stage,
+        severity,
+        file: None,
+        line: None,
+        column: None,
+        message: message.trim().to_string(),
+    }
+}
+
+fn locate(diagnostic: &mut Diagnostic, caps: &Captures) {
+    diagnostic.file = caps.name("file").map(|m| display_path(m.as_str()));
+    diagnostic.line = caps.name("line").and_then(|m| m.as_str().parse().ok());
+    diagnostic.column = caps.name("col").and_then(|m| m.as_str().parse().ok());
+}
+
+// Reports paths relative to the working directory: sandbox mount points are
+// stripped and other absolute paths are reduced to the file name.
+fn display_path(path: &str) -> String {
+    let path = path.trim_start_matches("./");
+    for root in ["/workspace/", "/build/"] {
+        if let Some(relative) = path.strip_prefix(root) {
+            return relative.to_string();
+        }
+    }
+    match path.rsplit_once('/') {
+        Some((_, name)) if path.starts_with('/') => name.to_string(),
+        _ => path.to_string(),
+    }
+}
+
+// Keeps the head and tail of long logs, where errors usually are.
+fn compact_log(text: &mut String) {
+    if text.len() <= AGENT_LOG_BYTES {
+        return;
+    }
+    let mut head = AGENT_LOG_BYTES / 2;
+    while !text.is_char_boundary(head) {
+        head -= 1;
+    }
+    let mut tail = text.len() - AGENT_LOG_BYTES / 2;
+    while !text.is_char_boundary(tail) {
+        tail += 1;
+    }
+    *text = format!(
+        "{}\n... [{} bytes omitted] ...\n{}",
+        &text[..head],
+        tail - head,
+        &text[tail..]
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compact_log, parse};
+    use crate::engine::models::{DiagnosticSeverity, DiagnosticStage};
+
+    fn locations(stage: DiagnosticStage, text: &str) -> Vec<(String, Option<u32>, String)> {
+        parse(stage, text)
+            .into_iter()
+            .map(|d| (d.file.unwrap_or_default(), d.line, d.message))
+            .collect()
+    }
+
+    #[test]
+    fn parses_compiler_diagnostics() {
+        let gcc =
+            "main.c: In function 'main':\nmain.c:3:5: error: 'x' undeclared\n    3 |     x = 1;\n";
+        assert_eq!(
+            locations(DiagnosticStage::Compile, gcc),
+            [("main.c".to_string(), Some(3), "'x' undeclared".to_string())]
+        );
+
+        let rustc = "error[E0425]: cannot find value `x` in this scope\n --> /workspace/main.rs:2:5\n  |\nerror: aborting due to 1 previous error\n";
+        assert_eq!(
+            locations(DiagnosticStage::Compile, rustc),
+            [(
+                "main.rs".to_string(),
+                Some(2),
+                "cannot find value `x` in this scope".to_string()
+            )]
+        );
+
+        let tsc = "main.ts(4,7): error TS2322: Type 'string' is not assignable to type 'number'.\n";
+        let diagnostics = parse(DiagnosticStage::Compile, tsc);
+        assert_eq!(diagnostics[0].column, Some(7));
+        assert_eq!(diagnostics[0].severity, DiagnosticSeverity::Error);
+
+        let javac = "Main.java:5: warning: [removal] x\n";
+        assert_eq!(
+            parse(DiagnosticStage::Compile, javac)[0].severity,
+            DiagnosticSeverity::Warning
+        );
+    }
+
+    #[test]
+    fn parses_runtime_errors() {
+        let python = "Traceback (most recent call last):\n  File \"/tmp/run/main.py\", line 4, in <module>\n    raise ValueError('bad')\nValueError: bad\n";
+        assert_eq!(
+            locations(DiagnosticStage::Runtime, python),
+            [(
+                "main.py".to_string(),
+                Some(4),
+                "ValueError: bad".to_string()
+            )]
+        );
+
+        let java = "Exception in thread \"main\" java.lang.ArithmeticException: / by zero\n\tat Main.main(Main.java:3)\n";
+        assert_eq!(
+            locations(DiagnosticStage::Runtime, java),
+            [(
+                "Main.java".to_string(),
+                Some(3),
+                "java.lang.ArithmeticException: / by zero".to_string()
+            )]
+        );
+
+        let node = "/workspace/main.js:1\nthrow new TypeError('boom')\n^\n\nTypeError: boom\n    at Object.<anonymous> (/workspace/main.js:1:7)\n    at Module._compile (node:internal/modules/cjs/loader:1:1)\n";
+        assert_eq!(
+            locations(DiagnosticStage::Runtime, node),
+            [(
+                "main.js".to_string(),
+                Some(1),
+                "TypeError: boom".to_string()
+            )]
+        );
+
+        let rust = "thread 'main' panicked at src/main.rs:2:5:\nindex out of bounds\nnote: run with `RUST_BACKTRACE=1`\n";
+        assert_eq!(
+            locations(DiagnosticStage::Runtime, rust)[0],
+            (
+                "src/main.rs".to_string(),
+                Some(2),
+                "index out of bounds".to_string()
+            )
+        );
+
+        let go = "panic: runtime error: index out of range [5] with length 3\n\ngoroutine 1 [running]:\nmain.main()\n\t/tmp/run/main.go:8 +0x1d\nexit status 2\n";
+        assert_eq!(
+            locations(DiagnosticStage::Runtime, go),
+            [(
+                "main.go".to_string(),
+                Some(8),
+                "runtime error: index out of range [5] with length 3".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn parses_linter_findings() {
+        let ruff = "main.py:1:8: F401 [*] `os` imported but unused\nFound 1 error.\n";
+        assert_eq!(
+            locations(DiagnosticStage::Lint, ruff),
+            [(
+                "main.py".to_string(),
+                Some(1),
+                "F401 [*] `os` imported but unused".to_string()
+            )]
+        );
+
+        let vet = "# command-line-arguments\n./main.go:6:2: fmt.Printf format %d has arg s of wrong type string\n";
+        assert_eq!(locations(DiagnosticStage::Lint, vet)[0].1, Some(6));
+    }
+
+    #[test]
+    fn compacts_long_logs() {
+        let mut log = format!("start{}end", "x".repeat(10_000));
+        compact_log(&mut log);
+        assert!(log.starts_with("start"));
+        assert!(log.ends_with("end"));
+        assert!(log.contains("bytes omitted"));
+        assert!(log.len() < 4200);
+    }
+}
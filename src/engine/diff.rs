@@ -0,0 +1,194 @@
+use std::fmt::Write;
+
+use crate::engine::models::OutputDiff;
+
+// Unchanged lines shown around each change.
+const CONTEXT: usize = 3;
+// Past this many line pairs the changed middle is shown as wholly replaced
+// rather than aligned.
+const MAX_ALIGN_CELLS: usize = 1_000_000;
+const MAX_UNIFIED_BYTES: usize = 8 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Equal,
+    Delete,
+    Insert,
+}
+
+// `None` when the two are equal.
+pub fn diff(expected: &str, actual: &str) -> Option<OutputDiff> {
+    if expected == actual {
+        return None;
+    }
+    let (line, column) = first_divergence(expected, actual);
+    let old: Vec<&str> = expected.lines().collect();
+    let new: Vec<&str> = actual.lines().collect();
+    let ops = align(&old, &new);
+    let (unified, truncated) = unified(&ops, &old, &new);
+    Some(OutputDiff {
+        line,
+        column,
+        unified,
+        truncated,
+    })
+}
+
+// 1-based line and column (in characters) of the first difference; one past
+// the end of the shorter text when it is a prefix of the other.
+fn first_divergence(expected: &str, actual: &str) -> (usize, usize) {
+    let (mut line, mut column) = (1, 1);
+    for (want, got) in expected.chars().zip(actual.chars()) {
+        if want != got {
+            break;
+        }
+        if want == '\n' {
+            line += 1;
+            column = 1;
+        } else {
+            column += 1;
+        }
+    }
+    (line, column)
+}
+
+// Lines shared at both ends are kept as they are; the rest is aligned on
+// their longest common subsequence.
+fn align(old: &[&str], new: &[&str]) -> Vec<Op> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+
+    let mut ops = vec![Op::Equal; prefix];
+    let (n, m) = (old_mid.len(), new_mid.len());
+    if n.saturating_mul(m) > MAX_ALIGN_CELLS {
+        ops.extend(std::iter::repeat_n(Op::Delete, n));
+        ops.extend(std::iter::repeat_n(Op::Insert, m));
+    } else {
+        // lengths[i * (m + 1) + j]: LCS of old_mid[i..] and new_mid[j..].
+        let mut lengths = vec![0u32; (n + 1) * (m + 1)];
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                lengths[i * (m + 1) + j] = if old_mid[i] == new_mid[j] {
+                    lengths[(i + 1) * (m + 1) + j + 1] + 1
+                } else {
+                    lengths[(i + 1) * (m + 1) + j].max(lengths[i * (m + 1) + j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < n || j < m {
+            if i < n && j < m && old_mid[i] == new_mid[j] {
+                ops.push(Op::Equal);
+                i += 1;
+                j += 1;
+            } else if j == m
+                || (i < n && lengths[(i + 1) * (m + 1) + j] >= lengths[i * (m + 1) + j + 1])
+            {
+                ops.push(Op::Delete);
+                i += 1;
+            } else {
+                ops.push(Op::Insert);
+                j += 1;
+            }
+        }
+    }
+    ops.extend(std::iter::repeat_n(Op::Equal, suffix));
+    ops
+}
+
+// Expected lines are `-`, actual ones `+`. Cut at MAX_UNIFIED_BYTES.
+fn unified(ops: &[Op], old: &[&str], new: &[&str]) -> (String, bool) {
+    // Line positions in each text before every op.
+    let mut positions = Vec::with_capacity(ops.len() + 1);
+    let (mut i, mut j) = (0, 0);
+    for op in ops {
+        positions.push((i, j));
+        match op {
+            Op::Equal => (i, j) = (i + 1, j + 1),
+            Op::Delete => i += 1,
+            Op::Insert => j += 1,
+        }
+    }
+    positions.push((i, j));
+
+    let changes: Vec<usize> = (0..ops.len()).filter(|&k| ops[k] != Op::Equal).collect();
+    let mut out = String::from("--- expected\n+++ actual\n");
+    let mut start = 0;
+    while start < changes.len() {
+        let mut end = start;
+        while end + 1 < changes.len() && changes[end + 1] - changes[end] <= 2 * CONTEXT + 1 {
+            end += 1;
+        }
+        let from = changes[start].saturating_sub(CONTEXT);
+        let to = (changes[end] + CONTEXT + 1).min(ops.len());
+        let (old_from, new_from) = positions[from];
+        let (old_to, new_to) = positions[to];
+        let range = |from: usize, count: usize| {
+            if count == 0 {
+                format!("{from},0")
+            } else {
+                format!("{},{count}", from + 1)
+            }
+        };
+        let _ = writeln!(
+            out,
+            "@@ -{} +{} @@",
+            range(old_from, old_to - old_from),
+            range(new_from, new_to - new_from)
+        );
+        for k in from..to {
+            let (i, j) = positions[k];
+            let _ = match ops[k] {
+                Op::Equal => writeln!(out, " {}", old[i]),
+                Op::Delete => writeln!(out, "-{}", old[i]),
+                Op::Insert => writeln!(out, "+{}", new[j]),
+            };
+        }
+        if out.len() > MAX_UNIFIED_BYTES {
+            let mut cut = MAX_UNIFIED_BYTES;
+            while !out.is_char_boundary(cut) {
+                cut -= 1;
+            }
+            out.truncate(cut);
+            return (out, true);
+        }
+        start = end + 1;
+    }
+    (out, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::diff;
+
+    #[test]
+    fn reports_first_divergence_and_unified_hunks() {
+        assert!(diff("same", "same").is_none());
+
+        let expected = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12";
+        let actual = "1\n2\n3\n4\nfive\n6\n7\n8\n9\n10\n11\n12\n13";
+        let diff = diff(expected, actual).unwrap();
+        assert_eq!((diff.line, diff.column), (5, 1));
+        assert!(!diff.truncated);
+        assert_eq!(
+            diff.unified,
+            "--- expected\n+++ actual\n\
+             @@ -2,7 +2,7 @@\n 2\n 3\n 4\n-5\n+five\n 6\n 7\n 8\n\
+             @@ -10,3 +10,4 @@\n 10\n 11\n 12\n+13\n"
+        );
+
+        let prefix = super::diff("ab", "abc").unwrap();
+        assert_eq!((prefix.line, prefix.column), (1, 3));
+        assert_eq!(
+            prefix.unified,
+            "--- expected\n+++ actual\n@@ -1,1 +1,1 @@\n-ab\n+abc\n"
+        );
+    }
+}
@@ -0,0 +1,165 @@
+use std::sync::Arc;
+
+use aes_gcm::{
+    Aes256Gcm, Key, KeyInit, Nonce,
+    aead::{Aead, AeadCore, OsRng, Payload},
+};
+use anyhow::Context;
+use base64::{Engine, prelude::BASE64_STANDARD};
+
+use crate::engine::config::EngineConfig;
+
+const PREFIX: &str = "enc1.";
+const NONCE_LEN: usize = 12;
+
+// Envelope encryption for execution records and jobs at rest: each payload
+// is sealed with a fresh AES-256-GCM data key, which is itself sealed with
+// the active key-encryption key. Sealed payloads read
+// `enc1.<key id>.<wrapped data key>.<ciphertext>`; the key id is bound to
+// both as associated data. Older keys stay listed to open what they sealed.
+#[derive(Clone, Default)]
+pub struct RecordCipher {
+    // The first key seals; empty leaves payloads in plaintext.
+    keys: Arc<Vec<(String, Aes256Gcm)>>,
+}
+
+impl RecordCipher {
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    // Keys from `ENCRYPTION_KEYS_FILE` (e.g. written by a KMS agent) take
+    // precedence over `ENCRYPTION_KEYS`.
+    pub fn from_config(config: &EngineConfig) -> anyhow::Result<Self> {
+        let spec = match &config.encryption_keys_file {
+            Some(path) => std::fs::read_to_string(path)
+                .with_context(|| format!("failed to read {}", path.display()))?,
+            None => config.encryption_keys.clone(),
+        };
+        Self::parse(&spec)
+    }
+
+    // `id:base64-key,...`, with 32-byte keys and the active key first.
+    fn parse(spec: &str) -> anyhow::Result<Self> {
+        let mut keys = Vec::new();
+        for entry in spec.split([',', '\n']).map(str::trim) {
+            if entry.is_empty() {
+                continue;
+            }
+            let (id, key) = entry
+                .split_once(':')
+                .context("encryption keys must be id:base64-key")?;
+            anyhow::ensure!(
+                !id.is_empty()
+                    && id
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'),
+                "invalid encryption key id {id:?}"
+            );
+            let key = BASE64_STANDARD
+                .decode(key.trim())
+                .with_context(|| format!("encryption key {id} is not base64"))?;
+            anyhow::ensure!(key.len() == 32, "encryption key {id} must be 32 bytes");
+            keys.push((
+                id.to_string(),
+                Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)),
+            ));
+        }
+        Ok(Self {
+            keys: Arc::new(keys),
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.keys.is_empty()
+    }
+
+    pub fn seal(&self, plaintext: &str) -> anyhow::Result<String> {
+        let Some((id, kek)) = self.keys.first() else {
+            return Ok(plaintext.to_string());
+        };
+        let data_key = Aes256Gcm::generate_key(OsRng);
+        let wrapped = encrypt(kek, &data_key, id)?;
+        let sealed = encrypt(&Aes256Gcm::new(&data_key), plaintext.as_bytes(), id)?;
+        Ok(format!(
+            "{PREFIX}{id}.{}.{}",
+            BASE64_STANDARD.encode(wrapped),
+            BASE64_STANDARD.encode(sealed)
+        ))
+    }
+
+    // Plaintext payloads, written before encryption was enabled, pass through.
+    pub fn open(&self, payload: &str) -> anyhow::Result<String> {
+        let Some(sealed) = payload.strip_prefix(PREFIX) else {
+            return Ok(payload.to_string());
+        };
+        let mut parts = sealed.splitn(3, '.');
+        let (Some(id), Some(wrapped), Some(data)) = (parts.next(), parts.next(), parts.next())
+        else {
+            anyhow::bail!("malformed sealed payload");
+        };
+        let (_, kek) = self
+            .keys
+            .iter()
+            .find(|(known, _)| known == id)
+            .with_context(|| format!("unknown encryption key {id}"))?;
+        let data_key = decrypt(kek, &BASE64_STANDARD.decode(wrapped)?, id)?;
+        anyhow::ensure!(data_key.len() == 32, "malformed data key");
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&data_key));
+        let plaintext = decrypt(&cipher, &BASE64_STANDARD.decode(data)?, id)?;
+        String::from_utf8(plaintext).context("sealed payload is not UTF-8")
+    }
+}
+
+fn encrypt(cipher: &Aes256Gcm, plaintext: &[u8], key_id: &str) -> anyhow::Result<Vec<u8>> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let payload = Payload {
+        msg: plaintext,
+        aad: key_id.as_bytes(),
+    };
+    let sealed = cipher
+        .encrypt(&nonce, payload)
+        .map_err(|_| anyhow::anyhow!("encryption failed"))?;
+    Ok([nonce.as_slice(), &sealed].concat())
+}
+
+fn decrypt(cipher: &Aes256Gcm, sealed: &[u8], key_id: &str) -> anyhow::Result<Vec<u8>> {
+    anyhow::ensure!(sealed.len() > NONCE_LEN, "malformed sealed payload");
+    let (nonce, sealed) = sealed.split_at(NONCE_LEN);
+    let payload = Payload {
+        msg: sealed,
+        aad: key_id.as_bytes(),
+    };
+    cipher
+        .decrypt(Nonce::from_slice(nonce), payload)
+        .map_err(|_| anyhow::anyhow!("decryption failed with key {key_id}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{Engine, prelude::BASE64_STANDARD};
+
+    use super::RecordCipher;
+
+    fn key(byte: u8) -> String {
+        BASE64_STANDARD.encode([byte; 32])
+    }
+
+    #[test]
+    fn seals_and_opens_across_key_rotation() {
+        let old = RecordCipher::parse(&format!("k1:{}", key(1))).unwrap();
+        let sealed = old.seal(r#"{"code":"print(1)"}"#).unwrap();
+        assert!(sealed.starts_with("enc1.k1."));
+        assert!(!sealed.contains("print"));
+
+        let rotated = RecordCipher::parse(&format!("k2:{},k1:{}", key(2), key(1))).unwrap();
+        assert_eq!(rotated.open(&sealed).unwrap(), r#"{"code":"print(1)"}"#);
+        assert!(rotated.seal("x").unwrap().starts_with("enc1.k2."));
+        assert_eq!(rotated.open("{}").unwrap(), "{}");
+
+        let wrong = RecordCipher::parse(&format!("k1:{}", key(3))).unwrap();
+        assert!(wrong.open(&sealed).is_err());
+        assert!(RecordCipher::parse(&format!("k1:{}", &key(2)[..8])).is_err());
+        assert_eq!(RecordCipher::disabled().seal("{}").unwrap(), "{}");
+    }
+}
@@ -2,10 +2,11 @@ use std::fmt::{Display, Formatter};
 
 use axum::{
     Json,
-    http::StatusCode,
+    http::{HeaderName, StatusCode, header},
     response::{IntoResponse, Response},
 };
 use serde::Serialize;
+use utoipa::ToSchema;
 
 #[derive(Debug)]
 pub enum EngineError {
@@ -13,14 +14,26 @@ pub enum EngineError {
     Forbidden,
     InvalidRequest(String),
     RateLimited,
-    QueueFull,
+    // `window` is `daily` or `monthly`.
+    QuotaExceeded {
+        window: &'static str,
+        limit: u64,
+        reset_secs: u64,
+    },
+    QueueFull {
+        retry_after_secs: u64,
+    },
     NotFound,
+    SessionNotFound,
+    TemplateNotFound,
+    SecretNotFound,
+    Conflict(String),
     Internal(String),
 }
 
-#[derive(Debug, Serialize)]
-struct ErrorBody {
-    error: String,
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct ErrorBody {
+    pub error: String,
 }
 
 impl Display for EngineError {
@@ -30,8 +43,15 @@ impl Display for EngineError {
             EngineError::Forbidden => write!(f, "forbidden"),
             EngineError::InvalidRequest(msg) => write!(f, "invalid request: {msg}"),
             EngineError::RateLimited => write!(f, "rate limit exceeded"),
-            EngineError::QueueFull => write!(f, "queue is full"),
+            EngineError::QuotaExceeded { window, limit, .. } => {
+                write!(f, "{window} quota of {limit} executions exceeded")
+            }
+            EngineError::QueueFull { .. } => write!(f, "queue is full"),
             EngineError::NotFound => write!(f, "execution not found"),
+            EngineError::SessionNotFound => write!(f, "session not found"),
+            EngineError::TemplateNotFound => write!(f, "template not found"),
+            EngineError::SecretNotFound => write!(f, "secret not found"),
+            EngineError::Conflict(msg) => write!(f, "conflict: {msg}"),
             EngineError::Internal(msg) => write!(f, "internal error: {msg}"),
         }
     }
@@ -45,14 +65,50 @@ impl IntoResponse for EngineError {
             EngineError::Unauthorized => StatusCode::UNAUTHORIZED,
             EngineError::Forbidden => StatusCode::FORBIDDEN,
             EngineError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
-            EngineError::RateLimited => StatusCode::TOO_MANY_REQUESTS,
-            EngineError::QueueFull => StatusCode::SERVICE_UNAVAILABLE,
-            EngineError::NotFound => StatusCode::NOT_FOUND,
+            EngineError::RateLimited | EngineError::QuotaExceeded { .. } => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
+            EngineError::QueueFull { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            EngineError::NotFound
+            | EngineError::SessionNotFound
+            | EngineError::TemplateNotFound
+            | EngineError::SecretNotFound => StatusCode::NOT_FOUND,
+            EngineError::Conflict(_) => StatusCode::CONFLICT,
             EngineError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
         };
         let body = Json(ErrorBody {
             error: self.to_string(),
         });
+        if let EngineError::QuotaExceeded {
+            limit, reset_secs, ..
+        } = self
+        {
+            return (
+                status,
+                [
+                    (header::RETRY_AFTER, reset_secs.to_string()),
+                    (HeaderName::from_static("x-quota-limit"), limit.to_string()),
+                    (
+                        HeaderName::from_static("x-quota-remaining"),
+                        "0".to_string(),
+                    ),
+                    (
+                        HeaderName::from_static("x-quota-reset"),
+                        reset_secs.to_string(),
+                    ),
+                ],
+                body,
+            )
+                .into_response();
+        }
+        if let EngineError::QueueFull { retry_after_secs } = self {
+            return (
+                status,
+                [(header::RETRY_AFTER, retry_after_secs.to_string())],
+                body,
+            )
+                .into_response();
+        }
         (status, body).into_response()
     }
 }
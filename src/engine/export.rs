@@ -0,0 +1,357 @@
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::Context;
+use prost::Message;
+use tokio::net::UdpSocket;
+
+use crate::engine::{config::EngineConfig, store::now_ms};
+
+// StatsD datagrams are kept under a typical MTU.
+const STATSD_DATAGRAM_BYTES: usize = 1400;
+
+// One sample of the /metrics text.
+#[derive(Debug, Clone, PartialEq)]
+struct Sample {
+    name: String,
+    labels: Vec<(String, String)>,
+    value: f64,
+    // Of a counter, or a histogram's sum or count: StatsD gets the increase.
+    counter: bool,
+}
+
+// Pushes what /metrics serves, for engines too short-lived to be scraped:
+// to a StatsD (or Datadog) agent and/or a Prometheus remote-write endpoint,
+// every METRICS_PUSH_INTERVAL_MS.
+pub async fn spawn_metrics_export(
+    config: &EngineConfig,
+    render: impl Fn() -> String + Send + 'static,
+) -> anyhow::Result<()> {
+    let mut statsd = match &config.statsd_addr {
+        Some(addr) => {
+            let socket = UdpSocket::bind("0.0.0.0:0").await?;
+            socket
+                .connect(addr.as_str())
+                .await
+                .with_context(|| format!("cannot reach STATSD_ADDR {addr}"))?;
+            Some(Statsd {
+                socket,
+                prefix: config.statsd_prefix.clone(),
+                previous: HashMap::new(),
+            })
+        }
+        None => None,
+    };
+    let remote_write = config
+        .metrics_remote_write_url
+        .clone()
+        .map(|url| RemoteWrite {
+            url,
+            token: config.metrics_remote_write_token.clone(),
+            instance: config.metrics_instance.clone(),
+            client: reqwest::Client::new(),
+        });
+    if statsd.is_none() && remote_write.is_none() {
+        return Ok(());
+    }
+    let interval = Duration::from_millis(config.metrics_push_interval_ms.max(1000));
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            let samples = parse(&render());
+            if let Some(statsd) = &mut statsd
+                && let Err(err) = statsd.push(&samples).await
+            {
+                tracing::warn!(error = %err, "statsd metrics export failed");
+            }
+            if let Some(remote_write) = &remote_write
+                && let Err(err) = remote_write.push(&samples).await
+            {
+                tracing::warn!(error = %format!("{err:#}"), "remote-write metrics export failed");
+            }
+        }
+    });
+    Ok(())
+}
+
+// Reads the Prometheus text format /metrics renders. Histogram buckets are
+// left out: neither StatsD nor a per-push sample needs them, and the sum and
+// count still give the mean.
+fn parse(text: &str) -> Vec<Sample> {
+    let mut counters: HashMap<&str, bool> = HashMap::new();
+    let mut samples = Vec::new();
+    for line in text.lines() {
+        if let Some(declaration) = line.strip_prefix("# TYPE ") {
+            if let Some((name, kind)) = declaration.split_once(' ') {
+                counters.insert(name, kind != "gauge");
+            }
+            continue;
+        }
+        if line.starts_with('#') || line.is_empty() {
+            continue;
+        }
+        let Some((series, value)) = line.rsplit_once(' ') else {
+            continue;
+        };
+        let Ok(value) = value.parse::<f64>() else {
+            continue;
+        };
+        let (name, labels) = match series.split_once('{') {
+            Some((name, labels)) => (name, parse_labels(labels.trim_end_matches('}'))),
+            None => (series, Vec::new()),
+        };
+        if name.ends_with("_bucket") {
+            continue;
+        }
+        let family = name
+            .strip_suffix("_sum")
+            .or_else(|| name.strip_suffix("_count"))
+            .filter(|family| counters.contains_key(family))
+            .unwrap_or(name);
+        samples.push(Sample {
+            name: name.to_string(),
+            labels,
+            value,
+            counter: counters.get(family).copied().unwrap_or(false),
+        });
+    }
+    samples
+}
+
+// `a="x",b="y \"quoted\""`, as `escape_label` writes them.
+fn parse_labels(input: &str) -> Vec<(String, String)> {
+    let mut labels = Vec::new();
+    let mut rest = input;
+    while let Some((name, after)) = rest.split_once("=\"") {
+        let mut value = String::new();
+        let mut chars = after.char_indices();
+        let mut end = after.len();
+        while let Some((index, c)) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some((_, 'n')) => value.push('\n'),
+                    Some((_, escaped)) => value.push(escaped),
+                    None => {}
+                },
+                '"' => {
+                    end = index + 1;
+                    break;
+                }
+                c => value.push(c),
+            }
+        }
+        labels.push((name.trim_start_matches(',').trim().to_string(), value));
+        rest = &after[end..];
+    }
+    labels
+}
+
+struct Statsd {
+    socket: UdpSocket,
+    prefix: String,
+    // Counter values last pushed, to send only the increase.
+    previous: HashMap<String, f64>,
+}
+
+impl Statsd {
+    // Datadog-style `|#name:value` tags carry the labels.
+    async fn push(&mut self, samples: &[Sample]) -> std::io::Result<()> {
+        let mut datagram = String::new();
+        for sample in samples {
+            let mut line = format!("{}{}:", self.prefix, sample.name);
+            if sample.counter {
+                let key = format!("{}{:?}", sample.name, sample.labels);
+                let previous = self.previous.insert(key, sample.value).unwrap_or(0.0);
+                // A restart resets the counter; everything since is new.
+                let increase = if sample.value >= previous {
+                    sample.value - previous
+                } else {
+                    sample.value
+                };
+                if increase == 0.0 {
+                    continue;
+                }
+                line.push_str(&format!("{increase}|c"));
+            } else {
+                line.push_str(&format!("{}|g", sample.value));
+            }
+            if !sample.labels.is_empty() {
+                let tags: Vec<String> = sample
+                    .labels
+                    .iter()
+                    .map(|(name, value)| format!("{name}:{}", value.replace([',', '|', '\n'], "_")))
+                    .collect();
+                line.push_str(&format!("|#{}", tags.join(",")));
+            }
+            if !datagram.is_empty() && datagram.len() + line.len() + 1 > STATSD_DATAGRAM_BYTES {
+                self.socket.send(datagram.as_bytes()).await?;
+                datagram.clear();
+            }
+            if !datagram.is_empty() {
+                datagram.push('\n');
+            }
+            datagram.push_str(&line);
+        }
+        if !datagram.is_empty() {
+            self.socket.send(datagram.as_bytes()).await?;
+        }
+        Ok(())
+    }
+}
+
+struct RemoteWrite {
+    url: String,
+    token: Option<String>,
+    // Added as the `instance` label, which scraping would have set.
+    instance: String,
+    client: reqwest::Client,
+}
+
+// The remote-write 1.0 protobuf messages.
+#[derive(Clone, PartialEq, prost::Message)]
+struct WriteRequest {
+    #[prost(message, repeated, tag = "1")]
+    timeseries: Vec<TimeSeries>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct TimeSeries {
+    #[prost(message, repeated, tag = "1")]
+    labels: Vec<Label>,
+    #[prost(message, repeated, tag = "2")]
+    samples: Vec<RemoteSample>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct Label {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(string, tag = "2")]
+    value: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct RemoteSample {
+    #[prost(double, tag = "1")]
+    value: f64,
+    #[prost(int64, tag = "2")]
+    timestamp: i64,
+}
+
+impl RemoteWrite {
+    async fn push(&self, samples: &[Sample]) -> anyhow::Result<()> {
+        let write = write_request(samples, &self.instance, now_ms() as i64);
+        let body = snap::raw::Encoder::new().compress_vec(&write.encode_to_vec())?;
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header("content-encoding", "snappy")
+            .header("content-type", "application/x-protobuf")
+            .header("x-prometheus-remote-write-version", "0.1.0")
+            .timeout(Duration::from_secs(10))
+            .body(body);
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            anyhow::bail!("{} answered {}", self.url, response.status());
+        }
+        Ok(())
+    }
+}
+
+// Labels are sorted by name, as receivers require.
+fn write_request(samples: &[Sample], instance: &str, timestamp_ms: i64) -> WriteRequest {
+    let timeseries = samples
+        .iter()
+        .map(|sample| {
+            let mut labels: Vec<Label> = sample
+                .labels
+                .iter()
+                .map(|(name, value)| Label {
+                    name: name.clone(),
+                    value: value.clone(),
+                })
+                .chain([
+                    Label {
+                        name: "__name__".to_string(),
+                        value: sample.name.clone(),
+                    },
+                    Label {
+                        name: "instance".to_string(),
+                        value: instance.to_string(),
+                    },
+                ])
+                .collect();
+            labels.sort_by(|a, b| a.name.cmp(&b.name));
+            TimeSeries {
+                labels,
+                samples: vec![RemoteSample {
+                    value: sample.value,
+                    timestamp: timestamp_ms,
+                }],
+            }
+        })
+        .collect();
+    WriteRequest { timeseries }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Statsd, parse, write_request};
+
+    #[tokio::test]
+    async fn exports_samples_to_statsd_and_remote_write() {
+        let text = "# HELP execution_submitted_total Executions.\n\
+            # TYPE execution_submitted_total counter\n\
+            execution_submitted_total 5\n\
+            # TYPE execution_workers gauge\n\
+            execution_workers{pool=\"default\",state=\"idle\"} 3\n\
+            # TYPE execution_duration_seconds histogram\n\
+            execution_duration_seconds_bucket{language=\"python\",le=\"0.5\"} 1\n\
+            execution_duration_seconds_sum{language=\"py \\\"3\\\"\"} 0.25\n\
+            execution_duration_seconds_count{language=\"py \\\"3\\\"\"} 1\n";
+        let samples = parse(text);
+        assert_eq!(samples.len(), 4);
+        assert!(samples[0].counter && !samples[1].counter && samples[3].counter);
+        assert_eq!(
+            samples[2].labels,
+            vec![("language".to_string(), "py \"3\"".to_string())]
+        );
+
+        let receiver = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket
+            .connect(receiver.local_addr().unwrap())
+            .await
+            .unwrap();
+        let mut statsd = Statsd {
+            socket,
+            prefix: "engine.".to_string(),
+            previous: Default::default(),
+        };
+        let mut buffer = [0u8; 2048];
+        statsd.push(&samples).await.unwrap();
+        let len = receiver.recv(&mut buffer).await.unwrap();
+        let datagram = String::from_utf8_lossy(&buffer[..len]).to_string();
+        assert!(datagram.starts_with("engine.execution_submitted_total:5|c\n"));
+        assert!(datagram.contains("engine.execution_workers:3|g|#pool:default,state:idle"));
+        // Unchanged counters are not sent again.
+        statsd.push(&samples).await.unwrap();
+        let len = receiver.recv(&mut buffer).await.unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&buffer[..len]),
+            "engine.execution_workers:3|g|#pool:default,state:idle"
+        );
+
+        let request = write_request(&samples[1..2], "node-1", 42);
+        let labels: Vec<&str> = request.timeseries[0]
+            .labels
+            .iter()
+            .map(|label| label.name.as_str())
+            .collect();
+        assert_eq!(labels, ["__name__", "instance", "pool", "state"]);
+        assert_eq!(request.timeseries[0].samples[0].timestamp, 42);
+    }
+}
@@ -0,0 +1,264 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::Duration,
+};
+
+use base64::{Engine, prelude::BASE64_STANDARD};
+use tokio::process::Command;
+
+use crate::engine::{
+    config::EngineConfig,
+    models::{ExecutionRequest, FileEncoding, GitSource, InputFile},
+    sandbox::{LanguageSpec, request_error},
+};
+
+// Caps on a checkout; the fetch runs on the engine host, so the sandbox
+// itself never gets network access for it.
+#[derive(Debug, Clone, Copy)]
+pub struct GitLimits {
+    pub timeout: Duration,
+    pub max_bytes: u64,
+    pub max_files: usize,
+}
+
+impl GitLimits {
+    pub fn from_config(config: &EngineConfig) -> Self {
+        Self {
+            timeout: Duration::from_millis(config.git_fetch_timeout_ms.max(1)),
+            max_bytes: config.git_max_bytes,
+            max_files: config.git_max_files,
+        }
+    }
+}
+
+// Replaces `request.git` with the checked-out tree: the entrypoint becomes
+// the program source, the language's dependency manifest (unless the
+// request has one) its dependencies, and every other file an input file
+// ahead of the request's own. Any failure is the request's.
+pub async fn checkout(
+    request: &mut ExecutionRequest,
+    id: uuid::Uuid,
+    limits: GitLimits,
+) -> anyhow::Result<()> {
+    let Some(source) = request.git.take() else {
+        return Ok(());
+    };
+    let dir = std::env::temp_dir().join(format!("git-{id}"));
+    let result = async {
+        fetch(&source, &dir, limits.timeout).await?;
+        let root = match &source.subdir {
+            Some(subdir) => dir.join(subdir),
+            None => dir.clone(),
+        };
+        collect(request, &source, &root, limits).await
+    }
+    .await;
+    let _ = tokio::fs::remove_dir_all(&dir).await;
+    result
+}
+
+async fn fetch(source: &GitSource, dir: &Path, timeout: Duration) -> anyhow::Result<()> {
+    let reference = source.reference.as_deref().unwrap_or("HEAD");
+    let steps: [&[&str]; 3] = [
+        &["init", "--quiet", "--template="],
+        &[
+            "fetch",
+            "--quiet",
+            "--depth",
+            "1",
+            "--no-tags",
+            "--no-recurse-submodules",
+            &source.url,
+            reference,
+        ],
+        &["checkout", "--quiet", "FETCH_HEAD"],
+    ];
+    tokio::fs::create_dir_all(dir).await?;
+    let deadline = tokio::time::Instant::now() + timeout;
+    for args in steps {
+        let output = Command::new("git")
+            .args([
+                "-c",
+                "protocol.allow=never",
+                "-c",
+                "protocol.https.allow=always",
+            ])
+            .args(["-c", "core.symlinks=false"])
+            .args(args)
+            .current_dir(dir)
+            .env("GIT_TERMINAL_PROMPT", "0")
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env("GIT_CONFIG_GLOBAL", "/dev/null")
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output();
+        let output = tokio::time::timeout_at(deadline, output)
+            .await
+            .map_err(|_| {
+                request_error(format!(
+                    "git fetch timed out after {} ms",
+                    timeout.as_millis()
+                ))
+            })??;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(request_error(format!(
+                "git {} failed: {}",
+                args[0],
+                stderr.trim()
+            )));
+        }
+    }
+    Ok(())
+}
+
+async fn collect(
+    request: &mut ExecutionRequest,
+    source: &GitSource,
+    root: &Path,
+    limits: GitLimits,
+) -> anyhow::Result<()> {
+    let spec = LanguageSpec::for_language(&request.language);
+    let reserved = spec.reserved_names();
+    let manifest = spec.dependencies.as_ref().map(|deps| deps.manifest);
+    let mut files = Vec::new();
+    let mut total = 0u64;
+    let mut code = None;
+    let mut pending = vec![PathBuf::new()];
+    while let Some(relative) = pending.pop() {
+        let mut entries = tokio::fs::read_dir(root.join(&relative))
+            .await
+            .map_err(|_| request_error("git subdir is not a directory in the repository"))?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = relative.join(entry.file_name());
+            // Symlinks are never followed out of the checkout.
+            let kind = entry.file_type().await?;
+            if kind.is_dir() {
+                if entry.file_name() != ".git" {
+                    pending.push(path);
+                }
+                continue;
+            }
+            if !kind.is_file() {
+                continue;
+            }
+            let bytes = tokio::fs::read(entry.path()).await?;
+            total += bytes.len() as u64;
+            if total > limits.max_bytes {
+                return Err(request_error(format!(
+                    "repository is larger than {} bytes",
+                    limits.max_bytes
+                )));
+            }
+            if path == Path::new(&source.entrypoint) {
+                code = Some(String::from_utf8(bytes).map_err(|_| {
+                    request_error(format!("entrypoint {} is not UTF-8", source.entrypoint))
+                })?);
+                continue;
+            }
+            if manifest.is_some_and(|manifest| path == Path::new(manifest))
+                && request.dependencies.is_none()
+                && let Ok(text) = String::from_utf8(bytes.clone())
+            {
+                request.dependencies = Some(text);
+                continue;
+            }
+            if reserved.iter().any(|name| path == Path::new(name)) {
+                return Err(request_error(format!(
+                    "repository file {} would overwrite the program source",
+                    path.display()
+                )));
+            }
+            files.push(input_file(&path, bytes));
+            if files.len() > limits.max_files {
+                return Err(request_error(format!(
+                    "repository has more than {} files",
+                    limits.max_files
+                )));
+            }
+        }
+    }
+    request.code = code.ok_or_else(|| {
+        request_error(format!(
+            "entrypoint {} is not in the repository",
+            source.entrypoint
+        ))
+    })?;
+    // Sorted so the compile cache sees the same tree the same way.
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+    files.append(&mut request.files);
+    request.files = files;
+    Ok(())
+}
+
+fn input_file(path: &Path, bytes: Vec<u8>) -> InputFile {
+    let name = path.to_string_lossy().to_string();
+    match String::from_utf8(bytes) {
+        Ok(content) => InputFile {
+            name,
+            content,
+            encoding: FileEncoding::Utf8,
+        },
+        Err(err) => InputFile {
+            name,
+            content: BASE64_STANDARD.encode(err.into_bytes()),
+            encoding: FileEncoding::Base64,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{GitLimits, collect};
+    use crate::engine::models::{ExecutionRequest, GitSource};
+
+    #[tokio::test]
+    async fn collects_the_checkout_into_the_request() {
+        let root = std::env::temp_dir().join(format!("git-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(root.join("app/lib")).unwrap();
+        std::fs::create_dir_all(root.join(".git")).unwrap();
+        std::fs::write(root.join(".git/HEAD"), "ref").unwrap();
+        std::fs::write(root.join("app/run.py"), "import lib.util").unwrap();
+        std::fs::write(root.join("app/lib/util.py"), "X = 1").unwrap();
+        std::fs::write(root.join("app/requirements.txt"), "requests").unwrap();
+        std::fs::write(root.join("app/logo.png"), [0xff, 0xfe]).unwrap();
+
+        let source: GitSource = serde_json::from_value(serde_json::json!({
+            "url": "https://example.com/repo.git",
+            "subdir": "app",
+            "entrypoint": "run.py",
+        }))
+        .unwrap();
+        let mut request: ExecutionRequest =
+            serde_json::from_value(serde_json::json!({ "language": "python" })).unwrap();
+        let limits = GitLimits {
+            timeout: Duration::from_secs(1),
+            max_bytes: 1024,
+            max_files: 8,
+        };
+        collect(&mut request, &source, &root.join("app"), limits)
+            .await
+            .unwrap();
+        assert_eq!(request.code, "import lib.util");
+        assert_eq!(request.dependencies.as_deref(), Some("requests"));
+        let names: Vec<&str> = request.files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["lib/util.py", "logo.png"]);
+        assert_eq!(request.files[1].content, "//4=");
+
+        let small = GitLimits {
+            max_files: 1,
+            ..limits
+        };
+        let mut request: ExecutionRequest =
+            serde_json::from_value(serde_json::json!({ "language": "python" })).unwrap();
+        assert!(
+            collect(&mut request, &source, &root.join("app"), small)
+                .await
+                .is_err()
+        );
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
@@ -0,0 +1,342 @@
+use std::path::{Component, Path};
+
+use regex::{Regex, RegexBuilder};
+use sha2::{Digest, Sha256};
+
+use crate::engine::{
+    diff,
+    models::{ExpectedFile, FileCheck, OutputDiff, OutputMatcher, TestCase, TestScore},
+    sandbox::CollectedFile,
+};
+
+const DEFAULT_TOLERANCE: f64 = 1e-6;
+const REGEX_SIZE_LIMIT: usize = 1024 * 1024;
+const MAX_WEIGHT: f64 = 1_000_000.0;
+
+// Checks that a test case's expectations can be evaluated, so bad patterns are
+// rejected at submit time rather than failing every run.
+pub fn validate_case(case: &TestCase) -> Result<(), String> {
+    if case.matcher == OutputMatcher::Regex {
+        let files = case.expected_files.iter().map(|file| &file.content);
+        for pattern in [&case.expected_stdout, &case.expected_stderr]
+            .into_iter()
+            .chain(files)
+            .flatten()
+        {
+            compile_pattern(pattern)?;
+        }
+    }
+    if let Some(tolerance) = case.tolerance
+        && !(tolerance.is_finite() && tolerance >= 0.0)
+    {
+        return Err("tolerance must be a non-negative number".to_string());
+    }
+    if let Some(weight) = case.weight
+        && !(weight.is_finite() && (0.0..=MAX_WEIGHT).contains(&weight))
+    {
+        return Err(format!("weight must be between 0 and {MAX_WEIGHT}"));
+    }
+    for file in &case.expected_files {
+        validate_expected_file(file)?;
+    }
+    Ok(())
+}
+
+fn validate_expected_file(file: &ExpectedFile) -> Result<(), String> {
+    let path = Path::new(&file.path);
+    let relative = !file.path.is_empty()
+        && path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+    if !relative {
+        return Err(format!(
+            "expected file path {:?} must be relative and stay inside the working directory",
+            file.path
+        ));
+    }
+    if file.content.is_none() && file.sha256.is_none() {
+        return Err(format!(
+            "expected file {} needs `content` or `sha256`",
+            file.path
+        ));
+    }
+    if let Some(sha256) = &file.sha256
+        && !(sha256.len() == 64 && sha256.bytes().all(|byte| byte.is_ascii_hexdigit()))
+    {
+        return Err(format!(
+            "sha256 of expected file {} must be 64 hex digits",
+            file.path
+        ));
+    }
+    Ok(())
+}
+
+// `None` when the case has no expectations, otherwise whether all of them
+// hold. `files` are the case's checked expected files.
+pub fn evaluate(
+    case: &TestCase,
+    stdout: &str,
+    stderr: &str,
+    exit_code: i32,
+    files: &[FileCheck],
+) -> Option<bool> {
+    let mut checks = Vec::new();
+    if let Some(expected) = &case.expected_stdout {
+        checks.push(output_matches(case, expected, stdout));
+    }
+    if let Some(expected) = &case.expected_stderr {
+        checks.push(output_matches(case, expected, stderr));
+    }
+    if let Some(expected) = case.expected_exit_code {
+        checks.push(exit_code == expected);
+    }
+    if !case.expected_files.is_empty() {
+        checks.push(files.iter().all(|file| file.passed));
+    }
+    (!checks.is_empty()).then(|| checks.into_iter().all(|passed| passed))
+}
+
+// Where `actual` strays from an `expected` output it fails to match; `None`
+// when there is no expectation, it holds, or it is a pattern.
+pub fn output_diff(case: &TestCase, expected: Option<&str>, actual: &str) -> Option<OutputDiff> {
+    let expected = expected?;
+    if case.matcher == OutputMatcher::Regex || output_matches(case, expected, actual) {
+        return None;
+    }
+    diff::diff(expected.trim(), actual.trim())
+}
+
+// Checks the case's expected files against those collected from its run; a
+// run that stopped early (e.g. failed to compile) collected none.
+pub fn check_files(case: &TestCase, collected: &[CollectedFile]) -> Vec<FileCheck> {
+    case.expected_files
+        .iter()
+        .map(|expected| {
+            let bytes = collected
+                .iter()
+                .find(|file| file.path == expected.path)
+                .and_then(|file| file.bytes.as_ref());
+            let Some(bytes) = bytes else {
+                return FileCheck {
+                    path: expected.path.clone(),
+                    exists: false,
+                    passed: false,
+                    sha256: None,
+                    diff: None,
+                };
+            };
+            let sha256 = hex::encode(Sha256::digest(bytes));
+            let text = String::from_utf8_lossy(bytes);
+            let hash_matches = expected
+                .sha256
+                .as_ref()
+                .is_none_or(|want| want.eq_ignore_ascii_case(&sha256));
+            let content_matches = expected
+                .content
+                .as_ref()
+                .is_none_or(|want| output_matches(case, want, &text));
+            FileCheck {
+                path: expected.path.clone(),
+                exists: true,
+                passed: hash_matches && content_matches,
+                sha256: Some(sha256),
+                diff: output_diff(case, expected.content.as_deref(), &text),
+            }
+        })
+        .collect()
+}
+
+// Scores the cases that carry expectations; `verdicts` lines up with `cases`,
+// with `None` for cases that were not run or are not graded. Returns `None`
+// when no case is graded.
+pub fn score(cases: &[TestCase], verdicts: &[Option<bool>]) -> Option<TestScore> {
+    let mut score = TestScore {
+        passed: 0,
+        failed: 0,
+        total: 0,
+        points_earned: 0.0,
+        points_total: 0.0,
+        score: 0.0,
+    };
+    for (case, verdict) in cases.iter().zip(verdicts) {
+        if !has_expectations(case) {
+            continue;
+        }
+        let weight = case.weight.unwrap_or(1.0);
+        score.total += 1;
+        score.points_total += weight;
+        if *verdict == Some(true) {
+            score.passed += 1;
+            score.points_earned += weight;
+        } else {
+            score.failed += 1;
+        }
+    }
+    if score.total == 0 {
+        return None;
+    }
+    if score.points_total > 0.0 {
+        score.score = score.points_earned / score.points_total;
+    }
+    Some(score)
+}
+
+fn has_expectations(case: &TestCase) -> bool {
+    case.expected_stdout.is_some()
+        || case.expected_stderr.is_some()
+        || case.expected_exit_code.is_some()
+        || !case.expected_files.is_empty()
+}
+
+fn output_matches(case: &TestCase, expected: &str, actual: &str) -> bool {
+    match case.matcher {
+        OutputMatcher::Exact => actual.trim() == expected.trim(),
+        OutputMatcher::Whitespace => actual.split_whitespace().eq(expected.split_whitespace()),
+        OutputMatcher::Regex => {
+            compile_pattern(expected).is_ok_and(|regex| regex.is_match(actual.trim()))
+        }
+        OutputMatcher::Numeric => numeric_matches(
+            expected,
+            actual,
+            case.tolerance.unwrap_or(DEFAULT_TOLERANCE),
+        ),
+    }
+}
+
+fn compile_pattern(pattern: &str) -> Result<Regex, String> {
+    RegexBuilder::new(&format!("^(?:{pattern})$"))
+        .size_limit(REGEX_SIZE_LIMIT)
+        .build()
+        .map_err(|err| format!("invalid expected output pattern: {err}"))
+}
+
+// Tokens must line up one to one; numeric tokens may differ by `tolerance`
+// absolutely or relative to the expected value, others must be equal.
+fn numeric_matches(expected: &str, actual: &str, tolerance: f64) -> bool {
+    let mut expected = expected.split_whitespace();
+    let mut actual = actual.split_whitespace();
+    loop {
+        match (expected.next(), actual.next()) {
+            (None, None) => return true,
+            (Some(want), Some(got)) => {
+                let equal = match (want.parse::<f64>(), got.parse::<f64>()) {
+                    (Ok(want), Ok(got)) => {
+                        let diff = (want - got).abs();
+                        diff <= tolerance || diff <= tolerance * want.abs()
+                    }
+                    _ => want == got,
+                };
+                if !equal {
+                    return false;
+                }
+            }
+            _ => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{evaluate, score, validate_case};
+    use crate::engine::models::{OutputMatcher, TestCase};
+
+    fn case(matcher: OutputMatcher, expected_stdout: &str) -> TestCase {
+        TestCase {
+            stdin: String::new(),
+            expected_stdout: Some(expected_stdout.to_string()),
+            expected_stderr: None,
+            expected_exit_code: None,
+            matcher,
+            tolerance: None,
+            weight: None,
+            expected_files: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn applies_selected_matcher() {
+        assert_eq!(
+            evaluate(&case(OutputMatcher::Exact, "1 2"), "1 2\n", "", 0, &[]),
+            Some(true)
+        );
+        assert_eq!(
+            evaluate(&case(OutputMatcher::Exact, "1 2"), "1  2", "", 0, &[]),
+            Some(false)
+        );
+        assert_eq!(
+            evaluate(
+                &case(OutputMatcher::Whitespace, "1 2\n3"),
+                " 1\t2 3 \n",
+                "",
+                0,
+                &[]
+            ),
+            Some(true)
+        );
+        assert_eq!(
+            evaluate(
+                &case(OutputMatcher::Regex, r"took \d+ms"),
+                "took 12ms\n",
+                "",
+                0,
+                &[]
+            ),
+            Some(true)
+        );
+        assert_eq!(
+            evaluate(&case(OutputMatcher::Regex, r"\d+"), "12 apples", "", 0, &[]),
+            Some(false)
+        );
+
+        let mut numeric = case(OutputMatcher::Numeric, "pi 3.14159 100");
+        numeric.tolerance = Some(0.001);
+        assert_eq!(
+            evaluate(&numeric, "pi 3.1416 100.05", "", 0, &[]),
+            Some(true)
+        );
+        assert_eq!(evaluate(&numeric, "pi 3.15 100", "", 0, &[]), Some(false));
+        assert_eq!(evaluate(&numeric, "pi 3.14159", "", 0, &[]), Some(false));
+    }
+
+    #[test]
+    fn combines_exit_code_and_stderr_expectations() {
+        let mut failing = case(OutputMatcher::Regex, ".*");
+        failing.expected_stderr = Some("(?s).*ValueError.*".to_string());
+        failing.expected_exit_code = Some(1);
+        assert_eq!(
+            evaluate(&failing, "", "Traceback\nValueError: x", 1, &[]),
+            Some(true)
+        );
+        assert_eq!(
+            evaluate(&failing, "", "Traceback\nValueError: x", 0, &[]),
+            Some(false)
+        );
+
+        let mut unchecked = case(OutputMatcher::Exact, "");
+        unchecked.expected_stdout = None;
+        assert_eq!(evaluate(&unchecked, "anything", "", 3, &[]), None);
+
+        assert!(validate_case(&case(OutputMatcher::Regex, "(")).is_err());
+    }
+
+    #[test]
+    fn scores_weighted_cases() {
+        let mut heavy = case(OutputMatcher::Exact, "a");
+        heavy.weight = Some(3.0);
+        let mut ungraded = case(OutputMatcher::Exact, "");
+        ungraded.expected_stdout = None;
+        let cases = [
+            case(OutputMatcher::Exact, "a"),
+            heavy,
+            ungraded,
+            case(OutputMatcher::Exact, "b"),
+        ];
+
+        let score = score(&cases, &[Some(false), Some(true), None, None]).unwrap();
+        assert_eq!((score.passed, score.failed, score.total), (1, 2, 3));
+        assert_eq!(score.points_earned, 3.0);
+        assert_eq!(score.points_total, 5.0);
+        assert_eq!(score.score, 0.6);
+        assert!(super::score(&cases[2..3], &[None]).is_none());
+    }
+}
@@ -0,0 +1,584 @@
+use std::{net::SocketAddr, pin::Pin};
+
+use base64::{Engine as _, prelude::BASE64_STANDARD};
+use futures_util::{Stream, stream};
+use serde::Serialize;
+use tonic::{Code, Request, Response, Status, transport::Server};
+use uuid::Uuid;
+
+use crate::engine::{
+    api::{
+        AppState, DEFAULT_LIST_LIMIT, EventFollow, MAX_LIST_LIMIT, SUBMIT_BODY_LIMIT, authenticate,
+        authenticate_read, enqueue_execution, load_for_tenant,
+    },
+    audit::{AuditEntry, AuditLog},
+    config::Scope,
+    detect,
+    error::EngineError,
+    models::{
+        BenchmarkReport, CompileOutput, Diagnostic, Distribution, ExecutionOutput, ExecutionRecord,
+        ExecutionRequest, ExecutionStatus, OutputDiff, ResourceUsage, StageTimings, TestCaseResult,
+        TestReport, TestScore,
+    },
+};
+
+pub mod proto {
+    tonic::include_proto!("engine.v1");
+}
+
+use proto::engine_server::{Engine, EngineServer};
+
+pub async fn serve(state: AppState, addr: SocketAddr) -> anyhow::Result<()> {
+    let service =
+        EngineServer::new(EngineService { state }).max_decoding_message_size(SUBMIT_BODY_LIMIT);
+    tracing::info!(bind = %addr, "grpc api ready");
+    Server::builder().add_service(service).serve(addr).await?;
+    Ok(())
+}
+
+struct EngineService {
+    state: AppState,
+}
+
+type EventStream = Pin<Box<dyn Stream<Item = Result<proto::ExecutionEvent, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl Engine for EngineService {
+    type StreamStream = EventStream;
+
+    async fn submit(
+        &self,
+        request: Request<proto::SubmitRequest>,
+    ) -> Result<Response<proto::SubmitResponse>, Status> {
+        let mut audit = self.audit_entry("Submit", &request);
+        let result = async {
+            let headers = request.metadata().clone().into_headers();
+            let execution = execution_request(request.into_inner())?;
+            let (_, created) = enqueue_execution(&self.state, &headers, execution).await?;
+            audit.execution_id = Some(created.id);
+            Ok(Response::new(proto::SubmitResponse {
+                id: created.id.to_string(),
+                status: wire_name(&created.status),
+            }))
+        }
+        .await;
+        self.finish_audit(audit, &result);
+        result
+    }
+
+    async fn get(
+        &self,
+        request: Request<proto::ExecutionRef>,
+    ) -> Result<Response<proto::Execution>, Status> {
+        let audit = self.execution_audit_entry("Get", &request);
+        let result = self
+            .load(&request, Scope::Read)
+            .await
+            .map(|record| Response::new(execution(record)));
+        self.finish_audit(audit, &result);
+        result
+    }
+
+    async fn stream(
+        &self,
+        request: Request<proto::ExecutionRef>,
+    ) -> Result<Response<EventStream>, Status> {
+        // Subscribe before the snapshot so nothing falls between the two.
+        let updates = self.state.store.subscribe();
+        let audit = self.execution_audit_entry("Stream", &request);
+        let record = self.load(&request, Scope::Read).await;
+        self.finish_audit(audit, &record);
+        let record = record?;
+        let follow = EventFollow::new(self.state.store.clone(), record, updates);
+        let events = stream::unfold(follow, |mut follow| async move {
+            let (seq, event) = follow.next().await?;
+            let event = proto::ExecutionEvent {
+                seq: seq as u64,
+                ts_ms: event.ts_ms,
+                stage: event.stage,
+                message: event.message,
+            };
+            Some((Ok(event), follow))
+        });
+        Ok(Response::new(Box::pin(events)))
+    }
+
+    async fn cancel(
+        &self,
+        request: Request<proto::ExecutionRef>,
+    ) -> Result<Response<proto::Execution>, Status> {
+        let audit = self.execution_audit_entry("Cancel", &request);
+        let result = async {
+            let record = self.load(&request, Scope::Submit).await?;
+            if !self.state.store.cancel(record.id).await {
+                let status = self
+                    .state
+                    .store
+                    .get(&record.id)
+                    .map_or(record.status, |current| current.status);
+                return Err(Status::failed_precondition(format!(
+                    "execution is {} and can no longer be cancelled",
+                    wire_name(&status)
+                )));
+            }
+            let record = self
+                .state
+                .store
+                .get(&record.id)
+                .ok_or(EngineError::NotFound)?;
+            Ok(Response::new(execution(record)))
+        }
+        .await;
+        self.finish_audit(audit, &result);
+        result
+    }
+
+    async fn list(
+        &self,
+        request: Request<proto::ListRequest>,
+    ) -> Result<Response<proto::ListResponse>, Status> {
+        let audit = self.audit_entry("List", &request);
+        let result = async {
+            let headers = request.metadata().clone().into_headers();
+            let tenant_id = authenticate_read(&self.state, &headers).await?;
+            let request = request.into_inner();
+            let status = request
+                .status
+                .map(|status| {
+                    serde_json::from_value::<ExecutionStatus>(serde_json::Value::String(status))
+                        .map_err(|_| Status::invalid_argument("unknown execution status"))
+                })
+                .transpose()?;
+            let limit = match request.limit {
+                0 => DEFAULT_LIST_LIMIT,
+                limit => limit.min(MAX_LIST_LIMIT),
+            };
+            let executions = self
+                .state
+                .store
+                .list_for_tenant(
+                    &tenant_id,
+                    status.as_ref(),
+                    &request.metadata.into_iter().collect(),
+                )
+                .into_iter()
+                .skip(request.offset as usize)
+                .take(limit as usize)
+                .map(|mut record| {
+                    record.output = None;
+                    execution(record)
+                })
+                .collect();
+            Ok(Response::new(proto::ListResponse { executions }))
+        }
+        .await;
+        self.finish_audit(audit, &result);
+        result
+    }
+}
+
+impl EngineService {
+    fn audit_entry<T>(&self, method: &str, request: &Request<T>) -> AuditEntry {
+        AuditLog::entry(
+            &self.state.config,
+            &request.metadata().clone().into_headers(),
+            "grpc",
+            method,
+            format!("/engine.v1.Engine/{method}"),
+            request.remote_addr(),
+        )
+    }
+
+    fn execution_audit_entry(
+        &self,
+        method: &str,
+        request: &Request<proto::ExecutionRef>,
+    ) -> AuditEntry {
+        AuditEntry {
+            execution_id: Uuid::parse_str(&request.get_ref().id).ok(),
+            ..self.audit_entry(method, request)
+        }
+    }
+
+    fn finish_audit<T>(&self, mut entry: AuditEntry, result: &Result<T, Status>) {
+        let code = result.as_ref().map_or_else(Status::code, |_| Code::Ok);
+        entry.status = format!("{code:?}");
+        entry.ok = code == Code::Ok;
+        self.state.audit.record(entry);
+    }
+
+    async fn load(
+        &self,
+        request: &Request<proto::ExecutionRef>,
+        scope: Scope,
+    ) -> Result<ExecutionRecord, Status> {
+        let headers = request.metadata().clone().into_headers();
+        let tenant_id = match scope {
+            Scope::Read => authenticate_read(&self.state, &headers).await?,
+            _ => authenticate(&self.state.config, &headers, scope)?,
+        };
+        let id = Uuid::parse_str(&request.get_ref().id)
+            .map_err(|_| Status::invalid_argument("invalid execution id"))?;
+        Ok(load_for_tenant(&self.state, id, &tenant_id).await?)
+    }
+}
+
+impl From<EngineError> for Status {
+    fn from(err: EngineError) -> Self {
+        let message = err.to_string();
+        match err {
+            EngineError::Unauthorized => Status::unauthenticated(message),
+            EngineError::Forbidden => Status::permission_denied(message),
+            EngineError::InvalidRequest(_) => Status::invalid_argument(message),
+            EngineError::RateLimited | EngineError::QuotaExceeded { .. } => {
+                Status::resource_exhausted(message)
+            }
+            EngineError::QueueFull { .. } => Status::unavailable(message),
+            EngineError::NotFound
+            | EngineError::SessionNotFound
+            | EngineError::TemplateNotFound
+            | EngineError::SecretNotFound => Status::not_found(message),
+            EngineError::Conflict(_) => Status::failed_precondition(message),
+            EngineError::Internal(_) => Status::internal(message),
+        }
+    }
+}
+
+// Goes through the JSON model so enum names, defaults and validation match
+// the REST API exactly.
+fn execution_request(request: proto::SubmitRequest) -> Result<ExecutionRequest, Status> {
+    let test_cases: Vec<_> = request
+        .test_cases
+        .into_iter()
+        .map(|case| {
+            let mut value = serde_json::json!({
+                "stdin": case.stdin,
+                "expected_stdout": case.expected_stdout,
+                "expected_stderr": case.expected_stderr,
+                "expected_exit_code": case.expected_exit_code,
+                "tolerance": case.tolerance,
+                "weight": case.weight,
+                "expected_files": case
+                    .expected_files
+                    .into_iter()
+                    .map(|file| serde_json::json!({
+                        "path": file.path,
+                        "content": file.content,
+                        "sha256": file.sha256,
+                    }))
+                    .collect::<Vec<_>>(),
+            });
+            if let Some(matcher) = case.matcher {
+                value["matcher"] = matcher.into();
+            }
+            value
+        })
+        .collect();
+    let files: Vec<_> = request
+        .files
+        .into_iter()
+        .map(|file| {
+            serde_json::json!({
+                "name": file.name,
+                "content": BASE64_STANDARD.encode(file.content),
+                "encoding": "base64",
+            })
+        })
+        .collect();
+    let limits = request.limits.map(|limits| {
+        serde_json::json!({
+            "cpu_cores": limits.cpu_cores,
+            "memory_mb": limits.memory_mb,
+            "timeout_ms": limits.timeout_ms,
+            "max_processes": limits.max_processes,
+            "max_file_size_bytes": limits.max_file_size_bytes,
+            "max_output_bytes": limits.max_output_bytes,
+        })
+    });
+    let mut value = serde_json::json!({
+        "language": request.language,
+        "code": request.code,
+        "stdin": request.stdin,
+        "args": request.args,
+        "env": request.env,
+        "secrets": request.secrets,
+        "allow_network": request.allow_network,
+        "egress": request.egress,
+        "limits": limits,
+        "profile": request.profile,
+        "mode": request.mode,
+        "test_cases": test_cases,
+        "metadata": request.metadata,
+        "image": request.image,
+        "version": request.version,
+        "dependencies": request.dependencies,
+        "files": files,
+        "cache": request.cache,
+        "gpu": request.gpu,
+        "backend": request.backend,
+        "interactive": request.interactive,
+        "combined_output": request.combined_output,
+        "deterministic": request.deterministic,
+        "seed": request.seed,
+        "priority": request.priority,
+        "filename": request.filename,
+        "git": request.git.map(|git| serde_json::json!({
+            "url": git.url,
+            "ref": git.r#ref,
+            "subdir": git.subdir,
+            "entrypoint": git.entrypoint,
+        })),
+        "archive": request.archive.map(|archive| serde_json::json!({
+            "content": BASE64_STANDARD.encode(archive.content),
+            "entrypoint": archive.entrypoint,
+        })),
+        "benchmark": request.benchmark.map(|benchmark| {
+            let mut options = serde_json::json!({
+                "warmup": benchmark.warmup,
+                "inputs": benchmark.inputs,
+            });
+            if let Some(runs) = benchmark.runs {
+                options["runs"] = runs.into();
+            }
+            options
+        }),
+    });
+    if let Some(encoding) = request.output_encoding {
+        value["output_encoding"] = encoding.into();
+    }
+    detect::parse_request(value).map_err(Status::invalid_argument)
+}
+
+fn execution(record: ExecutionRecord) -> proto::Execution {
+    proto::Execution {
+        id: record.id.to_string(),
+        tenant_id: record.tenant_id,
+        status: wire_name(&record.status),
+        created_at_ms: record.created_at_ms,
+        started_at_ms: record.started_at_ms,
+        finished_at_ms: record.finished_at_ms,
+        error: record.error,
+        output: record.output.map(output),
+        cost: record.cost,
+        deterministic: record.deterministic,
+    }
+}
+
+fn output(output: ExecutionOutput) -> proto::Output {
+    proto::Output {
+        stdout: output.stdout,
+        stderr: output.stderr,
+        exit_code: output.exit_code,
+        duration_ms: millis(output.duration_ms),
+        sandbox_backend: output.sandbox_backend,
+        usage: Some(usage(output.usage)),
+        oom_killed: output.oom_killed,
+        signal: output.signal,
+        termination_reason: wire_name(&output.termination_reason),
+        stdout_truncated: output.stdout_truncated,
+        stderr_truncated: output.stderr_truncated,
+        stdout_encoding: wire_name(&output.stdout_encoding),
+        stderr_encoding: wire_name(&output.stderr_encoding),
+        install: output.install.map(stage_output),
+        compile: output.compile.map(stage_output),
+        test_results: output.test_results.into_iter().map(test_result).collect(),
+        score: output.score.map(score),
+        failure: output.failure.as_ref().map(wire_name),
+        diagnostics: output.diagnostics.into_iter().map(diagnostic).collect(),
+        test_report: output.test_report.map(test_report),
+        benchmark: output.benchmark.map(benchmark_report),
+        timings: Some(timings(output.timings)),
+    }
+}
+
+fn timings(timings: StageTimings) -> proto::StageTimings {
+    proto::StageTimings {
+        queue_wait_ms: timings.queue_wait_ms,
+        setup_ms: timings.setup_ms,
+        install_ms: timings.install_ms,
+        compile_ms: timings.compile_ms,
+        run_ms: timings.run_ms,
+        collect_ms: timings.collect_ms,
+        persist_ms: timings.persist_ms,
+    }
+}
+
+fn benchmark_report(report: BenchmarkReport) -> proto::BenchmarkReport {
+    let distribution = |distribution: Distribution| proto::Distribution {
+        min: distribution.min,
+        median: distribution.median,
+        p95: distribution.p95,
+        max: distribution.max,
+        mean: distribution.mean,
+    };
+    proto::BenchmarkReport {
+        runs: report.runs as u64,
+        completed: report.completed,
+        duration_ms: report.duration_ms.map(distribution),
+        peak_memory_bytes: report.peak_memory_bytes.map(distribution),
+        samples: report
+            .samples
+            .into_iter()
+            .map(|sample| proto::BenchmarkSample {
+                warmup: sample.warmup,
+                duration_ms: millis(sample.duration_ms),
+                usage: Some(usage(sample.usage)),
+                exit_code: sample.exit_code,
+            })
+            .collect(),
+    }
+}
+
+fn test_report(report: TestReport) -> proto::TestReport {
+    proto::TestReport {
+        framework: report.framework,
+        passed: report.passed as u64,
+        failed: report.failed as u64,
+        skipped: report.skipped as u64,
+        cases: report
+            .cases
+            .into_iter()
+            .map(|case| proto::TestReportCase {
+                name: case.name,
+                suite: case.suite,
+                status: wire_name(&case.status),
+                duration_ms: case.duration_ms,
+                message: case.message,
+            })
+            .collect(),
+    }
+}
+
+fn usage(usage: ResourceUsage) -> proto::Usage {
+    proto::Usage {
+        peak_memory_bytes: usage.peak_memory_bytes,
+        user_cpu_ms: usage.user_cpu_ms,
+        system_cpu_ms: usage.system_cpu_ms,
+        stdout_bytes: usage.stdout_bytes,
+        stderr_bytes: usage.stderr_bytes,
+    }
+}
+
+fn stage_output(stage: CompileOutput) -> proto::StageOutput {
+    proto::StageOutput {
+        success: stage.success,
+        exit_code: stage.exit_code,
+        stdout: stage.stdout,
+        stderr: stage.stderr,
+        duration_ms: millis(stage.duration_ms),
+    }
+}
+
+fn test_result(result: TestCaseResult) -> proto::TestCaseResult {
+    proto::TestCaseResult {
+        stdin: result.stdin,
+        stdout: result.stdout,
+        stderr: result.stderr,
+        passed: result.passed,
+        exit_code: result.exit_code,
+        duration_ms: millis(result.duration_ms),
+        usage: Some(usage(result.usage)),
+        oom_killed: result.oom_killed,
+        signal: result.signal,
+        termination_reason: wire_name(&result.termination_reason),
+        stdout_truncated: result.stdout_truncated,
+        stderr_truncated: result.stderr_truncated,
+        stdout_encoding: wire_name(&result.stdout_encoding),
+        stderr_encoding: wire_name(&result.stderr_encoding),
+        stdout_diff: result.stdout_diff.map(output_diff),
+        stderr_diff: result.stderr_diff.map(output_diff),
+        files: result
+            .files
+            .into_iter()
+            .map(|file| proto::FileCheck {
+                path: file.path,
+                exists: file.exists,
+                passed: file.passed,
+                sha256: file.sha256,
+                diff: file.diff.map(output_diff),
+            })
+            .collect(),
+    }
+}
+
+fn output_diff(diff: OutputDiff) -> proto::OutputDiff {
+    proto::OutputDiff {
+        line: diff.line as u64,
+        column: diff.column as u64,
+        unified: diff.unified,
+        truncated: diff.truncated,
+    }
+}
+
+fn score(score: TestScore) -> proto::Score {
+    proto::Score {
+        passed: score.passed as u64,
+        failed: score.failed as u64,
+        total: score.total as u64,
+        points_earned: score.points_earned,
+        points_total: score.points_total,
+        score: score.score,
+    }
+}
+
+fn diagnostic(diagnostic: Diagnostic) -> proto::Diagnostic {
+    proto::Diagnostic {
+        stage: wire_name(&diagnostic.stage),
+        severity: wire_name(&diagnostic.severity),
+        file: diagnostic.file,
+        line: diagnostic.line,
+        column: diagnostic.column,
+        message: diagnostic.message,
+    }
+}
+
+fn millis(ms: u128) -> u64 {
+    u64::try_from(ms).unwrap_or(u64::MAX)
+}
+
+// The snake_case name the REST API uses for a unit enum variant.
+fn wire_name<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{execution_request, proto};
+    use crate::engine::models::{FileEncoding, Language, OutputEncoding, OutputMatcher};
+
+    #[test]
+    fn maps_submit_request_onto_the_rest_model() {
+        let request = execution_request(proto::SubmitRequest {
+            language: "python".to_string(),
+            code: "print(1)".to_string(),
+            test_cases: vec![proto::TestCase {
+                stdin: "1".to_string(),
+                expected_stdout: Some("1".to_string()),
+                matcher: Some("numeric".to_string()),
+                ..Default::default()
+            }],
+            files: vec![proto::InputFile {
+                name: "data.bin".to_string(),
+                content: vec![0, 159, 146, 150],
+            }],
+            output_encoding: Some("auto".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(request.language, Language::Python);
+        assert_eq!(request.test_cases[0].matcher, OutputMatcher::Numeric);
+        assert_eq!(request.files[0].encoding, FileEncoding::Base64);
+        assert_eq!(request.files[0].decode().unwrap(), vec![0, 159, 146, 150]);
+        assert_eq!(request.output_encoding, OutputEncoding::Auto);
+        assert!(request.limits.is_none() && request.mode.is_none());
+
+        let unknown = execution_request(proto::SubmitRequest {
+            language: "cobol".to_string(),
+            ..Default::default()
+        });
+        assert!(unknown.is_err());
+    }
+}
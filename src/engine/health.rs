@@ -0,0 +1,225 @@
+use std::{sync::Arc, sync::RwLock, time::Duration};
+
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::engine::{
+    config::SandboxBackendKind,
+    sandbox::{SandboxBackend, SandboxRegistry},
+    store::now_ms,
+};
+
+// A health check that takes longer than this counts as failed.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Ok,
+    // Running, but some backend or language is unavailable.
+    Degraded,
+    Down,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ReadinessReport {
+    pub ready: bool,
+    pub status: HealthStatus,
+    pub detail: Option<String>,
+    // The last check of each sandbox backend; empty on API nodes.
+    pub backends: Vec<BackendHealth>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct BackendHealth {
+    pub backend: SandboxBackendKind,
+    // Requests that name no backend run here.
+    pub default: bool,
+    pub status: HealthStatus,
+    pub detail: Option<String>,
+    pub checked_at_ms: u64,
+}
+
+#[derive(Debug)]
+pub struct Readiness {
+    prepared: RwLock<(bool, Option<String>)>,
+    backends: RwLock<Vec<BackendHealth>>,
+}
+
+impl Readiness {
+    pub fn new() -> Self {
+        Self {
+            prepared: RwLock::new((false, Some("sandbox backend is preparing".to_string()))),
+            backends: RwLock::new(Vec::new()),
+        }
+    }
+
+    // Ready once the backends are prepared and the default one is up; a
+    // down or degraded backend elsewhere only degrades the node.
+    pub fn report(&self) -> ReadinessReport {
+        let (Ok(prepared), Ok(backends)) = (self.prepared.read(), self.backends.read()) else {
+            return ReadinessReport {
+                ready: false,
+                status: HealthStatus::Down,
+                detail: Some("readiness state poisoned".to_string()),
+                backends: Vec::new(),
+            };
+        };
+        let (prepared, detail) = prepared.clone();
+        let default_down = backends
+            .iter()
+            .find(|backend| backend.default && backend.status == HealthStatus::Down);
+        let ready = prepared && default_down.is_none();
+        let status = if !ready {
+            HealthStatus::Down
+        } else if backends
+            .iter()
+            .any(|backend| backend.status != HealthStatus::Ok)
+        {
+            HealthStatus::Degraded
+        } else {
+            HealthStatus::Ok
+        };
+        ReadinessReport {
+            ready,
+            status,
+            detail: detail.or_else(|| {
+                default_down.map(|backend| {
+                    format!(
+                        "{} backend is down: {}",
+                        backend.backend.as_str(),
+                        backend.detail.as_deref().unwrap_or("unknown error")
+                    )
+                })
+            }),
+            backends: backends.clone(),
+        }
+    }
+
+    pub fn set(&self, ready: bool, detail: Option<String>) {
+        if let Ok(mut prepared) = self.prepared.write() {
+            *prepared = (ready, detail);
+        }
+    }
+
+    pub fn set_backends(&self, backends: Vec<BackendHealth>) {
+        if let Ok(mut state) = self.backends.write() {
+            *state = backends;
+        }
+    }
+}
+
+// Ready once every configured backend has prepared.
+pub fn spawn_sandbox_prepare(sandboxes: SandboxRegistry, readiness: Arc<Readiness>) {
+    tokio::spawn(async move {
+        for (_, sandbox) in sandboxes.iter() {
+            while let Err(err) = sandbox.prepare().await {
+                tracing::warn!(backend = sandbox.name(), error = %err, "sandbox prepare failed, retrying");
+                readiness.set(false, Some(err.to_string()));
+                tokio::time::sleep(Duration::from_secs(30)).await;
+            }
+            tracing::info!(backend = sandbox.name(), "sandbox backend prepared");
+        }
+        readiness.set(true, None);
+    });
+}
+
+// Checks every backend now and then every `interval`, so /readyz answers
+// from the last results without touching the backends itself.
+pub fn spawn_health_checks(
+    sandboxes: SandboxRegistry,
+    readiness: Arc<Readiness>,
+    interval: Duration,
+) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            let mut backends = Vec::new();
+            for (kind, sandbox) in sandboxes.iter() {
+                let health = check(kind, kind == sandboxes.default_kind(), sandbox.as_ref()).await;
+                sandboxes.set_down(kind, health.status == HealthStatus::Down);
+                if health.status == HealthStatus::Down {
+                    tracing::warn!(
+                        backend = kind.as_str(),
+                        detail = health.detail.as_deref(),
+                        "sandbox backend health check failed"
+                    );
+                }
+                backends.push(health);
+            }
+            readiness.set_backends(backends);
+        }
+    });
+}
+
+pub async fn check(
+    backend: SandboxBackendKind,
+    default: bool,
+    sandbox: &dyn SandboxBackend,
+) -> BackendHealth {
+    let (status, detail) = match tokio::time::timeout(CHECK_TIMEOUT, sandbox.health()).await {
+        Ok(Ok(None)) => (HealthStatus::Ok, None),
+        Ok(Ok(Some(note))) => (HealthStatus::Degraded, Some(note)),
+        Ok(Err(err)) => (HealthStatus::Down, Some(format!("{err:#}"))),
+        Err(_) => (
+            HealthStatus::Down,
+            Some(format!(
+                "health check timed out after {} s",
+                CHECK_TIMEOUT.as_secs()
+            )),
+        ),
+    };
+    BackendHealth {
+        backend,
+        default,
+        status,
+        detail,
+        checked_at_ms: now_ms(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BackendHealth, HealthStatus, Readiness};
+    use crate::engine::config::SandboxBackendKind;
+
+    fn backend(kind: SandboxBackendKind, default: bool, status: HealthStatus) -> BackendHealth {
+        BackendHealth {
+            backend: kind,
+            default,
+            status,
+            detail: Some("ping failed".to_string()),
+            checked_at_ms: 0,
+        }
+    }
+
+    #[test]
+    fn default_backend_down_makes_the_node_unready() {
+        let readiness = Readiness::new();
+        readiness.set(true, None);
+        assert_eq!(readiness.report().status, HealthStatus::Ok);
+
+        readiness.set_backends(vec![
+            backend(SandboxBackendKind::Docker, false, HealthStatus::Down),
+            backend(SandboxBackendKind::Process, true, HealthStatus::Ok),
+        ]);
+        let report = readiness.report();
+        assert!(report.ready);
+        assert_eq!(report.status, HealthStatus::Degraded);
+
+        readiness.set_backends(vec![backend(
+            SandboxBackendKind::Docker,
+            true,
+            HealthStatus::Down,
+        )]);
+        let report = readiness.report();
+        assert!(!report.ready);
+        assert_eq!(report.status, HealthStatus::Down);
+        assert_eq!(
+            report.detail.as_deref(),
+            Some("docker backend is down: ping failed")
+        );
+    }
+}
@@ -0,0 +1,119 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use uuid::Uuid;
+
+pub const MAX_KEY_LEN: usize = 255;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Claim {
+    // The key is new and now reserved for this submission.
+    Reserved,
+    // The key already created this execution.
+    Existing(Uuid),
+    // An earlier submission with the key has not finished enqueueing yet.
+    InProgress,
+    // The key was used for a different request body.
+    Mismatch,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    id: Option<Uuid>,
+    fingerprint: u64,
+    created: Instant,
+}
+
+// Remembers which execution each (tenant, Idempotency-Key) pair created, so
+// retried submissions return the original id. Keys expire after `ttl`.
+#[derive(Clone)]
+pub struct IdempotencyKeys {
+    entries: Arc<Mutex<HashMap<(String, String), Entry>>>,
+    ttl: Duration,
+}
+
+impl IdempotencyKeys {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            entries: Arc::new(Mutex::new(HashMap::new())),
+            ttl,
+        }
+    }
+
+    pub fn claim(&self, tenant_id: &str, key: &str, fingerprint: u64) -> Claim {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().expect("idempotency keys poisoned");
+        entries.retain(|_, entry| now.duration_since(entry.created) < self.ttl);
+        let slot = (tenant_id.to_string(), key.to_string());
+        match entries.get(&slot) {
+            Some(entry) if entry.fingerprint != fingerprint => Claim::Mismatch,
+            Some(Entry { id: Some(id), .. }) => Claim::Existing(*id),
+            Some(_) => Claim::InProgress,
+            None => {
+                entries.insert(
+                    slot,
+                    Entry {
+                        id: None,
+                        fingerprint,
+                        created: now,
+                    },
+                );
+                Claim::Reserved
+            }
+        }
+    }
+
+    // Records the execution a reserved key created.
+    pub fn commit(&self, tenant_id: &str, key: &str, id: Uuid) {
+        let mut entries = self.entries.lock().expect("idempotency keys poisoned");
+        if let Some(entry) = entries.get_mut(&(tenant_id.to_string(), key.to_string())) {
+            entry.id = Some(id);
+        }
+    }
+
+    // Frees a reserved key after the submission failed, so it can be retried.
+    pub fn release(&self, tenant_id: &str, key: &str) {
+        let mut entries = self.entries.lock().expect("idempotency keys poisoned");
+        let slot = (tenant_id.to_string(), key.to_string());
+        if entries.get(&slot).is_some_and(|entry| entry.id.is_none()) {
+            entries.remove(&slot);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use uuid::Uuid;
+
+    use super::{Claim, IdempotencyKeys};
+
+    #[test]
+    fn replays_committed_keys_per_tenant() {
+        let keys = IdempotencyKeys::new(Duration::from_secs(60));
+        let id = Uuid::new_v4();
+        assert_eq!(keys.claim("a", "k1", 7), Claim::Reserved);
+        assert_eq!(keys.claim("a", "k1", 7), Claim::InProgress);
+        keys.commit("a", "k1", id);
+        assert_eq!(keys.claim("a", "k1", 7), Claim::Existing(id));
+        assert_eq!(keys.claim("a", "k1", 8), Claim::Mismatch);
+        assert_eq!(keys.claim("b", "k1", 7), Claim::Reserved);
+
+        assert_eq!(keys.claim("a", "k2", 7), Claim::Reserved);
+        keys.release("a", "k2");
+        assert_eq!(keys.claim("a", "k2", 9), Claim::Reserved);
+    }
+
+    #[test]
+    fn forgets_keys_after_ttl() {
+        let keys = IdempotencyKeys::new(Duration::from_millis(20));
+        assert_eq!(keys.claim("a", "k", 1), Claim::Reserved);
+        keys.commit("a", "k", Uuid::new_v4());
+        std::thread::sleep(Duration::from_millis(40));
+        assert_eq!(keys.claim("a", "k", 2), Claim::Reserved);
+    }
+}
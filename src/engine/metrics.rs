@@ -1,4 +1,22 @@
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+    sync::{
+        Mutex, MutexGuard,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+// Tenants with their own label on the per-tenant series; the rest are
+// summed under `other`.
+pub const DEFAULT_TENANT_LABELS: usize = 20;
+const OTHER_TENANTS: &str = "other";
+
+// Upper bounds in seconds, shared by the latency histograms.
+const LATENCY_BUCKETS: [f64; 14] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0,
+];
 
 #[derive(Debug, Default)]
 pub struct MetricsRegistry {
@@ -7,17 +25,138 @@ pub struct MetricsRegistry {
     completed_total: AtomicU64,
     failed_total: AtomicU64,
     timed_out_total: AtomicU64,
+    oom_killed_total: AtomicU64,
+    cancelled_total: AtomicU64,
+    retried_total: AtomicU64,
+    failovers_total: AtomicU64,
+    stuck_total: AtomicU64,
+    cache_hits_total: AtomicU64,
     queue_depth: AtomicU64,
+    tenant_labels: usize,
+    submitted_by_tenant: Mutex<BTreeMap<String, u64>>,
+    failed_by_tenant: Mutex<BTreeMap<String, u64>>,
+    finished_total: Mutex<BTreeMap<(String, String, String), u64>>,
+    cost_total: Mutex<BTreeMap<String, f64>>,
+    queue_wait: Mutex<Histogram>,
+    execution_duration: Mutex<BTreeMap<String, Histogram>>,
+    tenant_duration: Mutex<BTreeMap<String, Histogram>>,
+}
+
+// What is running right now, read from the workers and sandboxes when
+// metrics are scraped.
+#[derive(Debug, Default)]
+pub struct Utilization {
+    pub running: usize,
+    // Live and busy workers by pool.
+    pub workers: BTreeMap<String, (usize, usize)>,
+    // Runs and sessions open by sandbox backend; empty on API-only nodes.
+    pub sandboxes: BTreeMap<String, usize>,
+}
+
+impl Utilization {
+    pub fn render(&self, out: &mut String) {
+        let _ = writeln!(
+            out,
+            "# HELP execution_running Executions running on this node's workers."
+        );
+        let _ = writeln!(out, "# TYPE execution_running gauge");
+        let _ = writeln!(out, "execution_running {}", self.running);
+
+        let _ = writeln!(out, "# HELP execution_workers Workers by pool and state.");
+        let _ = writeln!(out, "# TYPE execution_workers gauge");
+        for (pool, (live, busy)) in &self.workers {
+            let pool = escape_label(pool);
+            let _ = writeln!(
+                out,
+                "execution_workers{{pool=\"{pool}\",state=\"busy\"}} {busy}"
+            );
+            let _ = writeln!(
+                out,
+                "execution_workers{{pool=\"{pool}\",state=\"idle\"}} {}",
+                live.saturating_sub(*busy)
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP execution_sandbox_active Sandbox runs and sessions open (containers on docker) by backend."
+        );
+        let _ = writeln!(out, "# TYPE execution_sandbox_active gauge");
+        for (backend, active) in &self.sandboxes {
+            let _ = writeln!(
+                out,
+                "execution_sandbox_active{{backend=\"{}\"}} {active}",
+                escape_label(backend)
+            );
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    // Per-bucket (not cumulative) counts; the last slot is `+Inf`.
+    counts: [u64; LATENCY_BUCKETS.len() + 1],
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: Duration) {
+        let seconds = value.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.counts[bucket] += 1;
+        self.sum += seconds;
+    }
+
+    fn merge(&mut self, other: &Histogram) {
+        for (count, more) in self.counts.iter_mut().zip(other.counts) {
+            *count += more;
+        }
+        self.sum += other.sum;
+    }
+
+    // `labels` is empty or a label list ending in a comma.
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let mut cumulative = 0;
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(&self.counts) {
+            cumulative += count;
+            let _ = writeln!(out, "{name}_bucket{{{labels}le=\"{bound}\"}} {cumulative}");
+        }
+        cumulative += self.counts[LATENCY_BUCKETS.len()];
+        let _ = writeln!(out, "{name}_bucket{{{labels}le=\"+Inf\"}} {cumulative}");
+        let labels = labels.trim_end_matches(',');
+        let labels = if labels.is_empty() {
+            String::new()
+        } else {
+            format!("{{{labels}}}")
+        };
+        let _ = writeln!(out, "{name}_sum{labels} {}", self.sum);
+        let _ = writeln!(out, "{name}_count{labels} {cumulative}");
+    }
 }
 
 impl MetricsRegistry {
     pub fn new() -> Self {
-        Self::default()
+        Self {
+            tenant_labels: DEFAULT_TENANT_LABELS,
+            ..Self::default()
+        }
+    }
+
+    // How many tenants (METRICS_TENANT_LABELS) get their own label.
+    pub fn with_tenant_labels(mut self, tenant_labels: usize) -> Self {
+        self.tenant_labels = tenant_labels;
+        self
     }
 
-    pub fn submitted(&self) {
+    pub fn submitted(&self, tenant_id: &str) {
         self.submitted_total.fetch_add(1, Ordering::Relaxed);
         self.queue_depth.fetch_add(1, Ordering::Relaxed);
+        *lock(&self.submitted_by_tenant)
+            .entry(tenant_id.to_string())
+            .or_default() += 1;
     }
 
     pub fn started(&self) {
@@ -29,37 +168,292 @@ impl MetricsRegistry {
         self.completed_total.fetch_add(1, Ordering::Relaxed);
     }
 
-    pub fn failed(&self) {
+    pub fn failed(&self, tenant_id: &str) {
         self.failed_total.fetch_add(1, Ordering::Relaxed);
+        *lock(&self.failed_by_tenant)
+            .entry(tenant_id.to_string())
+            .or_default() += 1;
     }
 
     pub fn timed_out(&self) {
         self.timed_out_total.fetch_add(1, Ordering::Relaxed);
     }
 
+    pub fn oom_killed(&self) {
+        self.oom_killed_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // A cancelled job left the queue without being started.
+    pub fn cancelled(&self) {
+        self.cancelled_total.fetch_add(1, Ordering::Relaxed);
+        self.decrement_queue_depth();
+    }
+
+    // A started job went back on the queue after an infrastructure failure.
+    pub fn retried(&self) {
+        self.retried_total.fetch_add(1, Ordering::Relaxed);
+        self.queue_depth.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // A job moved on to the next backend of SANDBOX_FAILOVER.
+    pub fn failover(&self) {
+        self.failovers_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // The watchdog killed an execution stuck past its deadline.
+    pub fn stuck(&self) {
+        self.stuck_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // A submission answered from the result cache without running.
+    pub fn cache_hit(&self) {
+        self.cache_hits_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn queue_wait(&self, wait: Duration) {
+        lock(&self.queue_wait).observe(wait);
+    }
+
+    // Records a finished execution under its language, tenant and final status.
+    pub fn finished(&self, language: &str, tenant_id: &str, status: &str, duration: Duration) {
+        *lock(&self.finished_total)
+            .entry((
+                language.to_string(),
+                tenant_id.to_string(),
+                status.to_string(),
+            ))
+            .or_default() += 1;
+        lock(&self.execution_duration)
+            .entry(language.to_string())
+            .or_default()
+            .observe(duration);
+        lock(&self.tenant_duration)
+            .entry(tenant_id.to_string())
+            .or_default()
+            .observe(duration);
+    }
+
+    pub fn cost(&self, tenant_id: &str, cost: f64) {
+        *lock(&self.cost_total)
+            .entry(tenant_id.to_string())
+            .or_default() += cost;
+    }
+
+    // Mean run time over every finished execution, if any finished yet.
+    pub fn average_execution_duration(&self) -> Option<Duration> {
+        let durations = lock(&self.execution_duration);
+        let (sum, count) = durations
+            .values()
+            .fold((0.0, 0u64), |(sum, count), histogram| {
+                (
+                    sum + histogram.sum,
+                    count + histogram.counts.iter().sum::<u64>(),
+                )
+            });
+        (count > 0).then(|| Duration::from_secs_f64(sum / count as f64))
+    }
+
+    // The `tenant_labels` busiest tenants by submitted plus finished
+    // executions (API nodes only see the former, workers the latter), ranked
+    // at scrape time. A tenant moving into them takes its counts out of
+    // `other`.
+    fn top_tenants(&self) -> BTreeSet<String> {
+        let mut activity: BTreeMap<String, u64> = lock(&self.submitted_by_tenant).clone();
+        for (tenant, histogram) in lock(&self.tenant_duration).iter() {
+            *activity.entry(tenant.clone()).or_default() += histogram.counts.iter().sum::<u64>();
+        }
+        let mut tenants: Vec<_> = activity
+            .into_iter()
+            .map(|(tenant, count)| (count, tenant))
+            .collect();
+        tenants.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+        tenants
+            .into_iter()
+            .take(self.tenant_labels)
+            .map(|(_, tenant)| tenant)
+            .collect()
+    }
+
     pub fn render_prometheus(&self) -> String {
-        format!(
-            concat!(
-                "# TYPE execution_submitted_total counter\n",
-                "execution_submitted_total {}\n",
-                "# TYPE execution_started_total counter\n",
-                "execution_started_total {}\n",
-                "# TYPE execution_completed_total counter\n",
-                "execution_completed_total {}\n",
-                "# TYPE execution_failed_total counter\n",
-                "execution_failed_total {}\n",
-                "# TYPE execution_timed_out_total counter\n",
-                "execution_timed_out_total {}\n",
-                "# TYPE execution_queue_depth gauge\n",
-                "execution_queue_depth {}\n"
+        let mut out = String::new();
+        let top = self.top_tenants();
+        let tenant_label = |tenant: &str| {
+            if top.contains(tenant) {
+                tenant.to_string()
+            } else {
+                OTHER_TENANTS.to_string()
+            }
+        };
+        for (name, help, kind, value) in [
+            (
+                "execution_submitted_total",
+                "Executions accepted into the queue.",
+                "counter",
+                &self.submitted_total,
+            ),
+            (
+                "execution_started_total",
+                "Executions picked up by a worker.",
+                "counter",
+                &self.started_total,
+            ),
+            (
+                "execution_completed_total",
+                "Executions that produced a result.",
+                "counter",
+                &self.completed_total,
+            ),
+            (
+                "execution_failed_total",
+                "Executions that failed, ran out of memory or hit a sandbox error.",
+                "counter",
+                &self.failed_total,
+            ),
+            (
+                "execution_timed_out_total",
+                "Executions stopped at their timeout.",
+                "counter",
+                &self.timed_out_total,
+            ),
+            (
+                "execution_oom_killed_total",
+                "Executions killed by the memory limit.",
+                "counter",
+                &self.oom_killed_total,
+            ),
+            (
+                "execution_cancelled_total",
+                "Queued executions dropped after being cancelled.",
+                "counter",
+                &self.cancelled_total,
+            ),
+            (
+                "execution_retried_total",
+                "Executions requeued after an infrastructure failure.",
+                "counter",
+                &self.retried_total,
+            ),
+            (
+                "execution_failovers_total",
+                "Runs moved to the next sandbox backend after an infrastructure failure.",
+                "counter",
+                &self.failovers_total,
+            ),
+            (
+                "execution_stuck_total",
+                "Executions the watchdog killed for running far past their deadline.",
+                "counter",
+                &self.stuck_total,
+            ),
+            (
+                "execution_cache_hits_total",
+                "Submissions answered from the result cache.",
+                "counter",
+                &self.cache_hits_total,
+            ),
+            (
+                "execution_queue_depth",
+                "Executions waiting in the queue.",
+                "gauge",
+                &self.queue_depth,
+            ),
+        ] {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} {kind}");
+            let _ = writeln!(out, "{name} {}", value.load(Ordering::Relaxed));
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP execution_finished_total Finished executions by language, tenant and status."
+        );
+        let _ = writeln!(out, "# TYPE execution_finished_total counter");
+        let mut finished = BTreeMap::<_, u64>::new();
+        for ((language, tenant, status), count) in lock(&self.finished_total).iter() {
+            *finished
+                .entry((language.clone(), tenant_label(tenant), status.clone()))
+                .or_default() += count;
+        }
+        for ((language, tenant, status), count) in &finished {
+            let _ = writeln!(
+                out,
+                "execution_finished_total{{language=\"{}\",tenant=\"{}\",status=\"{}\"}} {count}",
+                escape_label(language),
+                escape_label(tenant),
+                escape_label(status),
+            );
+        }
+
+        for (name, help, counts) in [
+            (
+                "execution_tenant_submitted_total",
+                "Executions accepted into the queue by tenant.",
+                &self.submitted_by_tenant,
+            ),
+            (
+                "execution_tenant_failed_total",
+                "Failed executions by tenant.",
+                &self.failed_by_tenant,
             ),
-            self.submitted_total.load(Ordering::Relaxed),
-            self.started_total.load(Ordering::Relaxed),
-            self.completed_total.load(Ordering::Relaxed),
-            self.failed_total.load(Ordering::Relaxed),
-            self.timed_out_total.load(Ordering::Relaxed),
-            self.queue_depth.load(Ordering::Relaxed),
-        )
+        ] {
+            let mut by_label = BTreeMap::<_, u64>::new();
+            for (tenant, count) in lock(counts).iter() {
+                *by_label.entry(tenant_label(tenant)).or_default() += count;
+            }
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} counter");
+            for (tenant, count) in &by_label {
+                let _ = writeln!(out, "{name}{{tenant=\"{}\"}} {count}", escape_label(tenant));
+            }
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP execution_cost_total Weighted resource cost of finished executions by tenant."
+        );
+        let _ = writeln!(out, "# TYPE execution_cost_total counter");
+        for (tenant, cost) in lock(&self.cost_total).iter() {
+            let _ = writeln!(
+                out,
+                "execution_cost_total{{tenant=\"{}\"}} {cost}",
+                escape_label(tenant),
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP execution_queue_wait_seconds Time from submission until a worker starts the execution."
+        );
+        let _ = writeln!(out, "# TYPE execution_queue_wait_seconds histogram");
+        lock(&self.queue_wait).render(&mut out, "execution_queue_wait_seconds", "");
+
+        let _ = writeln!(
+            out,
+            "# HELP execution_duration_seconds Worker time per execution, including install, compile and all test cases."
+        );
+        let _ = writeln!(out, "# TYPE execution_duration_seconds histogram");
+        for (language, histogram) in lock(&self.execution_duration).iter() {
+            let labels = format!("language=\"{}\",", escape_label(language));
+            histogram.render(&mut out, "execution_duration_seconds", &labels);
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP execution_tenant_duration_seconds Worker time per execution by tenant."
+        );
+        let _ = writeln!(out, "# TYPE execution_tenant_duration_seconds histogram");
+        let mut by_label = BTreeMap::<_, Histogram>::new();
+        for (tenant, histogram) in lock(&self.tenant_duration).iter() {
+            by_label
+                .entry(tenant_label(tenant))
+                .or_default()
+                .merge(histogram);
+        }
+        for (tenant, histogram) in &by_label {
+            let labels = format!("tenant=\"{}\",", escape_label(tenant));
+            histogram.render(&mut out, "execution_tenant_duration_seconds", &labels);
+        }
+        out
     }
 
     fn decrement_queue_depth(&self) {
@@ -78,9 +472,25 @@ impl MetricsRegistry {
     }
 }
 
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// A panic while holding a metrics lock can't leave the data inconsistent.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
 #[cfg(test)]
 mod tests {
-    use super::MetricsRegistry;
+    use std::time::Duration;
+
+    use super::{MetricsRegistry, Utilization};
 
     #[test]
     fn queue_depth_does_not_underflow() {
@@ -89,4 +499,78 @@ mod tests {
         let rendered = metrics.render_prometheus();
         assert!(rendered.contains("execution_queue_depth 0"));
     }
+
+    #[test]
+    fn renders_utilization_gauges() {
+        let mut rendered = String::new();
+        Utilization {
+            running: 3,
+            workers: [("default".to_string(), (4, 3))].into(),
+            sandboxes: [("docker".to_string(), 5)].into(),
+        }
+        .render(&mut rendered);
+        assert!(rendered.contains("execution_running 3"));
+        assert!(rendered.contains("execution_workers{pool=\"default\",state=\"busy\"} 3"));
+        assert!(rendered.contains("execution_workers{pool=\"default\",state=\"idle\"} 1"));
+        assert!(rendered.contains("execution_sandbox_active{backend=\"docker\"} 5"));
+    }
+
+    #[test]
+    fn renders_labeled_counters_and_histograms() {
+        let metrics = MetricsRegistry::new();
+        metrics.queue_wait(Duration::from_millis(30));
+        metrics.finished("python", "acme", "succeeded", Duration::from_millis(700));
+        metrics.finished("python", "acme", "succeeded", Duration::from_secs(3));
+        metrics.finished("rust", "a\"b", "failed", Duration::from_secs(500));
+        metrics.cost("acme", 0.5);
+        metrics.cost("acme", 1.25);
+        let rendered = metrics.render_prometheus();
+
+        assert!(rendered.contains(
+            "execution_finished_total{language=\"python\",tenant=\"acme\",status=\"succeeded\"} 2"
+        ));
+        assert!(rendered.contains(r#"tenant="a\"b""#));
+        assert!(rendered.contains("execution_cost_total{tenant=\"acme\"} 1.75"));
+        assert!(rendered.contains("execution_queue_wait_seconds_bucket{le=\"0.025\"} 0"));
+        assert!(rendered.contains("execution_queue_wait_seconds_bucket{le=\"0.05\"} 1"));
+        assert!(rendered.contains("execution_queue_wait_seconds_count 1"));
+        assert!(
+            rendered.contains("execution_duration_seconds_bucket{language=\"python\",le=\"1\"} 1")
+        );
+        assert!(
+            rendered.contains("execution_duration_seconds_bucket{language=\"python\",le=\"5\"} 2")
+        );
+        assert!(
+            rendered.contains("execution_duration_seconds_bucket{language=\"rust\",le=\"+Inf\"} 1")
+        );
+        assert!(rendered.contains("execution_duration_seconds_sum{language=\"python\"} 3.7"));
+    }
+
+    #[test]
+    fn labels_the_busiest_tenants_and_sums_the_rest() {
+        let metrics = MetricsRegistry::new().with_tenant_labels(2);
+        for (tenant, submissions) in [("acme", 3), ("beta", 2), ("carl", 1), ("dora", 1)] {
+            for _ in 0..submissions {
+                metrics.submitted(tenant);
+            }
+            metrics.failed(tenant);
+            metrics.finished("python", tenant, "failed", Duration::from_millis(200));
+        }
+        let rendered = metrics.render_prometheus();
+
+        assert!(rendered.contains("execution_tenant_submitted_total{tenant=\"acme\"} 3"));
+        assert!(rendered.contains("execution_tenant_submitted_total{tenant=\"beta\"} 2"));
+        assert!(rendered.contains("execution_tenant_submitted_total{tenant=\"other\"} 2"));
+        assert!(!rendered.contains("tenant=\"carl\""));
+        assert!(rendered.contains("execution_tenant_failed_total{tenant=\"other\"} 2"));
+        assert!(rendered.contains(
+            "execution_finished_total{language=\"python\",tenant=\"other\",status=\"failed\"} 2"
+        ));
+        assert!(rendered.contains("execution_tenant_duration_seconds_count{tenant=\"other\"} 2"));
+        assert!(
+            rendered.contains(
+                "execution_tenant_duration_seconds_bucket{tenant=\"acme\",le=\"0.25\"} 1"
+            )
+        );
+    }
 }
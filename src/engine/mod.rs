@@ -1,57 +1,318 @@
+pub mod admin;
 pub mod api;
+pub mod archive;
+pub mod audit;
+pub mod benchmark;
+pub mod capacity;
 pub mod config;
+pub mod detect;
+pub mod determinism;
+pub mod diagnostics;
+pub mod diff;
+pub mod encryption;
 pub mod error;
+pub mod export;
+pub mod git;
+pub mod grading;
+pub mod grpc;
+pub mod health;
+pub mod idempotency;
 pub mod metrics;
 pub mod models;
+pub mod object_store;
 pub mod queue;
+pub mod quota;
 pub mod rate_limit;
+pub mod replication;
+pub mod result_cache;
 pub mod sandbox;
+pub mod secrets;
+pub mod session;
+pub mod share;
+pub mod stdin_upload;
 pub mod store;
+pub mod templates;
+pub mod test_report;
 pub mod worker;
 
-use std::{net::SocketAddr, sync::Arc};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 
 use anyhow::Context;
 use axum::Router;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{Resource, trace::SdkTracerProvider};
+use tokio::sync::Semaphore;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 use crate::engine::{
-    api::routes, config::EngineConfig, metrics::MetricsRegistry, queue::Scheduler,
-    sandbox::SandboxFactory, store::ExecutionStore, worker::spawn_worker_pool,
+    admin::WorkerControl,
+    api::{AppState, render_metrics, routes},
+    audit::AuditLog,
+    capacity::{Capacity, TenantSlots},
+    config::{EngineConfig, EngineRole, QueueBackendKind},
+    encryption::RecordCipher,
+    export::spawn_metrics_export,
+    git::GitLimits,
+    health::{Readiness, spawn_health_checks, spawn_sandbox_prepare},
+    metrics::MetricsRegistry,
+    object_store::ObjectStore,
+    queue::{Admission, DEFAULT_POOL, GPU_POOL, QueueBackend, Scheduler},
+    quota::Quotas,
+    replication::spawn_replication,
+    sandbox::SandboxFactory,
+    secrets::SecretStore,
+    session::spawn_session_reaper,
+    store::{ExecutionStore, spawn_compaction},
+    templates::TemplateStore,
+    worker::{Scaling, WorkerContext, spawn_watchdog, spawn_worker_pool},
 };
 
 pub async fn run() -> anyhow::Result<()> {
     let config = EngineConfig::from_env();
-    init_tracing(&config);
+    let tracer_provider = init_tracing(&config)?;
 
-    let store = Arc::new(ExecutionStore::new(config.persistence_path.clone()));
-    let metrics = Arc::new(MetricsRegistry::new());
-    let scheduler = Scheduler::new(config.queue_capacity, metrics.clone());
-    let sandbox = SandboxFactory::from_config(&config).context("sandbox backend init failed")?;
+    let cipher = RecordCipher::from_config(&config).context("encryption key init failed")?;
+    if cipher.enabled() {
+        tracing::info!("persisted and queued executions are encrypted at rest");
+    }
+    let secrets = Arc::new(
+        SecretStore::load(config.secrets_path.clone(), cipher.clone())
+            .context("secret store init failed")?,
+    );
+    let mut store = ExecutionStore::new(config.persistence_path.clone()).with_cipher(cipher);
+    if let Some(objects) = ObjectStore::from_config(&config).context("object store init failed")? {
+        tracing::info!("large output and finished records go to the object store");
+        store = store.with_object_store(objects, config.object_store_offload_bytes);
+    }
+    let store = Arc::new(store);
+    let retention = (config.record_retention_secs > 0)
+        .then(|| Duration::from_secs(config.record_retention_secs));
+    if config.persistence_path.is_some() || retention.is_some() {
+        spawn_compaction(
+            store.clone(),
+            Duration::from_secs(config.persist_compact_interval_secs.max(1)),
+            retention,
+            config.persist_keep_segments,
+        );
+    }
+    let metrics = Arc::new(MetricsRegistry::new().with_tenant_labels(config.metrics_tenant_labels));
+    let queues = QueueBackend::from_config(&config)
+        .await
+        .context("queue backend init failed")?;
+    let mut scheduler = Scheduler::new(
+        queues,
+        Admission {
+            max_depth: config.queue_max_depth,
+            max_wait: (config.queue_max_wait_ms > 0)
+                .then(|| Duration::from_millis(config.queue_max_wait_ms)),
+        },
+        metrics.clone(),
+    )
+    .with_pool(DEFAULT_POOL, config.worker_max);
+    if config.gpu_workers > 0 {
+        scheduler = scheduler.with_pool(GPU_POOL, config.gpu_workers);
+    }
+    for pool in &config.language_pools {
+        scheduler = scheduler.with_language_pool(&pool.name, &pool.languages, pool.workers);
+    }
+    if config.role != EngineRole::All {
+        if config.queue_backend != QueueBackendKind::Redis {
+            anyhow::bail!("api and worker nodes need QUEUE_BACKEND=redis");
+        }
+        spawn_replication(&config, store.clone())
+            .await
+            .context("record replication init failed")?;
+    }
 
-    spawn_worker_pool(
-        config.worker_count.max(1),
-        scheduler.receiver(),
+    let readiness = Arc::new(Readiness::new());
+    // API nodes never run code, so they need no sandbox.
+    let sandboxes = if config.role.runs_workers() {
+        let sandboxes = SandboxFactory::from_config(&config)
+            .await
+            .context("sandbox backend init failed")?;
+        spawn_sandbox_prepare(sandboxes.clone(), readiness.clone());
+        spawn_health_checks(
+            sandboxes.clone(),
+            readiness.clone(),
+            Duration::from_millis(config.health_check_interval_ms),
+        );
+        Some(sandboxes)
+    } else {
+        readiness.set(true, None);
+        None
+    };
+
+    let workers = Arc::new(WorkerControl::new());
+    let state = AppState::new(
+        config.clone(),
         store.clone(),
+        scheduler.clone(),
         metrics.clone(),
-        sandbox,
+        readiness,
+        sandboxes.clone(),
+        workers.clone(),
+    )
+    .with_templates(Arc::new(
+        TemplateStore::load(config.templates_path.clone()).context("template store init failed")?,
+    ))
+    .with_secrets(secrets)
+    .with_audit(match &config.audit_log_dir {
+        Some(dir) => AuditLog::spawn(dir.clone(), config.audit_retention_days)
+            .context("audit log init failed")?,
+        None => AuditLog::disabled(),
+    })
+    .with_quotas(
+        Quotas::connect(&config)
+            .await
+            .context("quota store init failed")?,
     );
+    let exported = state.clone();
+    spawn_metrics_export(&config, move || render_metrics(&exported))
+        .await
+        .context("metrics export init failed")?;
+    if sandboxes.is_some() && state.sessions.enabled() {
+        spawn_session_reaper(state.sessions.clone());
+    }
+    if let Some(sandboxes) = sandboxes {
+        if config.stuck_execution_grace_ms > 0 {
+            spawn_watchdog(
+                workers.clone(),
+                store.clone(),
+                metrics.clone(),
+                Duration::from_millis(config.watchdog_interval_ms.max(100)),
+                Duration::from_millis(config.stuck_execution_grace_ms),
+            );
+        }
+        spawn_workers(
+            &config,
+            WorkerContext {
+                pool: DEFAULT_POOL.to_string(),
+                store,
+                metrics,
+                sandboxes,
+                run_slots: Arc::new(Semaphore::new(config.max_concurrent_runs.max(1))),
+                test_case_parallelism: config.test_case_parallelism.max(1),
+                tenant_test_case_slots: TenantSlots::new(config.tenant_test_case_concurrency),
+                control: workers,
+                scheduler,
+                infra_retry_limit: config.infra_retry_limit,
+                result_cache: state.result_cache.clone(),
+                cost_weights: config.cost_weights,
+                git: GitLimits::from_config(&config),
+                remote_submissions: config.role == EngineRole::Worker,
+                heartbeat_interval: Duration::from_millis(
+                    config.worker_heartbeat_interval_ms.max(10),
+                ),
+                capacity: Capacity::from_config(&config),
+            },
+        );
+    }
 
-    let app: Router = routes(config.clone(), store, scheduler, metrics);
+    let app: Router = routes(state.clone());
     let listener = tokio::net::TcpListener::bind(config.bind_addr).await?;
     let local = listener
         .local_addr()
         .unwrap_or(SocketAddr::from(([0, 0, 0, 0], 0)));
     tracing::info!(bind = %local, "sandbox execution engine ready");
-    axum::serve(listener, app).await?;
-    Ok(())
+    let http = async {
+        let app = app.into_make_service_with_connect_info::<SocketAddr>();
+        Ok::<_, anyhow::Error>(axum::serve(listener, app).await?)
+    };
+    let served = match config.grpc_bind_addr {
+        Some(addr) if config.role.serves_api() => {
+            tokio::try_join!(http, grpc::serve(state, addr)).map(|_| ())
+        }
+        _ => http.await,
+    };
+    if let Some(provider) = tracer_provider {
+        // Flush spans still sitting in the batch processor.
+        let _ = provider.shutdown();
+    }
+    served
+}
+
+// The language and GPU pools, then the default pool from `context`.
+fn spawn_workers(config: &EngineConfig, context: WorkerContext) {
+    let scale_interval = Duration::from_millis(config.worker_scale_interval_ms.max(100));
+    // Dedicated pools are fixed-size, with one run slot per worker.
+    for pool in &config.language_pools {
+        spawn_worker_pool(
+            Scaling {
+                min: pool.workers,
+                max: pool.workers,
+                target_wait: Duration::ZERO,
+                interval: scale_interval,
+            },
+            WorkerContext {
+                pool: pool.name.clone(),
+                run_slots: Arc::new(Semaphore::new(pool.workers)),
+                ..context.clone()
+            },
+        );
+    }
+    if config.gpu_workers > 0 {
+        spawn_worker_pool(
+            Scaling {
+                min: config.gpu_workers,
+                max: config.gpu_workers,
+                target_wait: Duration::ZERO,
+                interval: scale_interval,
+            },
+            WorkerContext {
+                pool: GPU_POOL.to_string(),
+                run_slots: Arc::new(Semaphore::new(config.gpu_max_concurrent_runs.max(1))),
+                ..context.clone()
+            },
+        );
+    }
+    spawn_worker_pool(
+        Scaling {
+            min: config.worker_min,
+            max: config.worker_max,
+            target_wait: Duration::from_millis(config.worker_target_wait_ms.max(1)),
+            interval: scale_interval,
+        },
+        context,
+    );
 }
 
-fn init_tracing(config: &EngineConfig) {
+// Spans are also exported over OTLP (gRPC) when OTEL_EXPORTER_OTLP_ENDPOINT is
+// set; the returned provider must be shut down to flush them.
+fn init_tracing(config: &EngineConfig) -> anyhow::Result<Option<SdkTracerProvider>> {
     let filter = tracing_subscriber::EnvFilter::try_from_default_env()
         .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(config.log_level.clone()));
-    tracing_subscriber::fmt()
-        .with_env_filter(filter)
-        .with_target(false)
-        .compact()
+    let provider = match &config.otlp_endpoint {
+        Some(endpoint) => {
+            let exporter = opentelemetry_otlp::SpanExporter::builder()
+                .with_tonic()
+                .with_endpoint(endpoint)
+                .build()
+                .context("otlp exporter init failed")?;
+            Some(
+                SdkTracerProvider::builder()
+                    .with_batch_exporter(exporter)
+                    .with_resource(
+                        Resource::builder()
+                            .with_service_name(config.otel_service_name.clone())
+                            .build(),
+                    )
+                    .build(),
+            )
+        }
+        None => None,
+    };
+    let otel = provider
+        .as_ref()
+        .map(|provider| tracing_opentelemetry::layer().with_tracer(provider.tracer("engine")));
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_target(false)
+                .compact(),
+        )
+        .with(otel)
         .init();
+    Ok(provider)
 }
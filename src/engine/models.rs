@@ -1,31 +1,173 @@
-use std::collections::BTreeMap;
+use std::{
+    collections::BTreeMap,
+    path::{Component, Path},
+    str::FromStr,
+};
 
+use base64::{Engine, prelude::BASE64_STANDARD};
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use crate::engine::config::SandboxBackendKind;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum Language {
     Python,
+    #[serde(alias = "javascript")]
     JavaScript,
     Rust,
     C,
+    Go,
+    Java,
+    Kotlin,
+    #[serde(rename = "typescript")]
+    TypeScript,
+    Ruby,
+    Php,
+    #[serde(rename = "cpp", alias = "c++")]
+    Cpp,
+    #[serde(rename = "csharp", alias = "c#")]
+    CSharp,
+}
+
+impl Language {
+    pub const ALL: &'static [Language] = &[
+        Language::Python,
+        Language::JavaScript,
+        Language::Rust,
+        Language::C,
+        Language::Go,
+        Language::Java,
+        Language::Kotlin,
+        Language::TypeScript,
+        Language::Ruby,
+        Language::Php,
+        Language::Cpp,
+        Language::CSharp,
+    ];
+}
+
+impl Language {
+    // Same as the serialized name.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Language::Python => "python",
+            Language::JavaScript => "javascript",
+            Language::Rust => "rust",
+            Language::C => "c",
+            Language::Go => "go",
+            Language::Java => "java",
+            Language::Kotlin => "kotlin",
+            Language::TypeScript => "typescript",
+            Language::Ruby => "ruby",
+            Language::Php => "php",
+            Language::Cpp => "cpp",
+            Language::CSharp => "csharp",
+        }
+    }
+}
+
+impl Language {
+    // By source file extension, without the dot.
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        Some(match extension.to_ascii_lowercase().as_str() {
+            "py" => Language::Python,
+            "js" | "mjs" | "cjs" => Language::JavaScript,
+            "rs" => Language::Rust,
+            "c" => Language::C,
+            "go" => Language::Go,
+            "java" => Language::Java,
+            "kt" | "kts" => Language::Kotlin,
+            "ts" => Language::TypeScript,
+            "rb" => Language::Ruby,
+            "php" => Language::Php,
+            "cpp" | "cc" | "cxx" => Language::Cpp,
+            "cs" => Language::CSharp,
+            _ => return None,
+        })
+    }
+}
+
+impl FromStr for Language {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(s.trim().to_ascii_lowercase()))
+            .map_err(|_| format!("unsupported language: {s}"))
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum ExecutionMode {
     Human,
     AgentOptimized,
+    // Stops after the compile, or a syntax check for interpreted languages,
+    // and reports its diagnostics; nothing is run.
+    Check,
+    // Run the language's configured linter or formatter on the code instead
+    // of the program.
+    Lint,
+    Format,
+    // Run the language's configured test framework and parse its report
+    // into `output.test_report`.
+    Test,
+    // Run the program repeatedly, per `benchmark`, and report timing and
+    // memory statistics in `output.benchmark`.
+    Benchmark,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct TestCase {
     pub stdin: String,
     pub expected_stdout: Option<String>,
+    #[serde(default)]
+    pub expected_stderr: Option<String>,
+    #[serde(default)]
+    pub expected_exit_code: Option<i32>,
+    #[serde(default)]
+    pub matcher: OutputMatcher,
+    // Allowed absolute or relative difference for `numeric` matching.
+    #[serde(default)]
+    pub tolerance: Option<f64>,
+    // Points for passing this case; defaults to 1.
+    #[serde(default)]
+    pub weight: Option<f64>,
+    // Files the program must leave in its working directory.
+    #[serde(default)]
+    pub expected_files: Vec<ExpectedFile>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ExpectedFile {
+    // Relative to the working directory.
+    pub path: String,
+    // Compared with the case's `matcher`, like `expected_stdout`.
+    #[serde(default)]
+    pub content: Option<String>,
+    // Hex SHA-256 of the exact bytes.
+    #[serde(default)]
+    pub sha256: Option<String>,
+}
+
+// How expected stdout/stderr are compared with the program output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputMatcher {
+    // Equal after trimming leading and trailing whitespace.
+    #[default]
+    Exact,
+    // Equal after collapsing all whitespace runs.
+    Whitespace,
+    // The expectation is a regex that must match the whole trimmed output.
+    Regex,
+    // Whitespace-separated tokens, numbers compared within `tolerance`.
+    Numeric,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct ExecutionLimits {
     pub cpu_cores: f32,
     pub memory_mb: u64,
@@ -36,37 +178,267 @@ pub struct ExecutionLimits {
 }
 
 impl ExecutionLimits {
+    // The most `normalized` allows.
+    pub const MAX: ExecutionLimits = ExecutionLimits {
+        cpu_cores: 4.0,
+        memory_mb: 8192,
+        timeout_ms: 120_000,
+        max_processes: 256,
+        max_file_size_bytes: 100 * 1024 * 1024,
+        max_output_bytes: 4 * 1024 * 1024,
+    };
+
     pub fn normalized(mut self) -> Self {
-        self.cpu_cores = self.cpu_cores.clamp(0.1, 4.0);
-        self.memory_mb = self.memory_mb.clamp(32, 8192);
-        self.timeout_ms = self.timeout_ms.clamp(50, 120_000);
-        self.max_processes = self.max_processes.clamp(1, 256);
-        self.max_file_size_bytes = self.max_file_size_bytes.clamp(1024, 100 * 1024 * 1024);
-        self.max_output_bytes = self.max_output_bytes.clamp(1024, 4 * 1024 * 1024);
+        self.cpu_cores = self.cpu_cores.clamp(0.1, Self::MAX.cpu_cores);
+        self.memory_mb = self.memory_mb.clamp(32, Self::MAX.memory_mb);
+        self.timeout_ms = self.timeout_ms.clamp(50, Self::MAX.timeout_ms);
+        self.max_processes = self.max_processes.clamp(1, Self::MAX.max_processes);
+        self.max_file_size_bytes = self
+            .max_file_size_bytes
+            .clamp(1024, Self::MAX.max_file_size_bytes);
+        self.max_output_bytes = self
+            .max_output_bytes
+            .clamp(1024, Self::MAX.max_output_bytes);
+        self
+    }
+
+    // Each limit lowered to `ceiling`'s where it is above it.
+    pub fn capped(mut self, ceiling: &ExecutionLimits) -> Self {
+        self.cpu_cores = self.cpu_cores.min(ceiling.cpu_cores);
+        self.memory_mb = self.memory_mb.min(ceiling.memory_mb);
+        self.timeout_ms = self.timeout_ms.min(ceiling.timeout_ms);
+        self.max_processes = self.max_processes.min(ceiling.max_processes);
+        self.max_file_size_bytes = self.max_file_size_bytes.min(ceiling.max_file_size_bytes);
+        self.max_output_bytes = self.max_output_bytes.min(ceiling.max_output_bytes);
         self
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct ExecutionRequest {
+    // `auto` on submit is replaced with the language detected from
+    // `filename` and the code.
     pub language: Language,
+    // The program's file name, only used to detect the language.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+    // Empty when the code comes from `git`.
+    #[serde(default)]
     pub code: String,
     #[serde(default)]
     pub stdin: String,
     #[serde(default)]
     pub args: Vec<String>,
+    // Set for the program run only; install and build stages do not see it.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    // Environment variables set from the tenant's named secrets, as
+    // `{"VAR": "secret-name"}`; their values are redacted from the output.
+    #[serde(default)]
+    pub secrets: BTreeMap<String, String>,
     #[serde(default)]
     pub allow_network: bool,
+    // Destinations (`host[:port]`, `*.domain`) reachable through the egress
+    // proxy when `allow_network` is off.
+    #[serde(default)]
+    pub egress: Vec<String>,
     pub limits: Option<ExecutionLimits>,
+    // Name of a configured limit profile, used instead of `limits`.
+    #[serde(default)]
+    pub profile: Option<String>,
     #[serde(default)]
     pub mode: Option<ExecutionMode>,
     #[serde(default)]
     pub test_cases: Vec<TestCase>,
     #[serde(default)]
     pub metadata: BTreeMap<String, String>,
+    #[serde(default)]
+    pub image: Option<String>,
+    // A runtime version from the language's LANGUAGE_VERSIONS (docker) or
+    // LANGUAGE_VERSION_COMMANDS (process) entries; unset runs the default.
+    #[serde(default)]
+    pub version: Option<String>,
+    #[serde(default)]
+    pub dependencies: Option<String>,
+    #[serde(default)]
+    pub files: Vec<InputFile>,
+    #[serde(default)]
+    pub output_encoding: OutputEncoding,
+    // Reuse the result of an identical earlier request from the same tenant,
+    // when the result cache is enabled.
+    #[serde(default)]
+    pub cache: bool,
+    // Run with the host's GPUs on the GPU worker pool (docker backend,
+    // allowed tenants only).
+    #[serde(default)]
+    pub gpu: bool,
+    // One of SANDBOX_BACKENDS; when unset, the tenant's TENANT_BACKENDS entry
+    // or SANDBOX_BACKEND is filled in on submit.
+    #[serde(default)]
+    pub backend: Option<SandboxBackendKind>,
+    // Keep stdin open after `stdin` is written, so more input can be sent
+    // to the running program through the stdin endpoint until it is closed.
+    #[serde(default)]
+    pub interactive: bool,
+    // Also record stdout and stderr interleaved, in the order they were
+    // written, in `output.combined`.
+    #[serde(default)]
+    pub combined_output: bool,
+    // Run a file from a git repository, with the rest of the tree as input
+    // files, instead of `code`.
+    #[serde(default)]
+    pub git: Option<GitSource>,
+    // A project archive extracted into the working directory ahead of
+    // `files`.
+    #[serde(default)]
+    pub archive: Option<ProjectArchive>,
+    // Options for `benchmark` executions.
+    #[serde(default)]
+    pub benchmark: Option<BenchmarkOptions>,
+    // Pins locale, time zone, clock, PRNG seed and CPU so runs of the same
+    // program see the same environment.
+    #[serde(default)]
+    pub deterministic: bool,
+    // `SANDBOX_SEED` of a deterministic run; 0 when unset.
+    #[serde(default)]
+    pub seed: Option<u64>,
+    // 0 to MAX_PRIORITY; queued jobs of a higher priority are taken first.
+    // Falls back to the `X-Priority` header, then 0.
+    #[serde(default)]
+    pub priority: Option<u8>,
+    // Set on submit when `language` was `auto`.
+    #[serde(skip)]
+    pub language_detected: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct BenchmarkOptions {
+    #[serde(default = "default_benchmark_runs")]
+    pub runs: u32,
+    // Runs made first and left out of the statistics.
+    #[serde(default)]
+    pub warmup: u32,
+    // Stdin for each run in turn, cycling; the request's `stdin` when empty.
+    #[serde(default)]
+    pub inputs: Vec<String>,
+}
+
+impl Default for BenchmarkOptions {
+    fn default() -> Self {
+        Self {
+            runs: default_benchmark_runs(),
+            warmup: 0,
+            inputs: Vec::new(),
+        }
+    }
+}
+
+fn default_benchmark_runs() -> u32 {
+    10
+}
+
+// A zip, tar or tar.gz archive. With `entrypoint` set, that file of the
+// archive is the program source and `code` is left empty.
+#[derive(Debug, Clone, Hash, Serialize, Deserialize, ToSchema)]
+pub struct ProjectArchive {
+    // Base64; filled from the `archive` part on multipart uploads.
+    #[serde(default)]
+    pub content: String,
+    #[serde(default)]
+    pub entrypoint: Option<String>,
+}
+
+// Fetched by the worker with a shallow clone; `subdir` becomes the
+// workspace and `entrypoint`, relative to it, the program source.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct GitSource {
+    // https only, on a host in GIT_ALLOWED_HOSTS.
+    pub url: String,
+    // Branch, tag or commit; the remote's HEAD when unset.
+    #[serde(default, rename = "ref")]
+    pub reference: Option<String>,
+    #[serde(default)]
+    pub subdir: Option<String>,
+    pub entrypoint: String,
+}
+
+// Input for a running interactive execution. `close` ends its stdin after
+// `data` is written.
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct StdinInput {
+    #[serde(default)]
+    pub data: String,
+    #[serde(default)]
+    pub encoding: FileEncoding,
+    #[serde(default)]
+    pub close: bool,
+}
+
+// How stdout/stderr are returned. `utf8` replaces invalid sequences, `base64`
+// always encodes, `auto` encodes only output that is not valid UTF-8.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputEncoding {
+    #[default]
+    Utf8,
+    Base64,
+    Auto,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FileEncoding {
+    #[default]
+    Utf8,
+    Base64,
+}
+
+#[derive(Debug, Clone, Hash, Serialize, Deserialize, ToSchema)]
+pub struct InputFile {
+    pub name: String,
+    pub content: String,
+    #[serde(default)]
+    pub encoding: FileEncoding,
+}
+
+impl StdinInput {
+    pub fn decode(&self) -> Result<Vec<u8>, String> {
+        match self.encoding {
+            FileEncoding::Utf8 => Ok(self.data.as_bytes().to_vec()),
+            FileEncoding::Base64 => BASE64_STANDARD
+                .decode(self.data.trim())
+                .map_err(|err| format!("stdin data is not valid base64: {err}")),
+        }
+    }
+}
+
+impl InputFile {
+    pub fn decode(&self) -> Result<Vec<u8>, String> {
+        match self.encoding {
+            FileEncoding::Utf8 => Ok(self.content.as_bytes().to_vec()),
+            FileEncoding::Base64 => BASE64_STANDARD
+                .decode(self.content.trim())
+                .map_err(|err| format!("file {} is not valid base64: {err}", self.name)),
+        }
+    }
+
+    // Workspace-relative path made only of normal components, so a file can't
+    // escape the workspace or shadow it with an absolute path.
+    pub fn relative_path(&self) -> Option<&Path> {
+        relative_path(&self.name)
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// `name` as a path that stays inside the directory it is joined to.
+pub fn relative_path(name: &str) -> Option<&Path> {
+    let path = Path::new(name);
+    let normal = path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+    (normal && path.components().next().is_some()).then_some(path)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum ExecutionStatus {
     Queued,
@@ -74,10 +446,40 @@ pub enum ExecutionStatus {
     Succeeded,
     Failed,
     TimedOut,
+    OutOfMemory,
     Rejected,
+    Cancelled,
+}
+
+impl ExecutionStatus {
+    // Same as the serialized name.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExecutionStatus::Queued => "queued",
+            ExecutionStatus::Running => "running",
+            ExecutionStatus::Succeeded => "succeeded",
+            ExecutionStatus::Failed => "failed",
+            ExecutionStatus::TimedOut => "timed_out",
+            ExecutionStatus::OutOfMemory => "out_of_memory",
+            ExecutionStatus::Rejected => "rejected",
+            ExecutionStatus::Cancelled => "cancelled",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TerminationReason {
+    #[default]
+    Exited,
+    Signaled,
+    TimedOut,
+    OutOfMemory,
+    CompileFailed,
+    InstallFailed,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct TestCaseResult {
     pub stdin: String,
     pub stdout: String,
@@ -85,9 +487,75 @@ pub struct TestCaseResult {
     pub passed: Option<bool>,
     pub exit_code: i32,
     pub duration_ms: u128,
+    #[serde(default)]
+    pub usage: ResourceUsage,
+    #[serde(default)]
+    pub oom_killed: bool,
+    #[serde(default)]
+    pub signal: Option<String>,
+    #[serde(default)]
+    pub termination_reason: TerminationReason,
+    #[serde(default)]
+    pub stdout_truncated: bool,
+    #[serde(default)]
+    pub stderr_truncated: bool,
+    #[serde(default)]
+    pub stdout_encoding: FileEncoding,
+    #[serde(default)]
+    pub stderr_encoding: FileEncoding,
+    // Set when the output fails a non-regex expectation.
+    #[serde(default)]
+    pub stdout_diff: Option<OutputDiff>,
+    #[serde(default)]
+    pub stderr_diff: Option<OutputDiff>,
+    // One per expected file, in request order.
+    #[serde(default)]
+    pub files: Vec<FileCheck>,
+}
+
+// An expected file as the program left it.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct FileCheck {
+    pub path: String,
+    pub exists: bool,
+    pub passed: bool,
+    // Of what the program wrote.
+    pub sha256: Option<String>,
+    // Set when the content was expected and does not match.
+    pub diff: Option<OutputDiff>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// How a case's output differs from the expectation, both trimmed.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct OutputDiff {
+    // 1-based position of the first differing character.
+    pub line: usize,
+    pub column: usize,
+    // Expected lines as `-`, actual ones as `+`, with three lines of context.
+    pub unified: String,
+    // The unified diff was cut at 8 KiB.
+    pub truncated: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct ResourceUsage {
+    pub peak_memory_bytes: Option<u64>,
+    pub user_cpu_ms: Option<u64>,
+    pub system_cpu_ms: Option<u64>,
+    pub stdout_bytes: u64,
+    pub stderr_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CompileOutput {
+    pub success: bool,
+    pub exit_code: i32,
+    pub stdout: String,
+    pub stderr: String,
+    pub duration_ms: u128,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct ExecutionOutput {
     pub stdout: String,
     pub stderr: String,
@@ -95,10 +563,223 @@ pub struct ExecutionOutput {
     pub duration_ms: u128,
     pub sandbox_backend: String,
     #[serde(default)]
+    pub usage: ResourceUsage,
+    #[serde(default)]
+    pub oom_killed: bool,
+    #[serde(default)]
+    pub signal: Option<String>,
+    #[serde(default)]
+    pub termination_reason: TerminationReason,
+    // Set when output was cut at max_output_bytes; `usage` has the totals.
+    #[serde(default)]
+    pub stdout_truncated: bool,
+    #[serde(default)]
+    pub stderr_truncated: bool,
+    #[serde(default)]
+    pub stdout_encoding: FileEncoding,
+    #[serde(default)]
+    pub stderr_encoding: FileEncoding,
+    #[serde(default)]
+    pub install: Option<CompileOutput>,
+    #[serde(default)]
+    pub compile: Option<CompileOutput>,
+    #[serde(default)]
     pub test_results: Vec<TestCaseResult>,
+    #[serde(default)]
+    pub score: Option<TestScore>,
+    // Populated for `agent_optimized` executions only.
+    #[serde(default)]
+    pub failure: Option<FailureClass>,
+    #[serde(default)]
+    pub diagnostics: Vec<Diagnostic>,
+    // The formatter's output for a successful `format` execution.
+    #[serde(default)]
+    pub formatted: Option<String>,
+    // The test framework's results for a `test` execution.
+    #[serde(default)]
+    pub test_report: Option<TestReport>,
+    #[serde(default)]
+    pub benchmark: Option<BenchmarkReport>,
+    // The run's output chunks in arrival order, for `combined_output`.
+    #[serde(default)]
+    pub combined: Vec<OutputChunk>,
+    // Object key holding stdout, stderr and `combined` once they were moved
+    // to the object store; read back before the record is returned.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(ignore)]
+    pub offloaded: Option<String>,
+    #[serde(default)]
+    pub timings: StageTimings,
+}
+
+// Where an execution's time went. `setup` covers fetching git sources,
+// writing the workspace and starting the sandbox, `collect` reading the
+// output and cleaning up after the program exits, and `persist` storing the
+// record (known only once it is stored, so absent from the persisted copy).
+// Test cases and benchmarks run the program many times: their `run_ms` is
+// the wall time of all runs, setup included, and `setup_ms`/`collect_ms` are
+// unset. Stages that did not happen are unset too.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct StageTimings {
+    pub queue_wait_ms: Option<u64>,
+    pub setup_ms: Option<u64>,
+    pub install_ms: Option<u64>,
+    pub compile_ms: Option<u64>,
+    pub run_ms: Option<u64>,
+    pub collect_ms: Option<u64>,
+    pub persist_ms: Option<u64>,
+}
+
+impl ExecutionOutput {
+    // Usage and run time of each program run: one per test case or
+    // benchmark run, or the single run.
+    pub fn runs(&self) -> Vec<(&ResourceUsage, u128)> {
+        if let Some(benchmark) = &self.benchmark {
+            benchmark
+                .samples
+                .iter()
+                .map(|sample| (&sample.usage, sample.duration_ms))
+                .collect()
+        } else if self.test_results.is_empty() {
+            vec![(&self.usage, self.duration_ms)]
+        } else {
+            self.test_results
+                .iter()
+                .map(|case| (&case.usage, case.duration_ms))
+                .collect()
+        }
+    }
+}
+
+// Statistics over the measured runs; `completed` is false when a run failed
+// and ended the benchmark early.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct BenchmarkReport {
+    pub runs: usize,
+    pub completed: bool,
+    pub duration_ms: Option<Distribution>,
+    // Unset on backends that don't measure memory.
+    pub peak_memory_bytes: Option<Distribution>,
+    pub samples: Vec<BenchmarkSample>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct BenchmarkSample {
+    pub warmup: bool,
+    pub duration_ms: u128,
+    pub usage: ResourceUsage,
+    pub exit_code: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Distribution {
+    pub min: u64,
+    pub median: u64,
+    pub p95: u64,
+    pub max: u64,
+    pub mean: f64,
+}
+
+// `failed` also counts tests that errored.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TestReport {
+    // `junit`, `jest`, `go` or `libtest`: the format the report was read from.
+    pub framework: String,
+    pub passed: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    pub cases: Vec<TestReportCase>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TestReportCase {
+    pub name: String,
+    // Class, file or package, when the framework reports one.
+    pub suite: Option<String>,
+    pub status: TestReportStatus,
+    pub duration_ms: Option<u64>,
+    // Failure or skip message, capped at 4 KiB.
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TestReportStatus {
+    Passed,
+    Failed,
+    Error,
+    Skipped,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+// Output written to one stream, `offset_ms` after the program started.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct OutputChunk {
+    pub stream: OutputStream,
+    pub offset_ms: u64,
+    pub data: String,
+    #[serde(default)]
+    pub encoding: FileEncoding,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureClass {
+    DependencyError,
+    CompileError,
+    Timeout,
+    MemoryLimit,
+    Crash,
+    RuntimeError,
+    WrongAnswer,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DiagnosticStage {
+    Compile,
+    Runtime,
+    Lint,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DiagnosticSeverity {
+    Error,
+    Warning,
+    Note,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Diagnostic {
+    pub stage: DiagnosticStage,
+    pub severity: DiagnosticSeverity,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+    pub message: String,
+}
+
+// Verdict over the test cases that carry expectations. Cases skipped after a
+// timeout count as failed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct TestScore {
+    pub passed: usize,
+    pub failed: usize,
+    pub total: usize,
+    pub points_earned: f64,
+    pub points_total: f64,
+    // points_earned / points_total, 0 when no points are at stake.
+    pub score: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct ExecutionRecord {
     pub id: Uuid,
     pub tenant_id: String,
@@ -112,22 +793,46 @@ pub struct ExecutionRecord {
     pub created_at_ms: u64,
     pub started_at_ms: Option<u64>,
     pub finished_at_ms: Option<u64>,
+    // Weighted resource cost of the run (`COST_*`); unset for executions
+    // that did not run, such as result cache hits.
+    #[serde(default)]
+    pub cost: Option<f64>,
+    // Ran with `deterministic`, so its output is expected to be reproducible.
+    #[serde(default)]
+    pub deterministic: bool,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct ExecutionEvent {
     pub ts_ms: u64,
     pub stage: String,
     pub message: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// `ttl_secs` defaults to a day, at most SHARE_LINK_MAX_TTL_SECS. A redacted
+// link shows the code and result but not the other inputs (stdin, args, env,
+// files, metadata, sources) or the tenant.
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct ShareRequest {
+    #[serde(default)]
+    pub ttl_secs: Option<u64>,
+    #[serde(default)]
+    pub redact: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ShareLink {
+    pub url: String,
+    pub expires_at_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct CreateExecutionResponse {
     pub id: Uuid,
     pub status: ExecutionStatus,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct ExecutionSummaryResponse {
     pub id: Uuid,
     pub tenant_id: String,
@@ -135,11 +840,222 @@ pub struct ExecutionSummaryResponse {
     pub created_at_ms: u64,
     pub started_at_ms: Option<u64>,
     pub finished_at_ms: Option<u64>,
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl From<ExecutionRecord> for ExecutionSummaryResponse {
+    fn from(record: ExecutionRecord) -> Self {
+        Self {
+            id: record.id,
+            tenant_id: record.tenant_id,
+            status: record.status,
+            created_at_ms: record.created_at_ms,
+            started_at_ms: record.started_at_ms,
+            finished_at_ms: record.finished_at_ms,
+            metadata: record.request.metadata,
+        }
+    }
+}
+
+// The fixed part of executions submitted through a named template; each
+// submission only adds its code and input.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ExecutionTemplate {
+    pub language: Language,
+    #[serde(default)]
+    pub version: Option<String>,
+    #[serde(default)]
+    pub files: Vec<InputFile>,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    #[serde(default)]
+    pub secrets: BTreeMap<String, String>,
+    pub limits: Option<ExecutionLimits>,
+    #[serde(default)]
+    pub profile: Option<String>,
+    #[serde(default)]
+    pub test_cases: Vec<TestCase>,
+    #[serde(default)]
+    pub dependencies: Option<String>,
+    #[serde(default)]
+    pub mode: Option<ExecutionMode>,
+    #[serde(default)]
+    pub backend: Option<SandboxBackendKind>,
+    #[serde(default)]
+    pub deterministic: bool,
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TemplateInfo {
+    pub name: String,
+    pub tenant_id: String,
+    pub updated_at_ms: u64,
+    pub template: ExecutionTemplate,
+}
+
+// A secret's value is never returned.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SecretInfo {
+    pub name: String,
+    pub updated_at_ms: u64,
+}
+
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct PutSecretRequest {
+    pub value: String,
+}
+
+// `files` are added to the template's; `args`, when given, replace them.
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct TemplateExecutionRequest {
+    pub code: String,
+    #[serde(default)]
+    pub stdin: String,
+    #[serde(default)]
+    pub args: Option<Vec<String>>,
+    #[serde(default)]
+    pub files: Vec<InputFile>,
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+    #[serde(default)]
+    pub priority: Option<u8>,
+}
+
+impl ExecutionTemplate {
+    pub fn instantiate(&self, submission: TemplateExecutionRequest) -> ExecutionRequest {
+        let mut files = self.files.clone();
+        files.extend(submission.files);
+        ExecutionRequest {
+            language: self.language.clone(),
+            filename: None,
+            code: submission.code,
+            stdin: submission.stdin,
+            args: submission.args.unwrap_or_else(|| self.args.clone()),
+            env: self.env.clone(),
+            secrets: self.secrets.clone(),
+            allow_network: false,
+            egress: Vec::new(),
+            limits: self.limits.clone(),
+            profile: self.profile.clone(),
+            mode: self.mode.clone(),
+            test_cases: self.test_cases.clone(),
+            metadata: submission.metadata,
+            image: None,
+            version: self.version.clone(),
+            dependencies: self.dependencies.clone(),
+            files,
+            output_encoding: OutputEncoding::default(),
+            cache: false,
+            gpu: false,
+            backend: self.backend,
+            interactive: false,
+            combined_output: false,
+            git: None,
+            archive: None,
+            benchmark: None,
+            deterministic: self.deterministic,
+            seed: self.seed,
+            priority: submission.priority,
+            language_detected: false,
+        }
+    }
+}
+
+// Opens a REPL session: one long-lived runtime that keeps its state
+// between evaluations. `limits` apply to the whole session, except
+// `timeout_ms`, which bounds each evaluation.
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct CreateSessionRequest {
+    pub language: Language,
+    #[serde(default)]
+    pub backend: Option<SandboxBackendKind>,
+    pub limits: Option<ExecutionLimits>,
+    #[serde(default)]
+    pub profile: Option<String>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SessionInfo {
+    pub id: Uuid,
+    pub tenant_id: String,
+    pub language: Language,
+    pub backend: SandboxBackendKind,
+    pub limits: ExecutionLimits,
+    pub created_at_ms: u64,
+    pub last_used_at_ms: u64,
+    pub evals: u64,
+    // Checkpointed and stopped; the next evaluation restores it.
+    #[serde(default)]
+    pub suspended: bool,
+    // Names defined in the session, filled in when inspecting it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub variables: Option<Vec<SessionVariable>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SessionVariable {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub type_name: String,
+    pub repr: String,
+}
+
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct EvalRequest {
+    pub code: String,
+}
+
+// `value` is the representation of a trailing expression; `error` carries
+// the exception raised by the code. A timed-out evaluation, or one that took
+// the runtime down, closes the session.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct EvalResult {
+    #[serde(default)]
+    pub stdout: String,
+    #[serde(default)]
+    pub stderr: String,
+    #[serde(default)]
+    pub value: Option<String>,
+    #[serde(default)]
+    pub error: Option<String>,
+    #[serde(default)]
+    pub stdout_truncated: bool,
+    #[serde(default)]
+    pub stderr_truncated: bool,
+    #[serde(default)]
+    pub duration_ms: u64,
+    #[serde(default)]
+    pub timed_out: bool,
+    #[serde(default)]
+    pub session_closed: bool,
+}
+
+// Totals over a tenant's executions created in `[from_ms, to_ms)`. Resource
+// figures add up every sandbox run, so each test case counts.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct UsageReport {
+    pub tenant_id: String,
+    pub from_ms: u64,
+    pub to_ms: u64,
+    pub executions: u64,
+    pub by_status: BTreeMap<String, u64>,
+    // User plus system CPU time.
+    pub cpu_ms: u64,
+    pub duration_ms: u64,
+    pub stdout_bytes: u64,
+    pub stderr_bytes: u64,
+    pub cost: f64,
 }
 
 #[cfg(test)]
 mod tests {
-    use super::ExecutionLimits;
+    use super::{ExecutionLimits, FileEncoding, InputFile, Language};
 
     #[test]
     fn normalizes_limits_to_safe_bounds() {
@@ -160,4 +1076,30 @@ mod tests {
         assert_eq!(normalized.max_file_size_bytes, 1024);
         assert_eq!(normalized.max_output_bytes, 4 * 1024 * 1024);
     }
+
+    #[test]
+    fn rejects_input_files_outside_workspace() {
+        let file = |name: &str| InputFile {
+            name: name.to_string(),
+            content: "aGk=".to_string(),
+            encoding: FileEncoding::Base64,
+        };
+        assert!(file("data/input.csv").relative_path().is_some());
+        assert!(file("../etc/passwd").relative_path().is_none());
+        assert!(file("/etc/passwd").relative_path().is_none());
+        assert!(file("./a").relative_path().is_none());
+        assert!(file("").relative_path().is_none());
+        assert_eq!(file("x").decode().unwrap(), b"hi");
+    }
+
+    #[test]
+    fn language_names_parse_back() {
+        for language in Language::ALL {
+            assert_eq!(
+                serde_json::from_value::<Language>(language.as_str().into()).unwrap(),
+                *language,
+                "{language:?}"
+            );
+        }
+    }
 }
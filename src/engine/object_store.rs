@@ -0,0 +1,229 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use crate::engine::{audit::civil_date, config::EngineConfig};
+
+const SIGNED_HEADERS: &str = "host;x-amz-content-sha256;x-amz-date";
+
+// An S3-compatible bucket (AWS, MinIO, R2, ...) for what is too large to
+// keep in the execution store: run output over OBJECT_STORE_OFFLOAD_BYTES
+// and archived records. Requests are signed with SigV4 and use path-style
+// URLs, which every compatible service accepts.
+#[derive(Clone)]
+pub struct ObjectStore {
+    client: reqwest::Client,
+    endpoint: String,
+    host: String,
+    bucket: String,
+    prefix: String,
+    region: String,
+    access_key_id: String,
+    secret_access_key: String,
+}
+
+impl ObjectStore {
+    // None unless OBJECT_STORE_BUCKET is set.
+    pub fn from_config(config: &EngineConfig) -> anyhow::Result<Option<Self>> {
+        let Some(bucket) = &config.object_store_bucket else {
+            return Ok(None);
+        };
+        let endpoint = config
+            .object_store_endpoint
+            .clone()
+            .unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", config.object_store_region));
+        let url = reqwest::Url::parse(&endpoint).context("invalid OBJECT_STORE_ENDPOINT")?;
+        let host = url
+            .host_str()
+            .context("OBJECT_STORE_ENDPOINT has no host")?;
+        let host = match url.port() {
+            Some(port) => format!("{host}:{port}"),
+            None => host.to_string(),
+        };
+        Ok(Some(Self {
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_millis(config.object_store_timeout_ms))
+                .build()?,
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            host,
+            bucket: bucket.clone(),
+            prefix: config.object_store_prefix.clone(),
+            region: config.object_store_region.clone(),
+            access_key_id: config
+                .object_store_access_key_id
+                .clone()
+                .context("OBJECT_STORE_BUCKET needs OBJECT_STORE_ACCESS_KEY_ID")?,
+            secret_access_key: config
+                .object_store_secret_access_key
+                .clone()
+                .context("OBJECT_STORE_BUCKET needs OBJECT_STORE_SECRET_ACCESS_KEY")?,
+        }))
+    }
+
+    pub async fn put(&self, key: &str, body: Vec<u8>) -> anyhow::Result<()> {
+        self.send(reqwest::Method::PUT, key, body)
+            .await?
+            .error_for_status()
+            .with_context(|| format!("storing object {key} failed"))?;
+        Ok(())
+    }
+
+    // None when there is no such object.
+    pub async fn get(&self, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let response = self.send(reqwest::Method::GET, key, Vec::new()).await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let body = response
+            .error_for_status()
+            .with_context(|| format!("reading object {key} failed"))?
+            .bytes()
+            .await?;
+        Ok(Some(body.to_vec()))
+    }
+
+    async fn send(
+        &self,
+        method: reqwest::Method,
+        key: &str,
+        body: Vec<u8>,
+    ) -> anyhow::Result<reqwest::Response> {
+        let path = format!(
+            "/{}/{}",
+            encode_path(&self.bucket),
+            encode_path(&format!("{}{key}", self.prefix))
+        );
+        let payload_hash = hex::encode(Sha256::digest(&body));
+        let amz_date = amz_date(SystemTime::now());
+        let authorization = self.authorization(method.as_str(), &path, &payload_hash, &amz_date);
+        let response = self
+            .client
+            .request(method, format!("{}{path}", self.endpoint))
+            .header("x-amz-date", &amz_date)
+            .header("x-amz-content-sha256", &payload_hash)
+            .header("authorization", authorization)
+            .body(body)
+            .send()
+            .await
+            .with_context(|| format!("object store request for {key} failed"))?;
+        Ok(response)
+    }
+
+    fn authorization(
+        &self,
+        method: &str,
+        path: &str,
+        payload_hash: &str,
+        amz_date: &str,
+    ) -> String {
+        let date = &amz_date[..8];
+        let scope = format!("{date}/{}/s3/aws4_request", self.region);
+        let canonical_request = format!(
+            "{method}\n{path}\n\nhost:{}\nx-amz-content-sha256:{payload_hash}\nx-amz-date:{amz_date}\n\n{SIGNED_HEADERS}\n{payload_hash}",
+            self.host
+        );
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+            hex::encode(Sha256::digest(canonical_request))
+        );
+        let signing_key = [date, &self.region, "s3", "aws4_request"].iter().fold(
+            format!("AWS4{}", self.secret_access_key).into_bytes(),
+            |key, part| hmac(&key, part.as_bytes()),
+        );
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={SIGNED_HEADERS}, Signature={}",
+            self.access_key_id,
+            hex::encode(hmac(&signing_key, string_to_sign.as_bytes()))
+        )
+    }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac takes keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+// `20240102T030405Z`
+fn amz_date(now: SystemTime) -> String {
+    let secs = now
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (year, month, day) = civil_date((secs / 86_400) as i64);
+    let secs = secs % 86_400;
+    format!(
+        "{year:04}{month:02}{day:02}T{:02}{:02}{:02}Z",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+// URI-encodes everything but unreserved characters and `/`, as SigV4
+// expects of the canonical path.
+fn encode_path(path: &str) -> String {
+    path.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use sha2::{Digest, Sha256};
+
+    use super::{ObjectStore, amz_date, encode_path};
+    use crate::engine::config::EngineConfig;
+
+    #[test]
+    fn formats_dates_and_encodes_paths() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_704_164_645);
+        assert_eq!(amz_date(now), "20240102T030405Z");
+        assert_eq!(
+            encode_path("engine/records/a b+c~.json"),
+            "engine/records/a%20b%2Bc~.json"
+        );
+    }
+
+    #[test]
+    fn signs_path_style_requests() {
+        let mut config = EngineConfig::from_env();
+        assert!(ObjectStore::from_config(&config).unwrap().is_none());
+        config.object_store_bucket = Some("runs".into());
+        config.object_store_endpoint = Some("http://127.0.0.1:9000/".into());
+        config.object_store_region = "us-east-1".into();
+        config.object_store_access_key_id = Some("AKIDEXAMPLE".into());
+        config.object_store_secret_access_key = None;
+        assert!(ObjectStore::from_config(&config).is_err());
+        config.object_store_secret_access_key =
+            Some("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".into());
+        let store = ObjectStore::from_config(&config).unwrap().unwrap();
+        assert_eq!(
+            (store.endpoint.as_str(), store.host.as_str()),
+            ("http://127.0.0.1:9000", "127.0.0.1:9000")
+        );
+
+        let payload_hash = hex::encode(Sha256::digest(b""));
+        let authorization = store.authorization(
+            "PUT",
+            "/runs/engine/records/a%20b.json",
+            &payload_hash,
+            "20240102T030405Z",
+        );
+        assert_eq!(
+            authorization,
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20240102/us-east-1/s3/aws4_request, \
+             SignedHeaders=host;x-amz-content-sha256;x-amz-date, \
+             Signature=c5112ddd7e0d98c78fb974309e124a6743c3b2bce807bc4e34ce59b114b942f0"
+        );
+    }
+}
@@ -0,0 +1,253 @@
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashMap},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+use super::{JobQueue, QueuedJob};
+
+// How often a waiting `pop` looks for expired leases.
+const LEASE_CHECK: Duration = Duration::from_secs(1);
+
+// A bounded in-process priority queue; everything queued is lost when the
+// engine stops. A dequeued job is leased to its worker until acknowledged,
+// and goes out again if the lease runs out without being renewed (its worker
+// panicked or hung).
+pub struct MemoryQueue {
+    capacity: usize,
+    waiting: Mutex<Waiting>,
+    pushed: Notify,
+    lease: Duration,
+    // By receipt, with when the lease runs out.
+    leased: Mutex<HashMap<String, (QueuedJob, Instant)>>,
+}
+
+#[derive(Default)]
+struct Waiting {
+    jobs: BinaryHeap<Entry>,
+    next_seq: u64,
+}
+
+// Highest priority first, then oldest first.
+struct Entry {
+    key: (u8, Reverse<u64>),
+    job: QueuedJob,
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+impl Eq for Entry {}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key.cmp(&other.key)
+    }
+}
+
+impl MemoryQueue {
+    pub fn new(capacity: usize, lease: Duration) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            waiting: Default::default(),
+            pushed: Notify::new(),
+            lease,
+            leased: Default::default(),
+        }
+    }
+
+    fn lease_out(&self, mut job: QueuedJob) -> QueuedJob {
+        let receipt = Uuid::new_v4().simple().to_string();
+        job.receipt = Some(receipt.clone());
+        self.leased
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(receipt, (job.clone(), Instant::now() + self.lease));
+        job
+    }
+
+    fn expired(&self) -> Option<QueuedJob> {
+        let mut leased = self.leased.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let receipt = leased
+            .iter()
+            .find(|(_, (_, expires))| *expires <= now)
+            .map(|(receipt, _)| receipt.clone())?;
+        leased.remove(&receipt).map(|(job, _)| job)
+    }
+
+    fn take(&self) -> Option<QueuedJob> {
+        self.waiting
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .jobs
+            .pop()
+            .map(|entry| entry.job)
+    }
+}
+
+#[async_trait]
+impl JobQueue for MemoryQueue {
+    async fn push(&self, job: QueuedJob) -> Option<QueuedJob> {
+        {
+            let mut waiting = self.waiting.lock().unwrap_or_else(|e| e.into_inner());
+            if waiting.jobs.len() >= self.capacity {
+                return Some(job);
+            }
+            let seq = waiting.next_seq;
+            waiting.next_seq += 1;
+            waiting.jobs.push(Entry {
+                key: (job.priority(), Reverse(seq)),
+                job,
+            });
+        }
+        self.pushed.notify_one();
+        None
+    }
+
+    async fn pop(&self) -> Option<QueuedJob> {
+        loop {
+            if let Some(job) = self.expired() {
+                tracing::warn!(execution_id = %job.id, "job lease expired, redelivering");
+                return Some(self.lease_out(job));
+            }
+            if let Some(job) = self.take() {
+                return Some(self.lease_out(job));
+            }
+            // A push between `take` and here leaves a permit, so it is not
+            // missed.
+            let _ = tokio::time::timeout(LEASE_CHECK, self.pushed.notified()).await;
+        }
+    }
+
+    async fn ack(&self, receipt: &str) {
+        self.leased
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(receipt);
+    }
+
+    async fn renew(&self, receipt: &str) -> bool {
+        match self
+            .leased
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get_mut(receipt)
+        {
+            Some((_, expires)) => {
+                *expires = Instant::now() + self.lease;
+                true
+            }
+            None => false,
+        }
+    }
+
+    async fn depth(&self) -> usize {
+        self.waiting
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .jobs
+            .len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use tracing::Span;
+
+    use super::MemoryQueue;
+    use crate::engine::{
+        models::ExecutionLimits,
+        queue::{JobQueue, QueuedJob},
+    };
+
+    fn job(priority: Option<u8>) -> QueuedJob {
+        QueuedJob {
+            id: uuid::Uuid::new_v4(),
+            tenant_id: "t".into(),
+            request: serde_json::from_value(
+                serde_json::json!({ "language": "python", "code": "", "priority": priority }),
+            )
+            .unwrap(),
+            limits: ExecutionLimits {
+                cpu_cores: 1.0,
+                memory_mb: 256,
+                timeout_ms: 1000,
+                max_processes: 8,
+                max_file_size_bytes: 1024,
+                max_output_bytes: 1024,
+            },
+            secrets: Default::default(),
+            stdin_file: None,
+            enqueued_at: Instant::now(),
+            attempts: 0,
+            last_worker: None,
+            receipt: None,
+            span: Span::none(),
+            queue_wait: Span::none(),
+        }
+    }
+
+    #[tokio::test]
+    async fn dequeues_higher_priorities_first_then_in_order() {
+        let queue = MemoryQueue::new(4, Duration::from_secs(60));
+        let jobs = [job(None), job(Some(2)), job(Some(0)), job(Some(2))];
+        let ids: Vec<_> = jobs.iter().map(|job| job.id).collect();
+        for job in jobs {
+            assert!(queue.push(job).await.is_none());
+        }
+        assert!(queue.push(job(Some(3))).await.is_some());
+        assert_eq!(queue.depth().await, 4);
+        let mut order = Vec::new();
+        for _ in 0..4 {
+            order.push(queue.pop().await.unwrap().id);
+        }
+        assert_eq!(order, [ids[1], ids[3], ids[0], ids[2]]);
+    }
+
+    #[tokio::test]
+    async fn redelivers_jobs_whose_lease_ran_out() {
+        let queue = MemoryQueue::new(4, Duration::from_millis(100));
+        let job = job(None);
+        let id = job.id;
+        queue.push(job).await;
+        let first = queue.pop().await.unwrap();
+        let receipt = first.receipt.unwrap();
+
+        // Renewed leases hold; a job dequeued again gets a new receipt.
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(queue.renew(&receipt).await);
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(queue.expired().is_none());
+        let second = tokio::time::timeout(Duration::from_secs(3), queue.pop())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(second.id, id);
+        assert_ne!(second.receipt.as_deref(), Some(receipt.as_str()));
+        assert!(!queue.renew(&receipt).await);
+
+        queue.ack(second.receipt.as_deref().unwrap()).await;
+        assert!(
+            tokio::time::timeout(Duration::from_millis(1500), queue.pop())
+                .await
+                .is_err()
+        );
+    }
+}
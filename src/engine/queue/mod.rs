@@ -0,0 +1,361 @@
+mod memory;
+mod redis;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use tracing::Span;
+use uuid::Uuid;
+
+use crate::engine::{
+    config::{EngineConfig, QueueBackendKind},
+    error::EngineError,
+    metrics::MetricsRegistry,
+    models::{ExecutionLimits, ExecutionRequest, Language},
+};
+
+pub use memory::MemoryQueue;
+pub use redis::{RedisQueues, connection_config};
+
+#[derive(Debug, Clone)]
+pub struct QueuedJob {
+    pub id: Uuid,
+    pub tenant_id: String,
+    pub request: ExecutionRequest,
+    pub limits: ExecutionLimits,
+    // Secret values by environment variable, resolved at submission.
+    pub secrets: BTreeMap<String, String>,
+    // A spooled stdin upload the program reads instead of `request.stdin`.
+    pub stdin_file: Option<PathBuf>,
+    pub enqueued_at: Instant,
+    // Runs that already failed on an infrastructure error, and the worker
+    // that ran the last one.
+    pub attempts: u32,
+    pub last_worker: Option<usize>,
+    // Set by queues that redeliver jobs whose lease ran out; passed to
+    // `renew` while the job runs and to `ack` once it is done with.
+    pub receipt: Option<String>,
+    // Root span of the execution's trace, and its queue-wait child, which the
+    // worker closes when it claims the job.
+    pub span: Span,
+    pub queue_wait: Span,
+}
+
+impl QueuedJob {
+    pub fn priority(&self) -> u8 {
+        self.request.priority.unwrap_or(0).min(MAX_PRIORITY)
+    }
+}
+
+// Jobs of a higher priority are dequeued ahead of all lower ones; within a
+// priority they come out in submission order.
+pub const MAX_PRIORITY: u8 = 3;
+
+// Before any execution has finished, runs are assumed to take this long.
+pub(crate) const ASSUMED_RUN_TIME: Duration = Duration::from_secs(1);
+const MAX_RETRY_AFTER_SECS: u64 = 300;
+
+pub const DEFAULT_POOL: &str = "default";
+pub const GPU_POOL: &str = "gpu";
+
+// New submissions are turned away once this many jobs are queued for their
+// pool, or when a new job would wait longer than `max_wait` for a worker.
+#[derive(Debug, Clone, Copy)]
+pub struct Admission {
+    pub max_depth: usize,
+    pub max_wait: Option<Duration>,
+}
+
+#[async_trait]
+pub trait JobQueue: Send + Sync {
+    // Adds a job without waiting for room; the job comes back when the queue
+    // is full or unreachable.
+    async fn push(&self, job: QueuedJob) -> Option<QueuedJob>;
+    // Waits for the next job; `None` once the queue is closed.
+    async fn pop(&self) -> Option<QueuedJob>;
+    // Marks a popped job as done so it is not delivered again.
+    async fn ack(&self, _receipt: &str) {}
+    // Extends the lease on a popped job; called with every worker heartbeat.
+    // False once the lease ran out and the job went to another worker.
+    async fn renew(&self, _receipt: &str) -> bool {
+        true
+    }
+    // Jobs waiting for a worker.
+    async fn depth(&self) -> usize;
+}
+
+// Opens the queue for each worker pool.
+#[derive(Clone)]
+pub enum QueueBackend {
+    Memory { capacity: usize, lease: Duration },
+    Redis(RedisQueues),
+}
+
+impl QueueBackend {
+    pub async fn from_config(config: &EngineConfig) -> anyhow::Result<Self> {
+        match config.queue_backend {
+            QueueBackendKind::Memory => Ok(Self::Memory {
+                capacity: config.queue_capacity,
+                lease: Duration::from_millis(config.queue_lease_ms.max(1)),
+            }),
+            QueueBackendKind::Redis => Ok(Self::Redis(RedisQueues::connect(config).await?)),
+        }
+    }
+
+    fn open(&self, pool: &str) -> Arc<dyn JobQueue> {
+        match self {
+            Self::Memory { capacity, lease } => Arc::new(MemoryQueue::new(*capacity, *lease)),
+            Self::Redis(queues) => Arc::new(queues.open(pool)),
+        }
+    }
+}
+
+// Each worker pool takes jobs from its own queue.
+#[derive(Clone)]
+struct PoolQueue {
+    queue: Arc<dyn JobQueue>,
+    workers: usize,
+}
+
+#[derive(Clone)]
+pub struct Scheduler {
+    pools: BTreeMap<String, PoolQueue>,
+    language_pools: HashMap<Language, String>,
+    backend: QueueBackend,
+    admission: Admission,
+    metrics: Arc<MetricsRegistry>,
+}
+
+impl Scheduler {
+    // Pools are added with `with_pool`; `DEFAULT_POOL` must be one of them.
+    pub fn new(backend: QueueBackend, admission: Admission, metrics: Arc<MetricsRegistry>) -> Self {
+        Self {
+            pools: BTreeMap::new(),
+            language_pools: HashMap::new(),
+            backend,
+            admission,
+            metrics,
+        }
+    }
+
+    // `workers` is the pool's (maximum) size, used to estimate queue wait.
+    pub fn with_pool(mut self, name: &str, workers: usize) -> Self {
+        let queue = self.backend.open(name);
+        self.pools
+            .insert(name.to_string(), PoolQueue { queue, workers });
+        self
+    }
+
+    // A pool that takes every job in `languages` (except GPU jobs).
+    pub fn with_language_pool(
+        mut self,
+        name: &str,
+        languages: &[Language],
+        workers: usize,
+    ) -> Self {
+        for language in languages {
+            self.language_pools
+                .insert(language.clone(), name.to_string());
+        }
+        self.with_pool(name, workers)
+    }
+
+    pub fn has_pool(&self, name: &str) -> bool {
+        self.pools.contains_key(name)
+    }
+
+    // The pool whose workers run this request.
+    pub fn route(&self, request: &ExecutionRequest) -> &str {
+        if request.gpu && self.has_pool(GPU_POOL) {
+            return GPU_POOL;
+        }
+        self.language_pools
+            .get(&request.language)
+            .map_or(DEFAULT_POOL, String::as_str)
+    }
+
+    fn pool_queue(&self, pool: &str) -> &PoolQueue {
+        self.pools
+            .get(pool)
+            .or_else(|| self.pools.get(DEFAULT_POOL))
+            .expect("scheduler has no default pool")
+    }
+
+    pub async fn submit(&self, job: QueuedJob) -> Result<(), EngineError> {
+        let queue = self.pool_queue(self.route(&job.request));
+        let depth = queue.queue.depth().await;
+        let wait = self.time_to_run(queue, depth);
+        let too_slow = self.admission.max_wait.is_some_and(|max| wait > max);
+        if depth >= self.admission.max_depth || too_slow {
+            return Err(self.queue_full(queue, depth, wait));
+        }
+        let tenant_id = job.tenant_id.clone();
+        if queue.queue.push(job).await.is_some() {
+            return Err(self.queue_full(queue, depth, wait));
+        }
+        self.metrics.submitted(&tenant_id);
+        Ok(())
+    }
+
+    pub async fn depth(&self, pool: &str) -> usize {
+        self.pool_queue(pool).queue.depth().await
+    }
+
+    // How long the pool's workers need to get through `jobs` queued jobs.
+    fn time_to_run(&self, queue: &PoolQueue, jobs: usize) -> Duration {
+        let average = self
+            .metrics
+            .average_execution_duration()
+            .unwrap_or(ASSUMED_RUN_TIME);
+        average.mul_f64(jobs as f64 / queue.workers.max(1) as f64)
+    }
+
+    // Retry-After is the time until both limits would admit the job again.
+    fn queue_full(&self, queue: &PoolQueue, depth: usize, wait: Duration) -> EngineError {
+        let excess = depth.saturating_sub(self.admission.max_depth) + 1;
+        let until_depth = self.time_to_run(queue, excess);
+        let until_wait = self
+            .admission
+            .max_wait
+            .map_or(Duration::ZERO, |max| wait.saturating_sub(max));
+        let retry_after = until_depth.max(until_wait).as_secs_f64().ceil() as u64;
+        EngineError::QueueFull {
+            retry_after_secs: retry_after.clamp(1, MAX_RETRY_AFTER_SECS),
+        }
+    }
+
+    // Puts a started job back for another attempt, or returns it when the
+    // queue is full. Does not wait for room: a worker blocked on a full queue
+    // could otherwise never drain it.
+    pub async fn retry(&self, job: QueuedJob, from: &dyn JobQueue) -> Option<QueuedJob> {
+        let rejected = self.hand_off(job, from).await;
+        if rejected.is_none() {
+            self.metrics.retried();
+        }
+        rejected
+    }
+
+    // Passes a dequeued but unstarted job on to another worker. The copy
+    // taken from `from` is acknowledged once the new one is queued.
+    pub async fn hand_off(&self, mut job: QueuedJob, from: &dyn JobQueue) -> Option<QueuedJob> {
+        let receipt = job.receipt.take();
+        match self
+            .pool_queue(self.route(&job.request))
+            .queue
+            .push(job)
+            .await
+        {
+            None => {
+                if let Some(receipt) = receipt {
+                    from.ack(&receipt).await;
+                }
+                None
+            }
+            Some(mut job) => {
+                job.receipt = receipt;
+                Some(job)
+            }
+        }
+    }
+
+    pub fn queue(&self, pool: &str) -> Arc<dyn JobQueue> {
+        self.pool_queue(pool).queue.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use tracing::Span;
+    use uuid::Uuid;
+
+    use super::{Admission, DEFAULT_POOL, GPU_POOL, QueueBackend, QueuedJob, Scheduler};
+    use crate::engine::{
+        error::EngineError,
+        metrics::MetricsRegistry,
+        models::{ExecutionLimits, Language},
+    };
+
+    fn job() -> QueuedJob {
+        QueuedJob {
+            id: Uuid::new_v4(),
+            tenant_id: "t".into(),
+            request: serde_json::from_value(
+                serde_json::json!({ "language": "python", "code": "" }),
+            )
+            .unwrap(),
+            limits: ExecutionLimits {
+                cpu_cores: 1.0,
+                memory_mb: 256,
+                timeout_ms: 1000,
+                max_processes: 8,
+                max_file_size_bytes: 1024,
+                max_output_bytes: 1024,
+            },
+            secrets: Default::default(),
+            stdin_file: None,
+            enqueued_at: Instant::now(),
+            attempts: 0,
+            last_worker: None,
+            receipt: None,
+            span: Span::none(),
+            queue_wait: Span::none(),
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_past_max_depth_with_retry_after() {
+        let admission = Admission {
+            max_depth: 3,
+            max_wait: None,
+        };
+        let backend = QueueBackend::Memory {
+            capacity: 16,
+            lease: Duration::from_secs(60),
+        };
+        let scheduler = Scheduler::new(backend, admission, Arc::new(MetricsRegistry::new()))
+            .with_pool(DEFAULT_POOL, 1);
+        for _ in 0..3 {
+            scheduler.submit(job()).await.unwrap();
+        }
+        let err = scheduler.submit(job()).await.unwrap_err();
+        assert!(matches!(
+            err,
+            EngineError::QueueFull {
+                retry_after_secs: 1
+            }
+        ));
+        assert_eq!(scheduler.depth(DEFAULT_POOL).await, 3);
+    }
+
+    #[test]
+    fn routes_by_gpu_flag_then_language() {
+        let admission = Admission {
+            max_depth: 8,
+            max_wait: None,
+        };
+        let backend = QueueBackend::Memory {
+            capacity: 8,
+            lease: Duration::from_secs(60),
+        };
+        let scheduler = Scheduler::new(backend, admission, Arc::new(MetricsRegistry::new()))
+            .with_pool(DEFAULT_POOL, 4)
+            .with_pool(GPU_POOL, 1)
+            .with_language_pool("compiled", &[Language::Rust], 2);
+        let mut request = job().request;
+        assert_eq!(scheduler.route(&request), DEFAULT_POOL);
+        request.language = Language::Rust;
+        assert_eq!(scheduler.route(&request), "compiled");
+        request.gpu = true;
+        assert_eq!(scheduler.route(&request), GPU_POOL);
+    }
+}
@@ -0,0 +1,485 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use dashmap::DashMap;
+use redis::{
+    AsyncCommands, Client,
+    aio::{ConnectionManager, ConnectionManagerConfig},
+    streams::{
+        StreamAutoClaimOptions, StreamAutoClaimReply, StreamClaimOptions, StreamId,
+        StreamPendingCountReply, StreamPendingId, StreamReadOptions, StreamReadReply,
+    },
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, OnceCell};
+use tracing::Span;
+use uuid::Uuid;
+
+use super::{JobQueue, MAX_PRIORITY, QueuedJob};
+use crate::engine::{
+    config::EngineConfig,
+    encryption::RecordCipher,
+    models::{ExecutionLimits, ExecutionRequest},
+};
+
+const GROUP: &str = "workers";
+const FIELD: &str = "job";
+// A blocked read holds the reader connection, so it is kept short.
+const BLOCK_MS: usize = 1000;
+const CLAIM_INTERVAL: Duration = Duration::from_secs(10);
+const CLAIM_BATCH: usize = 16;
+
+// The crate's default backoff grows far too fast to wait out at startup.
+pub fn connection_config() -> ConnectionManagerConfig {
+    ConnectionManagerConfig::new()
+        .set_connection_timeout(Duration::from_secs(5))
+        .set_number_of_retries(3)
+        .set_max_delay(1000)
+}
+
+// The connection shared by every pool's stream.
+#[derive(Clone)]
+pub struct RedisQueues {
+    client: Client,
+    writer: ConnectionManager,
+    prefix: String,
+    consumer: String,
+    capacity: usize,
+    claim_idle: Duration,
+    cipher: RecordCipher,
+}
+
+impl RedisQueues {
+    pub async fn connect(config: &EngineConfig) -> anyhow::Result<Self> {
+        let client = Client::open(config.redis_url.as_str()).context("invalid REDIS_URL")?;
+        let writer = ConnectionManager::new_with_config(client.clone(), connection_config())
+            .await
+            .context("redis connect failed")?;
+        Ok(Self {
+            client,
+            writer,
+            prefix: config.redis_queue_prefix.clone(),
+            consumer: format!("engine-{}", Uuid::new_v4()),
+            capacity: config.queue_capacity.max(1),
+            claim_idle: Duration::from_millis(config.queue_lease_ms.max(1)),
+            cipher: RecordCipher::from_config(config)?,
+        })
+    }
+
+    pub fn open(&self, pool: &str) -> RedisQueue {
+        let key = format!("{}:{pool}", self.prefix);
+        RedisQueue {
+            queues: self.clone(),
+            keys: (0..=MAX_PRIORITY)
+                .map(|priority| match priority {
+                    0 => key.clone(),
+                    _ => format!("{key}:priority-{priority}"),
+                })
+                .collect(),
+            key,
+            reader: Mutex::new(Reader::default()),
+            group: OnceCell::new(),
+            local: DashMap::new(),
+        }
+    }
+}
+
+// One stream per pool and priority, read through a consumer group, highest
+// priority first. A job stays pending until it is acknowledged, and its
+// worker keeps it from going idle with `renew`; entries left idle longer than
+// `claim_idle` (their engine died or the worker hung mid-run) are claimed and
+// run again. Receipts are `<priority>:<entry id>`.
+pub struct RedisQueue {
+    queues: RedisQueues,
+    // Names the pool in logs; also the priority 0 stream.
+    key: String,
+    // By priority.
+    keys: Vec<String>,
+    reader: Mutex<Reader>,
+    group: OnceCell<()>,
+    // Trace context and enqueue time of jobs pushed by this engine.
+    local: DashMap<Uuid, (Instant, Span, Span)>,
+}
+
+#[derive(Default)]
+struct Reader {
+    connection: Option<ConnectionManager>,
+    reclaimed: VecDeque<(usize, StreamId)>,
+    last_claim: Option<Instant>,
+}
+
+// What goes into the stream; spans and timings stay in the process.
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredJob {
+    id: Uuid,
+    tenant_id: String,
+    request: ExecutionRequest,
+    limits: ExecutionLimits,
+    #[serde(default)]
+    secrets: BTreeMap<String, String>,
+    #[serde(default)]
+    stdin_file: Option<PathBuf>,
+    attempts: u32,
+    last_worker: Option<usize>,
+}
+
+impl RedisQueue {
+    async fn ensure_group(&self) -> anyhow::Result<()> {
+        self.group
+            .get_or_try_init(|| async {
+                let mut connection = self.queues.writer.clone();
+                for key in &self.keys {
+                    let created: redis::RedisResult<()> =
+                        connection.xgroup_create_mkstream(key, GROUP, "0").await;
+                    match created {
+                        Err(err) if err.code() != Some("BUSYGROUP") => return Err(err),
+                        _ => {}
+                    }
+                }
+                Ok(())
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn try_push(&self, job: &QueuedJob) -> anyhow::Result<bool> {
+        self.ensure_group().await?;
+        if self.waiting().await? >= self.queues.capacity {
+            return Ok(false);
+        }
+        let payload = serde_json::to_string(&StoredJob {
+            id: job.id,
+            tenant_id: job.tenant_id.clone(),
+            request: job.request.clone(),
+            limits: job.limits.clone(),
+            secrets: job.secrets.clone(),
+            stdin_file: job.stdin_file.clone(),
+            attempts: job.attempts,
+            last_worker: job.last_worker,
+        })?;
+        let payload = self.queues.cipher.seal(&payload)?;
+        // Jobs another engine picked up never come back through `decode`.
+        self.local
+            .retain(|_, (enqueued_at, ..)| enqueued_at.elapsed() < self.queues.claim_idle);
+        self.local.insert(
+            job.id,
+            (job.enqueued_at, job.span.clone(), job.queue_wait.clone()),
+        );
+        let mut connection = self.queues.writer.clone();
+        let key = &self.keys[job.priority() as usize];
+        let added: redis::RedisResult<String> =
+            connection.xadd(key, "*", &[(FIELD, payload)]).await;
+        if added.is_err() {
+            self.local.remove(&job.id);
+        }
+        added?;
+        Ok(true)
+    }
+
+    // Entries not yet delivered to any consumer.
+    async fn waiting(&self) -> anyhow::Result<usize> {
+        self.ensure_group().await?;
+        let mut connection = self.queues.writer.clone();
+        let mut waiting = 0;
+        for key in &self.keys {
+            let length: usize = connection.xlen(key).await?;
+            let pending: redis::streams::StreamPendingReply =
+                connection.xpending(key, GROUP).await?;
+            waiting += length.saturating_sub(pending.count());
+        }
+        Ok(waiting)
+    }
+
+    async fn next(&self) -> anyhow::Result<Option<QueuedJob>> {
+        self.ensure_group().await?;
+        let mut reader = self.reader.lock().await;
+        let mut connection = match &reader.connection {
+            Some(connection) => connection.clone(),
+            None => {
+                let connection = ConnectionManager::new_with_config(
+                    self.queues.client.clone(),
+                    connection_config(),
+                )
+                .await?;
+                reader.connection = Some(connection.clone());
+                connection
+            }
+        };
+        if reader
+            .last_claim
+            .is_none_or(|claimed| claimed.elapsed() >= CLAIM_INTERVAL)
+        {
+            reader.last_claim = Some(Instant::now());
+            for (priority, key) in self.keys.iter().enumerate().rev() {
+                let reply: StreamAutoClaimReply = connection
+                    .xautoclaim_options(
+                        key,
+                        GROUP,
+                        &self.queues.consumer,
+                        self.queues.claim_idle.as_millis() as u64,
+                        "0-0",
+                        StreamAutoClaimOptions::default().count(CLAIM_BATCH),
+                    )
+                    .await?;
+                if !reply.claimed.is_empty() {
+                    tracing::warn!(queue = %key, jobs = reply.claimed.len(), "reclaimed unacknowledged jobs");
+                }
+                reader
+                    .reclaimed
+                    .extend(reply.claimed.into_iter().map(|entry| (priority, entry)));
+            }
+        }
+        // Receipts carry the delivery, so a worker whose entry was reclaimed
+        // by this same engine can tell it lost the lease.
+        let (priority, entry, deliveries) = match reader.reclaimed.pop_front() {
+            Some((priority, entry)) => {
+                match self
+                    .pending(&mut connection, &self.keys[priority], &entry.id)
+                    .await?
+                {
+                    Some(pending) => (priority, entry, pending.times_delivered),
+                    None => return Ok(None),
+                }
+            }
+            None => match self.read_highest(&mut connection).await? {
+                Some((priority, entry)) => (priority, entry, 1),
+                None => {
+                    // Nothing waiting: block until something is added to any
+                    // of the streams, then look again from the top.
+                    let options = StreamReadOptions::default().count(1).block(BLOCK_MS);
+                    let _: Option<StreamReadReply> = connection
+                        .xread_options(&self.keys, &vec!["$"; self.keys.len()], &options)
+                        .await?;
+                    return Ok(None);
+                }
+            },
+        };
+        drop(reader);
+
+        match self.decode(priority, &entry, deliveries) {
+            Ok(job) => Ok(Some(job)),
+            Err(err) => {
+                // Left pending, it would be redelivered forever.
+                tracing::error!(queue = %self.key, entry = %entry.id, error = %format!("{err:#}"), "dropping undecodable job");
+                self.ack(&receipt(priority, &entry.id, deliveries)).await;
+                Ok(None)
+            }
+        }
+    }
+
+    // The oldest new entry of the highest priority that has one, without
+    // waiting.
+    async fn read_highest(
+        &self,
+        connection: &mut ConnectionManager,
+    ) -> anyhow::Result<Option<(usize, StreamId)>> {
+        let options = StreamReadOptions::default()
+            .group(GROUP, &self.queues.consumer)
+            .count(1);
+        for (priority, key) in self.keys.iter().enumerate().rev() {
+            let reply: Option<StreamReadReply> =
+                connection.xread_options(&[key], &[">"], &options).await?;
+            let entry = reply
+                .and_then(|reply| reply.keys.into_iter().next())
+                .and_then(|key| key.ids.into_iter().next());
+            if let Some(entry) = entry {
+                return Ok(Some((priority, entry)));
+            }
+        }
+        Ok(None)
+    }
+
+    // The entry's delivery count and idle time while it is pending for this
+    // engine; None once acknowledged or claimed by another.
+    async fn pending(
+        &self,
+        connection: &mut ConnectionManager,
+        key: &str,
+        id: &str,
+    ) -> redis::RedisResult<Option<StreamPendingId>> {
+        let reply: StreamPendingCountReply = connection
+            .xpending_consumer_count(key, GROUP, id, id, 1, &self.queues.consumer)
+            .await?;
+        Ok(reply.ids.into_iter().next())
+    }
+
+    fn decode(
+        &self,
+        priority: usize,
+        entry: &StreamId,
+        deliveries: usize,
+    ) -> anyhow::Result<QueuedJob> {
+        let payload: String = entry.get(FIELD).context("entry has no job field")?;
+        let stored: StoredJob = serde_json::from_str(&self.queues.cipher.open(&payload)?)?;
+        let (enqueued_at, span, queue_wait) = match self.local.remove(&stored.id) {
+            Some((_, local)) => local,
+            None => {
+                let span = tracing::info_span!(
+                    "execution",
+                    execution_id = %stored.id,
+                    tenant_id = %stored.tenant_id,
+                    language = stored.request.language.as_str(),
+                );
+                let queue_wait = tracing::info_span!(parent: &span, "execution.queue_wait");
+                (Instant::now(), span, queue_wait)
+            }
+        };
+        Ok(QueuedJob {
+            id: stored.id,
+            tenant_id: stored.tenant_id,
+            request: stored.request,
+            limits: stored.limits,
+            secrets: stored.secrets,
+            stdin_file: stored.stdin_file,
+            enqueued_at,
+            attempts: stored.attempts,
+            last_worker: stored.last_worker,
+            receipt: Some(receipt(priority, &entry.id, deliveries)),
+            span,
+            queue_wait,
+        })
+    }
+}
+
+#[async_trait]
+impl JobQueue for RedisQueue {
+    async fn push(&self, job: QueuedJob) -> Option<QueuedJob> {
+        match self.try_push(&job).await {
+            Ok(true) => None,
+            Ok(false) => Some(job),
+            Err(err) => {
+                tracing::warn!(queue = %self.key, error = %format!("{err:#}"), "redis enqueue failed");
+                Some(job)
+            }
+        }
+    }
+
+    async fn pop(&self) -> Option<QueuedJob> {
+        loop {
+            match self.next().await {
+                Ok(Some(job)) => return Some(job),
+                Ok(None) => {}
+                Err(err) => {
+                    tracing::warn!(queue = %self.key, error = %format!("{err:#}"), "redis dequeue failed, retrying");
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+    }
+
+    async fn ack(&self, receipt: &str) {
+        let Some((key, id, _)) = entry(&self.keys, receipt) else {
+            return;
+        };
+        let mut connection = self.queues.writer.clone();
+        let acked: redis::RedisResult<()> = async {
+            let _: usize = connection.xack(key, GROUP, &[id]).await?;
+            let _: usize = connection.xdel(key, &[id]).await?;
+            Ok(())
+        }
+        .await;
+        if let Err(err) = acked {
+            tracing::warn!(queue = %self.key, entry = receipt, error = %err, "redis ack failed");
+        }
+    }
+
+    // Claiming an entry it already owns resets its idle time. Only the
+    // delivery the worker got is renewed, and the claim's min-idle is the idle
+    // time just seen, so an entry reclaimed in between is not taken back.
+    async fn renew(&self, receipt: &str) -> bool {
+        let Some((key, id, deliveries)) = entry(&self.keys, receipt) else {
+            return false;
+        };
+        let mut connection = self.queues.writer.clone();
+        let renewed: redis::RedisResult<bool> = async {
+            let pending = self.pending(&mut connection, key, id).await?;
+            let Some(min_idle) = renewable(pending.as_ref(), deliveries) else {
+                return Ok(false);
+            };
+            let claimed: Vec<String> = connection
+                .xclaim_options(
+                    key,
+                    GROUP,
+                    &self.queues.consumer,
+                    min_idle,
+                    &[id],
+                    StreamClaimOptions::default().with_justid(),
+                )
+                .await?;
+            Ok(!claimed.is_empty())
+        }
+        .await;
+        renewed.unwrap_or_else(|err| {
+            // Unknown for now; checked again on the next heartbeat.
+            tracing::warn!(queue = %self.key, entry = receipt, error = %err, "redis lease renewal failed");
+            true
+        })
+    }
+
+    async fn depth(&self) -> usize {
+        self.waiting().await.unwrap_or_else(|err| {
+            tracing::warn!(queue = %self.key, error = %format!("{err:#}"), "redis depth check failed");
+            0
+        })
+    }
+}
+
+fn receipt(priority: usize, id: &str, deliveries: usize) -> String {
+    format!("{priority}:{id}:{deliveries}")
+}
+
+// The stream, entry id and delivery a receipt refers to.
+fn entry<'a>(keys: &'a [String], receipt: &'a str) -> Option<(&'a str, &'a str, usize)> {
+    let (priority, rest) = receipt.split_once(':')?;
+    let (id, deliveries) = rest.rsplit_once(':')?;
+    let key = keys.get(priority.parse::<usize>().ok()?)?;
+    Some((key, id, deliveries.parse().ok()?))
+}
+
+// The min-idle to renew a lease with, while the entry is still pending for
+// this engine under the delivery the worker got.
+fn renewable(pending: Option<&StreamPendingId>, deliveries: usize) -> Option<usize> {
+    pending
+        .filter(|pending| pending.times_delivered == deliveries)
+        .map(|pending| pending.last_delivered_ms)
+}
+
+#[cfg(test)]
+mod tests {
+    use redis::streams::StreamPendingId;
+
+    use super::{entry, receipt, renewable};
+
+    #[test]
+    fn receipts_round_trip_to_their_entry() {
+        let keys = vec!["jobs:0".to_string(), "jobs:1".to_string()];
+        let issued = receipt(1, "1700000000000-3", 2);
+        assert_eq!(
+            entry(&keys, &issued),
+            Some(("jobs:1", "1700000000000-3", 2))
+        );
+        assert_eq!(entry(&keys, &receipt(2, "1-0", 1)), None);
+        assert_eq!(entry(&keys, "0:1-0"), None);
+        assert_eq!(entry(&keys, "x:1-0:1"), None);
+    }
+
+    #[test]
+    fn renews_only_the_delivery_it_was_given() {
+        let pending = StreamPendingId {
+            id: "1-0".into(),
+            consumer: "engine".into(),
+            last_delivered_ms: 1500,
+            times_delivered: 2,
+        };
+        assert_eq!(renewable(Some(&pending), 2), Some(1500));
+        // Reclaimed since: the count moved past the worker's delivery.
+        assert_eq!(renewable(Some(&pending), 1), None);
+        // Acknowledged or claimed by another engine.
+        assert_eq!(renewable(None, 2), None);
+    }
+}
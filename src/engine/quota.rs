@@ -0,0 +1,324 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Context;
+use redis::{AsyncCommands, Client, aio::ConnectionManager};
+use serde::Serialize;
+
+use crate::engine::{
+    audit::civil_date,
+    config::{EngineConfig, QueueBackendKind, Quota},
+    error::EngineError,
+    queue::connection_config,
+    store::now_ms,
+};
+
+const DAY_SECS: u64 = 24 * 60 * 60;
+
+// Calendar windows in UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Window {
+    Day,
+    Month,
+}
+
+impl Window {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Window::Day => "daily",
+            Window::Month => "monthly",
+        }
+    }
+
+    // The window `now` falls in (days or months since the epoch), and the
+    // seconds until it ends.
+    fn at(self, now: u64) -> (u64, u64) {
+        let days = now / DAY_SECS;
+        match self {
+            Window::Day => (days, (days + 1) * DAY_SECS - now),
+            Window::Month => {
+                let (year, month, day) = civil_date(days as i64);
+                let end = (days + days_in_month(year, month) - u64::from(day) + 1) * DAY_SECS;
+                ((year as u64 - 1970) * 12 + u64::from(month) - 1, end - now)
+            }
+        }
+    }
+}
+
+// A window's count as of now, for the admin API.
+#[derive(Debug, Clone, Serialize)]
+pub struct WindowUsage {
+    pub used: u64,
+    pub limit: u64,
+    pub reset_secs: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct QuotaUsage {
+    pub daily: Option<WindowUsage>,
+    pub monthly: Option<WindowUsage>,
+}
+
+// Executions each tenant may submit per UTC day and month. Counts are kept in
+// Redis when the queue is, so every API node sees the same totals, and in
+// memory otherwise. Without a limit a window is not counted.
+#[derive(Clone)]
+pub struct Quotas {
+    default: Quota,
+    tenants: HashMap<String, Quota>,
+    counters: Counters,
+}
+
+// Counts by tenant, window and the window's day or month number.
+type Counts = HashMap<(String, Window, u64), u64>;
+
+#[derive(Clone)]
+enum Counters {
+    Memory(Arc<Mutex<Counts>>),
+    Redis {
+        connection: ConnectionManager,
+        prefix: String,
+    },
+}
+
+impl Quotas {
+    pub fn in_memory(config: &EngineConfig) -> Self {
+        Self {
+            default: config.execution_quota,
+            tenants: config.tenant_execution_quotas.clone(),
+            counters: Counters::Memory(Arc::default()),
+        }
+    }
+
+    pub async fn connect(config: &EngineConfig) -> anyhow::Result<Self> {
+        let mut quotas = Self::in_memory(config);
+        if config.queue_backend == QueueBackendKind::Redis && quotas.enabled() {
+            let client = Client::open(config.redis_url.as_str()).context("invalid REDIS_URL")?;
+            let connection = ConnectionManager::new_with_config(client, connection_config())
+                .await
+                .context("redis connect failed")?;
+            quotas.counters = Counters::Redis {
+                connection,
+                prefix: format!("{}.quota", config.redis_queue_prefix),
+            };
+        }
+        Ok(quotas)
+    }
+
+    fn enabled(&self) -> bool {
+        self.default.is_limited() || self.tenants.values().any(Quota::is_limited)
+    }
+
+    fn limits(&self, tenant_id: &str) -> [(Window, u64); 2] {
+        let quota = self.tenants.get(tenant_id).unwrap_or(&self.default);
+        [(Window::Day, quota.daily), (Window::Month, quota.monthly)]
+    }
+
+    // Counts one execution against the tenant's windows, or leaves them as
+    // they were and fails when one is used up.
+    pub async fn charge(&self, tenant_id: &str) -> Result<(), EngineError> {
+        let now = now_secs();
+        let mut charged = Vec::new();
+        for (window, limit) in self.limits(tenant_id) {
+            if limit == 0 {
+                continue;
+            }
+            let (bucket, reset_secs) = window.at(now);
+            let used = self
+                .counters
+                .add(tenant_id, window, bucket, reset_secs, 1)
+                .await;
+            charged.push((window, bucket, reset_secs));
+            if used > limit {
+                for (window, bucket, reset_secs) in charged {
+                    self.counters
+                        .add(tenant_id, window, bucket, reset_secs, -1)
+                        .await;
+                }
+                return Err(EngineError::QuotaExceeded {
+                    window: window.as_str(),
+                    limit,
+                    reset_secs,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // Gives back a charge for a submission that was not queued after all.
+    pub async fn refund(&self, tenant_id: &str) {
+        let now = now_secs();
+        for (window, limit) in self.limits(tenant_id) {
+            if limit > 0 {
+                let (bucket, reset_secs) = window.at(now);
+                self.counters
+                    .add(tenant_id, window, bucket, reset_secs, -1)
+                    .await;
+            }
+        }
+    }
+
+    // None for tenants without a quota.
+    pub async fn usage(&self, tenant_id: &str) -> Option<QuotaUsage> {
+        let now = now_secs();
+        let mut usage = QuotaUsage::default();
+        for (window, limit) in self.limits(tenant_id) {
+            if limit == 0 {
+                continue;
+            }
+            let (bucket, reset_secs) = window.at(now);
+            let used = self.counters.get(tenant_id, window, bucket).await;
+            let window_usage = Some(WindowUsage {
+                used: used.min(limit),
+                limit,
+                reset_secs,
+            });
+            match window {
+                Window::Day => usage.daily = window_usage,
+                Window::Month => usage.monthly = window_usage,
+            }
+        }
+        (usage.daily.is_some() || usage.monthly.is_some()).then_some(usage)
+    }
+}
+
+impl Counters {
+    // The count after adding `delta`. Redis errors let the submission through:
+    // an outage should not stop every tenant from submitting.
+    async fn add(
+        &self,
+        tenant_id: &str,
+        window: Window,
+        bucket: u64,
+        reset_secs: u64,
+        delta: i64,
+    ) -> u64 {
+        match self {
+            Counters::Memory(counts) => {
+                let mut counts = counts.lock().unwrap_or_else(|e| e.into_inner());
+                counts
+                    .retain(|(_, kept, kept_bucket), _| *kept != window || *kept_bucket == bucket);
+                let count = counts
+                    .entry((tenant_id.to_string(), window, bucket))
+                    .or_default();
+                *count = count.saturating_add_signed(delta);
+                *count
+            }
+            Counters::Redis { connection, prefix } => {
+                let key = redis_key(prefix, tenant_id, window, bucket);
+                let mut connection = connection.clone();
+                let counted: redis::RedisResult<i64> = redis::pipe()
+                    .atomic()
+                    .incr(&key, delta)
+                    .expire(&key, (reset_secs + DAY_SECS) as i64)
+                    .ignore()
+                    .query_async::<(i64,)>(&mut connection)
+                    .await
+                    .map(|(count,)| count);
+                match counted {
+                    Ok(count) => count.max(0) as u64,
+                    Err(err) => {
+                        tracing::warn!(tenant_id, error = %err, "quota count failed; not enforced");
+                        0
+                    }
+                }
+            }
+        }
+    }
+
+    async fn get(&self, tenant_id: &str, window: Window, bucket: u64) -> u64 {
+        match self {
+            Counters::Memory(counts) => counts
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .get(&(tenant_id.to_string(), window, bucket))
+                .copied()
+                .unwrap_or(0),
+            Counters::Redis { connection, prefix } => {
+                let mut connection = connection.clone();
+                connection
+                    .get::<_, Option<i64>>(redis_key(prefix, tenant_id, window, bucket))
+                    .await
+                    .ok()
+                    .flatten()
+                    .unwrap_or(0)
+                    .max(0) as u64
+            }
+        }
+    }
+}
+
+fn redis_key(prefix: &str, tenant_id: &str, window: Window, bucket: u64) -> String {
+    format!("{prefix}:{}:{bucket}:{tenant_id}", window.as_str())
+}
+
+fn now_secs() -> u64 {
+    now_ms() / 1000
+}
+
+fn days_in_month(year: i64, month: u32) -> u64 {
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Quotas, Window};
+    use crate::engine::{
+        config::{EngineConfig, Quota},
+        error::EngineError,
+    };
+
+    #[tokio::test]
+    async fn counts_executions_per_calendar_window() {
+        // 2024-02-29 12:00 UTC: the month ends at 2024-03-01 00:00.
+        let now = 1_709_208_000;
+        assert_eq!(Window::Day.at(now).1, 12 * 60 * 60);
+        assert_eq!(
+            Window::Month.at(now),
+            ((2024 - 1970) * 12 + 1, 12 * 60 * 60)
+        );
+        assert_eq!(Window::Month.at(1_735_689_599).1, 1);
+
+        let mut config = EngineConfig::from_env();
+        config.execution_quota = Quota {
+            daily: 2,
+            monthly: 0,
+        };
+        config.tenant_execution_quotas.insert(
+            "acme".into(),
+            Quota {
+                daily: 0,
+                monthly: 1,
+            },
+        );
+        let quotas = Quotas::in_memory(&config);
+        assert!(quotas.charge("beta").await.is_ok());
+        assert!(quotas.charge("beta").await.is_ok());
+        assert!(matches!(
+            quotas.charge("beta").await,
+            Err(EngineError::QuotaExceeded {
+                window: "daily",
+                limit: 2,
+                ..
+            })
+        ));
+        assert_eq!(quotas.usage("beta").await.unwrap().daily.unwrap().used, 2);
+        quotas.refund("beta").await;
+        assert!(quotas.charge("beta").await.is_ok());
+
+        assert!(quotas.charge("acme").await.is_ok());
+        assert!(quotas.charge("acme").await.is_err());
+        let usage = quotas.usage("acme").await.unwrap();
+        assert!(usage.daily.is_none());
+        assert_eq!(usage.monthly.unwrap().used, 1);
+    }
+}
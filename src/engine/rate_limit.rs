@@ -34,32 +34,54 @@ impl TokenBucket {
     }
 }
 
+#[derive(Debug, Clone, Copy)]
+struct Rate {
+    burst: f64,
+    refill_per_sec: f64,
+}
+
+impl Rate {
+    fn new(rate_per_minute: u32, burst: u32) -> Self {
+        Self {
+            burst: burst.max(1) as f64,
+            refill_per_sec: (rate_per_minute.max(1) as f64) / 60.0,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct TenantRateLimiter {
     state: std::sync::Arc<tokio::sync::Mutex<HashMap<String, TokenBucket>>>,
-    burst: f64,
-    refill_per_sec: f64,
+    default: Rate,
+    tenants: HashMap<String, Rate>,
     stale_after: Duration,
 }
 
 impl TenantRateLimiter {
     pub fn new(rate_per_minute: u32, burst: u32) -> Self {
-        let refill = (rate_per_minute.max(1) as f64) / 60.0;
         Self {
             state: std::sync::Arc::new(tokio::sync::Mutex::new(HashMap::new())),
-            burst: burst.max(1) as f64,
-            refill_per_sec: refill,
+            default: Rate::new(rate_per_minute, burst),
+            tenants: HashMap::new(),
             stale_after: Duration::from_secs(30 * 60),
         }
     }
 
+    // Replaces the default rate for one tenant.
+    pub fn with_tenant(mut self, tenant_id: &str, rate_per_minute: u32, burst: u32) -> Self {
+        self.tenants
+            .insert(tenant_id.to_string(), Rate::new(rate_per_minute, burst));
+        self
+    }
+
     pub async fn allow(&self, tenant_id: &str) -> bool {
         let now = Instant::now();
         let mut state = self.state.lock().await;
         state.retain(|_, bucket| now.duration_since(bucket.last_refill) < self.stale_after);
+        let rate = self.tenants.get(tenant_id).unwrap_or(&self.default);
         let bucket = state
             .entry(tenant_id.to_string())
-            .or_insert_with(|| TokenBucket::new(self.burst, self.refill_per_sec, now));
+            .or_insert_with(|| TokenBucket::new(rate.burst, rate.refill_per_sec, now));
         bucket.try_take(now)
     }
 }
@@ -78,4 +100,15 @@ mod tests {
         tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
         assert!(limiter.allow("tenant-a").await);
     }
+
+    #[tokio::test]
+    async fn applies_tenant_overrides() {
+        let limiter = TenantRateLimiter::new(60, 1).with_tenant("trusted", 600, 3);
+        for _ in 0..3 {
+            assert!(limiter.allow("trusted").await);
+        }
+        assert!(!limiter.allow("trusted").await);
+        assert!(limiter.allow("dev").await);
+        assert!(!limiter.allow("dev").await);
+    }
 }
@@ -0,0 +1,193 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Context;
+use redis::{
+    AsyncCommands, Client,
+    aio::ConnectionManager,
+    streams::{StreamMaxlen, StreamReadOptions, StreamReadReply},
+};
+use tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
+
+use crate::engine::{
+    config::EngineConfig,
+    models::ExecutionRecord,
+    queue::connection_config,
+    store::{ExecutionStore, now_ms},
+};
+
+// Snapshots kept in the stream; a node that falls further behind misses
+// updates until the record changes again.
+const MAX_LEN: usize = 10_000;
+const BLOCK_MS: usize = 1000;
+
+// Keeps the execution stores of API and worker nodes in step: every local
+// change to a record is published to a Redis stream as a full snapshot, and
+// snapshots from other nodes are applied to the local store.
+pub async fn spawn_replication(
+    config: &EngineConfig,
+    store: Arc<ExecutionStore>,
+) -> anyhow::Result<()> {
+    let client = Client::open(config.redis_url.as_str()).context("invalid REDIS_URL")?;
+    let publisher = ConnectionManager::new_with_config(client.clone(), connection_config())
+        .await
+        .context("redis connect failed")?;
+    let subscriber = ConnectionManager::new_with_config(client, connection_config())
+        .await
+        .context("redis connect failed")?;
+    let key = format!("{}.records", config.redis_queue_prefix);
+    let node = Uuid::new_v4().to_string();
+    tokio::spawn(publish(publisher, key.clone(), node.clone(), store.clone()));
+    tokio::spawn(follow(subscriber, key, node, store));
+    Ok(())
+}
+
+async fn publish(
+    mut connection: ConnectionManager,
+    key: String,
+    node: String,
+    store: Arc<ExecutionStore>,
+) {
+    let mut updates = store.subscribe();
+    loop {
+        let update = match updates.recv().await {
+            Ok(update) => update,
+            Err(RecvError::Lagged(skipped)) => {
+                tracing::warn!(skipped, "record replication lagged");
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        if update.remote {
+            continue;
+        }
+        let Some(payload) = snapshot(&store, &update.id) else {
+            continue;
+        };
+        let published: redis::RedisResult<String> = connection
+            .xadd_maxlen(
+                &key,
+                StreamMaxlen::Approx(MAX_LEN),
+                "*",
+                &[("node", node.as_str()), ("record", payload.as_str())],
+            )
+            .await;
+        if let Err(err) = published {
+            tracing::warn!(execution_id = %update.id, error = %err, "record publish failed");
+        }
+    }
+}
+
+async fn follow(
+    mut connection: ConnectionManager,
+    key: String,
+    node: String,
+    store: Arc<ExecutionStore>,
+) {
+    // Stream ids start with a millisecond timestamp, so this skips history.
+    let mut last_id = format!("{}-0", now_ms());
+    let options = StreamReadOptions::default().count(100).block(BLOCK_MS);
+    loop {
+        let reply: redis::RedisResult<Option<StreamReadReply>> = connection
+            .xread_options(&[&key], &[&last_id], &options)
+            .await;
+        let entries = match reply {
+            Ok(reply) => reply
+                .into_iter()
+                .flat_map(|reply| reply.keys)
+                .flat_map(|key| key.ids),
+            Err(err) => {
+                tracing::warn!(error = %err, "record replication read failed, retrying");
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+        for entry in entries {
+            last_id = entry.id.clone();
+            if entry.get::<String>("node").as_deref() == Some(node.as_str()) {
+                continue;
+            }
+            let record = entry
+                .get::<String>("record")
+                .and_then(|payload| restore(&store, &payload));
+            match record {
+                Some(record) => store.apply(record).await,
+                None => tracing::warn!(entry = %entry.id, "skipping malformed record snapshot"),
+            }
+        }
+    }
+}
+
+// The record as published: a sealed JSON snapshot.
+fn snapshot(store: &ExecutionStore, id: &Uuid) -> Option<String> {
+    let record = store.get(id)?;
+    let payload = serde_json::to_string(&record).ok()?;
+    match store.cipher().seal(&payload) {
+        Ok(payload) => Some(payload),
+        Err(err) => {
+            tracing::error!(execution_id = %id, error = %format!("{err:#}"), "failed to seal record snapshot");
+            None
+        }
+    }
+}
+
+fn restore(store: &ExecutionStore, payload: &str) -> Option<ExecutionRecord> {
+    let payload = store.cipher().open(payload).ok()?;
+    serde_json::from_str(&payload).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{Engine, prelude::BASE64_STANDARD};
+    use uuid::Uuid;
+
+    use super::{restore, snapshot};
+    use crate::engine::{
+        config::EngineConfig,
+        encryption::RecordCipher,
+        models::{ExecutionLimits, ExecutionRequest},
+        store::ExecutionStore,
+    };
+
+    fn cipher(key: u8) -> RecordCipher {
+        let mut config = EngineConfig::from_env();
+        config.encryption_keys = format!("k1:{}", BASE64_STANDARD.encode([key; 32]));
+        RecordCipher::from_config(&config).unwrap()
+    }
+
+    #[tokio::test]
+    async fn snapshots_round_trip_sealed_between_nodes() {
+        let origin = ExecutionStore::new(None).with_cipher(cipher(7));
+        let request: ExecutionRequest = serde_json::from_value(
+            serde_json::json!({ "language": "python", "code": "print('plaintext')" }),
+        )
+        .unwrap();
+        let limits = ExecutionLimits {
+            cpu_cores: 1.0,
+            memory_mb: 256,
+            timeout_ms: 1000,
+            max_processes: 8,
+            max_file_size_bytes: 1024,
+            max_output_bytes: 1024,
+        };
+        let id = Uuid::new_v4();
+        origin.insert(origin.create_record(id, "t".into(), request, limits));
+        origin.append_event(id, "worker", "claimed");
+
+        let payload = snapshot(&origin, &id).unwrap();
+        assert!(!payload.contains("plaintext"));
+        assert!(snapshot(&origin, &Uuid::new_v4()).is_none());
+
+        let follower = ExecutionStore::new(None).with_cipher(cipher(7));
+        let mut updates = follower.subscribe();
+        follower.apply(restore(&follower, &payload).unwrap()).await;
+        let applied = follower.get(&id).unwrap();
+        assert_eq!(applied.request.code, "print('plaintext')");
+        assert_eq!(applied.events.len(), origin.get(&id).unwrap().events.len());
+        // Marked remote, so the follower does not publish it back.
+        assert!(updates.recv().await.unwrap().remote);
+
+        let stranger = ExecutionStore::new(None).with_cipher(cipher(9));
+        assert!(restore(&stranger, &payload).is_none());
+    }
+}
@@ -0,0 +1,158 @@
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use uuid::Uuid;
+
+use crate::engine::{
+    models::{ExecutionLimits, ExecutionRecord, ExecutionRequest, ExecutionStatus},
+    store::ExecutionStore,
+};
+
+#[derive(Debug, Clone)]
+struct Entry {
+    id: Uuid,
+    stored: Instant,
+}
+
+// Remembers which execution produced the result for a (tenant, request hash)
+// pair, for requests that opt in with `cache: true`. A zero `ttl` disables it.
+#[derive(Clone)]
+pub struct ResultCache {
+    entries: Arc<Mutex<HashMap<(String, u64), Entry>>>,
+    ttl: Duration,
+}
+
+impl ResultCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            entries: Arc::new(Mutex::new(HashMap::new())),
+            ttl,
+        }
+    }
+
+    // The finished execution whose result can stand in for this request. The
+    // source record is compared in full, so a hash collision is only a miss.
+    pub fn lookup(
+        &self,
+        store: &ExecutionStore,
+        tenant_id: &str,
+        request: &ExecutionRequest,
+        limits: &ExecutionLimits,
+    ) -> Option<ExecutionRecord> {
+        if self.ttl.is_zero() {
+            return None;
+        }
+        let material = cache_material(request, limits)?;
+        let slot = (tenant_id.to_string(), hash(&material));
+        let id = {
+            let entries = self.entries.lock().expect("result cache poisoned");
+            let entry = entries.get(&slot)?;
+            if entry.stored.elapsed() >= self.ttl {
+                return None;
+            }
+            entry.id
+        };
+        store
+            .get(&id)
+            .filter(|record| cache_material(&record.request, &record.limits) == Some(material))
+    }
+
+    pub fn remember(&self, record: &ExecutionRecord) {
+        if self.ttl.is_zero() || !cacheable(&record.status) {
+            return;
+        }
+        let Some(material) = cache_material(&record.request, &record.limits) else {
+            return;
+        };
+        let now = Instant::now();
+        let mut entries = self.entries.lock().expect("result cache poisoned");
+        entries.retain(|_, entry| now.duration_since(entry.stored) < self.ttl);
+        entries.insert(
+            (record.tenant_id.clone(), hash(&material)),
+            Entry {
+                id: record.id,
+                stored: now,
+            },
+        );
+    }
+}
+
+// Outcomes of the code itself; timeouts, OOM kills and sandbox errors can go
+// differently on another run.
+fn cacheable(status: &ExecutionStatus) -> bool {
+    matches!(status, ExecutionStatus::Succeeded | ExecutionStatus::Failed)
+}
+
+// Everything that affects the result, or None when the request did not opt in
+// or can reach the network. Metadata and priority are left out.
+fn cache_material(request: &ExecutionRequest, limits: &ExecutionLimits) -> Option<Vec<u8>> {
+    if !request.cache || request.allow_network || !request.egress.is_empty() {
+        return None;
+    }
+    let mut request = request.clone();
+    request.metadata.clear();
+    request.priority = None;
+    serde_json::to_vec(&(request, limits)).ok()
+}
+
+fn hash(material: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    material.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use uuid::Uuid;
+
+    use super::ResultCache;
+    use crate::engine::{
+        models::{ExecutionLimits, ExecutionRequest, ExecutionStatus},
+        store::ExecutionStore,
+    };
+
+    #[tokio::test]
+    async fn reuses_results_for_identical_requests_only() {
+        let store = ExecutionStore::new(None);
+        let cache = ResultCache::new(Duration::from_secs(60));
+        let request: ExecutionRequest = serde_json::from_value(serde_json::json!({
+            "language": "python",
+            "code": "print(1)",
+            "cache": true,
+            "metadata": { "run": "1" },
+        }))
+        .unwrap();
+        let limits = ExecutionLimits {
+            cpu_cores: 1.0,
+            memory_mb: 256,
+            timeout_ms: 1000,
+            max_processes: 8,
+            max_file_size_bytes: 1024,
+            max_output_bytes: 1024,
+        };
+        let id = Uuid::new_v4();
+        store.insert(store.create_record(id, "t".into(), request.clone(), limits.clone()));
+        store
+            .mark_finished(id, ExecutionStatus::Succeeded, None, None)
+            .await;
+        cache.remember(&store.get(&id).unwrap());
+
+        let mut rerun = request.clone();
+        rerun.metadata.insert("run".into(), "2".into());
+        let hit = cache.lookup(&store, "t", &rerun, &limits).unwrap();
+        assert_eq!(hit.id, id);
+
+        assert!(cache.lookup(&store, "other", &rerun, &limits).is_none());
+        rerun.stdin = "x".into();
+        assert!(cache.lookup(&store, "t", &rerun, &limits).is_none());
+        rerun.stdin.clear();
+        rerun.cache = false;
+        assert!(cache.lookup(&store, "t", &rerun, &limits).is_none());
+    }
+}
@@ -0,0 +1,113 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    future::Future,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Context;
+use dashmap::DashMap;
+use tokio::sync::Mutex;
+
+use crate::engine::models::CompileOutput;
+
+const READY_MARKER: &str = ".complete";
+
+// Content-addressed directories (installed dependencies, compiled artifacts) that
+// are filled once and then shared read-only between runs.
+pub struct ContentCache {
+    root: PathBuf,
+    locks: DashMap<u64, Arc<Mutex<()>>>,
+}
+
+impl ContentCache {
+    pub fn new(root: PathBuf) -> Self {
+        Self {
+            root,
+            locks: DashMap::new(),
+        }
+    }
+
+    pub fn dir_for(&self, content: &impl Hash) -> (u64, PathBuf) {
+        let mut hasher = DefaultHasher::new();
+        content.hash(&mut hasher);
+        let key = hasher.finish();
+        (key, self.root.join(format!("{key:016x}")))
+    }
+
+    // Returns the fill output when the entry was built, or None on a cache hit.
+    // Concurrent runs with the same content wait for the first fill.
+    pub async fn ensure<F, Fut>(
+        &self,
+        content: &impl Hash,
+        fill: F,
+    ) -> anyhow::Result<(PathBuf, Option<CompileOutput>)>
+    where
+        F: FnOnce(PathBuf) -> Fut,
+        Fut: Future<Output = anyhow::Result<CompileOutput>>,
+    {
+        let (key, dir) = self.dir_for(content);
+        let lock = self.locks.entry(key).or_default().clone();
+        let _guard = lock.lock().await;
+
+        if dir.join(READY_MARKER).exists() {
+            return Ok((dir, None));
+        }
+
+        reset_dir(&dir).await?;
+        let output = fill(dir.clone()).await?;
+        if output.success {
+            tokio::fs::write(dir.join(READY_MARKER), b"")
+                .await
+                .context("failed to mark cache entry ready")?;
+        } else {
+            let _ = tokio::fs::remove_dir_all(&dir).await;
+        }
+        Ok((dir, Some(output)))
+    }
+}
+
+async fn reset_dir(dir: &Path) -> anyhow::Result<()> {
+    let _ = tokio::fs::remove_dir_all(dir).await;
+    tokio::fs::create_dir_all(dir)
+        .await
+        .with_context(|| format!("failed to create cache dir {}", dir.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ContentCache;
+    use crate::engine::models::CompileOutput;
+
+    #[tokio::test]
+    async fn fills_once_per_content() {
+        let root = std::env::temp_dir().join(format!("cache-test-{}", uuid::Uuid::new_v4()));
+        let cache = ContentCache::new(root.clone());
+        let ok = |_| async {
+            Ok(CompileOutput {
+                success: true,
+                exit_code: 0,
+                stdout: String::new(),
+                stderr: String::new(),
+                duration_ms: 0,
+            })
+        };
+
+        let (dir, first) = cache
+            .ensure(&("main.py", "requests==2.32.3"), ok)
+            .await
+            .unwrap();
+        assert!(first.is_some());
+        let (again, second) = cache
+            .ensure(&("main.py", "requests==2.32.3"), ok)
+            .await
+            .unwrap();
+        assert_eq!(dir, again);
+        assert!(second.is_none());
+        let (other, _) = cache.ensure(&("main.py", "numpy"), ok).await.unwrap();
+        assert_ne!(dir, other);
+
+        let _ = tokio::fs::remove_dir_all(root).await;
+    }
+}
@@ -0,0 +1,176 @@
+use std::{ffi::CString, os::unix::ffi::OsStrExt, path::PathBuf, time::Duration};
+
+use anyhow::Context;
+
+use crate::engine::models::ExecutionLimits;
+
+const CONTROLLERS: [&str; 3] = ["cpu", "memory", "pids"];
+const CPU_PERIOD_US: u64 = 100_000;
+
+// A cgroup v2 directory delegated to the engine (PROCESS_CGROUP_ROOT); each
+// process-backend run gets a child group carrying its limits.
+#[derive(Debug, Clone)]
+pub struct CgroupRoot {
+    path: PathBuf,
+}
+
+impl CgroupRoot {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    // Turns on the controllers for child groups. The root must hold no
+    // processes itself, as cgroup v2 requires of any group with children.
+    pub async fn prepare(&self) -> anyhow::Result<()> {
+        let available = tokio::fs::read_to_string(self.path.join("cgroup.controllers"))
+            .await
+            .with_context(|| format!("{} is not a cgroup v2 directory", self.path.display()))?;
+        let missing: Vec<&str> = CONTROLLERS
+            .into_iter()
+            .filter(|controller| !available.split_whitespace().any(|c| c == *controller))
+            .collect();
+        if !missing.is_empty() {
+            anyhow::bail!(
+                "cgroup {} lacks the {} controllers",
+                self.path.display(),
+                missing.join(", ")
+            );
+        }
+        let enable: Vec<String> = CONTROLLERS.iter().map(|c| format!("+{c}")).collect();
+        tokio::fs::write(self.path.join("cgroup.subtree_control"), enable.join(" "))
+            .await
+            .with_context(|| format!("failed to enable controllers in {}", self.path.display()))
+    }
+
+    pub async fn health(&self) -> anyhow::Result<()> {
+        tokio::fs::metadata(self.path.join("cgroup.procs"))
+            .await
+            .with_context(|| format!("cgroup {} is unavailable", self.path.display()))?;
+        Ok(())
+    }
+
+    pub fn create(&self, name: &str, limits: &ExecutionLimits) -> anyhow::Result<Cgroup> {
+        let path = self.path.join(name);
+        std::fs::create_dir(&path)
+            .with_context(|| format!("failed to create cgroup {}", path.display()))?;
+        let group = Cgroup {
+            procs: CString::new(path.join("cgroup.procs").as_os_str().as_bytes())?,
+            path,
+        };
+        for (file, value) in limit_files(limits) {
+            if let Err(err) = std::fs::write(group.path.join(file), value) {
+                let _ = std::fs::remove_dir(&group.path);
+                return Err(err)
+                    .with_context(|| format!("failed to set {file} on {}", group.path.display()));
+            }
+        }
+        // Absent without swap accounting; there is no swap to limit then.
+        let _ = std::fs::write(group.path.join("memory.swap.max"), "0");
+        Ok(group)
+    }
+}
+
+// The control files a group is limited through and their values.
+fn limit_files(limits: &ExecutionLimits) -> [(&'static str, String); 3] {
+    let quota = ((f64::from(limits.cpu_cores) * CPU_PERIOD_US as f64) as u64).max(1000);
+    [
+        ("memory.max", (limits.memory_mb * 1024 * 1024).to_string()),
+        ("cpu.max", format!("{quota} {CPU_PERIOD_US}")),
+        ("pids.max", limits.max_processes.to_string()),
+    ]
+}
+
+#[derive(Debug, Clone)]
+pub struct Cgroup {
+    path: PathBuf,
+    procs: CString,
+}
+
+impl Cgroup {
+    // Moves the calling process into the group. Only async-signal-safe calls
+    // on a path built beforehand, so it can run between fork and exec.
+    pub fn enter(&self) -> std::io::Result<()> {
+        // SAFETY: `procs` is a valid C string that outlives the calls, and the
+        // buffer passed to write is a one-byte static.
+        unsafe {
+            let fd = libc::open(self.procs.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+            if fd < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            let written = libc::write(fd, b"0".as_ptr().cast(), 1);
+            let err = std::io::Error::last_os_error();
+            libc::close(fd);
+            if written != 1 {
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    // Whether the kernel OOM-killed anything in the group.
+    pub fn oom_killed(&self) -> bool {
+        std::fs::read_to_string(self.path.join("memory.events"))
+            .ok()
+            .and_then(|events| {
+                events
+                    .lines()
+                    .find_map(|line| line.strip_prefix("oom_kill "))
+                    .and_then(|count| count.trim().parse::<u64>().ok())
+            })
+            .is_some_and(|count| count > 0)
+    }
+
+    // Peak memory of the whole group; None before Linux 5.19.
+    pub fn peak_memory_bytes(&self) -> Option<u64> {
+        std::fs::read_to_string(self.path.join("memory.peak"))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    }
+
+    // Kills whatever is left in the group, background processes included,
+    // and removes it.
+    pub async fn remove(self) {
+        let _ = tokio::fs::write(self.path.join("cgroup.kill"), "1").await;
+        for _ in 0..50 {
+            if tokio::fs::remove_dir(&self.path).await.is_ok() || !self.path.exists() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        tracing::warn!(cgroup = %self.path.display(), "failed to remove cgroup");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CgroupRoot;
+    use crate::engine::models::ExecutionLimits;
+
+    #[test]
+    fn writes_the_limits_into_the_group() {
+        let root = std::env::temp_dir().join(format!("cgroup-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&root).unwrap();
+        let limits = ExecutionLimits {
+            cpu_cores: 0.5,
+            memory_mb: 256,
+            timeout_ms: 1000,
+            max_processes: 16,
+            max_file_size_bytes: 1024,
+            max_output_bytes: 1024,
+        };
+        let group = CgroupRoot::new(root.clone())
+            .create("run", &limits)
+            .unwrap();
+        let read = |file: &str| std::fs::read_to_string(root.join("run").join(file)).unwrap();
+        assert_eq!(read("memory.max"), "268435456");
+        assert_eq!(read("cpu.max"), "50000 100000");
+        assert_eq!(read("pids.max"), "16");
+
+        std::fs::write(root.join("run/memory.events"), "oom 1\noom_kill 1\n").unwrap();
+        assert!(group.oom_killed());
+        assert_eq!(group.peak_memory_bytes(), None);
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
@@ -0,0 +1,236 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+// The process backend's compiled programs, one file or directory per source
+// hash, evicted least recently used first once there are more than
+// `max_entries` or they take more than `max_bytes`. Entries a run is still
+// using are never evicted.
+pub struct CompileCache {
+    dir: PathBuf,
+    max_entries: usize,
+    max_bytes: u64,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    entries: HashMap<u64, Entry>,
+    bytes: u64,
+    clock: u64,
+}
+
+struct Entry {
+    path: PathBuf,
+    bytes: u64,
+    last_used: u64,
+    in_use: usize,
+}
+
+// A cached program held by a run; dropping it lets the entry be evicted.
+pub struct CompiledArtifact {
+    key: u64,
+    path: PathBuf,
+    state: Arc<Mutex<State>>,
+}
+
+impl CompiledArtifact {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for CompiledArtifact {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(entry) = state.entries.get_mut(&self.key) {
+            entry.in_use = entry.in_use.saturating_sub(1);
+        }
+    }
+}
+
+impl CompileCache {
+    pub fn new(dir: PathBuf, max_entries: usize, max_bytes: u64) -> Self {
+        Self {
+            dir,
+            max_entries,
+            max_bytes,
+            state: Arc::default(),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // Removes what earlier engine processes left behind; the index only
+    // lives in memory, so nothing on disk at startup is reachable.
+    pub async fn clear_stale(&self) -> anyhow::Result<()> {
+        let mut entries = match tokio::fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+        let mut removed = 0;
+        while let Some(entry) = entries.next_entry().await? {
+            let known = self
+                .state
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .entries
+                .values()
+                .any(|known| known.path == entry.path());
+            if !known {
+                remove(&entry.path()).await;
+                removed += 1;
+            }
+        }
+        if removed > 0 {
+            tracing::info!(removed, dir = %self.dir.display(), "removed stale compiled programs");
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, key: u64) -> Option<CompiledArtifact> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.clock += 1;
+        let clock = state.clock;
+        let entry = state.entries.get_mut(&key)?;
+        if !entry.path.exists() {
+            let bytes = entry.bytes;
+            state.entries.remove(&key);
+            state.bytes -= bytes;
+            return None;
+        }
+        entry.last_used = clock;
+        entry.in_use += 1;
+        Some(CompiledArtifact {
+            key,
+            path: entry.path.clone(),
+            state: self.state.clone(),
+        })
+    }
+
+    // Adds a freshly compiled program and evicts until the cache is within
+    // its limits again.
+    pub async fn insert(&self, key: u64, path: PathBuf) -> CompiledArtifact {
+        let bytes = {
+            let path = path.clone();
+            tokio::task::spawn_blocking(move || disk_usage(&path))
+                .await
+                .unwrap_or(0)
+        };
+        let evicted = {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            state.clock += 1;
+            let clock = state.clock;
+            let previous = state.entries.insert(
+                key,
+                Entry {
+                    path: path.clone(),
+                    bytes,
+                    last_used: clock,
+                    in_use: 1,
+                },
+            );
+            state.bytes += bytes;
+            if let Some(previous) = previous {
+                state.bytes -= previous.bytes;
+            }
+            self.evict(&mut state)
+        };
+        for path in evicted {
+            remove(&path).await;
+        }
+        CompiledArtifact {
+            key,
+            path,
+            state: self.state.clone(),
+        }
+    }
+
+    fn evict(&self, state: &mut State) -> Vec<PathBuf> {
+        let mut evicted = Vec::new();
+        while state.entries.len() > self.max_entries || state.bytes > self.max_bytes {
+            let Some(key) = state
+                .entries
+                .iter()
+                .filter(|(_, entry)| entry.in_use == 0)
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| *key)
+            else {
+                break;
+            };
+            if let Some(entry) = state.entries.remove(&key) {
+                state.bytes -= entry.bytes;
+                evicted.push(entry.path);
+            }
+        }
+        evicted
+    }
+}
+
+// Artifacts are a single file, or a directory for the JVM and .NET.
+fn disk_usage(path: &Path) -> u64 {
+    let Ok(meta) = std::fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if !meta.is_dir() {
+        return meta.len();
+    }
+    std::fs::read_dir(path)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| disk_usage(&entry.path()))
+        .sum()
+}
+
+async fn remove(path: &Path) {
+    let removed = match tokio::fs::symlink_metadata(path).await {
+        Ok(meta) if meta.is_dir() => tokio::fs::remove_dir_all(path).await,
+        Ok(_) => tokio::fs::remove_file(path).await,
+        Err(_) => return,
+    };
+    if let Err(err) = removed {
+        tracing::warn!(path = %path.display(), error = %err, "failed to remove compiled program");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CompileCache;
+
+    #[tokio::test]
+    async fn evicts_least_recently_used_unheld_entries() {
+        let dir = std::env::temp_dir().join(format!("compile-cache-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("stale"), "old").unwrap();
+        let cache = CompileCache::new(dir.clone(), 2, 10);
+        cache.clear_stale().await.unwrap();
+        assert!(!dir.join("stale").exists());
+
+        let write = |name: &str, bytes: usize| {
+            let path = dir.join(name);
+            std::fs::write(&path, vec![0u8; bytes]).unwrap();
+            path
+        };
+        drop(cache.insert(1, write("a", 4)).await);
+        drop(cache.insert(2, write("b", 4)).await);
+        drop(cache.get(1));
+        // Over the entry count: b is the least recently used.
+        drop(cache.insert(3, write("c", 4)).await);
+        assert!(cache.get(2).is_none());
+        assert!(!dir.join("b").exists());
+
+        // Over the byte size, but a is held by a run, so c goes instead.
+        let held = cache.get(1).unwrap();
+        drop(cache.insert(4, write("d", 4)).await);
+        assert!(cache.get(3).is_none());
+        assert_eq!(held.path(), dir.join("a"));
+        assert!(cache.get(4).is_some());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -1,23 +1,271 @@
 use std::{
+    collections::{HashMap, HashSet},
+    fmt::{Display, Formatter},
+    os::unix::fs::PermissionsExt,
     path::{Path, PathBuf},
-    process::Stdio,
+    pin::Pin,
+    sync::Arc,
     time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::Context;
 use async_trait::async_trait;
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    process::Command,
+use bollard::{
+    Docker,
+    container::{AttachContainerResults, LogOutput},
+    errors::Error as BollardError,
+    exec::{StartExecOptions, StartExecResults},
+    models::{
+        ContainerCreateBody, ContainerStatsResponse, DeviceRequest, ExecConfig, HostConfig, Mount,
+        MountTypeEnum, ResourcesUlimits,
+    },
+    query_parameters::{
+        AttachContainerOptionsBuilder, CreateContainerOptionsBuilder, CreateImageOptionsBuilder,
+        KillContainerOptionsBuilder, ListContainersOptionsBuilder, RemoveContainerOptionsBuilder,
+        StatsOptionsBuilder,
+    },
 };
+use futures_util::{Stream, StreamExt, TryStreamExt};
+use tokio::sync::mpsc;
+use tracing::Instrument;
 
-use crate::engine::sandbox::{LanguageSpec, RunSpec, SandboxBackend, SandboxResult};
+use crate::engine::{
+    config::{EngineConfig, ToolCommands},
+    models::{
+        CompileOutput, EvalResult, ExecutionLimits, ExecutionMode, ExecutionRequest, Language,
+        OutputStream, ResourceUsage, SessionVariable,
+    },
+    sandbox::{
+        COMPILE_TIMEOUT, ContainerRuntime, ContentCache, Driver, DriverProgram, EgressGrant,
+        EgressPolicy, EgressProxy, EgressRule, INSTALL_TIMEOUT, ImageCatalog, LanguageOverrides,
+        LanguageSpec, OutputLog, RunSpec, SandboxBackend, SandboxResult, SandboxSession,
+        SessionSpec, collect_files, encode_output, feed_stdin, install_limits, jvm_options,
+        request_error, signal_from_exit_code,
+    },
+};
+
+#[derive(Debug)]
+pub enum DockerSandboxError {
+    Connect(String),
+    Pull(String, BollardError),
+    Create(BollardError),
+    Start(BollardError),
+    Exec(BollardError),
+}
+
+impl Display for DockerSandboxError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DockerSandboxError::Connect(msg) => write!(f, "container engine unavailable: {msg}"),
+            DockerSandboxError::Pull(image, err) => write!(f, "failed to pull {image}: {err}"),
+            DockerSandboxError::Create(err) => write!(f, "container create failed: {err}"),
+            DockerSandboxError::Start(err) => write!(f, "container start failed: {err}"),
+            DockerSandboxError::Exec(err) => write!(f, "container exec failed: {err}"),
+        }
+    }
+}
 
-pub struct DockerSandbox;
+impl std::error::Error for DockerSandboxError {}
+
+pub struct DockerSandbox {
+    runtime: ContainerRuntime,
+    docker: Docker,
+    images: ImageCatalog,
+    prepull: bool,
+    dependency_cache: ContentCache,
+    artifact_cache: ContentCache,
+    egress: Option<EgressRoute>,
+    // NETWORK_ALLOWED_TENANTS; everyone else installs dependencies through
+    // the proxy within their TENANT_EGRESS grant.
+    network_tenants: HashSet<String>,
+    security_opt: Vec<String>,
+    gpus: DeviceRequest,
+    tools: ToolCommands,
+    languages: HashMap<Language, LanguageOverrides>,
+    deterministic_cpu: usize,
+    // Session containers run the driver as their main process, so they can
+    // be checkpointed.
+    session_checkpoint: bool,
+}
+
+// Runs with egress rules, and with NETWORK_PROXY those with `allow_network`,
+// join `network`, an internal network whose only way out is the proxy.
+struct EgressRoute {
+    proxy: Arc<EgressProxy>,
+    network: String,
+    proxy_network: bool,
+    allowlists: HashMap<String, Vec<EgressRule>>,
+    grants: HashMap<String, Vec<EgressRule>>,
+    bandwidth: (u64, HashMap<String, u64>),
+}
+
+impl EgressRoute {
+    fn grant(&self, spec: &RunSpec, rules: Option<Vec<EgressRule>>) -> (EgressGrant, &str) {
+        let (default_bandwidth, tenant_bandwidth) = &self.bandwidth;
+        let bandwidth = tenant_bandwidth
+            .get(&spec.tenant_id)
+            .copied()
+            .unwrap_or(*default_bandwidth);
+        let grant = self.proxy.grant(EgressPolicy {
+            rules,
+            bandwidth: (bandwidth > 0).then_some(bandwidth),
+            execution_id: spec.id,
+            tenant_id: spec.tenant_id.clone(),
+            events: spec.events.clone(),
+        });
+        (grant, self.network.as_str())
+    }
+}
 
 impl DockerSandbox {
-    pub fn new() -> anyhow::Result<Self> {
-        Ok(Self)
+    pub async fn connect(runtime: ContainerRuntime, config: &EngineConfig) -> anyhow::Result<Self> {
+        let docker = runtime.connect()?;
+        let version = docker
+            .version()
+            .await
+            .map_err(|err| DockerSandboxError::Connect(err.to_string()))?;
+        tracing::info!(
+            runtime = runtime.name(),
+            endpoint = runtime.endpoint.as_deref().unwrap_or("default"),
+            rootless = runtime.rootless,
+            api_version = version.api_version.as_deref().unwrap_or("unknown"),
+            "container engine connected"
+        );
+        let egress = match (&config.egress_network, &config.egress_proxy_url) {
+            (Some(network), Some(url)) => {
+                let proxy = EgressProxy::start(config.egress_proxy_addr, url.clone()).await?;
+                tracing::info!(%network, bind = %proxy.local_addr(), "egress proxy listening");
+                Some(EgressRoute {
+                    proxy,
+                    network: network.clone(),
+                    proxy_network: config.network_proxy,
+                    allowlists: config.tenant_network_allowlist.clone(),
+                    grants: config.tenant_egress.clone(),
+                    bandwidth: (
+                        config.default_network_bandwidth,
+                        config.tenant_network_bandwidth.clone(),
+                    ),
+                })
+            }
+            _ if config.network_proxy => {
+                anyhow::bail!("NETWORK_PROXY needs EGRESS_NETWORK and EGRESS_PROXY_URL")
+            }
+            _ => None,
+        };
+        let security_opt = security_options(config)?;
+        tracing::info!(?security_opt, "container security options");
+        Ok(Self {
+            runtime,
+            docker,
+            images: ImageCatalog::from_config(config),
+            prepull: config.prepull_images,
+            dependency_cache: ContentCache::new(config.dependency_cache_dir.clone()),
+            artifact_cache: ContentCache::new(config.compile_cache_dir.clone()),
+            egress,
+            network_tenants: config.network_allowed_tenants.clone(),
+            security_opt,
+            gpus: gpu_request(&config.gpu_devices),
+            tools: config.tools.clone(),
+            languages: config.language_overrides.clone(),
+            deterministic_cpu: config.deterministic_cpu,
+            session_checkpoint: config.session_checkpoint,
+        })
+    }
+
+    async fn create_container(
+        &self,
+        name: &str,
+        image: &str,
+        body: ContainerCreateBody,
+    ) -> Result<(), DockerSandboxError> {
+        let options = CreateContainerOptionsBuilder::new().name(name).build();
+        match self
+            .docker
+            .create_container(Some(options.clone()), body.clone())
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(BollardError::DockerResponseServerError {
+                status_code: 404, ..
+            }) => {
+                self.pull_image(image).await?;
+                self.docker
+                    .create_container(Some(options), body)
+                    .await
+                    .map(|_| ())
+                    .map_err(DockerSandboxError::Create)
+            }
+            Err(err) => Err(DockerSandboxError::Create(err)),
+        }
+    }
+
+    async fn pull_image(&self, image: &str) -> Result<(), DockerSandboxError> {
+        let options = CreateImageOptionsBuilder::new().from_image(image).build();
+        self.docker
+            .create_image(Some(options), None, self.images.credentials_for(image))
+            .try_collect::<Vec<_>>()
+            .await
+            .map(|_| ())
+            .map_err(|err| DockerSandboxError::Pull(image.to_string(), err))
+    }
+
+    fn egress_grant(&self, spec: &RunSpec) -> anyhow::Result<Option<(EgressGrant, &str)>> {
+        let proxied = self
+            .egress
+            .as_ref()
+            .is_some_and(|route| route.proxy_network);
+        let wanted = if spec.request.allow_network {
+            proxied
+        } else {
+            !spec.request.egress.is_empty()
+        };
+        if !wanted {
+            return Ok(None);
+        }
+        let route = self
+            .egress
+            .as_ref()
+            .ok_or_else(|| request_error("egress policies are not enabled"))?;
+        let rules = if spec.request.allow_network {
+            route.allowlists.get(&spec.tenant_id).cloned()
+        } else {
+            Some(
+                spec.request
+                    .egress
+                    .iter()
+                    .map(|rule| rule.parse().map_err(request_error))
+                    .collect::<anyhow::Result<Vec<EgressRule>>>()?,
+            )
+        };
+        Ok(Some(route.grant(spec, rules)))
+    }
+
+    // Installs go through the proxy whenever it is configured: tenants with
+    // network access reach what their `allow_network` runs may, the rest
+    // only their TENANT_EGRESS destinations (package registries).
+    fn install_grant(&self, spec: &RunSpec) -> anyhow::Result<Option<(EgressGrant, &str)>> {
+        let network = self.network_tenants.contains(&spec.tenant_id);
+        let denied = || request_error("dependency installs need network access");
+        let Some(route) = &self.egress else {
+            return if network { Ok(None) } else { Err(denied()) };
+        };
+        let rules = if network {
+            route.allowlists.get(&spec.tenant_id).cloned()
+        } else {
+            let rules = route.grants.get(&spec.tenant_id);
+            Some(
+                rules
+                    .filter(|rules| !rules.is_empty())
+                    .ok_or_else(denied)?
+                    .clone(),
+            )
+        };
+        Ok(Some(route.grant(spec, rules)))
+    }
+
+    async fn remove_container(&self, name: &str) {
+        let options = RemoveContainerOptionsBuilder::new().force(true).build();
+        let _ = self.docker.remove_container(name, Some(options)).await;
     }
 }
 
@@ -27,120 +275,1096 @@ impl SandboxBackend for DockerSandbox {
         "docker"
     }
 
-    async fn execute(&self, spec: RunSpec) -> anyhow::Result<SandboxResult> {
-        if spec.request.code.as_bytes().len() as u64 > spec.limits.max_file_size_bytes {
-            anyhow::bail!("source exceeds configured file size limit");
+    async fn health(&self) -> anyhow::Result<Option<String>> {
+        self.docker
+            .ping()
+            .await
+            .context("container runtime ping failed")?;
+        Ok(None)
+    }
+
+    // Every container of a run (exec, install, compile) is named after its
+    // execution id.
+    async fn kill(&self, attempt: uuid::Uuid) {
+        let filters = HashMap::from([("name", vec![attempt.simple().to_string()])]);
+        let options = ListContainersOptionsBuilder::new()
+            .all(true)
+            .filters(&filters)
+            .build();
+        let containers = match self.docker.list_containers(Some(options)).await {
+            Ok(containers) => containers,
+            Err(err) => {
+                tracing::warn!(%attempt, error = %err, "failed to list containers to kill");
+                return;
+            }
+        };
+        for id in containers.into_iter().filter_map(|container| container.id) {
+            self.remove_container(&id).await;
         }
+    }
 
-        let lang = LanguageSpec::for_language(&spec.request.language);
-        let work_dir = make_work_dir(spec.id)?;
-        write_source(&work_dir, &lang, &spec.request.code).await?;
-
-        let container_name = format!("exec-{}-{}", spec.id.as_simple(), now_nanos() % 1_000_000);
-
-        let mut args: Vec<String> = vec![
-            "run".to_string(),
-            "--name".to_string(),
-            container_name.clone(),
-            "--rm".to_string(),
-            "--init".to_string(),
-            "--cpus".to_string(),
-            spec.limits.cpu_cores.to_string(),
-            "--memory".to_string(),
-            format!("{}m", spec.limits.memory_mb),
-            "--pids-limit".to_string(),
-            spec.limits.max_processes.to_string(),
-            "--ulimit".to_string(),
-            format!("nproc={}", spec.limits.max_processes),
-            "--ulimit".to_string(),
-            format!("fsize={}", spec.limits.max_file_size_bytes),
-            "--read-only".to_string(),
-            "--tmpfs".to_string(),
-            "/tmp:rw,nosuid,nodev,noexec,size=64m".to_string(),
-            "--mount".to_string(),
-            format!(
-                "type=bind,src={},dst=/workspace,readonly",
-                normalize_mount_path(&work_dir)
-            ),
-            "-w".to_string(),
-            "/workspace".to_string(),
-            "--security-opt".to_string(),
-            "no-new-privileges".to_string(),
-            "--cap-drop".to_string(),
-            "ALL".to_string(),
-        ];
-        if !spec.request.allow_network {
-            args.push("--network".to_string());
-            args.push("none".to_string());
+    async fn prepare(&self) -> anyhow::Result<()> {
+        if !self.prepull {
+            return Ok(());
+        }
+        for image in self.images.images() {
+            if self.docker.inspect_image(&image).await.is_ok() {
+                continue;
+            }
+            tracing::info!(%image, "pulling sandbox image");
+            self.pull_image(&image).await?;
         }
+        Ok(())
+    }
 
-        args.push(lang.docker_image.to_string());
-        args.push("sh".to_string());
-        args.push("-lc".to_string());
-        args.push(lang.docker_script.to_string());
-        args.push("--".to_string());
-        args.extend(spec.request.args.iter().cloned());
+    async fn execute(&self, mut spec: RunSpec) -> anyhow::Result<SandboxResult> {
+        if spec.request.code.len() as u64 > spec.limits.max_file_size_bytes {
+            return Err(request_error("source exceeds configured file size limit"));
+        }
 
-        let started = Instant::now();
-        let mut cmd = Command::new("docker");
-        cmd.args(args);
-        cmd.stdin(Stdio::piped());
-        cmd.stdout(Stdio::piped());
-        cmd.stderr(Stdio::piped());
-
-        let mut child = cmd.spawn().context("failed to spawn docker run")?;
-        if let Some(mut stdin) = child.stdin.take() {
-            let stdin_bytes = spec.request.stdin.into_bytes();
-            tokio::spawn(async move {
-                let _ = stdin.write_all(&stdin_bytes).await;
+        let egress = self.egress_grant(&spec)?;
+        let lang = LanguageSpec::configured(&spec.request.language, &self.languages);
+        let tool = self
+            .tools
+            .command(&spec.request.language, spec.request.mode.as_ref());
+        if tool.is_some() {
+            spec.request.stdin.clear();
+            spec.stdin_file = None;
+            // Linters and formatters see the sources only; test runners get
+            // the dependencies and args too.
+            if spec.request.mode != Some(ExecutionMode::Test) {
+                spec.request.dependencies = None;
+                spec.request.args.clear();
+            }
+        }
+        let tool_image = tool.and_then(|_| self.tools.images.get(&spec.request.language));
+        let image = match (&spec.request.image, tool_image, &spec.request.version) {
+            (Some(image), _, _) | (None, Some(image), _) => image.as_str(),
+            (None, None, Some(version)) => self
+                .images
+                .version_image(&spec.request.language, version)
+                .ok_or_else(|| request_error(format!("unknown runtime version: {version}")))?,
+            (None, None, None) => self.images.image_for(&spec.request.language),
+        };
+        let work_dir = make_work_dir(spec.id)?;
+        write_source(&work_dir, &lang, &spec.request).await?;
+
+        let (deps_dir, install) = match self
+            .install_dependencies(&spec, &lang, image, &work_dir)
+            .instrument(tracing::info_span!("sandbox.install"))
+            .await
+        {
+            Ok(installed) => installed,
+            Err(err) => {
+                cleanup_dir(&work_dir).await;
+                return Err(err);
+            }
+        };
+        if let Some(output) = install.as_ref().filter(|output| !output.success) {
+            cleanup_dir(&work_dir).await;
+            return Ok(SandboxResult::install_failed(output.clone()));
+        }
+
+        let checking = spec.request.mode == Some(ExecutionMode::Check);
+        if checking && let Some(script) = lang.docker_check {
+            let checked = self
+                .check_syntax(&spec, &lang, image, &work_dir, deps_dir.as_deref(), script)
+                .instrument(tracing::info_span!("sandbox.compile"))
+                .await;
+            cleanup_dir(&work_dir).await;
+            return checked.map(|output| {
+                if output.success {
+                    return SandboxResult::checked(install, Some(output));
+                }
+                let mut result = SandboxResult::compile_failed(output);
+                result.install = install;
+                result
             });
         }
 
-        let stdout = child.stdout.take().context("missing stdout pipe")?;
-        let stderr = child.stderr.take().context("missing stderr pipe")?;
-        let out_limit = spec.limits.max_output_bytes;
-        let stdout_task = tokio::spawn(async move { read_limited(stdout, out_limit).await });
-        let stderr_task = tokio::spawn(async move { read_limited(stderr, out_limit).await });
+        let compiled = match tool {
+            Some(_) => Ok((None, None)),
+            None => {
+                self.compile_artifacts(&spec, &lang, image, &work_dir, deps_dir.as_deref())
+                    .instrument(tracing::info_span!("sandbox.compile"))
+                    .await
+            }
+        };
+        let (artifacts_dir, compile) = match compiled {
+            Ok(compiled) => compiled,
+            Err(err) => {
+                cleanup_dir(&work_dir).await;
+                return Err(err);
+            }
+        };
+        if let Some(output) = compile.as_ref().filter(|output| !output.success) {
+            cleanup_dir(&work_dir).await;
+            let mut result = SandboxResult::compile_failed(output.clone());
+            result.install = install;
+            return Ok(result);
+        }
+        if checking {
+            cleanup_dir(&work_dir).await;
+            return Ok(SandboxResult::checked(install, compile));
+        }
 
-        let wait_result =
-            tokio::time::timeout(Duration::from_millis(spec.limits.timeout_ms), child.wait()).await;
+        let container_name = format!(
+            "exec-{}-{}-{}",
+            spec.id.as_simple(),
+            spec.attempt.as_simple(),
+            now_nanos() % 1_000_000
+        );
 
-        let (status_code, timed_out) = match wait_result {
-            Ok(Ok(status)) => (status.code().unwrap_or(-1), false),
-            Ok(Err(err)) => {
-                cleanup_container(&container_name).await;
+        let mut host_config = self.host_config(&spec.limits, &work_dir);
+        if spec.request.deterministic {
+            host_config.cpuset_cpus = Some(self.deterministic_cpu.to_string());
+        }
+        if spec.request.gpu {
+            host_config.device_requests = Some(vec![self.gpus.clone()]);
+        }
+        let mounts = host_config.mounts.get_or_insert_with(Vec::new);
+        if let Some(deps_dir) = &deps_dir {
+            mounts.push(bind_mount(deps_dir, "/deps", true));
+        }
+        if let Some(artifacts_dir) = &artifacts_dir {
+            mounts.push(bind_mount(artifacts_dir, "/cache", true));
+        }
+        // Files to collect are written into the workspace, by whatever user
+        // the image runs as.
+        if !spec.collect.is_empty() {
+            if let Some(workspace) = mounts
+                .iter_mut()
+                .find(|mount| mount.target.as_deref() == Some("/workspace"))
+            {
+                workspace.read_only = Some(false);
+            }
+            if let Err(err) =
+                std::fs::set_permissions(&work_dir, std::fs::Permissions::from_mode(0o777))
+            {
                 cleanup_dir(&work_dir).await;
-                return Err(err).context("docker wait failed");
+                return Err(err).context("failed to open the workspace for writing");
             }
-            Err(_) => {
-                let _ = child.kill().await;
-                cleanup_container(&container_name).await;
-                (-1, true)
+        }
+        match &egress {
+            Some((_, network)) => host_config.network_mode = Some(network.to_string()),
+            None if !spec.request.allow_network => {
+                host_config.network_mode = Some("none".to_string())
             }
+            None => {}
+        }
+
+        // The container only idles; restore and run are separate execs so the
+        // run's output and exit code are reported on their own.
+        let mut env = container_env(&lang, &spec.limits, deps_dir.is_some());
+        env.extend(
+            spec.request
+                .env
+                .iter()
+                .map(|(key, value)| format!("{key}={value}")),
+        );
+        if let Some((grant, _)) = &egress {
+            env.extend(proxy_env(grant));
+        }
+        let body = ContainerCreateBody {
+            image: Some(image.to_string()),
+            env: Some(env),
+            cmd: Some(keepalive_cmd()),
+            working_dir: Some("/workspace".to_string()),
+            network_disabled: Some(!spec.request.allow_network && egress.is_none()),
+            host_config: Some(host_config),
+            ..Default::default()
         };
 
-        let stdout_bytes = stdout_task.await.unwrap_or_default();
-        let stderr_bytes = stderr_task.await.unwrap_or_default();
+        if let Err(err) = self.create_container(&container_name, image, body).await {
+            cleanup_dir(&work_dir).await;
+            return Err(err.into());
+        }
 
+        let collect = std::mem::take(&mut spec.collect);
+        let max_file_size = spec.limits.max_file_size_bytes;
+        let mut result = self
+            .run_stages(
+                &container_name,
+                &lang,
+                tool.unwrap_or(lang.docker_run),
+                spec,
+                artifacts_dir.is_some(),
+            )
+            .instrument(tracing::info_span!("sandbox.run"))
+            .await
+            .map(|mut result| {
+                result.install = install;
+                result.compile = compile;
+                result
+            });
+        self.remove_container(&container_name).await;
+        if let Ok(result) = &mut result {
+            result.files = collect_files(&work_dir, &collect, max_file_size).await;
+        }
         cleanup_dir(&work_dir).await;
+        result
+    }
+
+    // Sessions get the same container limits as runs, never any network, and
+    // keep the container until they are closed.
+    async fn open_session(&self, spec: SessionSpec) -> anyhow::Result<Box<dyn SandboxSession>> {
+        let program = DriverProgram::for_language(&spec.language)
+            .ok_or_else(|| request_error("sessions are not supported for this language"))?;
+        let lang = LanguageSpec::configured(&spec.language, &self.languages);
+        let image = self.images.image_for(&spec.language);
+        let work_dir = make_work_dir(spec.id)?;
+        if let Err(err) = tokio::fs::write(work_dir.join(program.file_name), program.source).await {
+            cleanup_dir(&work_dir).await;
+            return Err(err.into());
+        }
+
+        let token = uuid::Uuid::new_v4().simple().to_string();
+        let mut host_config = self.host_config(&spec.limits, &work_dir);
+        host_config.network_mode = Some("none".to_string());
+        let mut env = container_env(&lang, &spec.limits, false);
+        env.extend(spec.env.iter().map(|(key, value)| format!("{key}={value}")));
+        let mut body = ContainerCreateBody {
+            image: Some(image.to_string()),
+            env: Some(env),
+            cmd: Some(keepalive_cmd()),
+            working_dir: Some("/workspace".to_string()),
+            network_disabled: Some(true),
+            host_config: Some(host_config),
+            ..Default::default()
+        };
+        // CRIU cannot checkpoint a container with exec'd processes.
+        if self.session_checkpoint {
+            body.cmd = Some(driver_cmd(&program));
+            if let Some(env) = &mut body.env {
+                env.extend(
+                    Driver::env(&token, &spec.limits)
+                        .into_iter()
+                        .map(|(key, value)| format!("{key}={value}")),
+                );
+            }
+            body.open_stdin = Some(true);
+            body.attach_stdin = Some(true);
+            body.attach_stdout = Some(true);
+        }
+        let name = format!(
+            "session-{}-{}",
+            spec.id.as_simple(),
+            now_nanos() % 1_000_000
+        );
+        let started = async {
+            self.create_container(&name, image, body).await?;
+            if self.session_checkpoint {
+                let driver = attach_main_driver(&self.docker, &name, &token).await?;
+                self.start_container(&name).await?;
+                return Ok(driver);
+            }
+            self.start_container(&name).await?;
+            self.attach_driver(&name, &program, &token, &spec.limits)
+                .await
+        }
+        .await;
+        match started {
+            Ok(driver) => Ok(Box::new(DockerSession {
+                driver,
+                docker: self.docker.clone(),
+                runtime: self.session_checkpoint.then(|| self.runtime.clone()),
+                name,
+                token,
+                checkpoints: 0,
+                work_dir,
+            })),
+            Err(err) => {
+                self.remove_container(&name).await;
+                cleanup_dir(&work_dir).await;
+                Err(err)
+            }
+        }
+    }
+}
+
+struct DockerSession {
+    driver: Driver,
+    docker: Docker,
+    // Set when the session can be checkpointed.
+    runtime: Option<ContainerRuntime>,
+    name: String,
+    token: String,
+    // Taken so far; the latest is the one to restore.
+    checkpoints: u32,
+    work_dir: PathBuf,
+}
+
+impl DockerSession {
+    fn checkpoint_name(&self) -> String {
+        format!("suspend-{}", self.checkpoints)
+    }
+}
+
+#[async_trait]
+impl SandboxSession for DockerSession {
+    async fn eval(&mut self, code: &str, timeout: Duration) -> anyhow::Result<Option<EvalResult>> {
+        self.driver.eval(code, timeout).await
+    }
+
+    async fn inspect(&mut self, timeout: Duration) -> anyhow::Result<Option<Vec<SessionVariable>>> {
+        self.driver.inspect(timeout).await
+    }
+
+    async fn suspend(&mut self) -> anyhow::Result<()> {
+        let Some(runtime) = &self.runtime else {
+            return Err(request_error("session checkpoints are not enabled"));
+        };
+        let checkpoint = format!("suspend-{}", self.checkpoints + 1);
+        runtime.checkpoint(&self.name, &checkpoint).await?;
+        self.checkpoints += 1;
+        Ok(())
+    }
+
+    // The restored driver reads from a new attachment.
+    async fn resume(&mut self) -> anyhow::Result<()> {
+        let Some(runtime) = self.runtime.clone() else {
+            return Ok(());
+        };
+        self.driver = attach_main_driver(&self.docker, &self.name, &self.token).await?;
+        runtime.restore(&self.name, &self.checkpoint_name()).await
+    }
+
+    async fn close(&mut self) {
+        let options = RemoveContainerOptionsBuilder::new().force(true).build();
+        let _ = self
+            .docker
+            .remove_container(&self.name, Some(options))
+            .await;
+        cleanup_dir(&self.work_dir).await;
+    }
+}
+
+// Reads the container's own cgroup (private cgroup namespace) on v2, falling
+// back to the v1 memory controller files.
+const CGROUP_MEMORY_SCRIPT: &str = "if [ -f /sys/fs/cgroup/memory.events ]; then \
+      grep '^oom_kill ' /sys/fs/cgroup/memory.events; \
+      echo \"peak $(cat /sys/fs/cgroup/memory.peak)\"; \
+    else \
+      grep '^oom_kill ' /sys/fs/cgroup/memory/memory.oom_control; \
+      echo \"peak $(cat /sys/fs/cgroup/memory/memory.max_usage_in_bytes)\"; \
+    fi";
+
+#[derive(Debug, Default, PartialEq)]
+struct CgroupMemory {
+    peak_bytes: Option<u64>,
+    oom_kills: Option<u64>,
+}
+
+impl CgroupMemory {
+    fn parse(output: &str) -> Self {
+        let mut memory = Self::default();
+        for line in output.lines() {
+            match line.split_once(' ') {
+                Some(("oom_kill", value)) => memory.oom_kills = value.trim().parse().ok(),
+                Some(("peak", value)) => memory.peak_bytes = value.trim().parse().ok(),
+                _ => {}
+            }
+        }
+        memory
+    }
+}
+
+// Stdin for an exec: `initial` is written first, then whatever arrives on
+// `live` until its sender is dropped.
+struct ExecInput {
+    initial: String,
+    file: Option<PathBuf>,
+    live: Option<mpsc::Receiver<Vec<u8>>>,
+    // Record interleaved output in `ExecOutcome::combined`.
+    combined: bool,
+}
+
+struct ExecOutcome {
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    stdout_bytes: u64,
+    stderr_bytes: u64,
+    combined: Option<OutputLog>,
+    exit_code: i32,
+    timed_out: bool,
+    duration_ms: u128,
+}
+
+impl DockerSandbox {
+    fn host_config(&self, limits: &ExecutionLimits, work_dir: &Path) -> HostConfig {
+        let mut host_config = HostConfig {
+            init: Some(true),
+            nano_cpus: Some((f64::from(limits.cpu_cores) * 1e9) as i64),
+            memory: Some((limits.memory_mb * 1024 * 1024) as i64),
+            pids_limit: Some(limits.max_processes as i64),
+            ulimits: Some(vec![ResourcesUlimits {
+                name: Some("fsize".to_string()),
+                soft: Some(limits.max_file_size_bytes as i64),
+                hard: Some(limits.max_file_size_bytes as i64),
+            }]),
+            readonly_rootfs: Some(true),
+            tmpfs: Some(HashMap::from([
+                (
+                    "/tmp".to_string(),
+                    "rw,nosuid,nodev,noexec,size=64m".to_string(),
+                ),
+                (
+                    "/build".to_string(),
+                    "rw,nosuid,nodev,exec,size=512m".to_string(),
+                ),
+            ])),
+            mounts: Some(vec![Mount {
+                target: Some("/workspace".to_string()),
+                source: Some(normalize_mount_path(work_dir)),
+                typ: Some(MountTypeEnum::BIND),
+                read_only: Some(true),
+                ..Default::default()
+            }]),
+            security_opt: Some(self.security_opt.clone()),
+            cap_drop: Some(vec!["ALL".to_string()]),
+            ..Default::default()
+        };
+        self.runtime
+            .apply_host_config(&mut host_config, limits.max_processes);
+        host_config
+    }
+
+    // Installs run in their own short-lived container: it is the only stage that
+    // gets network access (through the egress proxy when there is one) and the
+    // only one that can write to the cache entry.
+    async fn install_dependencies(
+        &self,
+        spec: &RunSpec,
+        lang: &LanguageSpec,
+        image: &str,
+        work_dir: &Path,
+    ) -> anyhow::Result<(Option<PathBuf>, Option<CompileOutput>)> {
+        let Some(manifest) = spec.request.dependencies.as_deref() else {
+            return Ok((None, None));
+        };
+        let deps = lang
+            .dependencies
+            .as_ref()
+            .ok_or_else(|| request_error("language does not support dependency manifests"))?;
+        // Checked before the cache so a cached manifest is no way around it.
+        let egress = self.install_grant(spec)?;
+        let (dir, output) = self
+            .dependency_cache
+            .ensure(&(lang.source_name, manifest), |dir| async move {
+                spec.events.emit("installing", "installing dependencies");
+                let mut host_config = self.host_config(&install_limits(&spec.limits), work_dir);
+                host_config
+                    .mounts
+                    .get_or_insert_with(Vec::new)
+                    .push(bind_mount(&dir, "/deps", false));
+                let mut env = container_env(lang, &spec.limits, true);
+                if let Some((grant, network)) = &egress {
+                    host_config.network_mode = Some(network.to_string());
+                    env.extend(proxy_env(grant));
+                }
+                let outcome = self
+                    .run_stage_container(
+                        "deps",
+                        spec,
+                        image,
+                        host_config,
+                        env,
+                        deps.install,
+                        INSTALL_TIMEOUT,
+                    )
+                    .await?;
+                Ok(stage_output(outcome, INSTALL_TIMEOUT))
+            })
+            .await?;
+        Ok((Some(dir), output))
+    }
+
+    // Compiles in a separate container that can write the artifact cache entry,
+    // so the run container (and the user program) only ever sees it read-only.
+    async fn compile_artifacts(
+        &self,
+        spec: &RunSpec,
+        lang: &LanguageSpec,
+        image: &str,
+        work_dir: &Path,
+        deps_dir: Option<&Path>,
+    ) -> anyhow::Result<(Option<PathBuf>, Option<CompileOutput>)> {
+        let script = lang
+            .dependencies
+            .as_ref()
+            .and_then(|deps| deps.compile)
+            .filter(|_| deps_dir.is_some())
+            .or(lang.docker_compile);
+        let Some(script) = script else {
+            return Ok((None, None));
+        };
+        let script = if lang.docker_artifacts.is_empty() {
+            script.to_string()
+        } else {
+            format!(
+                "{script} && cd /build && cp -a {} /cache/",
+                lang.docker_artifacts.join(" ")
+            )
+        };
+
+        let content = (
+            image,
+            script.clone(),
+            lang.source_name,
+            &spec.request.code,
+            &spec.request.dependencies,
+            &spec.request.archive,
+            &spec.request.files,
+        );
+        let (dir, output) = self
+            .artifact_cache
+            .ensure(&content, |dir| async move {
+                spec.events.emit("compiling", "compiling source");
+                let mut host_config = self.host_config(&spec.limits, work_dir);
+                let mounts = host_config.mounts.get_or_insert_with(Vec::new);
+                if let Some(deps_dir) = deps_dir {
+                    mounts.push(bind_mount(deps_dir, "/deps", true));
+                }
+                mounts.push(bind_mount(&dir, "/cache", false));
+                host_config.network_mode = Some("none".to_string());
+                let outcome = self
+                    .run_stage_container(
+                        "build",
+                        spec,
+                        image,
+                        host_config,
+                        container_env(lang, &spec.limits, deps_dir.is_some()),
+                        &script,
+                        COMPILE_TIMEOUT,
+                    )
+                    .await?;
+                Ok(stage_output(outcome, COMPILE_TIMEOUT))
+            })
+            .await?;
+        Ok((Some(dir), output))
+    }
+
+    // Runs the language's syntax check in a throwaway container without
+    // network access.
+    async fn check_syntax(
+        &self,
+        spec: &RunSpec,
+        lang: &LanguageSpec,
+        image: &str,
+        work_dir: &Path,
+        deps_dir: Option<&Path>,
+        script: &str,
+    ) -> anyhow::Result<CompileOutput> {
+        spec.events.emit("checking", "checking syntax");
+        let mut host_config = self.host_config(&spec.limits, work_dir);
+        if let Some(deps_dir) = deps_dir {
+            host_config
+                .mounts
+                .get_or_insert_with(Vec::new)
+                .push(bind_mount(deps_dir, "/deps", true));
+        }
+        host_config.network_mode = Some("none".to_string());
+        let outcome = self
+            .run_stage_container(
+                "check",
+                spec,
+                image,
+                host_config,
+                container_env(lang, &spec.limits, deps_dir.is_some()),
+                script,
+                COMPILE_TIMEOUT,
+            )
+            .await?;
+        Ok(stage_output(outcome, COMPILE_TIMEOUT))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn run_stage_container(
+        &self,
+        prefix: &str,
+        spec: &RunSpec,
+        image: &str,
+        host_config: HostConfig,
+        env: Vec<String>,
+        script: &str,
+        timeout: Duration,
+    ) -> anyhow::Result<ExecOutcome> {
+        let name = format!(
+            "{prefix}-{}-{}-{}",
+            spec.id.as_simple(),
+            spec.attempt.as_simple(),
+            now_nanos() % 1_000_000
+        );
+        let body = ContainerCreateBody {
+            image: Some(image.to_string()),
+            env: Some(env),
+            cmd: Some(keepalive_cmd()),
+            working_dir: Some("/workspace".to_string()),
+            host_config: Some(host_config),
+            ..Default::default()
+        };
+        self.create_container(&name, image, body).await?;
+        let outcome = match self.start_container(&name).await {
+            Ok(()) => {
+                self.exec(
+                    &name,
+                    script,
+                    &[],
+                    None,
+                    timeout,
+                    spec.limits.max_output_bytes,
+                )
+                .await
+            }
+            Err(err) => Err(err.into()),
+        };
+        self.remove_container(&name).await;
+        outcome
+    }
+
+    // Starts the session driver in the container; only its stdout is read.
+    async fn attach_driver(
+        &self,
+        name: &str,
+        program: &DriverProgram,
+        token: &str,
+        limits: &ExecutionLimits,
+    ) -> anyhow::Result<Driver> {
+        let exec = self
+            .docker
+            .create_exec(
+                name,
+                ExecConfig {
+                    attach_stdin: Some(true),
+                    attach_stdout: Some(true),
+                    attach_stderr: Some(false),
+                    cmd: Some(driver_cmd(program)),
+                    env: Some(
+                        Driver::env(token, limits)
+                            .into_iter()
+                            .map(|(key, value)| format!("{key}={value}"))
+                            .collect(),
+                    ),
+                    working_dir: Some("/workspace".to_string()),
+                    ..Default::default()
+                },
+            )
+            .await
+            .map_err(DockerSandboxError::Exec)?;
+        let StartExecResults::Attached { output, input } = self
+            .docker
+            .start_exec(&exec.id, None::<StartExecOptions>)
+            .await
+            .map_err(DockerSandboxError::Exec)?
+        else {
+            anyhow::bail!("exec started detached");
+        };
+        Ok(Driver::new(
+            input,
+            forward_stdout(output),
+            token.to_string(),
+        ))
+    }
 
+    async fn start_container(&self, name: &str) -> Result<(), DockerSandboxError> {
+        self.docker
+            .start_container(
+                name,
+                None::<bollard::query_parameters::StartContainerOptions>,
+            )
+            .await
+            .map_err(DockerSandboxError::Start)
+    }
+
+    async fn run_stages(
+        &self,
+        name: &str,
+        lang: &LanguageSpec,
+        script: &str,
+        spec: RunSpec,
+        restore_artifacts: bool,
+    ) -> anyhow::Result<SandboxResult> {
+        self.start_container(name).await?;
+
+        let out_limit = spec.limits.max_output_bytes;
+        let output_encoding = spec.request.output_encoding;
+        if restore_artifacts && !lang.docker_artifacts.is_empty() {
+            let restore = self
+                .exec(
+                    name,
+                    "cp -a /cache/. /build/",
+                    &[],
+                    None,
+                    COMPILE_TIMEOUT,
+                    out_limit,
+                )
+                .await?;
+            if restore.exit_code != 0 {
+                anyhow::bail!(
+                    "failed to restore compiled artifacts: {}",
+                    String::from_utf8_lossy(&restore.stderr)
+                );
+            }
+        }
+
+        let baseline = self.container_stats(name).await;
+        let oom_before = self.cgroup_memory(name).await.oom_kills;
+        spec.events.emit("running", "program started");
+        let run = self
+            .exec(
+                name,
+                script,
+                &spec.request.args,
+                Some(ExecInput {
+                    initial: spec.request.stdin,
+                    file: spec.stdin_file,
+                    live: spec.stdin.take(),
+                    combined: spec.request.combined_output,
+                }),
+                Duration::from_millis(spec.limits.timeout_ms),
+                out_limit,
+            )
+            .await?;
+        let (usage, oom_after) = self.resource_usage(name, baseline, &run).await;
+        // The kernel OOM-kills with SIGKILL, so pair the 137 exit with an
+        // increase of the cgroup's oom_kill counter to rule out plain kills.
+        let oom_killed = run.exit_code == 137
+            && matches!((oom_before, oom_after), (Some(before), Some(after)) if after > before);
+
+        let (stdout, stdout_encoding) = encode_output(&run.stdout, output_encoding);
+        let (stderr, stderr_encoding) = encode_output(&run.stderr, output_encoding);
+        let combined = run
+            .combined
+            .map(|log| log.into_chunks(output_encoding))
+            .unwrap_or_default();
         Ok(SandboxResult {
-            stdout: String::from_utf8_lossy(&stdout_bytes).to_string(),
-            stderr: String::from_utf8_lossy(&stderr_bytes).to_string(),
-            exit_code: status_code,
-            duration_ms: started.elapsed().as_millis(),
+            stdout_truncated: (run.stdout.len() as u64) < run.stdout_bytes,
+            stderr_truncated: (run.stderr.len() as u64) < run.stderr_bytes,
+            stdout,
+            stderr,
+            stdout_encoding,
+            stderr_encoding,
+            exit_code: run.exit_code,
+            duration_ms: run.duration_ms,
+            timed_out: run.timed_out,
+            compile: None,
+            install: None,
+            usage,
+            oom_killed,
+            signal: if run.timed_out {
+                None
+            } else {
+                signal_from_exit_code(run.exit_code)
+            },
+            combined,
+            files: Vec::new(),
+        })
+    }
+
+    async fn container_stats(&self, name: &str) -> Option<ContainerStatsResponse> {
+        let options = StatsOptionsBuilder::new()
+            .stream(false)
+            .one_shot(true)
+            .build();
+        self.docker.stats(name, Some(options)).next().await?.ok()
+    }
+
+    // CPU time is the container's delta across the run exec. Peak memory covers
+    // the container's whole life, which is dominated by the run. Also returns
+    // the cgroup's oom_kill counter after the run.
+    async fn resource_usage(
+        &self,
+        name: &str,
+        baseline: Option<ContainerStatsResponse>,
+        run: &ExecOutcome,
+    ) -> (ResourceUsage, Option<u64>) {
+        let mut usage = ResourceUsage {
+            stdout_bytes: run.stdout_bytes,
+            stderr_bytes: run.stderr_bytes,
+            ..Default::default()
+        };
+        if run.timed_out {
+            return (usage, None);
+        }
+
+        if let Some(stats) = self.container_stats(name).await {
+            let cpu = |stats: Option<&ContainerStatsResponse>| {
+                stats
+                    .and_then(|stats| stats.cpu_stats.as_ref())
+                    .and_then(|cpu| cpu.cpu_usage.clone())
+                    .unwrap_or_default()
+            };
+            let (before, after) = (cpu(baseline.as_ref()), cpu(Some(&stats)));
+            let delta_ms = |after: Option<u64>, before: Option<u64>| {
+                after.map(|after| after.saturating_sub(before.unwrap_or(0)) / 1_000_000)
+            };
+            usage.user_cpu_ms = delta_ms(after.usage_in_usermode, before.usage_in_usermode);
+            usage.system_cpu_ms = delta_ms(after.usage_in_kernelmode, before.usage_in_kernelmode);
+        }
+
+        let memory = self.cgroup_memory(name).await;
+        usage.peak_memory_bytes = memory.peak_bytes;
+        (usage, memory.oom_kills)
+    }
+
+    async fn cgroup_memory(&self, name: &str) -> CgroupMemory {
+        self.exec(
+            name,
+            CGROUP_MEMORY_SCRIPT,
+            &[],
+            None,
+            Duration::from_secs(5),
+            1024,
+        )
+        .await
+        .map(|out| CgroupMemory::parse(&String::from_utf8_lossy(&out.stdout)))
+        .unwrap_or_default()
+    }
+
+    async fn exec(
+        &self,
+        name: &str,
+        script: &str,
+        args: &[String],
+        stdin: Option<ExecInput>,
+        timeout: Duration,
+        out_limit: usize,
+    ) -> anyhow::Result<ExecOutcome> {
+        let mut cmd = vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            script.to_string(),
+            "sh".to_string(),
+        ];
+        cmd.extend(args.iter().cloned());
+
+        let exec = self
+            .docker
+            .create_exec(
+                name,
+                ExecConfig {
+                    attach_stdin: Some(stdin.is_some()),
+                    attach_stdout: Some(true),
+                    attach_stderr: Some(true),
+                    cmd: Some(cmd),
+                    working_dir: Some("/workspace".to_string()),
+                    ..Default::default()
+                },
+            )
+            .await
+            .map_err(DockerSandboxError::Exec)?;
+
+        let combined = stdin.as_ref().is_some_and(|stdin| stdin.combined);
+        let started = Instant::now();
+        let StartExecResults::Attached { mut output, input } = self
+            .docker
+            .start_exec(&exec.id, None::<StartExecOptions>)
+            .await
+            .map_err(DockerSandboxError::Exec)?
+        else {
+            anyhow::bail!("exec started detached");
+        };
+
+        if let Some(stdin) = stdin {
+            tokio::spawn(feed_stdin(
+                input,
+                stdin.initial.into_bytes(),
+                stdin.file,
+                stdin.live,
+            ));
+        }
+
+        // Told to stop on a timeout, so what was read before the kill is kept.
+        let (stop, mut stopped) = tokio::sync::oneshot::channel::<()>();
+        let mut output_task = tokio::spawn(async move {
+            let mut stdout = Vec::with_capacity(out_limit.min(8192));
+            let mut stderr = Vec::with_capacity(out_limit.min(8192));
+            let (mut stdout_bytes, mut stderr_bytes) = (0u64, 0u64);
+            let mut log = combined.then(|| OutputLog::new(out_limit));
+            loop {
+                let chunk = tokio::select! {
+                    chunk = output.next() => chunk,
+                    _ = &mut stopped => None,
+                };
+                let Some(Ok(chunk)) = chunk else {
+                    break;
+                };
+                match chunk {
+                    LogOutput::StdOut { message } | LogOutput::Console { message } => {
+                        stdout_bytes += message.len() as u64;
+                        if let Some(log) = &mut log {
+                            log.push(OutputStream::Stdout, &message);
+                        }
+                        push_limited(&mut stdout, &message, out_limit)
+                    }
+                    LogOutput::StdErr { message } => {
+                        stderr_bytes += message.len() as u64;
+                        if let Some(log) = &mut log {
+                            log.push(OutputStream::Stderr, &message);
+                        }
+                        push_limited(&mut stderr, &message, out_limit)
+                    }
+                    LogOutput::StdIn { .. } => {}
+                }
+            }
+            (stdout, stderr, stdout_bytes, stderr_bytes, log)
+        });
+
+        let (joined, timed_out) = match tokio::time::timeout(timeout, &mut output_task).await {
+            Ok(joined) => (joined, false),
+            Err(_) => {
+                let kill = KillContainerOptionsBuilder::new().signal("SIGKILL").build();
+                let _ = self.docker.kill_container(name, Some(kill)).await;
+                let _ = stop.send(());
+                (output_task.await, true)
+            }
+        };
+        let (stdout, stderr, stdout_bytes, stderr_bytes, combined) = joined.unwrap_or_default();
+        let duration_ms = started.elapsed().as_millis();
+
+        let exit_code = if timed_out {
+            -1
+        } else {
+            self.docker
+                .inspect_exec(&exec.id)
+                .await
+                .map_err(DockerSandboxError::Exec)?
+                .exit_code
+                .unwrap_or(-1) as i32
+        };
+
+        Ok(ExecOutcome {
+            stdout,
+            stderr,
+            stdout_bytes,
+            stderr_bytes,
+            combined,
+            exit_code,
             timed_out,
+            duration_ms,
         })
     }
 }
 
-async fn write_source(work_dir: &Path, lang: &LanguageSpec, source: &str) -> anyhow::Result<()> {
+fn stage_output(outcome: ExecOutcome, timeout: Duration) -> CompileOutput {
+    CompileOutput {
+        success: outcome.exit_code == 0 && !outcome.timed_out,
+        exit_code: outcome.exit_code,
+        stdout: String::from_utf8_lossy(&outcome.stdout).to_string(),
+        stderr: if outcome.timed_out {
+            format!("stage timed out after {}s", timeout.as_secs())
+        } else {
+            String::from_utf8_lossy(&outcome.stderr).to_string()
+        },
+        duration_ms: outcome.duration_ms,
+    }
+}
+
+fn proxy_env(grant: &EgressGrant) -> Vec<String> {
+    let url = grant.proxy_url();
+    ["HTTP_PROXY", "HTTPS_PROXY", "http_proxy", "https_proxy"]
+        .iter()
+        .map(|key| format!("{key}={url}"))
+        .collect()
+}
+
+fn container_env(lang: &LanguageSpec, limits: &ExecutionLimits, with_deps: bool) -> Vec<String> {
+    let mut env = Vec::new();
+    if lang.jvm {
+        env.push(format!(
+            "JVM_OPTS={}",
+            jvm_options(limits.memory_mb).join(" ")
+        ));
+    }
+    if with_deps {
+        env.extend([
+            "WORKSPACE=/workspace".to_string(),
+            "DEPS=/deps".to_string(),
+            "OUT=/build/app".to_string(),
+        ]);
+        env.extend(
+            lang.dependency_env(Path::new("/deps"))
+                .into_iter()
+                .map(|(key, value)| format!("{key}={value}")),
+        );
+    }
+    env
+}
+
+fn driver_cmd(program: &DriverProgram) -> Vec<String> {
+    let mut cmd: Vec<String> = program
+        .docker_cmd
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+    cmd.push(format!("/workspace/{}", program.file_name));
+    cmd
+}
+
+// Attaches to a session container whose main process is the driver; done
+// before the container starts (or is restored) so no output is missed.
+async fn attach_main_driver(docker: &Docker, name: &str, token: &str) -> anyhow::Result<Driver> {
+    let options = AttachContainerOptionsBuilder::new()
+        .stream(true)
+        .stdin(true)
+        .stdout(true)
+        .build();
+    let AttachContainerResults { output, input } = docker
+        .attach_container(name, Some(options))
+        .await
+        .map_err(DockerSandboxError::Exec)?;
+    Ok(Driver::new(
+        input,
+        forward_stdout(output),
+        token.to_string(),
+    ))
+}
+
+fn forward_stdout(
+    mut output: Pin<Box<dyn Stream<Item = Result<LogOutput, BollardError>> + Send>>,
+) -> mpsc::Receiver<Vec<u8>> {
+    let (sender, receiver) = mpsc::channel(16);
+    tokio::spawn(async move {
+        while let Some(Ok(chunk)) = output.next().await {
+            if let LogOutput::StdOut { message } | LogOutput::Console { message } = chunk
+                && sender.send(message.to_vec()).await.is_err()
+            {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+fn keepalive_cmd() -> Vec<String> {
+    vec![
+        "tail".to_string(),
+        "-f".to_string(),
+        "/dev/null".to_string(),
+    ]
+}
+
+fn bind_mount(dir: &Path, target: &str, read_only: bool) -> Mount {
+    Mount {
+        target: Some(target.to_string()),
+        source: Some(normalize_mount_path(dir)),
+        typ: Some(MountTypeEnum::BIND),
+        read_only: Some(read_only),
+        ..Default::default()
+    }
+}
+
+async fn write_source(
+    work_dir: &Path,
+    lang: &LanguageSpec,
+    request: &ExecutionRequest,
+) -> anyhow::Result<()> {
     tokio::fs::create_dir_all(work_dir)
         .await
         .with_context(|| format!("failed to create work dir {}", work_dir.display()))?;
-    tokio::fs::write(lang.source_path(work_dir), source.as_bytes())
+    lang.write_sources(work_dir, request)
         .await
-        .context("failed to write source file")?;
+        .context("failed to write source files")?;
     Ok(())
 }
 
@@ -162,38 +1386,109 @@ fn now_nanos() -> u128 {
         .unwrap_or(0)
 }
 
-async fn cleanup_container(name: &str) {
-    let _ = Command::new("docker")
-        .arg("rm")
-        .arg("-f")
-        .arg(name)
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .status()
-        .await;
-}
-
 async fn cleanup_dir(path: &Path) {
     let _ = tokio::fs::remove_dir_all(path).await;
 }
 
-async fn read_limited<R>(mut reader: R, limit: usize) -> Vec<u8>
-where
-    R: tokio::io::AsyncRead + Unpin,
-{
-    let mut out = Vec::with_capacity(limit.min(8192));
-    let mut chunk = [0u8; 4096];
-    loop {
-        match reader.read(&mut chunk).await {
-            Ok(0) => break,
-            Ok(n) => {
-                if out.len() < limit {
-                    let remaining = limit - out.len();
-                    out.extend_from_slice(&chunk[..remaining.min(n)]);
-                }
-            }
-            Err(_) => break,
+fn push_limited(out: &mut Vec<u8>, chunk: &[u8], limit: usize) {
+    if out.len() < limit {
+        let remaining = limit - out.len();
+        out.extend_from_slice(&chunk[..remaining.min(chunk.len())]);
+    }
+}
+
+// `no-new-privileges` always applies. SECCOMP_PROFILE is a path to a JSON
+// profile, which the Engine API wants inline, or `unconfined`; unset keeps the
+// runtime's default profile.
+fn security_options(config: &EngineConfig) -> anyhow::Result<Vec<String>> {
+    let mut options = vec!["no-new-privileges".to_string()];
+    match config.seccomp_profile.as_deref() {
+        None => {}
+        Some("unconfined") => options.push("seccomp=unconfined".to_string()),
+        Some(path) => {
+            let profile = std::fs::read_to_string(path)
+                .with_context(|| format!("failed to read seccomp profile {path}"))?;
+            let profile: serde_json::Value = serde_json::from_str(&profile)
+                .with_context(|| format!("seccomp profile {path} is not valid JSON"))?;
+            options.push(format!("seccomp={profile}"));
         }
     }
-    out
+    if let Some(profile) = &config.apparmor_profile {
+        options.push(format!("apparmor={profile}"));
+    }
+    let mut labels = config.selinux_labels.clone();
+    labels.sort();
+    options.extend(labels.into_iter().map(|label| format!("label={label}")));
+    Ok(options)
+}
+
+// Same forms as `docker run --gpus`: `all`, a count, or device ids.
+fn gpu_request(devices: &str) -> DeviceRequest {
+    let (count, device_ids) = match devices.trim() {
+        "all" | "" => (Some(-1), None),
+        devices => match devices.parse::<i64>() {
+            Ok(count) => (Some(count), None),
+            Err(_) => (
+                None,
+                Some(devices.split(',').map(|id| id.trim().to_string()).collect()),
+            ),
+        },
+    };
+    DeviceRequest {
+        count,
+        device_ids,
+        capabilities: Some(vec![vec!["gpu".to_string()]]),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CgroupMemory, gpu_request, security_options};
+    use crate::engine::config::EngineConfig;
+
+    #[test]
+    fn parses_cgroup_memory_report() {
+        assert_eq!(
+            CgroupMemory::parse("oom_kill 2\npeak 73400320\n"),
+            CgroupMemory {
+                peak_bytes: Some(73_400_320),
+                oom_kills: Some(2),
+            }
+        );
+        assert_eq!(CgroupMemory::parse("peak \n"), CgroupMemory::default());
+    }
+
+    #[test]
+    fn builds_security_options_from_config() {
+        let profile = std::env::temp_dir().join(format!("seccomp-{}.json", std::process::id()));
+        std::fs::write(&profile, r#"{ "defaultAction": "SCMP_ACT_ERRNO" }"#).unwrap();
+        let mut config = EngineConfig::from_env();
+        config.seccomp_profile = Some(profile.display().to_string());
+        config.apparmor_profile = Some("sandbox-runner".to_string());
+        config.selinux_labels = vec!["type:container_t".to_string()];
+        let options = security_options(&config).unwrap();
+        std::fs::remove_file(&profile).unwrap();
+        assert_eq!(
+            options,
+            [
+                "no-new-privileges",
+                r#"seccomp={"defaultAction":"SCMP_ACT_ERRNO"}"#,
+                "apparmor=sandbox-runner",
+                "label=type:container_t",
+            ]
+        );
+
+        config.seccomp_profile = Some("/missing/profile.json".to_string());
+        assert!(security_options(&config).is_err());
+    }
+
+    #[test]
+    fn maps_gpu_devices_like_the_cli() {
+        assert_eq!(gpu_request("all").count, Some(-1));
+        assert_eq!(gpu_request("2").count, Some(2));
+        let ids = gpu_request("0, 3");
+        assert_eq!(ids.count, None);
+        assert_eq!(ids.device_ids.unwrap(), ["0", "3"]);
+    }
 }
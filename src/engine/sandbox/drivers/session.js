@@ -0,0 +1,115 @@
+// REPL driver for sandbox sessions. Requests arrive on stdin as
+// "<op> <length>\n<payload>"; each reply is one "<token> <json>" line.
+const util = require("util");
+const vm = require("vm");
+
+const TOKEN = process.env.SESSION_TOKEN || "";
+const LIMIT = Number(process.env.SESSION_OUTPUT_LIMIT || 65536);
+delete process.env.SESSION_TOKEN;
+delete process.env.SESSION_OUTPUT_LIMIT;
+
+let out = capture();
+let err = capture();
+
+function capture() {
+  return { text: "", truncated: false };
+}
+
+function append(target, args) {
+  let text = util.format(...args) + "\n";
+  const room = LIMIT - target.text.length;
+  if (text.length > room) {
+    target.truncated = true;
+    text = text.slice(0, Math.max(room, 0));
+  }
+  target.text += text;
+}
+
+const sessionConsole = {
+  log: (...args) => append(out, args),
+  info: (...args) => append(out, args),
+  debug: (...args) => append(out, args),
+  warn: (...args) => append(err, args),
+  error: (...args) => append(err, args),
+};
+const globals = {
+  console: sessionConsole,
+  require,
+  process,
+  Buffer,
+  setTimeout,
+  clearTimeout,
+  setInterval,
+  clearInterval,
+};
+const builtins = new Set(Object.keys(globals));
+const context = vm.createContext({ ...globals });
+
+function evaluate(code) {
+  out = capture();
+  err = capture();
+  let value = null;
+  let error = null;
+  try {
+    const result = vm.runInContext(code, context, { filename: "<session>" });
+    if (result !== undefined) {
+      value = util.inspect(result).slice(0, LIMIT);
+    }
+  } catch (e) {
+    error = describeError(e);
+  }
+  return {
+    stdout: out.text,
+    stderr: err.text,
+    value,
+    error,
+    stdout_truncated: out.truncated,
+    stderr_truncated: err.truncated,
+  };
+}
+
+// Stack frames below the evaluated code belong to the driver.
+function describeError(e) {
+  const text = String(e && e.stack ? e.stack : e);
+  const cut = text.indexOf("\n    at Script.runInContext");
+  return (cut < 0 ? text : text.slice(0, cut)).slice(0, LIMIT);
+}
+
+function describe() {
+  const variables = Object.keys(context)
+    .filter((name) => !builtins.has(name) && !name.startsWith("_"))
+    .sort()
+    .map((name) => ({
+      name,
+      type: typeof context[name],
+      repr: util.inspect(context[name]).slice(0, 200),
+    }));
+  return { variables };
+}
+
+let pending = Buffer.alloc(0);
+process.stdin.on("data", (chunk) => {
+  pending = Buffer.concat([pending, chunk]);
+  for (;;) {
+    const newline = pending.indexOf(10);
+    if (newline < 0) {
+      return;
+    }
+    const [op, size] = pending.subarray(0, newline).toString().trim().split(" ");
+    const end = newline + 1 + Number(size || 0);
+    if (pending.length < end) {
+      return;
+    }
+    const payload = pending.subarray(newline + 1, end).toString();
+    pending = pending.subarray(end);
+    let reply;
+    if (op === "eval") {
+      reply = evaluate(payload);
+    } else if (op === "inspect") {
+      reply = describe();
+    } else {
+      reply = { error: `unknown op ${op}` };
+    }
+    process.stdout.write(`\n${TOKEN} ${JSON.stringify(reply)}\n`);
+  }
+});
@@ -0,0 +1,99 @@
+# REPL driver for sandbox sessions. Requests arrive on stdin as
+# "<op> <length>\n<payload>"; each reply is one "<token> <json>" line.
+import ast
+import io
+import json
+import os
+import sys
+import traceback
+import types
+
+TOKEN = os.environ.pop("SESSION_TOKEN", "")
+LIMIT = int(os.environ.pop("SESSION_OUTPUT_LIMIT", "65536"))
+
+# Replies get a private copy of stdout; anything written straight to fd 1
+# goes to stderr instead.
+channel = os.fdopen(os.dup(1), "w")
+os.dup2(2, 1)
+requests = sys.stdin.buffer
+namespace = {"__name__": "__main__", "__builtins__": __builtins__}
+
+
+class Capture(io.TextIOBase):
+    def __init__(self):
+        self.parts = []
+        self.size = 0
+        self.truncated = False
+
+    def writable(self):
+        return True
+
+    def write(self, text):
+        room = LIMIT - self.size
+        if len(text) > room:
+            self.truncated = True
+        kept = text[: max(room, 0)]
+        self.parts.append(kept)
+        self.size += len(kept)
+        return len(text)
+
+    def text(self):
+        return "".join(self.parts)
+
+
+def evaluate(code):
+    out, err = Capture(), Capture()
+    value = error = None
+    sys.stdout, sys.stderr = out, err
+    try:
+        tree = ast.parse(code, "<session>", "exec")
+        last = None
+        if tree.body and isinstance(tree.body[-1], ast.Expr):
+            last = ast.Expression(tree.body.pop().value)
+        exec(compile(tree, "<session>", "exec"), namespace)
+        if last is not None:
+            result = eval(compile(last, "<session>", "eval"), namespace)
+            if result is not None:
+                value = repr(result)[:LIMIT]
+    except BaseException:
+        kind, exc, tb = sys.exc_info()
+        error = "".join(traceback.format_exception(kind, exc, tb.tb_next))[:LIMIT]
+    finally:
+        sys.stdout, sys.stderr = sys.__stdout__, sys.__stderr__
+    return {
+        "stdout": out.text(),
+        "stderr": err.text(),
+        "value": value,
+        "error": error,
+        "stdout_truncated": out.truncated,
+        "stderr_truncated": err.truncated,
+    }
+
+
+def describe():
+    variables = []
+    for name, obj in sorted(namespace.items()):
+        if name.startswith("_") or isinstance(obj, types.ModuleType):
+            continue
+        try:
+            text = repr(obj)
+        except Exception:
+            text = "<unrepresentable>"
+        variables.append({"name": name, "type": type(obj).__name__, "repr": text[:200]})
+    return {"variables": variables}
+
+
+while True:
+    header = requests.readline()
+    if not header:
+        break
+    op, _, size = header.decode().strip().partition(" ")
+    payload = requests.read(int(size or 0)).decode("utf-8", "replace")
+    if op == "eval":
+        reply = evaluate(payload)
+    elif op == "inspect":
+        reply = describe()
+    else:
+        reply = {"error": "unknown op " + op}
+    channel.write("\n" + TOKEN + " " + json.dumps(reply) + "\n")
+    channel.flush()
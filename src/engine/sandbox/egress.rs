@@ -0,0 +1,666 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use base64::{Engine, prelude::BASE64_STANDARD};
+use dashmap::DashMap;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, copy_bidirectional},
+    net::{TcpListener, TcpStream, lookup_host},
+    time::timeout,
+};
+use uuid::Uuid;
+
+use super::EventSink;
+
+const MAX_HEAD_BYTES: usize = 16 * 1024;
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// Largest read relayed at once on a throttled connection.
+const THROTTLED_CHUNK: usize = 16 * 1024;
+
+// One allowed destination: `host`, `*.suffix` or either with `:port`. Without
+// a port every port is allowed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EgressRule {
+    host: String,
+    port: Option<u16>,
+}
+
+impl EgressRule {
+    // Whether every destination `other` allows is also allowed by `self`.
+    pub fn covers(&self, other: &EgressRule) -> bool {
+        let port_ok = self.port.is_none() || self.port == other.port;
+        let host_ok = self.host == other.host
+            || self
+                .host
+                .strip_prefix('*')
+                .is_some_and(|suffix| other.host.ends_with(suffix));
+        port_ok && host_ok
+    }
+
+    pub fn allows(&self, host: &str, port: u16) -> bool {
+        self.covers(&EgressRule {
+            host: host.to_ascii_lowercase(),
+            port: Some(port),
+        })
+    }
+}
+
+impl FromStr for EgressRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let raw = s.trim().to_ascii_lowercase();
+        let (host, port) = match raw.rsplit_once(':') {
+            Some((host, port)) => {
+                let port = port
+                    .parse::<u16>()
+                    .ok()
+                    .filter(|port| *port != 0)
+                    .ok_or_else(|| format!("invalid egress port in {s}"))?;
+                (host.to_string(), Some(port))
+            }
+            None => (raw, None),
+        };
+        let name = host.strip_prefix("*.").unwrap_or(&host);
+        let valid = !name.is_empty()
+            && name.len() <= 253
+            && name.split('.').all(|label| {
+                !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            });
+        if !valid {
+            return Err(format!("invalid egress host in {s}"));
+        }
+        Ok(Self { host, port })
+    }
+}
+
+// HTTP proxy that containers on the egress network reach the outside through.
+// Each run gets a grant whose token is the proxy password, so the proxy knows
+// which policy applies to a connection. Every connection is logged and
+// reported as a `network` event of the run.
+pub struct EgressProxy {
+    local_addr: SocketAddr,
+    public_url: String,
+    grants: DashMap<String, Arc<Grant>>,
+}
+
+// What one run may reach through the proxy.
+pub struct EgressPolicy {
+    // `None` allows every public destination (`allow_network` runs of
+    // tenants with no TENANT_NETWORK_ALLOWLIST entry).
+    pub rules: Option<Vec<EgressRule>>,
+    // Bytes per second over all of the run's connections, both ways.
+    pub bandwidth: Option<u64>,
+    pub execution_id: Uuid,
+    pub tenant_id: String,
+    pub events: EventSink,
+}
+
+struct Grant {
+    policy: EgressPolicy,
+    throttle: Option<Throttle>,
+}
+
+impl Grant {
+    fn allows(&self, host: &str, port: u16) -> bool {
+        self.policy
+            .rules
+            .as_ref()
+            .is_none_or(|rules| rules.iter().any(|rule| rule.allows(host, port)))
+    }
+
+    // Whether a rule names `host` itself rather than through a wildcard or
+    // the allow-everything policy.
+    fn names(&self, host: &str, port: u16) -> bool {
+        self.policy.rules.as_ref().is_some_and(|rules| {
+            rules
+                .iter()
+                .any(|rule| !rule.host.starts_with('*') && rule.allows(host, port))
+        })
+    }
+}
+
+// Addresses inside the engine's own networks: loopback, link-local, private
+// and unspecified.
+fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            ip.is_loopback() || ip.is_link_local() || ip.is_private() || ip.is_unspecified()
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_internal(IpAddr::V4(ip)),
+            None => {
+                ip.is_loopback()
+                    || ip.is_unicast_link_local()
+                    || ip.is_unique_local()
+                    || ip.is_unspecified()
+            }
+        },
+    }
+}
+
+// A token bucket holding up to one second of bandwidth. Takers go into debt
+// and sleep it off, so a chunk is never split.
+struct Throttle {
+    rate: f64,
+    state: Mutex<(f64, Instant)>,
+}
+
+impl Throttle {
+    fn new(rate: u64) -> Self {
+        let rate = rate.max(1) as f64;
+        Self {
+            rate,
+            state: Mutex::new((rate, Instant::now())),
+        }
+    }
+
+    async fn take(&self, bytes: usize) {
+        let wait = {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            let (available, refilled) = &mut *state;
+            let now = Instant::now();
+            *available = (*available + now.duration_since(*refilled).as_secs_f64() * self.rate)
+                .min(self.rate)
+                - bytes as f64;
+            *refilled = now;
+            (*available < 0.0).then(|| Duration::from_secs_f64(-*available / self.rate))
+        };
+        if let Some(wait) = wait {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    fn chunk(&self) -> usize {
+        (self.rate as usize).clamp(1024, THROTTLED_CHUNK)
+    }
+}
+
+pub struct EgressGrant {
+    proxy: Arc<EgressProxy>,
+    token: String,
+}
+
+impl EgressGrant {
+    // Proxy URL with credentials, for HTTP(S)_PROXY.
+    pub fn proxy_url(&self) -> String {
+        match self.proxy.public_url.split_once("://") {
+            Some((scheme, rest)) => format!("{scheme}://sandbox:{}@{rest}", self.token),
+            None => format!("http://sandbox:{}@{}", self.token, self.proxy.public_url),
+        }
+    }
+}
+
+impl Drop for EgressGrant {
+    fn drop(&mut self) {
+        self.proxy.grants.remove(&self.token);
+    }
+}
+
+impl EgressProxy {
+    pub async fn start(bind_addr: SocketAddr, public_url: String) -> anyhow::Result<Arc<Self>> {
+        let listener = TcpListener::bind(bind_addr)
+            .await
+            .with_context(|| format!("failed to bind egress proxy on {bind_addr}"))?;
+        let proxy = Arc::new(Self {
+            local_addr: listener.local_addr()?,
+            public_url,
+            grants: DashMap::new(),
+        });
+        let acceptor = proxy.clone();
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        let proxy = acceptor.clone();
+                        tokio::spawn(async move {
+                            if let Err(err) = proxy.handle(stream).await {
+                                tracing::debug!(error = %err, "egress proxy connection failed");
+                            }
+                        });
+                    }
+                    Err(err) => {
+                        tracing::warn!(error = %err, "egress proxy accept failed");
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                    }
+                }
+            }
+        });
+        Ok(proxy)
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn grant(self: &Arc<Self>, policy: EgressPolicy) -> EgressGrant {
+        let token = Uuid::new_v4().simple().to_string();
+        let throttle = policy.bandwidth.map(Throttle::new);
+        self.grants
+            .insert(token.clone(), Arc::new(Grant { policy, throttle }));
+        EgressGrant {
+            proxy: self.clone(),
+            token,
+        }
+    }
+
+    async fn handle(&self, mut client: TcpStream) -> anyhow::Result<()> {
+        let (head, body) = timeout(HANDSHAKE_TIMEOUT, read_head(&mut client))
+            .await
+            .context("proxy handshake timed out")??;
+        let Some(request) = ProxyRequest::parse(&head) else {
+            return respond(&mut client, "400 Bad Request").await;
+        };
+        let grant = request
+            .token
+            .as_deref()
+            .and_then(|token| self.grants.get(token).map(|grant| grant.clone()));
+        let Some(grant) = grant else {
+            return respond(&mut client, "407 Proxy Authentication Required").await;
+        };
+        let policy = &grant.policy;
+        let target = format!("{} {}:{}", request.method, request.host, request.port);
+        if !grant.allows(&request.host, request.port) {
+            tracing::info!(execution_id = %policy.execution_id, tenant_id = %policy.tenant_id, host = %request.host, port = request.port, method = %request.method, "egress denied");
+            policy.events.emit("network", format!("{target}: denied"));
+            return respond(&mut client, "403 Forbidden").await;
+        }
+
+        // Resolve here and connect to the vetted addresses, so a name cannot
+        // lead into the engine's own networks unless a rule names it.
+        let resolved = timeout(
+            HANDSHAKE_TIMEOUT,
+            lookup_host((request.host.as_str(), request.port)),
+        )
+        .await;
+        let Ok(Ok(resolved)) = resolved else {
+            policy
+                .events
+                .emit("network", format!("{target}: upstream unreachable"));
+            return respond(&mut client, "502 Bad Gateway").await;
+        };
+        let explicit = grant.names(&request.host, request.port);
+        let addrs: Vec<SocketAddr> = resolved
+            .filter(|addr| explicit || !is_internal(addr.ip()))
+            .collect();
+        if addrs.is_empty() {
+            tracing::info!(execution_id = %policy.execution_id, tenant_id = %policy.tenant_id, host = %request.host, port = request.port, method = %request.method, "egress denied: internal address");
+            policy.events.emit("network", format!("{target}: denied"));
+            return respond(&mut client, "403 Forbidden").await;
+        }
+
+        let upstream = timeout(HANDSHAKE_TIMEOUT, TcpStream::connect(&addrs[..])).await;
+        let Ok(Ok(mut upstream)) = upstream else {
+            policy
+                .events
+                .emit("network", format!("{target}: upstream unreachable"));
+            return respond(&mut client, "502 Bad Gateway").await;
+        };
+        match &request.forward_head {
+            None => {
+                client
+                    .write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")
+                    .await?
+            }
+            Some(head) => upstream.write_all(head.as_bytes()).await?,
+        }
+        upstream.write_all(&body).await?;
+        let started = Instant::now();
+        let relayed = match &grant.throttle {
+            Some(throttle) => relay_throttled(&mut client, &mut upstream, throttle).await,
+            None => copy_bidirectional(&mut client, &mut upstream).await,
+        };
+        let (sent, received) = relayed.as_ref().copied().unwrap_or_default();
+        tracing::info!(execution_id = %policy.execution_id, tenant_id = %policy.tenant_id, host = %request.host, port = request.port, method = %request.method, sent = sent + body.len() as u64, received, duration_ms = started.elapsed().as_millis() as u64, "egress connection closed");
+        policy.events.emit(
+            "network",
+            format!(
+                "{target}: {} bytes sent, {received} received",
+                sent + body.len() as u64
+            ),
+        );
+        relayed?;
+        Ok(())
+    }
+}
+
+// `copy_bidirectional` with both directions drawing on `throttle`. Returns
+// the bytes sent by the client and by the upstream.
+async fn relay_throttled(
+    client: &mut TcpStream,
+    upstream: &mut TcpStream,
+    throttle: &Throttle,
+) -> std::io::Result<(u64, u64)> {
+    let (mut client_read, mut client_write) = client.split();
+    let (mut upstream_read, mut upstream_write) = upstream.split();
+    tokio::try_join!(
+        pipe(&mut client_read, &mut upstream_write, throttle),
+        pipe(&mut upstream_read, &mut client_write, throttle),
+    )
+}
+
+async fn pipe(
+    from: &mut (impl AsyncRead + Unpin),
+    to: &mut (impl AsyncWrite + Unpin),
+    throttle: &Throttle,
+) -> std::io::Result<u64> {
+    let mut buf = vec![0u8; throttle.chunk()];
+    let mut total = 0;
+    loop {
+        let read = from.read(&mut buf).await?;
+        if read == 0 {
+            to.shutdown().await?;
+            return Ok(total);
+        }
+        throttle.take(read).await;
+        to.write_all(&buf[..read]).await?;
+        total += read as u64;
+    }
+}
+
+// Reads up to the end of the request head; returns the head and any bytes
+// read past it.
+async fn read_head(stream: &mut TcpStream) -> anyhow::Result<(String, Vec<u8>)> {
+    let mut buf = Vec::with_capacity(1024);
+    let mut chunk = [0u8; 1024];
+    loop {
+        let read = stream.read(&mut chunk).await?;
+        anyhow::ensure!(read > 0, "connection closed before request head");
+        buf.extend_from_slice(&chunk[..read]);
+        if let Some(end) = buf.windows(4).position(|window| window == b"\r\n\r\n") {
+            let body = buf.split_off(end + 4);
+            return Ok((String::from_utf8_lossy(&buf).into_owned(), body));
+        }
+        anyhow::ensure!(buf.len() <= MAX_HEAD_BYTES, "request head too large");
+    }
+}
+
+async fn respond(client: &mut TcpStream, status: &str) -> anyhow::Result<()> {
+    let extra = if status.starts_with("407") {
+        "Proxy-Authenticate: Basic realm=\"sandbox\"\r\n"
+    } else {
+        ""
+    };
+    client
+        .write_all(
+            format!("HTTP/1.1 {status}\r\n{extra}Content-Length: 0\r\nConnection: close\r\n\r\n")
+                .as_bytes(),
+        )
+        .await?;
+    Ok(())
+}
+
+#[derive(Debug)]
+struct ProxyRequest {
+    method: String,
+    host: String,
+    port: u16,
+    token: Option<String>,
+    // Head to send upstream for plain HTTP; `None` for CONNECT tunnels.
+    forward_head: Option<String>,
+}
+
+impl ProxyRequest {
+    fn parse(head: &str) -> Option<Self> {
+        let mut lines = head.split("\r\n");
+        let mut request_line = lines.next()?.split(' ');
+        let (method, target, version) = (
+            request_line.next()?,
+            request_line.next()?,
+            request_line.next()?,
+        );
+
+        let mut token = None;
+        let mut headers = Vec::new();
+        for line in lines.filter(|line| !line.is_empty()) {
+            let (name, value) = line.split_once(':')?;
+            if name.eq_ignore_ascii_case("proxy-authorization") {
+                token = value
+                    .trim()
+                    .strip_prefix("Basic ")
+                    .and_then(|encoded| BASE64_STANDARD.decode(encoded.trim()).ok())
+                    .and_then(|decoded| String::from_utf8(decoded).ok())
+                    .and_then(|credentials| {
+                        credentials
+                            .split_once(':')
+                            .map(|(_, password)| password.to_string())
+                    });
+            } else if !name.eq_ignore_ascii_case("proxy-connection") {
+                headers.push(line);
+            }
+        }
+
+        if method.eq_ignore_ascii_case("CONNECT") {
+            let (host, port) = split_host_port(target, None)?;
+            return Some(Self {
+                method: method.to_string(),
+                host,
+                port,
+                token,
+                forward_head: None,
+            });
+        }
+
+        // Plain HTTP requests arrive in absolute form and go upstream in
+        // origin form.
+        let rest = target.strip_prefix("http://")?;
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, "/"),
+        };
+        let (host, port) = split_host_port(authority, Some(80))?;
+        let mut forward_head = format!("{method} {path} {version}\r\n");
+        for header in headers {
+            forward_head.push_str(header);
+            forward_head.push_str("\r\n");
+        }
+        forward_head.push_str("\r\n");
+        Some(Self {
+            method: method.to_string(),
+            host,
+            port,
+            token,
+            forward_head: Some(forward_head),
+        })
+    }
+}
+
+fn split_host_port(authority: &str, default_port: Option<u16>) -> Option<(String, u16)> {
+    let authority = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    match authority.rsplit_once(':') {
+        Some((host, port)) => Some((host.to_ascii_lowercase(), port.parse().ok()?)),
+        None => Some((authority.to_ascii_lowercase(), default_port?)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    use std::{
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    use super::{EgressPolicy, EgressProxy, EgressRule};
+    use crate::engine::sandbox::EventSink;
+
+    fn policy(rules: Option<Vec<EgressRule>>, bandwidth: Option<u64>) -> EgressPolicy {
+        EgressPolicy {
+            rules,
+            bandwidth,
+            execution_id: uuid::Uuid::new_v4(),
+            tenant_id: "acme".into(),
+            events: EventSink::default(),
+        }
+    }
+
+    #[test]
+    fn matches_hosts_ports_and_wildcards() {
+        let rule: EgressRule = "*.pythonhosted.org:443".parse().unwrap();
+        assert!(rule.allows("files.pythonhosted.org", 443));
+        assert!(!rule.allows("files.pythonhosted.org", 80));
+        assert!(!rule.allows("pythonhosted.org.evil.com", 443));
+
+        let any_port: EgressRule = "pypi.org".parse().unwrap();
+        assert!(any_port.covers(&"pypi.org:443".parse().unwrap()));
+        assert!(!any_port.covers(&"*.pypi.org".parse().unwrap()));
+        assert!("bad host".parse::<EgressRule>().is_err());
+        assert!("pypi.org:0".parse::<EgressRule>().is_err());
+    }
+
+    #[tokio::test]
+    async fn tunnels_only_granted_destinations() {
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_port = upstream.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = upstream.accept().await.unwrap();
+            stream.write_all(b"hello").await.unwrap();
+        });
+
+        let proxy = EgressProxy::start(
+            "127.0.0.1:0".parse().unwrap(),
+            "http://proxy:3128".to_string(),
+        )
+        .await
+        .unwrap();
+        let proxy_addr = proxy.local_addr();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        let grant = proxy.grant(EgressPolicy {
+            events: EventSink::new(move |_, message| recorded.lock().unwrap().push(message)),
+            ..policy(Some(vec!["localhost".parse().unwrap()]), None)
+        });
+        let proxy_url = grant.proxy_url();
+        let credentials = proxy_url
+            .split_once("://")
+            .and_then(|(_, rest)| rest.split_once('@'))
+            .map(|(credentials, _)| credentials.to_string())
+            .unwrap();
+        let auth = base64::Engine::encode(&base64::prelude::BASE64_STANDARD, credentials);
+
+        let connect = |target: String| {
+            let auth = auth.clone();
+            async move {
+                let mut client = TcpStream::connect(proxy_addr).await.unwrap();
+                client
+                    .write_all(
+                        format!(
+                            "CONNECT {target} HTTP/1.1\r\nProxy-Authorization: Basic {auth}\r\n\r\n"
+                        )
+                        .as_bytes(),
+                    )
+                    .await
+                    .unwrap();
+                client.shutdown().await.unwrap();
+                let mut response = Vec::new();
+                client.read_to_end(&mut response).await.unwrap();
+                String::from_utf8(response).unwrap()
+            }
+        };
+
+        let denied = connect(format!("127.0.0.1:{upstream_port}")).await;
+        assert!(denied.starts_with("HTTP/1.1 403"), "{denied}");
+        let allowed = connect(format!("localhost:{upstream_port}")).await;
+        assert!(allowed.starts_with("HTTP/1.1 200"), "{allowed}");
+        assert!(allowed.ends_with("hello"));
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                format!("CONNECT 127.0.0.1:{upstream_port}: denied"),
+                format!("CONNECT localhost:{upstream_port}: 0 bytes sent, 5 received"),
+            ]
+        );
+
+        drop(grant);
+        let revoked = connect(format!("localhost:{upstream_port}")).await;
+        assert!(revoked.starts_with("HTTP/1.1 407"), "{revoked}");
+    }
+
+    #[tokio::test]
+    async fn denies_internal_addresses_without_a_rule_naming_them() {
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_port = upstream.local_addr().unwrap().port();
+        let proxy = EgressProxy::start("127.0.0.1:0".parse().unwrap(), "proxy:3128".into())
+            .await
+            .unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        let grant = proxy.grant(EgressPolicy {
+            events: EventSink::new(move |_, message| recorded.lock().unwrap().push(message)),
+            ..policy(None, None)
+        });
+        let url = grant.proxy_url();
+        let (_, rest) = url.split_once("://").unwrap();
+        let (credentials, _) = rest.split_once('@').unwrap();
+        let auth = base64::Engine::encode(&base64::prelude::BASE64_STANDARD, credentials);
+
+        let mut client = TcpStream::connect(proxy.local_addr()).await.unwrap();
+        client
+            .write_all(
+                format!(
+                    "CONNECT 127.0.0.1:{upstream_port} HTTP/1.1\r\nProxy-Authorization: Basic {auth}\r\n\r\n"
+                )
+                .as_bytes(),
+            )
+            .await
+            .unwrap();
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).await.unwrap();
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 403"), "{response}");
+        assert_eq!(
+            *events.lock().unwrap(),
+            [format!("CONNECT 127.0.0.1:{upstream_port}: denied")]
+        );
+        drop(upstream);
+    }
+
+    #[tokio::test]
+    async fn throttles_granted_bandwidth_across_directions() {
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = upstream.accept().await.unwrap();
+            stream.write_all(&[7u8; 60_000]).await.unwrap();
+        });
+        let proxy = EgressProxy::start("127.0.0.1:0".parse().unwrap(), "proxy:3128".into())
+            .await
+            .unwrap();
+        // 40 KB/s: one second of burst, then the rest.
+        let grant = proxy.grant(policy(
+            Some(vec!["127.0.0.1".parse().unwrap()]),
+            Some(40_000),
+        ));
+        let url = grant.proxy_url();
+        let (_, rest) = url.split_once("://").unwrap();
+        let (credentials, _) = rest.split_once('@').unwrap();
+        let auth = base64::Engine::encode(&base64::prelude::BASE64_STANDARD, credentials);
+
+        let started = Instant::now();
+        let mut client = TcpStream::connect(proxy.local_addr()).await.unwrap();
+        client
+            .write_all(
+                format!(
+                    "CONNECT {upstream_addr} HTTP/1.1\r\nProxy-Authorization: Basic {auth}\r\n\r\n"
+                )
+                .as_bytes(),
+            )
+            .await
+            .unwrap();
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).await.unwrap();
+        assert!(response.ends_with(&[7u8; 60_000]));
+        assert!(started.elapsed() >= Duration::from_millis(400));
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::{BTreeMap, HashMap};
+
+use bollard::auth::DockerCredentials;
+
+use crate::engine::{
+    config::{EngineConfig, RegistryAuth},
+    models::Language,
+    sandbox::LanguageSpec,
+};
+
+#[derive(Debug, Clone)]
+pub struct ImageCatalog {
+    images: HashMap<Language, String>,
+    versions: HashMap<Language, BTreeMap<String, String>>,
+    auth: HashMap<String, RegistryAuth>,
+}
+
+impl ImageCatalog {
+    pub fn from_config(config: &EngineConfig) -> Self {
+        let images = Language::ALL
+            .iter()
+            .map(|language| {
+                let image = config
+                    .language_images
+                    .get(language)
+                    .cloned()
+                    .unwrap_or_else(|| {
+                        LanguageSpec::for_language(language)
+                            .docker_image
+                            .to_string()
+                    });
+                (language.clone(), image)
+            })
+            .collect();
+        Self {
+            images,
+            versions: config.language_versions.clone(),
+            auth: config.registry_auth.clone(),
+        }
+    }
+
+    pub fn image_for(&self, language: &Language) -> &str {
+        self.images
+            .get(language)
+            .map(String::as_str)
+            .unwrap_or_else(|| LanguageSpec::for_language(language).docker_image)
+    }
+
+    pub fn version_image(&self, language: &Language, version: &str) -> Option<&str> {
+        self.versions
+            .get(language)
+            .and_then(|versions| versions.get(version))
+            .map(String::as_str)
+    }
+
+    // Default and versioned images, for pre-pulling.
+    pub fn images(&self) -> Vec<String> {
+        let mut images: Vec<String> = self
+            .images
+            .values()
+            .chain(self.versions.values().flat_map(BTreeMap::values))
+            .cloned()
+            .collect();
+        images.sort();
+        images.dedup();
+        images
+    }
+
+    pub fn credentials_for(&self, image: &str) -> Option<DockerCredentials> {
+        let registry = registry_host(image);
+        self.auth.get(registry).map(|auth| DockerCredentials {
+            username: Some(auth.username.clone()),
+            password: Some(auth.password.clone()),
+            serveraddress: Some(registry.to_string()),
+            ..Default::default()
+        })
+    }
+}
+
+pub fn image_allowed(patterns: &[String], image: &str) -> bool {
+    patterns.iter().any(|pattern| {
+        if pattern.contains('@') {
+            // Digest-pinned entries must match exactly so a tag can't be repointed.
+            pattern == image
+        } else {
+            wildcard_match(pattern, image)
+        }
+    })
+}
+
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let Some((head, rest)) = pattern.split_once('*') else {
+        return pattern == text;
+    };
+    let Some(mut remaining) = text.strip_prefix(head) else {
+        return false;
+    };
+    let mut parts: Vec<&str> = rest.split('*').collect();
+    let tail = parts.pop().unwrap_or_default();
+    for part in parts {
+        match remaining.find(part) {
+            Some(idx) => remaining = &remaining[idx + part.len()..],
+            None => return false,
+        }
+    }
+    remaining.len() >= tail.len() && remaining.ends_with(tail)
+}
+
+fn registry_host(image: &str) -> &str {
+    match image.split_once('/') {
+        Some((host, _)) if host.contains('.') || host.contains(':') || host == "localhost" => host,
+        _ => "docker.io",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{image_allowed, registry_host};
+
+    #[test]
+    fn extracts_registry_host_from_image_reference() {
+        assert_eq!(registry_host("python:3.12-alpine"), "docker.io");
+        assert_eq!(registry_host("library/node:22"), "docker.io");
+        assert_eq!(registry_host("ghcr.io/acme/runner:1"), "ghcr.io");
+        assert_eq!(registry_host("localhost:5000/img"), "localhost:5000");
+    }
+
+    #[test]
+    fn matches_allowlist_patterns_and_pinned_digests() {
+        let patterns = vec![
+            "ghcr.io/acme/*".to_string(),
+            "python:3.*-slim".to_string(),
+            "node@sha256:abc123".to_string(),
+        ];
+        assert!(image_allowed(&patterns, "ghcr.io/acme/ml:2024"));
+        assert!(image_allowed(&patterns, "python:3.12-slim"));
+        assert!(image_allowed(&patterns, "node@sha256:abc123"));
+        assert!(!image_allowed(&patterns, "node@sha256:def456"));
+        assert!(!image_allowed(&patterns, "python:3.12"));
+        assert!(!image_allowed(&patterns, "ghcr.io/other/ml:1"));
+        assert!(!image_allowed(&[], "python:3.12-slim"));
+    }
+}
@@ -0,0 +1,270 @@
+use std::{
+    ffi::CString,
+    os::unix::{ffi::OsStrExt, fs::PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use tokio::process::Command;
+
+use crate::engine::config::EngineConfig;
+
+// Where programs look for commands once the engine's environment is gone.
+pub const DEFAULT_PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunUser {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl RunUser {
+    // `uid:gid`, or a user name resolved with its primary group.
+    pub fn resolve(user: &str) -> anyhow::Result<Self> {
+        if let Some((uid, gid)) = user.split_once(':') {
+            return Ok(Self {
+                uid: uid
+                    .trim()
+                    .parse()
+                    .context("invalid uid in PROCESS_RUN_USER")?,
+                gid: gid
+                    .trim()
+                    .parse()
+                    .context("invalid gid in PROCESS_RUN_USER")?,
+            });
+        }
+        let name = CString::new(user).context("invalid PROCESS_RUN_USER")?;
+        // SAFETY: passwd is plain old data filled in by getpwnam_r, which
+        // only writes into `buf`; `result` points at `passwd` or is null.
+        unsafe {
+            let mut passwd: libc::passwd = std::mem::zeroed();
+            let mut buf = vec![0 as libc::c_char; 16 * 1024];
+            let mut result = std::ptr::null_mut();
+            let rc = libc::getpwnam_r(
+                name.as_ptr(),
+                &mut passwd,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            );
+            if rc != 0 || result.is_null() {
+                anyhow::bail!("unknown user {user}");
+            }
+            Ok(Self {
+                uid: passwd.pw_uid,
+                gid: passwd.pw_gid,
+            })
+        }
+    }
+}
+
+// How process-backend programs are confined (PROCESS_RUN_USER,
+// PROCESS_CHROOT): they run as a dedicated user with only PATH, HOME and
+// TMPDIR set, optionally chrooted. Off when neither is configured, and
+// programs then run as the engine with its environment.
+#[derive(Debug, Clone)]
+pub struct Isolation {
+    user: Option<String>,
+    root: Option<PathBuf>,
+    path: String,
+}
+
+impl Isolation {
+    pub fn new(config: &EngineConfig) -> Self {
+        Self {
+            user: config.process_run_user.clone(),
+            root: config.process_chroot.clone(),
+            path: config.process_path.clone(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.user.is_some() || self.root.is_some()
+    }
+
+    // Work and compile directories go inside the chroot so programs can
+    // reach them.
+    pub fn temp_dir(&self) -> PathBuf {
+        match &self.root {
+            Some(root) => root.join("tmp"),
+            None => std::env::temp_dir(),
+        }
+    }
+
+    pub fn run_user(&self) -> anyhow::Result<Option<RunUser>> {
+        self.user.as_deref().map(RunUser::resolve).transpose()
+    }
+
+    // Switching users and chrooting both need root.
+    pub async fn check(&self) -> anyhow::Result<()> {
+        if !self.enabled() {
+            return Ok(());
+        }
+        // A program left running as root could simply leave the chroot.
+        if self.root.is_some() && self.user.is_none() {
+            anyhow::bail!("PROCESS_CHROOT needs PROCESS_RUN_USER");
+        }
+        let user = self.run_user()?;
+        // SAFETY: geteuid cannot fail.
+        let euid = unsafe { libc::geteuid() };
+        if euid != 0 {
+            anyhow::bail!("process isolation needs the engine to run as root");
+        }
+        if user.is_some_and(|user| user.uid == 0) {
+            anyhow::bail!("PROCESS_RUN_USER must not be root");
+        }
+        if let Some(root) = &self.root {
+            tokio::fs::create_dir_all(root.join("tmp"))
+                .await
+                .with_context(|| format!("chroot {} is unusable", root.display()))?;
+        }
+        Ok(())
+    }
+
+    // The path a program inside the chroot sees for `path`.
+    pub fn inside(&self, path: &Path) -> anyhow::Result<PathBuf> {
+        let Some(root) = &self.root else {
+            return Ok(path.to_path_buf());
+        };
+        let relative = path
+            .strip_prefix(root)
+            .with_context(|| format!("{} is outside PROCESS_CHROOT", path.display()))?;
+        Ok(Path::new("/").join(relative))
+    }
+
+    // Whether `binary` is on the programs' PATH, inside the chroot if any.
+    pub fn on_path(&self, binary: &str) -> bool {
+        let root = self.root.as_deref().unwrap_or(Path::new("/"));
+        let path = if self.enabled() {
+            self.path.clone().into()
+        } else {
+            std::env::var_os("PATH").unwrap_or_default()
+        };
+        std::env::split_paths(&path).any(|dir| {
+            let dir = dir.strip_prefix("/").unwrap_or(&dir);
+            super::process::is_executable(&root.join(dir).join(binary))
+        })
+    }
+
+    // Hands the work dir to the run user, readable by nobody else.
+    pub async fn prepare_dir(&self, work_dir: &Path) -> anyhow::Result<()> {
+        let Some(user) = self.run_user()? else {
+            return Ok(());
+        };
+        let work_dir = work_dir.to_path_buf();
+        tokio::task::spawn_blocking(move || {
+            chown_tree(&work_dir, user)?;
+            std::fs::set_permissions(&work_dir, std::fs::Permissions::from_mode(0o700))
+        })
+        .await??;
+        Ok(())
+    }
+
+    // Starts the program in `work_dir` and, when isolated, with only PATH,
+    // HOME and TMPDIR set. Call before adding the run's own variables.
+    pub fn env(&self, cmd: &mut Command, work_dir: &Path) -> anyhow::Result<()> {
+        cmd.current_dir(work_dir);
+        if !self.enabled() {
+            return Ok(());
+        }
+        let inside = self.inside(work_dir)?;
+        cmd.env_clear()
+            .env("PATH", &self.path)
+            .env("HOME", &inside)
+            .env("TMPDIR", &inside);
+        Ok(())
+    }
+
+    // Switches root and user in the child. Call after any other pre_exec
+    // hook, since joining a cgroup needs the engine's privileges.
+    pub fn confine(&self, cmd: &mut Command, work_dir: &Path) -> anyhow::Result<()> {
+        if !self.enabled() {
+            return Ok(());
+        }
+        let root = match &self.root {
+            Some(root) => Some((
+                CString::new(root.as_os_str().as_bytes())?,
+                CString::new(self.inside(work_dir)?.as_os_str().as_bytes())?,
+            )),
+            None => None,
+        };
+        let user = self.run_user()?;
+        // SAFETY: `enter` only makes async-signal-safe calls on strings
+        // built before the fork.
+        unsafe {
+            cmd.pre_exec(move || enter(root.as_ref(), user));
+        }
+        Ok(())
+    }
+}
+
+// Chroots into `root` and moves to `dir` inside it, then drops the
+// supplementary groups and switches gid before uid.
+fn enter(root: Option<&(CString, CString)>, user: Option<RunUser>) -> std::io::Result<()> {
+    let check = |rc: libc::c_int| {
+        if rc == 0 {
+            Ok(())
+        } else {
+            Err(std::io::Error::last_os_error())
+        }
+    };
+    // SAFETY: the pointers are valid C strings (or null with a zero count)
+    // for the duration of the calls.
+    unsafe {
+        if let Some((root, dir)) = root {
+            check(libc::chroot(root.as_ptr()))?;
+            check(libc::chdir(dir.as_ptr()))?;
+        }
+        if let Some(user) = user {
+            check(libc::setgroups(0, std::ptr::null()))?;
+            check(libc::setgid(user.gid))?;
+            check(libc::setuid(user.uid))?;
+        }
+    }
+    Ok(())
+}
+
+fn chown_tree(path: &Path, user: RunUser) -> std::io::Result<()> {
+    std::os::unix::fs::lchown(path, Some(user.uid), Some(user.gid))?;
+    if std::fs::symlink_metadata(path)?.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            chown_tree(&entry?.path(), user)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{Isolation, RunUser};
+    use crate::engine::config::EngineConfig;
+
+    #[tokio::test]
+    async fn resolves_users_and_chroot_paths() {
+        assert_eq!(
+            RunUser::resolve("1000:1001").unwrap(),
+            RunUser {
+                uid: 1000,
+                gid: 1001
+            }
+        );
+        assert_eq!(RunUser::resolve("root").unwrap().uid, 0);
+        assert!(RunUser::resolve("no-such-user-here").is_err());
+
+        let mut config = EngineConfig::from_env();
+        config.process_run_user = None;
+        config.process_chroot = Some("/srv/jail".into());
+        let isolation = Isolation::new(&config);
+        assert!(isolation.enabled());
+        assert_eq!(isolation.temp_dir(), Path::new("/srv/jail/tmp"));
+        assert_eq!(
+            isolation.inside(Path::new("/srv/jail/tmp/run")).unwrap(),
+            Path::new("/tmp/run")
+        );
+        assert!(isolation.inside(Path::new("/var/cache/deps")).is_err());
+        let err = isolation.check().await.unwrap_err();
+        assert!(err.to_string().contains("PROCESS_RUN_USER"), "{err}");
+    }
+}
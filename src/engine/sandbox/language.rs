@@ -1,46 +1,382 @@
-use std::path::{Path, PathBuf};
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
 
-use crate::engine::models::Language;
+use crate::engine::{
+    archive,
+    models::{ExecutionRequest, Language},
+    sandbox::request_error,
+};
 
 #[derive(Debug, Clone)]
 pub struct LanguageSpec {
     pub source_name: &'static str,
     pub docker_image: &'static str,
-    pub docker_script: &'static str,
+    pub docker_compile: Option<&'static str>,
+    pub docker_run: &'static str,
+    pub docker_artifacts: &'static [&'static str],
+    // Syntax check for interpreted languages in `check` mode; compiled
+    // languages are checked by compiling.
+    pub docker_check: Option<&'static str>,
     pub process_interpreted_cmd: Option<&'static str>,
     pub process_compile_cmd: Option<&'static str>,
+    pub process_compile_args: &'static [&'static str],
+    pub process_run_args: &'static [&'static str],
+    // Arguments for the interpreter in `check` mode.
+    pub process_check_args: &'static [&'static str],
+    pub process_artifact_ext: &'static str,
+    pub jvm: bool,
+    pub support_files: &'static [(&'static str, &'static str)],
+    pub dependencies: Option<DependencySpec>,
+}
+
+// Install and compile scripts run under `sh -c` with $WORKSPACE, $DEPS and (for
+// compile) $OUT set; `{deps}` in env values is replaced with the deps dir.
+// Installs must not run package code: wheels only for pip, no npm scripts.
+#[derive(Debug, Clone)]
+pub struct DependencySpec {
+    pub manifest: &'static str,
+    pub install: &'static str,
+    pub env: &'static [(&'static str, &'static str)],
+    pub compile: Option<&'static str>,
+}
+
+// An operator's replacements for a language's commands (LANGUAGE_COMMANDS),
+// such as `-Werror` in the compile flags or pypy for python. Parsed once at
+// startup, so the strings are leaked to sit with the built-in ones.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LanguageOverrides {
+    pub docker_compile: Option<&'static str>,
+    pub docker_run: Option<&'static str>,
+    pub docker_check: Option<&'static str>,
+    pub interpreter: Option<&'static str>,
+    pub compiler: Option<&'static str>,
+    pub compile_args: Option<&'static [&'static str]>,
+    pub run_args: Option<&'static [&'static str]>,
+}
+
+impl LanguageOverrides {
+    // `python.interpreter=pypy3,c.compile_args={src} -O2 -Werror -o {out}`;
+    // argument lists split on whitespace, scripts run under `sh -c`.
+    // Entries for unknown languages or fields are skipped.
+    pub fn parse<'a>(entries: impl Iterator<Item = (&'a str, &'a str)>) -> HashMap<Language, Self> {
+        let mut overrides: HashMap<Language, Self> = HashMap::new();
+        for (key, value) in entries {
+            let Some((language, field)) = key.split_once('.') else {
+                continue;
+            };
+            let Ok(language) = language.parse::<Language>() else {
+                continue;
+            };
+            let text = || Some(&*String::leak(value.to_string()));
+            let args = || {
+                let args: Vec<&'static str> = value
+                    .split_whitespace()
+                    .map(|arg| &*String::leak(arg.to_string()))
+                    .collect();
+                Some(&*Vec::leak(args))
+            };
+            let entry = overrides.entry(language).or_default();
+            match field {
+                "docker_compile" => entry.docker_compile = text(),
+                "docker_run" => entry.docker_run = text(),
+                "docker_check" => entry.docker_check = text(),
+                "interpreter" => entry.interpreter = text(),
+                "compiler" => entry.compiler = text(),
+                "compile_args" => entry.compile_args = args(),
+                "run_args" => entry.run_args = args(),
+                _ => continue,
+            }
+        }
+        overrides
+    }
 }
 
 impl LanguageSpec {
+    // The built-in spec with the operator's overrides applied.
+    pub fn configured(
+        language: &Language,
+        overrides: &HashMap<Language, LanguageOverrides>,
+    ) -> Self {
+        let mut spec = Self::for_language(language);
+        let Some(overrides) = overrides.get(language) else {
+            return spec;
+        };
+        if let Some(script) = overrides.docker_compile {
+            spec.docker_compile = Some(script);
+        }
+        if let Some(script) = overrides.docker_run {
+            spec.docker_run = script;
+        }
+        if let Some(script) = overrides.docker_check {
+            spec.docker_check = Some(script);
+        }
+        if let Some(interpreter) = overrides.interpreter {
+            spec.process_interpreted_cmd = Some(interpreter);
+        }
+        if let Some(compiler) = overrides.compiler {
+            spec.process_compile_cmd = Some(compiler);
+        }
+        if let Some(args) = overrides.compile_args {
+            spec.process_compile_args = args;
+        }
+        if let Some(args) = overrides.run_args {
+            spec.process_run_args = args;
+        }
+        spec
+    }
+
     pub fn for_language(language: &Language) -> Self {
         match language {
             Language::Python => Self {
                 source_name: "main.py",
                 docker_image: "python:3.12-alpine",
-                docker_script: "python3 -I /workspace/main.py \"$@\"",
+                docker_compile: None,
+                docker_run: "python3 -s /workspace/main.py \"$@\"",
+                docker_artifacts: &[],
+                docker_check: Some(PYTHON_CHECK_DOCKER),
                 process_interpreted_cmd: Some("python"),
                 process_compile_cmd: None,
+                process_compile_args: &[],
+                process_run_args: &[],
+                process_check_args: &["-c", PYTHON_CHECK, "{src}"],
+                process_artifact_ext: "",
+                jvm: false,
+                support_files: &[],
+                dependencies: Some(DependencySpec {
+                    manifest: "requirements.txt",
+                    install: "python3 -m pip install --quiet --no-cache-dir \
+                              --disable-pip-version-check --only-binary=:all: \
+                              --target \"$DEPS\" -r \"$WORKSPACE/requirements.txt\"",
+                    env: &[("PYTHONPATH", "{deps}")],
+                    compile: None,
+                }),
             },
             Language::JavaScript => Self {
                 source_name: "main.js",
                 docker_image: "node:22-alpine",
-                docker_script: "node /workspace/main.js \"$@\"",
+                docker_compile: None,
+                docker_run: "node /workspace/main.js \"$@\"",
+                docker_artifacts: &[],
+                docker_check: Some("node --check /workspace/main.js"),
                 process_interpreted_cmd: Some("node"),
                 process_compile_cmd: None,
+                process_compile_args: &[],
+                process_run_args: &[],
+                process_check_args: &["--check", "{src}"],
+                process_artifact_ext: "",
+                jvm: false,
+                support_files: &[],
+                dependencies: Some(DependencySpec {
+                    manifest: "package.json",
+                    install: "cp \"$WORKSPACE/package.json\" \"$DEPS/\" && cd \"$DEPS\" \
+                              && npm_config_cache=/tmp/npm npm install --omit=dev \
+                              --ignore-scripts --no-audit --no-fund --loglevel=error",
+                    env: &[("NODE_PATH", "{deps}/node_modules")],
+                    compile: None,
+                }),
             },
             Language::Rust => Self {
                 source_name: "main.rs",
                 docker_image: "rust:1.76-alpine",
-                docker_script: "rustc /workspace/main.rs -O -o /tmp/app && /tmp/app \"$@\"",
+                docker_compile: Some("rustc /workspace/main.rs -O -o /build/app"),
+                docker_run: "/build/app \"$@\"",
+                docker_artifacts: &["app"],
+                docker_check: None,
                 process_interpreted_cmd: None,
                 process_compile_cmd: Some("rustc"),
+                process_compile_args: &["{src}", "-O", "-o", "{out}"],
+                process_run_args: &[],
+                process_check_args: &[],
+                process_artifact_ext: "",
+                jvm: false,
+                support_files: &[],
+                dependencies: Some(DependencySpec {
+                    manifest: "Cargo.toml",
+                    install: RUST_VENDOR,
+                    env: &[],
+                    compile: Some(RUST_CARGO_BUILD),
+                }),
             },
             Language::C => Self {
                 source_name: "main.c",
                 docker_image: "gcc:14",
-                docker_script: "gcc /workspace/main.c -O2 -o /tmp/app && /tmp/app \"$@\"",
+                docker_compile: Some("gcc /workspace/main.c -O2 -o /build/app"),
+                docker_run: "/build/app \"$@\"",
+                docker_artifacts: &["app"],
+                docker_check: None,
                 process_interpreted_cmd: None,
                 process_compile_cmd: Some("gcc"),
+                process_compile_args: &["{src}", "-O2", "-o", "{out}"],
+                process_run_args: &[],
+                process_check_args: &[],
+                process_artifact_ext: "",
+                jvm: false,
+                support_files: &[],
+                dependencies: None,
+            },
+            Language::Go => Self {
+                source_name: "main.go",
+                docker_image: "golang:1.22-alpine",
+                docker_compile: Some(
+                    "export HOME=/build GOCACHE=/build/cache GOPATH=/build/go \
+                     && go build -o /build/app /workspace/main.go",
+                ),
+                docker_run: "/build/app \"$@\"",
+                docker_artifacts: &["app"],
+                docker_check: None,
+                process_interpreted_cmd: None,
+                process_compile_cmd: Some("go"),
+                process_compile_args: &["build", "-o", "{out}", "{src}"],
+                process_run_args: &[],
+                process_check_args: &[],
+                process_artifact_ext: "",
+                jvm: false,
+                support_files: &[],
+                dependencies: None,
+            },
+            Language::Java => Self {
+                source_name: "Main.java",
+                docker_image: "eclipse-temurin:21-jdk",
+                docker_compile: Some("javac -d /build/classes /workspace/Main.java"),
+                docker_run: "java $JVM_OPTS -cp /build/classes Main \"$@\"",
+                docker_artifacts: &["classes"],
+                docker_check: None,
+                process_interpreted_cmd: None,
+                process_compile_cmd: Some("javac"),
+                process_compile_args: &["-d", "{out}", "{src}"],
+                process_run_args: &["java", "{jvm}", "-cp", "{out}", "Main"],
+                process_check_args: &[],
+                process_artifact_ext: "",
+                jvm: true,
+                support_files: &[],
+                dependencies: None,
+            },
+            Language::Kotlin => Self {
+                source_name: "Main.kt",
+                docker_image: "zenika/kotlin",
+                docker_compile: Some(
+                    "kotlinc /workspace/Main.kt -include-runtime -d /build/app.jar",
+                ),
+                docker_run: "java $JVM_OPTS -jar /build/app.jar \"$@\"",
+                docker_artifacts: &["app.jar"],
+                docker_check: None,
+                process_interpreted_cmd: None,
+                process_compile_cmd: Some("kotlinc"),
+                process_compile_args: &["{src}", "-include-runtime", "-d", "{out}"],
+                process_run_args: &["java", "{jvm}", "-jar", "{out}"],
+                process_check_args: &[],
+                process_artifact_ext: ".jar",
+                jvm: true,
+                support_files: &[],
+                dependencies: None,
+            },
+            Language::TypeScript => Self {
+                source_name: "main.ts",
+                docker_image: "denoland/deno:alpine",
+                docker_compile: Some("DENO_DIR=/build/deno deno check --quiet /workspace/main.ts"),
+                docker_run: "DENO_DIR=/build/deno deno run --quiet --no-prompt /workspace/main.ts \"$@\"",
+                docker_artifacts: &[],
+                docker_check: None,
+                process_interpreted_cmd: None,
+                process_compile_cmd: Some("tsc"),
+                process_compile_args: &[
+                    "--pretty", "false", "--target", "es2022", "--module", "commonjs", "--outDir",
+                    "{out}", "{src}",
+                ],
+                process_run_args: &["node", "{out}/main.js"],
+                process_check_args: &[],
+                process_artifact_ext: "",
+                jvm: false,
+                support_files: &[],
+                dependencies: None,
+            },
+            Language::Ruby => Self {
+                source_name: "main.rb",
+                docker_image: "ruby:3.3-alpine",
+                docker_compile: None,
+                docker_run: "ruby /workspace/main.rb \"$@\"",
+                docker_artifacts: &[],
+                docker_check: Some("ruby -c /workspace/main.rb"),
+                process_interpreted_cmd: Some("ruby"),
+                process_compile_cmd: None,
+                process_compile_args: &[],
+                process_run_args: &[],
+                process_check_args: &["-c", "{src}"],
+                process_artifact_ext: "",
+                jvm: false,
+                support_files: &[],
+                dependencies: None,
+            },
+            Language::Php => Self {
+                source_name: "main.php",
+                docker_image: "php:8.3-cli-alpine",
+                docker_compile: None,
+                docker_run: "php /workspace/main.php \"$@\"",
+                docker_artifacts: &[],
+                docker_check: Some("php -l /workspace/main.php"),
+                process_interpreted_cmd: Some("php"),
+                process_compile_cmd: None,
+                process_compile_args: &[],
+                process_run_args: &[],
+                process_check_args: &["-l", "{src}"],
+                process_artifact_ext: "",
+                jvm: false,
+                support_files: &[],
+                dependencies: None,
+            },
+            Language::Cpp => Self {
+                source_name: "main.cpp",
+                docker_image: "gcc:14",
+                docker_compile: Some("g++ -std=c++20 /workspace/main.cpp -O2 -o /build/app"),
+                docker_run: "/build/app \"$@\"",
+                docker_artifacts: &["app"],
+                docker_check: None,
+                process_interpreted_cmd: None,
+                process_compile_cmd: Some("g++"),
+                process_compile_args: &["-std=c++20", "{src}", "-O2", "-o", "{out}"],
+                process_run_args: &[],
+                process_check_args: &[],
+                process_artifact_ext: "",
+                jvm: false,
+                support_files: &[],
+                dependencies: None,
+            },
+            // dotnet needs a project file and writes obj/ next to it, so the
+            // read-only workspace is copied into /build before building.
+            Language::CSharp => Self {
+                source_name: "Program.cs",
+                docker_image: "mcr.microsoft.com/dotnet/sdk:8.0",
+                docker_compile: Some(
+                    "export HOME=/build DOTNET_CLI_HOME=/build DOTNET_NOLOGO=1 \
+                     DOTNET_CLI_TELEMETRY_OPTOUT=1 && mkdir -p /build/src \
+                     && cp /workspace/Program.cs /workspace/main.csproj /build/src/ \
+                     && dotnet build /build/src/main.csproj -c Release -o /build/out -v q",
+                ),
+                docker_run: "DOTNET_CLI_HOME=/build DOTNET_NOLOGO=1 dotnet /build/out/main.dll \"$@\"",
+                docker_artifacts: &["out"],
+                docker_check: None,
+                process_interpreted_cmd: None,
+                process_compile_cmd: Some("dotnet"),
+                process_compile_args: &[
+                    "build",
+                    "{dir}/main.csproj",
+                    "-c",
+                    "Release",
+                    "-o",
+                    "{out}",
+                    "--nologo",
+                    "-v",
+                    "q",
+                ],
+                process_run_args: &["dotnet", "{out}/main.dll"],
+                process_check_args: &[],
+                process_artifact_ext: "",
+                jvm: false,
+                support_files: &[("main.csproj", CSHARP_PROJECT)],
+                dependencies: None,
             },
         }
     }
@@ -48,4 +384,219 @@ impl LanguageSpec {
     pub fn source_path(&self, work_dir: &Path) -> PathBuf {
         work_dir.join(self.source_name)
     }
+
+    pub async fn write_sources(
+        &self,
+        work_dir: &Path,
+        request: &ExecutionRequest,
+    ) -> anyhow::Result<PathBuf> {
+        if let Some(archive) = &request.archive {
+            let files = archive::extract_base64(&archive.content, archive::CEILING)
+                .map_err(request_error)?;
+            for (relative, bytes) in files {
+                let path = work_dir.join(relative);
+                if let Some(parent) = path.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+                tokio::fs::write(path, bytes).await?;
+            }
+        }
+        for file in &request.files {
+            let relative = file
+                .relative_path()
+                .ok_or_else(|| request_error(format!("invalid input file name {}", file.name)))?;
+            let bytes = file.decode().map_err(request_error)?;
+            let path = work_dir.join(relative);
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::write(path, bytes).await?;
+        }
+        for (name, contents) in self.support_files {
+            tokio::fs::write(work_dir.join(name), contents).await?;
+        }
+        if let (Some(deps), Some(manifest)) = (&self.dependencies, &request.dependencies) {
+            tokio::fs::write(work_dir.join(deps.manifest), manifest).await?;
+        }
+        let source_path = self.source_path(work_dir);
+        tokio::fs::write(&source_path, &request.code).await?;
+        Ok(source_path)
+    }
+
+    pub fn reserved_names(&self) -> Vec<&'static str> {
+        let mut names = vec![self.source_name];
+        names.extend(self.support_files.iter().map(|(name, _)| *name));
+        names.extend(self.dependencies.iter().map(|deps| deps.manifest));
+        names
+    }
+
+    pub fn dependency_env(&self, deps_dir: &Path) -> Vec<(String, String)> {
+        self.dependencies
+            .iter()
+            .flat_map(|deps| deps.env)
+            .map(|(key, value)| {
+                (
+                    key.to_string(),
+                    value.replace("{deps}", &deps_dir.to_string_lossy()),
+                )
+            })
+            .collect()
+    }
+
+    pub fn compile_args(&self, source: &Path, output: &Path) -> Vec<OsString> {
+        render_args(self.process_compile_args, source, output, 0)
+    }
+
+    pub fn check_args(&self, source: &Path) -> Vec<OsString> {
+        render_args(self.process_check_args, source, source, 0)
+    }
+
+    pub fn run_args(&self, source: &Path, output: &Path, memory_mb: u64) -> Vec<OsString> {
+        render_args(self.process_run_args, source, output, memory_mb)
+    }
+}
+
+// Parses without compiling to bytecode, which would write next to the
+// read-only source.
+const PYTHON_CHECK: &str = "import ast, sys; ast.parse(open(sys.argv[1]).read(), sys.argv[1])";
+const PYTHON_CHECK_DOCKER: &str = "python3 -c 'import ast, sys; \
+    ast.parse(open(sys.argv[1]).read(), sys.argv[1])' /workspace/main.py";
+
+// Crates are vendored during install so the build itself runs offline against a
+// read-only cache.
+const RUST_VENDOR: &str = "mkdir -p \"$DEPS/project/src\" \
+    && cp \"$WORKSPACE/Cargo.toml\" \"$DEPS/project/\" && touch \"$DEPS/project/src/main.rs\" \
+    && CARGO_HOME=\"$DEPS/home\" cargo vendor \
+       --manifest-path \"$DEPS/project/Cargo.toml\" \"$DEPS/vendor\" > \"$DEPS/vendor.toml\"";
+
+const RUST_CARGO_BUILD: &str = "mkdir -p \"$OUT.d/src\" \
+    && cp \"$DEPS/project/Cargo.toml\" \"$DEPS/project/Cargo.lock\" \"$OUT.d/\" \
+    && cp \"$WORKSPACE/main.rs\" \"$OUT.d/src/\" \
+    && CARGO_HOME=\"$OUT.d/home\" cargo build --release --offline --quiet \
+       --config \"$DEPS/vendor.toml\" --manifest-path \"$OUT.d/Cargo.toml\" \
+       --target-dir \"$OUT.d/target\" \
+    && find \"$OUT.d/target/release\" -maxdepth 1 -type f -perm -u+x -exec cp {} \"$OUT\" \\; ; \
+    status=$?; rm -rf \"$OUT.d\"; exit $status";
+
+const CSHARP_PROJECT: &str = r#"<Project Sdk="Microsoft.NET.Sdk">
+  <PropertyGroup>
+    <OutputType>Exe</OutputType>
+    <TargetFramework>net8.0</TargetFramework>
+    <ImplicitUsings>enable</ImplicitUsings>
+    <AssemblyName>main</AssemblyName>
+  </PropertyGroup>
+</Project>
+"#;
+
+// Leaves a quarter of the container budget for metaspace, thread stacks and
+// the JIT so the heap limit trips before the kernel OOM killer does.
+pub fn jvm_options(memory_mb: u64) -> Vec<String> {
+    let heap_mb = (memory_mb * 3 / 4).max(16);
+    vec![
+        format!("-Xmx{heap_mb}m"),
+        format!("-Xms{}m", heap_mb.min(64)),
+        "-Xss16m".to_string(),
+        "-XX:+UseSerialGC".to_string(),
+        "-XX:TieredStopAtLevel=1".to_string(),
+    ]
+}
+
+fn render_args(template: &[&str], source: &Path, output: &Path, memory_mb: u64) -> Vec<OsString> {
+    let mut args = Vec::with_capacity(template.len());
+    for arg in template {
+        match *arg {
+            "{src}" => args.push(source.as_os_str().to_os_string()),
+            "{out}" => args.push(output.as_os_str().to_os_string()),
+            "{jvm}" => args.extend(jvm_options(memory_mb).into_iter().map(OsString::from)),
+            other if other.contains("{out}") || other.contains("{dir}") => {
+                let dir = source.parent().unwrap_or(source);
+                args.push(OsString::from(
+                    other
+                        .replace("{out}", &output.to_string_lossy())
+                        .replace("{dir}", &dir.to_string_lossy()),
+                ))
+            }
+            other => args.push(OsString::from(other)),
+        }
+    }
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsString, path::Path};
+
+    use super::{LanguageOverrides, LanguageSpec, jvm_options};
+    use crate::engine::models::Language;
+
+    #[test]
+    fn renders_go_build_arguments() {
+        let spec = LanguageSpec::for_language(&Language::Go);
+        let args = spec.compile_args(Path::new("/w/main.go"), Path::new("/c/bin"));
+        assert_eq!(
+            args,
+            ["build", "-o", "/c/bin", "/w/main.go"]
+                .iter()
+                .map(OsString::from)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(spec.docker_run, "/build/app \"$@\"");
+    }
+
+    #[test]
+    fn derives_jvm_heap_from_memory_limit() {
+        assert_eq!(jvm_options(256)[0], "-Xmx192m");
+        assert_eq!(jvm_options(8)[0], "-Xmx16m");
+
+        let spec = LanguageSpec::for_language(&Language::Java);
+        let args = spec.run_args(Path::new("/w/Main.java"), Path::new("/c/classes"), 512);
+        assert_eq!(args.first().unwrap(), "java");
+        assert!(args.contains(&OsString::from("-Xmx384m")));
+        assert_eq!(&args[args.len() - 3..], ["-cp", "/c/classes", "Main"]);
+    }
+
+    #[test]
+    fn runs_transpiled_typescript_from_output_dir() {
+        let spec = LanguageSpec::for_language(&Language::TypeScript);
+        let args = spec.run_args(Path::new("/w/main.ts"), Path::new("/c/ts-1"), 256);
+        assert_eq!(args, ["node", "/c/ts-1/main.js"]);
+    }
+
+    #[test]
+    fn builds_csharp_project_next_to_source() {
+        let spec = LanguageSpec::for_language(&Language::CSharp);
+        let args = spec.compile_args(Path::new("/w/Program.cs"), Path::new("/c/cs-1"));
+        assert_eq!(args[1], "/w/main.csproj");
+        assert!(args.contains(&OsString::from("/c/cs-1")));
+        assert_eq!(spec.support_files[0].0, "main.csproj");
+    }
+
+    #[test]
+    fn applies_configured_command_overrides() {
+        let overrides = LanguageOverrides::parse(
+            [
+                ("c.compile_args", "{src} -O2 -Werror -o {out}"),
+                ("python.interpreter", "pypy3"),
+                ("python.docker_run", "pypy3 /workspace/main.py \"$@\""),
+                ("python.bogus", "x"),
+                ("cobol.compiler", "cobc"),
+            ]
+            .into_iter(),
+        );
+        assert_eq!(overrides.len(), 2);
+        let c = LanguageSpec::configured(&Language::C, &overrides);
+        assert_eq!(
+            c.compile_args(Path::new("/w/main.c"), Path::new("/w/app")),
+            ["/w/main.c", "-O2", "-Werror", "-o", "/w/app"].map(OsString::from)
+        );
+        assert_eq!(c.process_compile_cmd, Some("gcc"));
+        let python = LanguageSpec::configured(&Language::Python, &overrides);
+        assert_eq!(python.process_interpreted_cmd, Some("pypy3"));
+        assert!(python.docker_run.starts_with("pypy3 "));
+        let go = LanguageSpec::configured(&Language::Go, &overrides);
+        assert_eq!(
+            go.docker_run,
+            LanguageSpec::for_language(&Language::Go).docker_run
+        );
+    }
 }
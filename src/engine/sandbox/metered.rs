@@ -0,0 +1,111 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+
+use super::{RunSpec, SandboxBackend, SandboxResult, SandboxSession, SessionSpec};
+use crate::engine::models::{EvalResult, SessionVariable};
+
+// Counts the runs and sessions a backend has open (containers on docker,
+// process trees on process) for the `execution_sandbox_active` gauge.
+pub(super) struct Metered {
+    inner: Arc<dyn SandboxBackend>,
+    active: Arc<AtomicUsize>,
+}
+
+impl Metered {
+    pub(super) fn new(inner: Arc<dyn SandboxBackend>, active: Arc<AtomicUsize>) -> Self {
+        Self { inner, active }
+    }
+}
+
+// Holds one count until dropped, so abandoned runs are not left counted.
+struct Active(Arc<AtomicUsize>);
+
+impl Active {
+    fn new(active: &Arc<AtomicUsize>) -> Self {
+        active.fetch_add(1, Ordering::Relaxed);
+        Self(active.clone())
+    }
+}
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[async_trait]
+impl SandboxBackend for Metered {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    async fn execute(&self, spec: RunSpec) -> anyhow::Result<SandboxResult> {
+        let _active = Active::new(&self.active);
+        self.inner.execute(spec).await
+    }
+
+    async fn open_session(&self, spec: SessionSpec) -> anyhow::Result<Box<dyn SandboxSession>> {
+        let active = Active::new(&self.active);
+        let inner = self.inner.open_session(spec).await?;
+        Ok(Box::new(MeteredSession {
+            inner,
+            counter: self.active.clone(),
+            active: Some(active),
+        }))
+    }
+
+    async fn prepare(&self) -> anyhow::Result<()> {
+        self.inner.prepare().await
+    }
+
+    async fn kill(&self, attempt: uuid::Uuid) {
+        self.inner.kill(attempt).await
+    }
+
+    async fn health(&self) -> anyhow::Result<Option<String>> {
+        self.inner.health().await
+    }
+}
+
+// A suspended session holds no runtime, so it is not counted until resumed.
+struct MeteredSession {
+    inner: Box<dyn SandboxSession>,
+    counter: Arc<AtomicUsize>,
+    active: Option<Active>,
+}
+
+#[async_trait]
+impl SandboxSession for MeteredSession {
+    async fn eval(&mut self, code: &str, timeout: Duration) -> anyhow::Result<Option<EvalResult>> {
+        self.inner.eval(code, timeout).await
+    }
+
+    async fn inspect(&mut self, timeout: Duration) -> anyhow::Result<Option<Vec<SessionVariable>>> {
+        self.inner.inspect(timeout).await
+    }
+
+    async fn close(&mut self) {
+        self.inner.close().await;
+        self.active = None;
+    }
+
+    async fn suspend(&mut self) -> anyhow::Result<()> {
+        self.inner.suspend().await?;
+        self.active = None;
+        Ok(())
+    }
+
+    async fn resume(&mut self) -> anyhow::Result<()> {
+        self.inner.resume().await?;
+        self.active
+            .get_or_insert_with(|| Active::new(&self.counter));
+        Ok(())
+    }
+}
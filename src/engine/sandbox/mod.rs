@@ -1,20 +1,54 @@
+mod cache;
+mod cgroup;
+mod compile_cache;
 mod docker;
+mod egress;
+mod images;
+mod isolation;
 mod language;
+mod metered;
 mod process;
+mod runtime;
+mod session;
 
-use std::sync::Arc;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
 
 use async_trait::async_trait;
+use base64::{Engine, prelude::BASE64_STANDARD};
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    sync::mpsc,
+};
 
 use crate::engine::{
     config::{EngineConfig, SandboxBackendKind},
-    models::ExecutionRequest,
+    models::{
+        CompileOutput, ExecutionLimits, ExecutionRequest, FileEncoding, OutputChunk,
+        OutputEncoding, OutputStream, ResourceUsage, TerminationReason,
+    },
     queue::QueuedJob,
 };
 
+pub use cache::ContentCache;
 pub use docker::DockerSandbox;
-pub use language::LanguageSpec;
+pub use egress::{EgressGrant, EgressPolicy, EgressProxy, EgressRule};
+pub use images::{ImageCatalog, image_allowed};
+pub use isolation::DEFAULT_PATH as DEFAULT_PROCESS_PATH;
+pub use language::{LanguageOverrides, LanguageSpec, jvm_options};
 pub use process::ProcessSandbox;
+pub use runtime::ContainerRuntime;
+pub use session::{Driver, DriverProgram, SandboxSession, SessionSpec};
+
+pub const COMPILE_TIMEOUT: Duration = Duration::from_secs(30);
+pub const INSTALL_TIMEOUT: Duration = Duration::from_secs(180);
 
 #[derive(Debug, Clone)]
 pub struct SandboxResult {
@@ -23,6 +57,266 @@ pub struct SandboxResult {
     pub exit_code: i32,
     pub duration_ms: u128,
     pub timed_out: bool,
+    pub compile: Option<CompileOutput>,
+    pub install: Option<CompileOutput>,
+    pub usage: ResourceUsage,
+    pub oom_killed: bool,
+    pub signal: Option<i32>,
+    // Output beyond max_output_bytes was dropped; usage has the full counts.
+    pub stdout_truncated: bool,
+    pub stderr_truncated: bool,
+    pub stdout_encoding: FileEncoding,
+    pub stderr_encoding: FileEncoding,
+    // Empty unless the request asked for `combined_output`.
+    pub combined: Vec<OutputChunk>,
+    // One per path in `RunSpec::collect`.
+    pub files: Vec<CollectedFile>,
+}
+
+// A file read back from the run's working directory; `bytes` is unset when
+// the program did not leave one there.
+#[derive(Debug, Clone)]
+pub struct CollectedFile {
+    pub path: String,
+    pub bytes: Option<Vec<u8>>,
+}
+
+// Reads back `paths` once the program is gone. Anything resolving outside
+// `work_dir` (through a symlink the program planted) or larger than
+// `max_bytes` counts as missing.
+pub async fn collect_files(
+    work_dir: &Path,
+    paths: &[String],
+    max_bytes: u64,
+) -> Vec<CollectedFile> {
+    let root = tokio::fs::canonicalize(work_dir).await.ok();
+    let mut files = Vec::with_capacity(paths.len());
+    for path in paths {
+        let bytes = match &root {
+            Some(root) => read_inside(root, path, max_bytes).await,
+            None => None,
+        };
+        files.push(CollectedFile {
+            path: path.clone(),
+            bytes,
+        });
+    }
+    files
+}
+
+async fn read_inside(root: &Path, path: &str, max_bytes: u64) -> Option<Vec<u8>> {
+    let file = tokio::fs::canonicalize(root.join(path)).await.ok()?;
+    if !file.starts_with(root) {
+        return None;
+    }
+    let meta = tokio::fs::metadata(&file).await.ok()?;
+    if !meta.is_file() || meta.len() > max_bytes {
+        return None;
+    }
+    tokio::fs::read(&file).await.ok()
+}
+
+impl SandboxResult {
+    pub fn termination_reason(&self) -> TerminationReason {
+        if self
+            .install
+            .as_ref()
+            .is_some_and(|install| !install.success)
+        {
+            TerminationReason::InstallFailed
+        } else if self
+            .compile
+            .as_ref()
+            .is_some_and(|compile| !compile.success)
+        {
+            TerminationReason::CompileFailed
+        } else if self.timed_out {
+            TerminationReason::TimedOut
+        } else if self.oom_killed {
+            TerminationReason::OutOfMemory
+        } else if self.signal.is_some() {
+            TerminationReason::Signaled
+        } else {
+            TerminationReason::Exited
+        }
+    }
+
+    pub fn compile_failed(compile: CompileOutput) -> Self {
+        Self {
+            stdout: String::new(),
+            stderr: String::new(),
+            exit_code: if compile.exit_code == 0 {
+                -1
+            } else {
+                compile.exit_code
+            },
+            duration_ms: 0,
+            timed_out: false,
+            compile: Some(compile),
+            install: None,
+            usage: ResourceUsage::default(),
+            oom_killed: false,
+            signal: None,
+            stdout_truncated: false,
+            stderr_truncated: false,
+            stdout_encoding: FileEncoding::Utf8,
+            stderr_encoding: FileEncoding::Utf8,
+            combined: Vec::new(),
+            files: Vec::new(),
+        }
+    }
+
+    // A `check` execution that compiled (or parsed) cleanly.
+    pub fn checked(install: Option<CompileOutput>, compile: Option<CompileOutput>) -> Self {
+        Self {
+            stdout: String::new(),
+            stderr: String::new(),
+            exit_code: 0,
+            duration_ms: 0,
+            timed_out: false,
+            compile,
+            install,
+            usage: ResourceUsage::default(),
+            oom_killed: false,
+            signal: None,
+            stdout_truncated: false,
+            stderr_truncated: false,
+            stdout_encoding: FileEncoding::Utf8,
+            stderr_encoding: FileEncoding::Utf8,
+            combined: Vec::new(),
+            files: Vec::new(),
+        }
+    }
+
+    pub fn install_failed(install: CompileOutput) -> Self {
+        Self {
+            stdout: String::new(),
+            stderr: String::new(),
+            exit_code: if install.exit_code == 0 {
+                -1
+            } else {
+                install.exit_code
+            },
+            duration_ms: 0,
+            timed_out: false,
+            compile: None,
+            install: Some(install),
+            usage: ResourceUsage::default(),
+            oom_killed: false,
+            signal: None,
+            stdout_truncated: false,
+            stderr_truncated: false,
+            stdout_encoding: FileEncoding::Utf8,
+            stderr_encoding: FileEncoding::Utf8,
+            combined: Vec::new(),
+            files: Vec::new(),
+        }
+    }
+}
+
+// Renders captured output per the requested encoding. Output cut at the byte
+// limit may end inside a character; that alone does not make it binary.
+pub fn encode_output(bytes: &[u8], requested: OutputEncoding) -> (String, FileEncoding) {
+    let binary = match requested {
+        OutputEncoding::Utf8 => false,
+        OutputEncoding::Base64 => true,
+        OutputEncoding::Auto => {
+            std::str::from_utf8(bytes).is_err_and(|err| err.error_len().is_some())
+        }
+    };
+    if binary {
+        (BASE64_STANDARD.encode(bytes), FileEncoding::Base64)
+    } else {
+        (
+            String::from_utf8_lossy(bytes).into_owned(),
+            FileEncoding::Utf8,
+        )
+    }
+}
+
+// Interleaved stdout and stderr in arrival order, for `combined_output`.
+// Recording stops at `limit` bytes across both streams; consecutive writes to
+// one stream within the same millisecond share a chunk.
+#[derive(Debug)]
+pub struct OutputLog {
+    started: Instant,
+    limit: usize,
+    recorded: usize,
+    chunks: Vec<(OutputStream, u64, Vec<u8>)>,
+}
+
+impl OutputLog {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            started: Instant::now(),
+            limit,
+            recorded: 0,
+            chunks: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, stream: OutputStream, bytes: &[u8]) {
+        let bytes = &bytes[..bytes.len().min(self.limit - self.recorded)];
+        if bytes.is_empty() {
+            return;
+        }
+        self.recorded += bytes.len();
+        let offset_ms = self.started.elapsed().as_millis() as u64;
+        match self.chunks.last_mut() {
+            Some((last, at, data)) if *last == stream && *at == offset_ms => {
+                data.extend_from_slice(bytes)
+            }
+            _ => self.chunks.push((stream, offset_ms, bytes.to_vec())),
+        }
+    }
+
+    pub fn into_chunks(self, requested: OutputEncoding) -> Vec<OutputChunk> {
+        self.chunks
+            .into_iter()
+            .map(|(stream, offset_ms, bytes)| {
+                let (data, encoding) = encode_output(&bytes, requested);
+                OutputChunk {
+                    stream,
+                    offset_ms,
+                    data,
+                    encoding,
+                }
+            })
+            .collect()
+    }
+}
+
+// Linux numbering; container exit codes above 128 encode the signal that
+// killed the process.
+pub fn signal_from_exit_code(exit_code: i32) -> Option<i32> {
+    (129..=128 + 64)
+        .contains(&exit_code)
+        .then(|| exit_code - 128)
+}
+
+pub fn signal_name(signal: i32) -> String {
+    let name = match signal {
+        1 => "SIGHUP",
+        2 => "SIGINT",
+        3 => "SIGQUIT",
+        4 => "SIGILL",
+        5 => "SIGTRAP",
+        6 => "SIGABRT",
+        7 => "SIGBUS",
+        8 => "SIGFPE",
+        9 => "SIGKILL",
+        10 => "SIGUSR1",
+        11 => "SIGSEGV",
+        12 => "SIGUSR2",
+        13 => "SIGPIPE",
+        14 => "SIGALRM",
+        15 => "SIGTERM",
+        24 => "SIGXCPU",
+        25 => "SIGXFSZ",
+        31 => "SIGSYS",
+        other => return format!("SIG{other}"),
+    };
+    name.to_string()
 }
 
 #[derive(Debug, Clone)]
@@ -30,31 +324,376 @@ pub struct RunSpec {
     pub request: ExecutionRequest,
     pub limits: crate::engine::models::ExecutionLimits,
     pub id: uuid::Uuid,
+    // This worker's attempt at the execution. Containers and processes are
+    // keyed by it, so killing an abandoned attempt spares a later one of the
+    // same execution.
+    pub attempt: uuid::Uuid,
+    pub tenant_id: String,
+    pub events: EventSink,
+    pub stdin: LiveStdin,
+    // Read to the end before `stdin`, in place of `request.stdin`.
+    pub stdin_file: Option<PathBuf>,
+    // Paths, relative to the working directory, read back after the run.
+    pub collect: Vec<String>,
 }
 
 impl From<QueuedJob> for RunSpec {
-    fn from(value: QueuedJob) -> Self {
+    fn from(mut value: QueuedJob) -> Self {
+        value.request.env.append(&mut value.secrets);
         Self {
             request: value.request,
             limits: value.limits,
             id: value.id,
+            attempt: uuid::Uuid::new_v4(),
+            tenant_id: value.tenant_id,
+            events: EventSink::default(),
+            stdin: LiveStdin::default(),
+            stdin_file: value.stdin_file,
+            collect: Vec::new(),
         }
     }
 }
 
+// A backend error caused by the request itself (bad input, unsupported
+// option). Any other error is treated as an infrastructure failure, which the
+// worker may retry.
+#[derive(Debug)]
+pub struct RequestError(pub String);
+
+impl std::fmt::Display for RequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for RequestError {}
+
+// Package managers need far more room than the user program, so installs get
+// raised floors on top of the request limits.
+pub fn install_limits(limits: &ExecutionLimits) -> ExecutionLimits {
+    ExecutionLimits {
+        cpu_cores: limits.cpu_cores.max(1.0),
+        memory_mb: limits.memory_mb.max(1024),
+        timeout_ms: INSTALL_TIMEOUT.as_millis() as u64,
+        max_processes: limits.max_processes.max(256),
+        max_file_size_bytes: limits.max_file_size_bytes.max(512 * 1024 * 1024),
+        max_output_bytes: limits.max_output_bytes,
+    }
+}
+
+pub fn request_error(message: impl Into<String>) -> anyhow::Error {
+    RequestError(message.into()).into()
+}
+
+pub fn is_infrastructure_error(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<RequestError>().is_none()
+}
+
+// Receives the lifecycle stages a backend goes through (`installing`,
+// `compiling`, `running`); does nothing by default.
+#[derive(Clone, Default)]
+pub struct EventSink(Option<Arc<EventFn>>);
+
+type EventFn = dyn Fn(&str, String) + Send + Sync;
+
+impl EventSink {
+    pub fn new(sink: impl Fn(&str, String) + Send + Sync + 'static) -> Self {
+        Self(Some(Arc::new(sink)))
+    }
+
+    pub fn emit(&self, stage: &str, message: impl Into<String>) {
+        if let Some(sink) = &self.0 {
+            sink(stage, message.into());
+        }
+    }
+}
+
+impl std::fmt::Debug for EventSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("EventSink")
+    }
+}
+
+// Input sent to an interactive execution while it runs. The backend that
+// takes the receiver writes each chunk to the program's stdin and closes it
+// once the sender is dropped.
+#[derive(Clone, Default)]
+pub struct LiveStdin(Arc<std::sync::Mutex<Option<mpsc::Receiver<Vec<u8>>>>>);
+
+impl LiveStdin {
+    pub fn new(receiver: mpsc::Receiver<Vec<u8>>) -> Self {
+        Self(Arc::new(std::sync::Mutex::new(Some(receiver))))
+    }
+
+    // Only the first run of a spec gets the input.
+    pub fn take(&self) -> Option<mpsc::Receiver<Vec<u8>>> {
+        self.0.lock().expect("stdin lock poisoned").take()
+    }
+}
+
+// Writes a run's input to the program: the uploaded file or else `initial`,
+// then live input until its sender goes away, and closes the stream. Stops
+// early once the program no longer reads.
+pub(crate) async fn feed_stdin(
+    mut input: impl AsyncWrite + Unpin,
+    initial: Vec<u8>,
+    file: Option<PathBuf>,
+    live: Option<mpsc::Receiver<Vec<u8>>>,
+) {
+    let written = match file {
+        Some(path) => match tokio::fs::File::open(&path).await {
+            Ok(mut file) => tokio::io::copy(&mut file, &mut input).await.map(drop),
+            Err(err) => {
+                tracing::warn!(path = %path.display(), error = %err, "failed to open stdin upload");
+                Err(err)
+            }
+        },
+        None => input.write_all(&initial).await,
+    };
+    if written.is_ok()
+        && let Some(mut live) = live
+    {
+        while let Some(chunk) = live.recv().await {
+            if input.write_all(&chunk).await.is_err() {
+                break;
+            }
+        }
+    }
+    let _ = input.shutdown().await;
+}
+
+impl std::fmt::Debug for LiveStdin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("LiveStdin")
+    }
+}
+
 #[async_trait]
 pub trait SandboxBackend: Send + Sync {
     fn name(&self) -> &'static str;
     async fn execute(&self, spec: RunSpec) -> anyhow::Result<SandboxResult>;
+
+    async fn open_session(&self, _spec: SessionSpec) -> anyhow::Result<Box<dyn SandboxSession>> {
+        Err(request_error(format!(
+            "the {} backend does not support sessions",
+            self.name()
+        )))
+    }
+
+    async fn prepare(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    // Best-effort teardown of whatever is still running for an attempt
+    // (`RunSpec::attempt`) whose `execute` was abandoned.
+    async fn kill(&self, _attempt: uuid::Uuid) {}
+
+    // Cheap liveness check of whatever the backend depends on: an error when
+    // nothing can run, a note when only some languages can.
+    async fn health(&self) -> anyhow::Result<Option<String>> {
+        Ok(None)
+    }
+}
+
+// The configured backends, keyed by kind. Requests name theirs in
+// `request.backend`; anything else runs on the default.
+#[derive(Clone)]
+pub struct SandboxRegistry {
+    backends: BTreeMap<SandboxBackendKind, Arc<dyn SandboxBackend>>,
+    // Runs and sessions open on each backend.
+    active: BTreeMap<SandboxBackendKind, Arc<AtomicUsize>>,
+    default: SandboxBackendKind,
+    failover: Vec<SandboxBackendKind>,
+    // `allow_network` runs need docker's filtering proxy (NETWORK_PROXY).
+    network_proxy: bool,
+    // Backends whose last health check failed.
+    down: Arc<std::sync::RwLock<BTreeSet<SandboxBackendKind>>>,
+}
+
+impl SandboxRegistry {
+    pub fn new(default: SandboxBackendKind, backend: Arc<dyn SandboxBackend>) -> Self {
+        Self {
+            backends: BTreeMap::new(),
+            active: BTreeMap::new(),
+            default,
+            failover: Vec::new(),
+            network_proxy: false,
+            down: Arc::default(),
+        }
+        .with(default, backend)
+    }
+
+    pub fn with(mut self, kind: SandboxBackendKind, backend: Arc<dyn SandboxBackend>) -> Self {
+        let active = self.active.entry(kind).or_default().clone();
+        self.backends
+            .insert(kind, Arc::new(metered::Metered::new(backend, active)));
+        self
+    }
+
+    // The order backends are tried in when one fails; every kind in it must
+    // be added with `with`.
+    pub fn with_failover(mut self, chain: Vec<SandboxBackendKind>) -> Self {
+        self.failover = chain;
+        self
+    }
+
+    pub fn with_network_proxy(mut self, network_proxy: bool) -> Self {
+        self.network_proxy = network_proxy;
+        self
+    }
+
+    pub fn set_down(&self, kind: SandboxBackendKind, down: bool) {
+        let mut set = self.down.write().expect("backend health poisoned");
+        if down {
+            set.insert(kind);
+        } else {
+            set.remove(&kind);
+        }
+    }
+
+    // The request's backend, then the backends after it in the failover
+    // chain that can run the request (custom images, egress rules, GPUs and
+    // proxied network access need docker). Backends last seen down are tried after the others.
+    pub fn chain_for(
+        &self,
+        request: &ExecutionRequest,
+    ) -> Vec<(SandboxBackendKind, Arc<dyn SandboxBackend>)> {
+        let primary = request
+            .backend
+            .filter(|kind| self.backends.contains_key(kind))
+            .unwrap_or(self.default);
+        let docker_only = request.image.is_some()
+            || !request.egress.is_empty()
+            || request.gpu
+            || (request.allow_network && self.network_proxy);
+        let fallbacks = self
+            .failover
+            .iter()
+            .skip_while(|kind| **kind != primary)
+            .skip(1)
+            .filter(|kind| !docker_only || **kind == SandboxBackendKind::Docker);
+        let mut chain: Vec<SandboxBackendKind> = vec![primary];
+        if self.failover.contains(&primary) {
+            chain.extend(fallbacks.filter(|kind| **kind != primary));
+        }
+        let down = self.down.read().expect("backend health poisoned");
+        chain.sort_by_key(|kind| down.contains(kind));
+        chain
+            .into_iter()
+            .filter_map(|kind| Some((kind, self.backends.get(&kind)?.clone())))
+            .collect()
+    }
+
+    pub fn default_kind(&self) -> SandboxBackendKind {
+        self.default
+    }
+
+    pub fn contains(&self, kind: SandboxBackendKind) -> bool {
+        self.backends.contains_key(&kind)
+    }
+
+    pub fn get(&self, kind: SandboxBackendKind) -> Option<Arc<dyn SandboxBackend>> {
+        self.backends.get(&kind).cloned()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (SandboxBackendKind, &Arc<dyn SandboxBackend>)> {
+        self.backends.iter().map(|(kind, backend)| (*kind, backend))
+    }
+
+    // Runs and sessions currently open, by backend.
+    pub fn active(&self) -> impl Iterator<Item = (SandboxBackendKind, usize)> {
+        self.active
+            .iter()
+            .map(|(kind, active)| (*kind, active.load(Ordering::Relaxed)))
+    }
 }
 
 pub struct SandboxFactory;
 
 impl SandboxFactory {
-    pub fn from_config(config: &EngineConfig) -> anyhow::Result<Arc<dyn SandboxBackend>> {
-        match config.sandbox_backend {
-            SandboxBackendKind::Docker => Ok(Arc::new(DockerSandbox::new()?)),
-            SandboxBackendKind::Process => Ok(Arc::new(ProcessSandbox::new())),
+    pub async fn from_config(config: &EngineConfig) -> anyhow::Result<SandboxRegistry> {
+        let mut registry = SandboxRegistry::new(
+            config.sandbox_backend,
+            Self::backend(config.sandbox_backend, config).await?,
+        );
+        for &kind in config
+            .sandbox_backends
+            .iter()
+            .chain(&config.sandbox_failover)
+        {
+            if !registry.contains(kind) {
+                registry = registry.with(kind, Self::backend(kind, config).await?);
+            }
+        }
+        Ok(registry
+            .with_failover(config.sandbox_failover.clone())
+            .with_network_proxy(config.network_proxy))
+    }
+
+    async fn backend(
+        kind: SandboxBackendKind,
+        config: &EngineConfig,
+    ) -> anyhow::Result<Arc<dyn SandboxBackend>> {
+        match kind {
+            SandboxBackendKind::Docker => {
+                let runtime = ContainerRuntime::resolve(
+                    &config.container_runtime,
+                    config.container_rootless,
+                    config.container_host.clone(),
+                )?;
+                runtime.check_binary(config.session_checkpoint)?;
+                Ok(Arc::new(DockerSandbox::connect(runtime, config).await?))
+            }
+            SandboxBackendKind::Process => Ok(Arc::new(ProcessSandbox::new(config))),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{ProcessSandbox, SandboxRegistry};
+    use crate::engine::{
+        config::{EngineConfig, SandboxBackendKind},
+        models::ExecutionRequest,
+    };
+
+    #[test]
+    fn failover_chain_follows_the_request_backend() {
+        let backend = Arc::new(ProcessSandbox::new(&EngineConfig::from_env()));
+        let registry = SandboxRegistry::new(SandboxBackendKind::Docker, backend.clone())
+            .with(SandboxBackendKind::Process, backend)
+            .with_failover(vec![
+                SandboxBackendKind::Docker,
+                SandboxBackendKind::Process,
+            ]);
+        let kinds = |request: serde_json::Value| -> Vec<SandboxBackendKind> {
+            let request: ExecutionRequest = serde_json::from_value(request).unwrap();
+            registry
+                .chain_for(&request)
+                .into_iter()
+                .map(|(kind, _)| kind)
+                .collect()
+        };
+        let plain = serde_json::json!({ "language": "python", "code": "print(1)" });
+        assert_eq!(
+            kinds(plain.clone()),
+            [SandboxBackendKind::Docker, SandboxBackendKind::Process]
+        );
+        assert_eq!(
+            kinds(serde_json::json!({ "language": "python", "code": "1", "backend": "process" })),
+            [SandboxBackendKind::Process]
+        );
+        assert_eq!(
+            kinds(serde_json::json!({ "language": "python", "code": "1", "image": "python:3" })),
+            [SandboxBackendKind::Docker]
+        );
+
+        registry.set_down(SandboxBackendKind::Docker, true);
+        assert_eq!(
+            kinds(plain),
+            [SandboxBackendKind::Process, SandboxBackendKind::Docker]
+        );
+    }
+}
@@ -1,9 +1,10 @@
 use std::{
-    collections::hash_map::DefaultHasher,
+    collections::{BTreeMap, HashMap, HashSet, hash_map::DefaultHasher},
     hash::{Hash, Hasher},
-    path::PathBuf,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
     process::Stdio,
-    sync::Arc,
+    sync::{Arc, Mutex},
     time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
@@ -11,22 +12,83 @@ use anyhow::Context;
 use async_trait::async_trait;
 use dashmap::DashMap;
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    process::Command,
+    io::AsyncReadExt,
+    process::{Child, Command},
+    sync::mpsc,
 };
+use tracing::Instrument;
 
-use crate::engine::sandbox::{LanguageSpec, RunSpec, SandboxBackend, SandboxResult};
+use crate::engine::{
+    config::{EngineConfig, ToolCommands},
+    models::{
+        CompileOutput, EvalResult, ExecutionMode, Language, OutputStream, ResourceUsage,
+        SessionVariable,
+    },
+    sandbox::{
+        COMPILE_TIMEOUT, ContentCache, Driver, DriverProgram, INSTALL_TIMEOUT, LanguageOverrides,
+        LanguageSpec, OutputLog, RunSpec, SandboxBackend, SandboxResult, SandboxSession,
+        SessionSpec,
+        cgroup::{Cgroup, CgroupRoot},
+        collect_files,
+        compile_cache::{CompileCache, CompiledArtifact},
+        encode_output, feed_stdin, install_limits,
+        isolation::Isolation,
+        request_error,
+    },
+};
 
 pub struct ProcessSandbox {
-    compile_cache: Arc<DashMap<u64, PathBuf>>,
+    compile_cache: CompileCache,
+    dependency_cache: ContentCache,
+    version_commands: HashMap<Language, BTreeMap<String, String>>,
+    languages: HashMap<Language, LanguageOverrides>,
+    tools: ToolCommands,
+    deterministic_cpu: usize,
+    // Without it only the timeout is enforced.
+    cgroups: Option<CgroupRoot>,
+    isolation: Isolation,
+    // There is no egress proxy here, so only tenants with full network
+    // access may install dependencies, and none under NETWORK_PROXY.
+    network_tenants: HashSet<String>,
+    network_proxy: bool,
+    // Programs being waited on, with their attempt.
+    running: DashMap<libc::pid_t, uuid::Uuid>,
 }
 
 impl ProcessSandbox {
-    pub fn new() -> Self {
+    pub fn new(config: &EngineConfig) -> Self {
+        let isolation = Isolation::new(config);
         Self {
-            compile_cache: Arc::new(DashMap::new()),
+            compile_cache: CompileCache::new(
+                isolation.temp_dir().join("unsafe-process-compile-cache"),
+                config.process_compile_cache_max_entries,
+                config.process_compile_cache_max_bytes,
+            ),
+            dependency_cache: ContentCache::new(config.dependency_cache_dir.clone()),
+            version_commands: config.language_version_commands.clone(),
+            languages: config.language_overrides.clone(),
+            tools: config.tools.clone(),
+            deterministic_cpu: config.deterministic_cpu,
+            cgroups: config.process_cgroup_root.clone().map(CgroupRoot::new),
+            isolation,
+            network_tenants: config.network_allowed_tenants.clone(),
+            network_proxy: config.network_proxy,
+            running: DashMap::new(),
         }
     }
+
+    // The interpreter or compiler for the requested version, replacing the
+    // language's default command.
+    fn version_command<'a>(&'a self, spec: &RunSpec) -> anyhow::Result<Option<&'a str>> {
+        let Some(version) = &spec.request.version else {
+            return Ok(None);
+        };
+        self.version_commands
+            .get(&spec.request.language)
+            .and_then(|versions| versions.get(version))
+            .map(|command| Some(command.as_str()))
+            .ok_or_else(|| request_error(format!("unknown runtime version: {version}")))
+    }
 }
 
 #[async_trait]
@@ -35,128 +97,637 @@ impl SandboxBackend for ProcessSandbox {
         "process"
     }
 
-    async fn execute(&self, spec: RunSpec) -> anyhow::Result<SandboxResult> {
-        if spec.request.code.as_bytes().len() as u64 > spec.limits.max_file_size_bytes {
-            anyhow::bail!("source exceeds configured file size limit");
+    async fn prepare(&self) -> anyhow::Result<()> {
+        self.isolation.check().await?;
+        self.compile_cache.clear_stale().await?;
+        match &self.cgroups {
+            Some(cgroups) => cgroups.prepare().await,
+            None => Ok(()),
         }
+    }
 
-        let lang = LanguageSpec::for_language(&spec.request.language);
-        let work_dir = std::env::temp_dir().join(format!(
+    // The detached waiter still reaps the programs; their work dirs and
+    // cgroups are left for the operator.
+    async fn kill(&self, attempt: uuid::Uuid) {
+        let pids: Vec<_> = self
+            .running
+            .iter()
+            .filter(|entry| *entry.value() == attempt)
+            .map(|entry| *entry.key())
+            .collect();
+        for pid in pids {
+            if self.running.remove(&pid).is_some() {
+                // SAFETY: kill has no memory effects.
+                unsafe { libc::kill(pid, libc::SIGKILL) };
+            }
+        }
+    }
+
+    // Every run needs `sh` for its scripts (and the cgroup, when limits are
+    // enforced); a missing toolchain only takes its languages out. Commands
+    // are looked up where programs run, inside the chroot if any.
+    async fn health(&self) -> anyhow::Result<Option<String>> {
+        self.isolation.check().await?;
+        if !self.isolation.on_path("sh") {
+            anyhow::bail!("sh is not on PATH");
+        }
+        if let Some(cgroups) = &self.cgroups {
+            cgroups.health().await?;
+        }
+        let missing: Vec<&str> = Language::ALL
+            .iter()
+            .map(|language| LanguageSpec::configured(language, &self.languages))
+            .filter_map(|lang| lang.process_interpreted_cmd.or(lang.process_compile_cmd))
+            .filter(|binary| !self.isolation.on_path(binary))
+            .collect();
+        Ok((!missing.is_empty()).then(|| format!("not on PATH: {}", missing.join(", "))))
+    }
+
+    async fn execute(&self, mut spec: RunSpec) -> anyhow::Result<SandboxResult> {
+        if spec.request.code.len() as u64 > spec.limits.max_file_size_bytes {
+            return Err(request_error("source exceeds configured file size limit"));
+        }
+
+        let lang = LanguageSpec::configured(&spec.request.language, &self.languages);
+        let tool = self
+            .tools
+            .command(&spec.request.language, spec.request.mode.as_ref());
+        if tool.is_some() {
+            spec.request.stdin.clear();
+            spec.stdin_file = None;
+            // Linters and formatters see the sources only; test runners get
+            // the dependencies and args too.
+            if spec.request.mode != Some(ExecutionMode::Test) {
+                spec.request.dependencies = None;
+                spec.request.args.clear();
+            }
+        }
+        let work_dir = self.isolation.temp_dir().join(format!(
             "unsafe-process-{}-{}",
             spec.id.as_simple(),
             now_nanos()
         ));
         tokio::fs::create_dir_all(&work_dir).await?;
 
-        let started = Instant::now();
-        let source_path = lang.source_path(&work_dir);
-        tokio::fs::write(&source_path, spec.request.code.as_bytes()).await?;
+        let source_path = match lang.write_sources(&work_dir, &spec.request).await {
+            Ok(path) => path,
+            Err(err) => {
+                cleanup_dir(&work_dir).await;
+                return Err(err);
+            }
+        };
 
-        let mut cmd = if let Some(interpreter) = lang.process_interpreted_cmd {
-            let mut cmd = Command::new(interpreter);
-            cmd.arg(&source_path);
+        let (deps_dir, install) = match self
+            .install_dependencies(&spec, &lang, &work_dir)
+            .instrument(tracing::info_span!("sandbox.install"))
+            .await
+        {
+            Ok(installed) => installed,
+            Err(err) => {
+                cleanup_dir(&work_dir).await;
+                return Err(err);
+            }
+        };
+        if let Some(output) = install.as_ref().filter(|output| !output.success) {
+            cleanup_dir(&work_dir).await;
+            return Ok(SandboxResult::install_failed(output.clone()));
+        }
+
+        let mut compile = None;
+        // Held until the run ends so the cache cannot evict it meanwhile.
+        let mut program = None;
+        let version_command = match self.version_command(&spec) {
+            Ok(command) => command,
+            Err(err) => {
+                cleanup_dir(&work_dir).await;
+                return Err(err);
+            }
+        };
+        // The paths programs see, inside the chroot if any, in a work dir
+        // handed to the run user.
+        let confined = async {
+            self.isolation.prepare_dir(&work_dir).await?;
+            let deps_dir = deps_dir
+                .as_deref()
+                .map(|dir| self.isolation.inside(dir))
+                .transpose()?;
+            anyhow::Ok((self.isolation.inside(&source_path)?, deps_dir))
+        };
+        let (run_source, run_deps) = match confined.await {
+            Ok(paths) => paths,
+            Err(err) => {
+                cleanup_dir(&work_dir).await;
+                return Err(err);
+            }
+        };
+        let checking = spec.request.mode == Some(ExecutionMode::Check);
+        if checking && let Some(interpreter) = lang.process_interpreted_cmd {
+            spec.events.emit("checking", "checking syntax");
+            let mut check = Command::new(version_command.unwrap_or(interpreter));
+            check.args(lang.check_args(&run_source));
+            let isolated = self
+                .isolation
+                .env(&mut check, &work_dir)
+                .and_then(|()| self.isolation.confine(&mut check, &work_dir));
+            if let Err(err) = isolated {
+                cleanup_dir(&work_dir).await;
+                return Err(err);
+            }
+            if let Some(deps_dir) = &run_deps {
+                check.envs(lang.dependency_env(deps_dir));
+            }
+            let checked = run_stage(check, COMPILE_TIMEOUT)
+                .instrument(tracing::info_span!("sandbox.compile"))
+                .await;
+            cleanup_dir(&work_dir).await;
+            return checked.map(|output| {
+                if output.success {
+                    return SandboxResult::checked(install, Some(output));
+                }
+                let mut result = SandboxResult::compile_failed(output);
+                result.install = install;
+                result
+            });
+        }
+        let mut cmd = if let Some(tool) = tool {
+            let mut cmd = Command::new("sh");
+            cmd.arg("-c").arg(tool).arg("sh").args(&spec.request.args);
+            cmd
+        } else if let Some(interpreter) = lang.process_interpreted_cmd {
+            let mut cmd = Command::new(version_command.unwrap_or(interpreter));
+            cmd.arg(&run_source);
             cmd.args(&spec.request.args);
             cmd
         } else {
-            let bin_path = self
-                .compile_or_get_cached(&spec, &lang, &source_path)
-                .await?;
-            let mut cmd = Command::new(bin_path);
+            let (compiled_program, compiled) = match self
+                .compile_or_get_cached(&spec, &lang, &source_path, deps_dir.as_deref())
+                .instrument(tracing::info_span!("sandbox.compile"))
+                .await
+            {
+                Ok(compiled) => compiled,
+                Err(err) => {
+                    cleanup_dir(&work_dir).await;
+                    return Err(err);
+                }
+            };
+            let Some(compiled_program) = compiled_program else {
+                cleanup_dir(&work_dir).await;
+                let mut result = SandboxResult::compile_failed(
+                    compiled.context("compile produced no program and no output")?,
+                );
+                result.install = install;
+                return Ok(result);
+            };
+            if checking {
+                cleanup_dir(&work_dir).await;
+                return Ok(SandboxResult::checked(install, compiled));
+            }
+            compile = compiled;
+            let bin_path = match self.isolation.inside(compiled_program.path()) {
+                Ok(path) => path,
+                Err(err) => {
+                    cleanup_dir(&work_dir).await;
+                    return Err(err);
+                }
+            };
+            let mut cmd = match lang
+                .run_args(&run_source, &bin_path, spec.limits.memory_mb)
+                .split_first()
+            {
+                Some((program, rest)) => {
+                    let mut cmd = Command::new(program);
+                    cmd.args(rest);
+                    cmd
+                }
+                None => Command::new(bin_path),
+            };
             cmd.args(&spec.request.args);
+            program = Some(compiled_program);
             cmd
         };
 
+        if let Err(err) = self.isolation.env(&mut cmd, &work_dir) {
+            cleanup_dir(&work_dir).await;
+            return Err(err);
+        }
+        cmd.envs(&spec.request.env);
+        if let Some(deps_dir) = &run_deps {
+            cmd.envs(lang.dependency_env(deps_dir));
+        }
+        if spec.request.deterministic {
+            let cpu = self.deterministic_cpu;
+            // SAFETY: sched_setaffinity is async-signal-safe and only touches
+            // the child's own copy of the set.
+            unsafe {
+                cmd.pre_exec(move || {
+                    let mut set: libc::cpu_set_t = std::mem::zeroed();
+                    libc::CPU_SET(cpu, &mut set);
+                    if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0
+                    {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+        }
+        // The child joins its cgroup before exec, so the limits cover it and
+        // everything it starts from the first instruction.
+        let cgroup = match &self.cgroups {
+            Some(cgroups) => {
+                let name = work_dir.file_name().unwrap_or_default().to_string_lossy();
+                match cgroups.create(&name, &spec.limits) {
+                    Ok(cgroup) => Some(cgroup),
+                    Err(err) => {
+                        cleanup_dir(&work_dir).await;
+                        return Err(err);
+                    }
+                }
+            }
+            None => None,
+        };
+        if let Some(cgroup) = cgroup.clone() {
+            // SAFETY: `enter` only makes async-signal-safe calls.
+            unsafe {
+                cmd.pre_exec(move || cgroup.enter());
+            }
+        }
+        // Last, so the hooks above still run with the engine's privileges.
+        if let Err(err) = self.isolation.confine(&mut cmd, &work_dir) {
+            if let Some(cgroup) = cgroup {
+                cgroup.remove().await;
+            }
+            cleanup_dir(&work_dir).await;
+            return Err(err);
+        }
         cmd.stdin(Stdio::piped());
         cmd.stdout(Stdio::piped());
         cmd.stderr(Stdio::piped());
 
-        let mut child = cmd
-            .spawn()
-            .context("failed to spawn process backend command")?;
-        if let Some(mut stdin) = child.stdin.take() {
-            let stdin_bytes = spec.request.stdin.into_bytes();
-            tokio::spawn(async move {
-                let _ = stdin.write_all(&stdin_bytes).await;
-            });
+        spec.events.emit("running", "program started");
+        // Spans are timed from creation to drop, so this one covers spawn to reap.
+        let run_span = tracing::info_span!("sandbox.run");
+        let started = Instant::now();
+        let mut child = match cmd.spawn() {
+            Ok(child) => child,
+            Err(err) => {
+                if let Some(cgroup) = cgroup {
+                    cgroup.remove().await;
+                }
+                return Err(err).context("failed to spawn process backend command");
+            }
+        };
+        if let Some(stdin) = child.stdin.take() {
+            tokio::spawn(feed_stdin(
+                stdin,
+                spec.request.stdin.into_bytes(),
+                spec.stdin_file,
+                spec.stdin.take(),
+            ));
         }
 
         let stdout = child.stdout.take().context("missing stdout pipe")?;
         let stderr = child.stderr.take().context("missing stderr pipe")?;
         let limit = spec.limits.max_output_bytes;
-        let stdout_task = tokio::spawn(async move { read_limited(stdout, limit).await });
-        let stderr_task = tokio::spawn(async move { read_limited(stderr, limit).await });
+        let combined = spec
+            .request
+            .combined_output
+            .then(|| Arc::new(Mutex::new(OutputLog::new(limit))));
+        let stdout_log = combined.clone().map(|log| (OutputStream::Stdout, log));
+        let stderr_log = combined.clone().map(|log| (OutputStream::Stderr, log));
+        let stdout_task =
+            tokio::spawn(async move { read_limited(stdout, limit, stdout_log).await });
+        let stderr_task =
+            tokio::spawn(async move { read_limited(stderr, limit, stderr_log).await });
 
+        // Reap the child ourselves with wait4 so its rusage comes back with the
+        // exit status; tokio's wait() only exposes the status.
+        let pid = child.id().context("process backend child has no pid")? as libc::pid_t;
+        self.running.insert(pid, spec.attempt);
+        let mut waiter = tokio::task::spawn_blocking(move || wait_with_rusage(pid));
         let wait_result =
-            tokio::time::timeout(Duration::from_millis(spec.limits.timeout_ms), child.wait()).await;
+            tokio::time::timeout(Duration::from_millis(spec.limits.timeout_ms), &mut waiter).await;
 
-        let (status_code, timed_out) = match wait_result {
-            Ok(Ok(status)) => (status.code().unwrap_or(-1), false),
+        let (waited, timed_out) = match wait_result {
+            Ok(waited) => (waited, false),
+            Err(_) => {
+                // SAFETY: kill has no memory effects; the waiter has not reaped the
+                // child yet unless it exited in the last instant.
+                unsafe { libc::kill(pid, libc::SIGKILL) };
+                (waiter.await, true)
+            }
+        };
+        self.running.remove(&pid);
+        // Measured before the group and anything left in it goes away.
+        let oom_killed = cgroup.as_ref().is_some_and(Cgroup::oom_killed);
+        let group_peak = cgroup.as_ref().and_then(Cgroup::peak_memory_bytes);
+        if let Some(cgroup) = cgroup {
+            cgroup.remove().await;
+        }
+        let (status, rusage) = match waited {
+            Ok(Ok(waited)) => waited,
             Ok(Err(err)) => {
                 cleanup_dir(&work_dir).await;
                 return Err(err).context("process backend command wait failed");
             }
-            Err(_) => {
-                let _ = child.kill().await;
-                (-1, true)
+            Err(err) => {
+                cleanup_dir(&work_dir).await;
+                return Err(err).context("process backend wait task failed");
             }
         };
+        drop(run_span);
+        let signal = (!timed_out && libc::WIFSIGNALED(status)).then(|| libc::WTERMSIG(status));
+        let status_code = match signal {
+            _ if timed_out => -1,
+            Some(signal) => 128 + signal,
+            None => libc::WEXITSTATUS(status),
+        };
 
-        let stdout = stdout_task.await.unwrap_or_default();
-        let stderr = stderr_task.await.unwrap_or_default();
+        let (stdout, stdout_bytes) = stdout_task.await.unwrap_or_default();
+        let (stderr, stderr_bytes) = stderr_task.await.unwrap_or_default();
+        let files = collect_files(&work_dir, &spec.collect, spec.limits.max_file_size_bytes).await;
         cleanup_dir(&work_dir).await;
+        drop(program);
 
+        let combined = combined
+            .and_then(|log| Arc::into_inner(log)?.into_inner().ok())
+            .map(|log| log.into_chunks(spec.request.output_encoding))
+            .unwrap_or_default();
+        let (stdout_text, stdout_encoding) = encode_output(&stdout, spec.request.output_encoding);
+        let (stderr_text, stderr_encoding) = encode_output(&stderr, spec.request.output_encoding);
         Ok(SandboxResult {
-            stdout: String::from_utf8_lossy(&stdout).to_string(),
-            stderr: String::from_utf8_lossy(&stderr).to_string(),
+            stdout_truncated: (stdout.len() as u64) < stdout_bytes,
+            stderr_truncated: (stderr.len() as u64) < stderr_bytes,
+            stdout: stdout_text,
+            stderr: stderr_text,
+            stdout_encoding,
+            stderr_encoding,
             exit_code: status_code,
             duration_ms: started.elapsed().as_millis(),
             timed_out,
+            compile,
+            install,
+            usage: ResourceUsage {
+                // ru_maxrss is reported in KiB on Linux, and only covers the
+                // largest single process.
+                peak_memory_bytes: Some(group_peak.unwrap_or(rusage.ru_maxrss as u64 * 1024)),
+                user_cpu_ms: Some(timeval_ms(rusage.ru_utime)),
+                system_cpu_ms: Some(timeval_ms(rusage.ru_stime)),
+                stdout_bytes,
+                stderr_bytes,
+            },
+            // Only attributable when the cgroup enforced the memory limit.
+            oom_killed,
+            signal,
+            combined,
+            files,
         })
     }
+
+    async fn open_session(&self, spec: SessionSpec) -> anyhow::Result<Box<dyn SandboxSession>> {
+        let program = DriverProgram::for_language(&spec.language)
+            .ok_or_else(|| request_error("sessions are not supported for this language"))?;
+        let work_dir = self.isolation.temp_dir().join(format!(
+            "unsafe-process-session-{}-{}",
+            spec.id.as_simple(),
+            now_nanos()
+        ));
+        tokio::fs::create_dir_all(&work_dir).await?;
+        let driver_path = work_dir.join(program.file_name);
+        if let Err(err) = tokio::fs::write(&driver_path, program.source).await {
+            cleanup_dir(&work_dir).await;
+            return Err(err.into());
+        }
+
+        let token = uuid::Uuid::new_v4().simple().to_string();
+        let (interpreter, args) = program
+            .process_cmd
+            .split_first()
+            .context("session driver has no command")?;
+        let mut cmd = Command::new(interpreter);
+        let isolated = async {
+            self.isolation.prepare_dir(&work_dir).await?;
+            self.isolation.env(&mut cmd, &work_dir)?;
+            self.isolation.confine(&mut cmd, &work_dir)?;
+            self.isolation.inside(&driver_path)
+        };
+        let driver_path = match isolated.await {
+            Ok(path) => path,
+            Err(err) => {
+                cleanup_dir(&work_dir).await;
+                return Err(err);
+            }
+        };
+        cmd.args(args)
+            .arg(&driver_path)
+            .envs(&spec.env)
+            .envs(Driver::env(&token, &spec.limits))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true);
+        let mut child = match cmd.spawn() {
+            Ok(child) => child,
+            Err(err) => {
+                cleanup_dir(&work_dir).await;
+                return Err(err).context("failed to spawn session driver");
+            }
+        };
+        let stdin = child.stdin.take().context("missing stdin pipe")?;
+        let mut stdout = child.stdout.take().context("missing stdout pipe")?;
+        let (sender, receiver) = mpsc::channel(16);
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 8192];
+            while let Ok(read @ 1..) = stdout.read(&mut buf).await {
+                if sender.send(buf[..read].to_vec()).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Box::new(ProcessSession {
+            driver: Driver::new(Box::pin(stdin), receiver, token),
+            child,
+            work_dir,
+        }))
+    }
+}
+
+// A driver running as a plain child process, with the same isolation as
+// process runs.
+struct ProcessSession {
+    driver: Driver,
+    child: Child,
+    work_dir: PathBuf,
+}
+
+#[async_trait]
+impl SandboxSession for ProcessSession {
+    async fn eval(&mut self, code: &str, timeout: Duration) -> anyhow::Result<Option<EvalResult>> {
+        self.driver.eval(code, timeout).await
+    }
+
+    async fn inspect(&mut self, timeout: Duration) -> anyhow::Result<Option<Vec<SessionVariable>>> {
+        self.driver.inspect(timeout).await
+    }
+
+    async fn close(&mut self) {
+        let _ = self.child.kill().await;
+        cleanup_dir(&self.work_dir).await;
+    }
 }
 
 impl ProcessSandbox {
+    // Installs are confined like the program, in a cgroup of their own with
+    // the install limits.
+    async fn install_dependencies(
+        &self,
+        spec: &RunSpec,
+        lang: &LanguageSpec,
+        work_dir: &Path,
+    ) -> anyhow::Result<(Option<PathBuf>, Option<CompileOutput>)> {
+        let Some(manifest) = spec.request.dependencies.as_deref() else {
+            return Ok((None, None));
+        };
+        let deps = lang
+            .dependencies
+            .as_ref()
+            .ok_or_else(|| request_error("language does not support dependency manifests"))?;
+        if self.network_proxy || !self.network_tenants.contains(&spec.tenant_id) {
+            return Err(request_error(
+                "dependency installs on the process backend need network access",
+            ));
+        }
+        self.isolation.prepare_dir(work_dir).await?;
+        let (dir, output) =
+            self.dependency_cache
+                .ensure(&(lang.source_name, manifest), |dir| async move {
+                    spec.events.emit("installing", "installing dependencies");
+                    self.isolation.prepare_dir(&dir).await?;
+                    let mut install = Command::new("sh");
+                    install.arg("-c").arg(deps.install);
+                    self.isolation.env(&mut install, work_dir)?;
+                    install
+                        .env("WORKSPACE", self.isolation.inside(work_dir)?)
+                        .env("DEPS", self.isolation.inside(&dir)?);
+                    let cgroup = match &self.cgroups {
+                        Some(cgroups) => {
+                            let name = work_dir.file_name().unwrap_or_default().to_string_lossy();
+                            Some(cgroups.create(
+                                &format!("{name}-install"),
+                                &install_limits(&spec.limits),
+                            )?)
+                        }
+                        None => None,
+                    };
+                    if let Some(cgroup) = cgroup.clone() {
+                        // SAFETY: `enter` only makes async-signal-safe calls.
+                        unsafe {
+                            install.pre_exec(move || cgroup.enter());
+                        }
+                    }
+                    let output = match self.isolation.confine(&mut install, work_dir) {
+                        Ok(()) => run_stage(install, INSTALL_TIMEOUT).await,
+                        Err(err) => Err(err),
+                    };
+                    if let Some(cgroup) = cgroup {
+                        cgroup.remove().await;
+                    }
+                    output
+                })
+                .await?;
+        Ok((Some(dir), output))
+    }
+
     async fn compile_or_get_cached(
         &self,
         spec: &RunSpec,
         lang: &LanguageSpec,
-        source_path: &std::path::Path,
-    ) -> anyhow::Result<PathBuf> {
+        source_path: &Path,
+        deps_dir: Option<&Path>,
+    ) -> anyhow::Result<(Option<CompiledArtifact>, Option<CompileOutput>)> {
         let mut hasher = DefaultHasher::new();
         lang.source_name.hash(&mut hasher);
+        lang.process_compile_cmd.hash(&mut hasher);
+        lang.process_compile_args.hash(&mut hasher);
         spec.request.code.hash(&mut hasher);
+        spec.request.dependencies.hash(&mut hasher);
+        spec.request.archive.hash(&mut hasher);
+        spec.request.files.hash(&mut hasher);
+        spec.request.version.hash(&mut hasher);
         let key = hasher.finish();
 
-        if let Some(cached) = self.compile_cache.get(&key) {
-            if cached.exists() {
-                return Ok(cached.value().clone());
-            }
+        if let Some(cached) = self.compile_cache.get(key) {
+            return Ok((Some(cached), None));
         }
 
-        let cache_dir = std::env::temp_dir().join("unsafe-process-compile-cache");
-        tokio::fs::create_dir_all(&cache_dir).await?;
-        let bin_path = cache_dir.join(format!("compiled-{}", key));
-        let compiler = lang
-            .process_compile_cmd
-            .context("compile command missing for compiled language")?;
+        let cache_dir = self.compile_cache.dir();
+        tokio::fs::create_dir_all(cache_dir).await?;
+        let bin_path = cache_dir.join(format!("compiled-{}{}", key, lang.process_artifact_ext));
 
-        let mut compile = Command::new(compiler);
-        compile.arg(source_path);
-        if compiler == "rustc" {
-            compile.args(["-O", "-o"]);
-        } else {
-            compile.args(["-O2", "-o"]);
-        }
-        compile.arg(&bin_path);
-        let output = compile.output().await?;
-        if !output.status.success() {
-            anyhow::bail!("{}", String::from_utf8_lossy(&output.stderr).to_string());
+        let script = lang.dependencies.as_ref().and_then(|deps| deps.compile);
+        let compile = match (deps_dir, script) {
+            (Some(deps_dir), Some(script)) => {
+                let mut compile = Command::new("sh");
+                compile
+                    .arg("-c")
+                    .arg(script)
+                    .env("WORKSPACE", source_path.parent().unwrap_or(source_path))
+                    .env("DEPS", deps_dir)
+                    .env("OUT", &bin_path);
+                compile
+            }
+            _ => {
+                let compiler = match self.version_command(spec)? {
+                    Some(command) => command,
+                    None => lang
+                        .process_compile_cmd
+                        .context("compile command missing for compiled language")?,
+                };
+                let mut compile = Command::new(compiler);
+                compile.args(lang.compile_args(source_path, &bin_path));
+                compile
+            }
+        };
+
+        spec.events.emit("compiling", "compiling source");
+        let output = run_stage(compile, COMPILE_TIMEOUT).await?;
+        if !output.success {
+            return Ok((None, Some(output)));
         }
-        self.compile_cache.insert(key, bin_path.clone());
-        Ok(bin_path)
+        Ok((
+            Some(self.compile_cache.insert(key, bin_path).await),
+            Some(output),
+        ))
     }
 }
 
+async fn run_stage(mut cmd: Command, timeout: Duration) -> anyhow::Result<CompileOutput> {
+    let program = cmd.as_std().get_program().to_string_lossy().to_string();
+    cmd.kill_on_drop(true);
+    let started = Instant::now();
+    let (exit_code, stdout, stderr) = match tokio::time::timeout(timeout, cmd.output()).await {
+        Ok(output) => {
+            let output = output.with_context(|| format!("failed to spawn {program}"))?;
+            (
+                output.status.code().unwrap_or(-1),
+                String::from_utf8_lossy(&output.stdout).to_string(),
+                String::from_utf8_lossy(&output.stderr).to_string(),
+            )
+        }
+        Err(_) => (
+            -1,
+            String::new(),
+            format!("{program} timed out after {}s", timeout.as_secs()),
+        ),
+    };
+    Ok(CompileOutput {
+        success: exit_code == 0,
+        exit_code,
+        stdout,
+        stderr,
+        duration_ms: started.elapsed().as_millis(),
+    })
+}
+
 fn now_nanos() -> u128 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -164,20 +735,53 @@ fn now_nanos() -> u128 {
         .unwrap_or(0)
 }
 
-async fn cleanup_dir(path: &std::path::Path) {
+async fn cleanup_dir(path: &Path) {
     let _ = tokio::fs::remove_dir_all(path).await;
 }
 
-async fn read_limited<R>(mut reader: R, limit: usize) -> Vec<u8>
+fn wait_with_rusage(pid: libc::pid_t) -> std::io::Result<(libc::c_int, libc::rusage)> {
+    let mut status = 0;
+    // SAFETY: rusage is plain old data and wait4 fully initialises it on success.
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+    loop {
+        // SAFETY: both out-pointers reference live locals for the duration of the call.
+        let rc = unsafe { libc::wait4(pid, &mut status, 0, &mut rusage) };
+        if rc == pid {
+            return Ok((status, rusage));
+        }
+        let err = std::io::Error::last_os_error();
+        if err.kind() != std::io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+fn timeval_ms(tv: libc::timeval) -> u64 {
+    tv.tv_sec as u64 * 1000 + tv.tv_usec as u64 / 1000
+}
+
+// `log`, when set, also records every chunk read under its stream label.
+async fn read_limited<R>(
+    mut reader: R,
+    limit: usize,
+    log: Option<(OutputStream, Arc<Mutex<OutputLog>>)>,
+) -> (Vec<u8>, u64)
 where
     R: tokio::io::AsyncRead + Unpin,
 {
     let mut out = Vec::with_capacity(limit.min(8192));
+    let mut total = 0u64;
     let mut chunk = [0u8; 4096];
     loop {
         match reader.read(&mut chunk).await {
             Ok(0) => break,
             Ok(n) => {
+                total += n as u64;
+                if let Some((stream, log)) = &log {
+                    log.lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .push(*stream, &chunk[..n]);
+                }
                 if out.len() < limit {
                     let remaining = limit - out.len();
                     out.extend_from_slice(&chunk[..remaining.min(n)]);
@@ -186,5 +790,358 @@ where
             Err(_) => break,
         }
     }
-    out
+    (out, total)
+}
+
+pub(super) fn is_executable(path: &Path) -> bool {
+    std::fs::metadata(path)
+        .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, time::Duration};
+
+    use base64::{Engine, prelude::BASE64_STANDARD};
+
+    use super::ProcessSandbox;
+    use crate::engine::{
+        config::EngineConfig,
+        models::{
+            ExecutionLimits, ExecutionMode, ExecutionRequest, FileEncoding, Language,
+            OutputEncoding, OutputStream, TerminationReason,
+        },
+        sandbox::{LiveStdin, RunSpec, SandboxBackend, SessionSpec},
+    };
+
+    fn run_spec(language: Language, code: &str, args: &[&str]) -> RunSpec {
+        RunSpec {
+            request: ExecutionRequest {
+                language,
+                filename: None,
+                code: code.to_string(),
+                stdin: String::new(),
+                args: args.iter().map(|a| a.to_string()).collect(),
+                env: BTreeMap::new(),
+                secrets: BTreeMap::new(),
+                allow_network: false,
+                egress: Vec::new(),
+                limits: None,
+                profile: None,
+                mode: None,
+                test_cases: Vec::new(),
+                metadata: BTreeMap::new(),
+                image: None,
+                version: None,
+                dependencies: None,
+                files: Vec::new(),
+                output_encoding: OutputEncoding::Utf8,
+                cache: false,
+                gpu: false,
+                backend: None,
+                interactive: false,
+                combined_output: false,
+                git: None,
+                archive: None,
+                benchmark: None,
+                deterministic: false,
+                seed: None,
+                priority: None,
+                language_detected: false,
+            },
+            limits: ExecutionLimits {
+                cpu_cores: 1.0,
+                memory_mb: 512,
+                timeout_ms: 60_000,
+                max_processes: 64,
+                max_file_size_bytes: 1024 * 1024,
+                max_output_bytes: 64 * 1024,
+            },
+            id: uuid::Uuid::new_v4(),
+            attempt: uuid::Uuid::new_v4(),
+            tenant_id: "t".into(),
+            events: Default::default(),
+            stdin: Default::default(),
+            stdin_file: None,
+            collect: Vec::new(),
+        }
+    }
+
+    fn go_available() -> bool {
+        std::process::Command::new("go")
+            .arg("version")
+            .output()
+            .is_ok_and(|out| out.status.success())
+    }
+
+    #[tokio::test]
+    async fn go_program_receives_args() {
+        if !go_available() {
+            return;
+        }
+        let code =
+            "package main\nimport (\"fmt\"; \"os\")\nfunc main() { fmt.Println(os.Args[1:]) }\n";
+        let result = ProcessSandbox::new(&EngineConfig::from_env())
+            .execute(run_spec(Language::Go, code, &["a", "b c"]))
+            .await
+            .unwrap();
+        assert_eq!(result.exit_code, 0);
+        assert_eq!(result.stdout.trim(), "[a b c]");
+    }
+
+    #[tokio::test]
+    async fn go_compile_error_is_reported() {
+        if !go_available() {
+            return;
+        }
+        let result = ProcessSandbox::new(&EngineConfig::from_env())
+            .execute(run_spec(
+                Language::Go,
+                "package main\nfunc main() { undefined() }\n",
+                &[],
+            ))
+            .await
+            .unwrap();
+        let compile = result.compile.unwrap();
+        assert!(!compile.success);
+        assert!(compile.stderr.contains("undefined"));
+        assert_ne!(result.exit_code, 0);
+    }
+
+    #[tokio::test]
+    async fn writes_live_stdin_until_closed() {
+        let mut spec = run_spec(
+            Language::Python,
+            "import sys\nprint(input())\nprint(sys.stdin.read().split())\n",
+            &[],
+        );
+        spec.request.stdin = "first\n".to_string();
+        let (sender, receiver) = tokio::sync::mpsc::channel(4);
+        spec.stdin = LiveStdin::new(receiver);
+        let run = tokio::spawn(async move {
+            ProcessSandbox::new(&EngineConfig::from_env())
+                .execute(spec)
+                .await
+        });
+        sender.send(b"second third\n".to_vec()).await.unwrap();
+        drop(sender);
+        let result = run.await.unwrap().unwrap();
+        assert_eq!(result.stdout, "first\n['second', 'third']\n");
+    }
+
+    #[tokio::test]
+    async fn session_keeps_state_between_evals() {
+        let spec = run_spec(Language::Python, "", &[]);
+        let mut session = ProcessSandbox::new(&EngineConfig::from_env())
+            .open_session(SessionSpec {
+                id: spec.id,
+                language: Language::Python,
+                limits: spec.limits,
+                env: BTreeMap::new(),
+            })
+            .await
+            .unwrap();
+        let timeout = Duration::from_secs(10);
+        let set = session
+            .eval("total = 40\nprint('set')", timeout)
+            .await
+            .unwrap();
+        assert_eq!(set.unwrap().stdout, "set\n");
+        let read = session.eval("total + 2", timeout).await.unwrap().unwrap();
+        assert_eq!(read.value.as_deref(), Some("42"));
+        let variables = session.inspect(timeout).await.unwrap().unwrap();
+        assert_eq!(variables[0].name, "total");
+        let slow = session.eval("while True: pass", Duration::from_millis(200));
+        assert!(slow.await.unwrap().is_none());
+        session.close().await;
+    }
+
+    #[tokio::test]
+    async fn reports_usage_beyond_truncated_output() {
+        let mut spec = run_spec(
+            Language::Python,
+            "data = bytearray(64 * 1024 * 1024)\nprint('x' * 200000)\n",
+            &[],
+        );
+        spec.limits.max_output_bytes = 1024;
+        let result = ProcessSandbox::new(&EngineConfig::from_env())
+            .execute(spec)
+            .await
+            .unwrap();
+        assert_eq!(result.exit_code, 0);
+        assert_eq!(result.stdout.len(), 1024);
+        assert_eq!(result.usage.stdout_bytes, 200_001);
+        assert!(result.stdout_truncated);
+        assert!(!result.stderr_truncated);
+        assert!(result.usage.peak_memory_bytes.unwrap() > 64 * 1024 * 1024);
+        assert!(result.usage.user_cpu_ms.is_some());
+    }
+
+    #[tokio::test]
+    async fn check_mode_parses_without_running() {
+        let sandbox = ProcessSandbox::new(&EngineConfig::from_env());
+        let mut spec = run_spec(Language::Python, "print('ran')\n", &[]);
+        spec.request.mode = Some(ExecutionMode::Check);
+        let clean = sandbox.execute(spec.clone()).await.unwrap();
+        assert_eq!(clean.exit_code, 0);
+        assert!(clean.stdout.is_empty());
+        assert!(clean.compile.unwrap().success);
+
+        spec.request.code = "print('ran'\n".to_string();
+        let broken = sandbox.execute(spec).await.unwrap();
+        assert_eq!(
+            broken.termination_reason(),
+            TerminationReason::CompileFailed
+        );
+        assert!(broken.compile.unwrap().stderr.contains("SyntaxError"));
+    }
+
+    #[tokio::test]
+    async fn format_mode_runs_the_configured_command() {
+        let mut config = EngineConfig::from_env();
+        config
+            .tools
+            .format
+            .insert(Language::Python, "tr a-z A-Z < main.py".to_string());
+        let mut spec = run_spec(Language::Python, "print('x')\n", &["ignored"]);
+        spec.request.mode = Some(ExecutionMode::Format);
+        let result = ProcessSandbox::new(&config).execute(spec).await.unwrap();
+        assert_eq!(result.exit_code, 0);
+        assert_eq!(result.stdout, "PRINT('X')\n");
+    }
+
+    #[tokio::test]
+    async fn records_interleaved_output() {
+        let mut spec = run_spec(
+            Language::Python,
+            "import sys, time\nfor stream, text in [(sys.stdout, 'a'), (sys.stderr, 'b'), (sys.stdout, 'c')]:\n    stream.write(text + '\\n')\n    stream.flush()\n    time.sleep(0.2)\n",
+            &[],
+        );
+        spec.request.combined_output = true;
+        let result = ProcessSandbox::new(&EngineConfig::from_env())
+            .execute(spec)
+            .await
+            .unwrap();
+        // A pipe read may split or join writes, so compare each run of one
+        // stream rather than chunk boundaries.
+        let mut chunks: Vec<(OutputStream, String)> = Vec::new();
+        for chunk in &result.combined {
+            match chunks.last_mut() {
+                Some((stream, data)) if *stream == chunk.stream => data.push_str(&chunk.data),
+                _ => chunks.push((chunk.stream, chunk.data.clone())),
+            }
+        }
+        assert_eq!(
+            chunks,
+            [
+                (OutputStream::Stdout, "a\n".to_string()),
+                (OutputStream::Stderr, "b\n".to_string()),
+                (OutputStream::Stdout, "c\n".to_string()),
+            ]
+        );
+        assert_eq!(result.stdout, "a\nc\n");
+    }
+
+    #[tokio::test]
+    async fn reports_terminating_signal() {
+        let result = ProcessSandbox::new(&EngineConfig::from_env())
+            .execute(run_spec(Language::Python, "import os\nos.abort()\n", &[]))
+            .await
+            .unwrap();
+        assert_eq!(result.signal, Some(6));
+        assert_eq!(result.exit_code, 134);
+        assert_eq!(result.termination_reason(), TerminationReason::Signaled);
+    }
+
+    #[tokio::test]
+    async fn encodes_binary_output_as_base64() {
+        let mut spec = run_spec(
+            Language::Python,
+            "import sys\nsys.stdout.buffer.write(bytes([0xff, 0, 0x80]))\nprint('ok', file=sys.stderr)\n",
+            &[],
+        );
+        spec.request.output_encoding = OutputEncoding::Auto;
+        let result = ProcessSandbox::new(&EngineConfig::from_env())
+            .execute(spec)
+            .await
+            .unwrap();
+        assert_eq!(result.stdout_encoding, FileEncoding::Base64);
+        assert_eq!(
+            BASE64_STANDARD.decode(&result.stdout).unwrap(),
+            [0xff, 0, 0x80]
+        );
+        assert_eq!(result.stderr_encoding, FileEncoding::Utf8);
+        assert_eq!(result.stderr, "ok\n");
+    }
+
+    #[tokio::test]
+    async fn streams_stdin_from_an_uploaded_file() {
+        let path = std::env::temp_dir().join(format!("stdin-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, vec![b'x'; 3 * 1024 * 1024]).unwrap();
+        let mut spec = run_spec(
+            Language::Python,
+            "import sys\nprint(len(sys.stdin.buffer.read()))\n",
+            &[],
+        );
+        spec.stdin_file = Some(path.clone());
+        let result = ProcessSandbox::new(&EngineConfig::from_env())
+            .execute(spec)
+            .await
+            .unwrap();
+        let _ = std::fs::remove_file(path);
+        assert_eq!(result.stdout, "3145728\n");
+    }
+
+    #[tokio::test]
+    async fn collects_files_left_in_the_working_directory() {
+        let mut spec = run_spec(
+            Language::Python,
+            "import os\nos.makedirs('out', exist_ok=True)\nopen('out/result.txt', 'w').write('42\\n')\nos.symlink('/etc/hostname', 'leak')\n",
+            &[],
+        );
+        spec.collect = vec![
+            "out/result.txt".to_string(),
+            "leak".to_string(),
+            "missing".to_string(),
+        ];
+        let result = ProcessSandbox::new(&EngineConfig::from_env())
+            .execute(spec)
+            .await
+            .unwrap();
+        let files: Vec<_> = result
+            .files
+            .iter()
+            .map(|file| (file.path.as_str(), file.bytes.as_deref()))
+            .collect();
+        assert_eq!(
+            files,
+            [
+                ("out/result.txt", Some(&b"42\n"[..])),
+                ("leak", None),
+                ("missing", None)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn runs_as_the_configured_user_with_a_scrubbed_environment() {
+        // Switching users needs root, and the interpreter must be reachable
+        // by the run user.
+        if unsafe { libc::geteuid() } != 0 || !std::path::Path::new("/usr/bin/node").exists() {
+            return;
+        }
+        let mut config = EngineConfig::from_env();
+        config.process_run_user = Some("65534:65534".to_string());
+        config.process_chroot = None;
+        config.process_path = "/usr/bin:/bin".to_string();
+        let code = "require('fs').writeFileSync('out.txt', 'x');\n\
+            console.log(process.getuid(), process.env.PATH, process.env.HOME === process.cwd(), \
+            Object.keys(process.env).sort().join(','));\n";
+        let result = ProcessSandbox::new(&config)
+            .execute(run_spec(Language::JavaScript, code, &[]))
+            .await
+            .unwrap();
+        assert_eq!(result.stderr, "");
+        assert_eq!(result.stdout, "65534 /usr/bin:/bin true HOME,PATH,TMPDIR\n");
+    }
 }
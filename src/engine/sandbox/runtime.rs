@@ -0,0 +1,205 @@
+use std::path::Path;
+
+use anyhow::Context;
+use bollard::{API_DEFAULT_VERSION, Docker, models::HostConfig, models::ResourcesUlimits};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuntimeFlavor {
+    Docker,
+    Podman,
+}
+
+impl RuntimeFlavor {
+    fn detect(binary: &Path) -> anyhow::Result<Self> {
+        let stem = binary
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        match stem.as_str() {
+            "docker" => Ok(Self::Docker),
+            "podman" => Ok(Self::Podman),
+            // No Docker Engine API to drive it through.
+            "nerdctl" => anyhow::bail!("nerdctl is not supported; use docker or podman"),
+            _ => anyhow::bail!("unsupported container runtime: {}", binary.display()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ContainerRuntime {
+    pub flavor: RuntimeFlavor,
+    // The CLI, as configured; used for what the Engine API lacks.
+    pub binary: String,
+    pub rootless: bool,
+    pub endpoint: Option<String>,
+}
+
+impl ContainerRuntime {
+    pub fn resolve(name: &str, rootless: bool, endpoint: Option<String>) -> anyhow::Result<Self> {
+        let flavor = RuntimeFlavor::detect(Path::new(name))?;
+        let endpoint = endpoint.or_else(|| default_endpoint(flavor, rootless));
+        Ok(Self {
+            flavor,
+            binary: name.to_string(),
+            rootless,
+            endpoint,
+        })
+    }
+
+    // A configured path must exist; a bare name only has to be on PATH when
+    // the CLI is used (session checkpoints), since runs go through the
+    // Engine API.
+    pub fn check_binary(&self, needs_cli: bool) -> anyhow::Result<()> {
+        let binary = Path::new(&self.binary);
+        let found = if binary.components().count() > 1 {
+            binary.is_file()
+        } else if needs_cli {
+            std::env::split_paths(&std::env::var_os("PATH").unwrap_or_default())
+                .any(|dir| dir.join(binary).is_file())
+        } else {
+            true
+        };
+        if !found {
+            anyhow::bail!("container runtime {} not found", self.binary);
+        }
+        Ok(())
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self.flavor {
+            RuntimeFlavor::Docker => "docker",
+            RuntimeFlavor::Podman => "podman",
+        }
+    }
+
+    pub fn connect(&self) -> anyhow::Result<Docker> {
+        let docker = match self.endpoint.as_deref() {
+            None => Docker::connect_with_defaults(),
+            Some(addr) if addr.starts_with("tcp://") || addr.starts_with("http://") => {
+                Docker::connect_with_http(addr, 120, API_DEFAULT_VERSION)
+            }
+            Some(addr) => Docker::connect_with_socket(addr, 120, API_DEFAULT_VERSION),
+        };
+        docker.with_context(|| format!("failed to connect to {} engine", self.name()))
+    }
+
+    // CRIU checkpoints of a running container, which stops it; docker keeps
+    // them by name with the container, podman keeps the latest one.
+    pub async fn checkpoint(&self, container: &str, checkpoint: &str) -> anyhow::Result<()> {
+        match self.flavor {
+            RuntimeFlavor::Podman => self.cli(&["container", "checkpoint", container]).await,
+            _ => {
+                self.cli(&["checkpoint", "create", container, checkpoint])
+                    .await
+            }
+        }
+    }
+
+    // Starts a container again from its checkpoint.
+    pub async fn restore(&self, container: &str, checkpoint: &str) -> anyhow::Result<()> {
+        match self.flavor {
+            RuntimeFlavor::Podman => self.cli(&["container", "restore", container]).await,
+            _ => {
+                self.cli(&["start", "--checkpoint", checkpoint, container])
+                    .await
+            }
+        }
+    }
+
+    // Runs the runtime's CLI against the same engine the API client uses.
+    async fn cli(&self, args: &[&str]) -> anyhow::Result<()> {
+        let mut command = tokio::process::Command::new(&self.binary);
+        command
+            .args(args)
+            .stdin(std::process::Stdio::null())
+            .kill_on_drop(true);
+        if let Some(endpoint) = &self.endpoint {
+            let variable = match self.flavor {
+                RuntimeFlavor::Podman => "CONTAINER_HOST",
+                _ => "DOCKER_HOST",
+            };
+            command.env(variable, endpoint);
+        }
+        let output = command
+            .output()
+            .await
+            .with_context(|| format!("failed to run {}", self.binary))?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "{} {} failed: {}",
+                self.name(),
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+
+    pub fn apply_host_config(&self, host: &mut HostConfig, max_processes: u64) {
+        // RLIMIT_NPROC is counted per host uid, which in rootless mode is shared by
+        // every container, so rely on the pids limit alone there.
+        if !self.rootless {
+            host.ulimits
+                .get_or_insert_with(Vec::new)
+                .push(ResourcesUlimits {
+                    name: Some("nproc".to_string()),
+                    soft: Some(max_processes as i64),
+                    hard: Some(max_processes as i64),
+                });
+        }
+        if self.rootless && self.flavor == RuntimeFlavor::Podman {
+            host.userns_mode = Some("keep-id".to_string());
+        }
+    }
+}
+
+fn default_endpoint(flavor: RuntimeFlavor, rootless: bool) -> Option<String> {
+    match flavor {
+        RuntimeFlavor::Podman if rootless => std::env::var("XDG_RUNTIME_DIR")
+            .ok()
+            .map(|dir| format!("unix://{dir}/podman/podman.sock")),
+        RuntimeFlavor::Podman => Some("unix:///run/podman/podman.sock".to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use bollard::models::HostConfig;
+
+    use super::{ContainerRuntime, RuntimeFlavor};
+
+    #[test]
+    fn detects_flavor_from_binary_name() {
+        assert_eq!(
+            RuntimeFlavor::detect(Path::new("/usr/bin/podman")).unwrap(),
+            RuntimeFlavor::Podman
+        );
+        assert!(RuntimeFlavor::detect(Path::new("nerdctl")).is_err());
+        assert!(RuntimeFlavor::detect(Path::new("/usr/bin/lxc")).is_err());
+
+        let missing = ContainerRuntime::resolve("/nonexistent/bin/podman", false, None).unwrap();
+        assert!(missing.check_binary(false).is_err());
+        let bare = ContainerRuntime::resolve("docker", false, None).unwrap();
+        assert!(bare.check_binary(false).is_ok());
+    }
+
+    #[test]
+    fn rootless_podman_drops_nproc_and_keeps_uid() {
+        let runtime = ContainerRuntime::resolve("podman", true, None).unwrap();
+        let mut host = HostConfig::default();
+        runtime.apply_host_config(&mut host, 32);
+        assert!(host.ulimits.is_none());
+        assert_eq!(host.userns_mode.as_deref(), Some("keep-id"));
+
+        let rootful = ContainerRuntime::resolve("docker", false, None).unwrap();
+        let mut host = HostConfig::default();
+        rootful.apply_host_config(&mut host, 32);
+        let ulimits = host.ulimits.unwrap();
+        assert_eq!(ulimits[0].name.as_deref(), Some("nproc"));
+        assert_eq!(ulimits[0].hard, Some(32));
+    }
+}
@@ -0,0 +1,170 @@
+use std::{collections::BTreeMap, pin::Pin, time::Duration};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use serde::{Deserialize, de::DeserializeOwned};
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    sync::mpsc,
+};
+use uuid::Uuid;
+
+use super::request_error;
+use crate::engine::models::{EvalResult, ExecutionLimits, Language, SessionVariable};
+
+// Replies are single lines; anything much longer is runaway output.
+const MAX_REPLY_BYTES: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct SessionSpec {
+    pub id: Uuid,
+    pub language: Language,
+    pub limits: ExecutionLimits,
+    pub env: BTreeMap<String, String>,
+}
+
+// A runtime kept alive between evaluations. `None` from `eval` or `inspect`
+// means the call timed out; the caller is expected to close the session
+// after that or after any error.
+#[async_trait]
+pub trait SandboxSession: Send {
+    async fn eval(&mut self, code: &str, timeout: Duration) -> anyhow::Result<Option<EvalResult>>;
+    async fn inspect(&mut self, timeout: Duration) -> anyhow::Result<Option<Vec<SessionVariable>>>;
+    async fn close(&mut self);
+
+    // Checkpoints the runtime and stops it, keeping its state but no longer
+    // holding memory or CPU, until `resume`. A failed `resume` leaves the
+    // session to be closed.
+    async fn suspend(&mut self) -> anyhow::Result<()> {
+        Err(request_error(
+            "sessions on this backend cannot be suspended",
+        ))
+    }
+
+    async fn resume(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+// The REPL program a backend starts for a session, per language.
+pub struct DriverProgram {
+    pub file_name: &'static str,
+    pub source: &'static str,
+    pub process_cmd: &'static [&'static str],
+    pub docker_cmd: &'static [&'static str],
+}
+
+impl DriverProgram {
+    pub fn for_language(language: &Language) -> Option<Self> {
+        match language {
+            Language::Python => Some(Self {
+                file_name: "session.py",
+                source: include_str!("drivers/session.py"),
+                process_cmd: &["python", "-u"],
+                docker_cmd: &["python3", "-u", "-s"],
+            }),
+            Language::JavaScript => Some(Self {
+                file_name: "session.js",
+                source: include_str!("drivers/session.js"),
+                process_cmd: &["node"],
+                docker_cmd: &["node"],
+            }),
+            _ => None,
+        }
+    }
+}
+
+// Talks to a running driver: requests go to its stdin, replies are picked
+// out of its stdout by the per-session token, so stray output the code
+// writes around them is ignored.
+pub struct Driver {
+    input: Pin<Box<dyn AsyncWrite + Send>>,
+    output: mpsc::Receiver<Vec<u8>>,
+    pending: Vec<u8>,
+    token: String,
+}
+
+#[derive(Deserialize)]
+struct Variables {
+    variables: Vec<SessionVariable>,
+}
+
+impl Driver {
+    pub fn new(
+        input: Pin<Box<dyn AsyncWrite + Send>>,
+        output: mpsc::Receiver<Vec<u8>>,
+        token: String,
+    ) -> Self {
+        Self {
+            input,
+            output,
+            pending: Vec::new(),
+            token,
+        }
+    }
+
+    // Variables the driver reads at startup.
+    pub fn env(token: &str, limits: &ExecutionLimits) -> [(String, String); 2] {
+        [
+            ("SESSION_TOKEN".to_string(), token.to_string()),
+            (
+                "SESSION_OUTPUT_LIMIT".to_string(),
+                limits.max_output_bytes.to_string(),
+            ),
+        ]
+    }
+
+    pub async fn eval(
+        &mut self,
+        code: &str,
+        timeout: Duration,
+    ) -> anyhow::Result<Option<EvalResult>> {
+        self.call("eval", code, timeout).await
+    }
+
+    pub async fn inspect(
+        &mut self,
+        timeout: Duration,
+    ) -> anyhow::Result<Option<Vec<SessionVariable>>> {
+        let reply: Option<Variables> = self.call("inspect", "", timeout).await?;
+        Ok(reply.map(|reply| reply.variables))
+    }
+
+    async fn call<T: DeserializeOwned>(
+        &mut self,
+        op: &str,
+        payload: &str,
+        timeout: Duration,
+    ) -> anyhow::Result<Option<T>> {
+        let request = async {
+            let header = format!("{op} {}\n", payload.len());
+            self.input.write_all(header.as_bytes()).await?;
+            self.input.write_all(payload.as_bytes()).await?;
+            self.input.flush().await?;
+            let reply = self.reply().await?;
+            serde_json::from_str(&reply).context("malformed session reply")
+        };
+        match tokio::time::timeout(timeout, request).await {
+            Ok(reply) => reply.map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    async fn reply(&mut self) -> anyhow::Result<String> {
+        let marker = format!("{} ", self.token);
+        loop {
+            while let Some(newline) = self.pending.iter().position(|byte| *byte == b'\n') {
+                let line: Vec<u8> = self.pending.drain(..=newline).collect();
+                let line = String::from_utf8_lossy(&line);
+                if let Some((_, reply)) = line.split_once(&marker) {
+                    return Ok(reply.trim_end().to_string());
+                }
+            }
+            if self.pending.len() > MAX_REPLY_BYTES {
+                anyhow::bail!("session output exceeded {MAX_REPLY_BYTES} bytes");
+            }
+            let chunk = self.output.recv().await.context("session runtime exited")?;
+            self.pending.extend(chunk);
+        }
+    }
+}
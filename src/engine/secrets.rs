@@ -0,0 +1,341 @@
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Context;
+use base64::{Engine, prelude::BASE64_STANDARD};
+use serde::{Deserialize, Serialize};
+
+use crate::engine::{
+    encryption::RecordCipher,
+    models::{ExecutionOutput, FileEncoding, SecretInfo},
+    store::now_ms,
+};
+
+pub const REDACTED: &str = "[REDACTED]";
+
+// Named secrets per tenant, held sealed with the record cipher in memory and
+// on disk; values are only opened to start a run. Without encryption keys
+// there is nowhere safe to keep them, so the store stays disabled.
+pub struct SecretStore {
+    secrets: Mutex<BTreeMap<(String, String), StoredSecret>>,
+    cipher: RecordCipher,
+    path: Option<PathBuf>,
+    write_lock: tokio::sync::Mutex<()>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredSecret {
+    tenant_id: String,
+    name: String,
+    updated_at_ms: u64,
+    sealed: String,
+}
+
+impl SecretStore {
+    // Disabled.
+    pub fn new() -> Self {
+        Self {
+            secrets: Mutex::new(BTreeMap::new()),
+            cipher: RecordCipher::disabled(),
+            path: None,
+            write_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    pub fn load(path: Option<PathBuf>, cipher: RecordCipher) -> anyhow::Result<Self> {
+        let mut secrets = BTreeMap::new();
+        if let Some(path) = &path
+            && path.exists()
+        {
+            let saved = std::fs::read(path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            let saved: Vec<StoredSecret> = serde_json::from_slice(&saved)
+                .with_context(|| format!("failed to parse {}", path.display()))?;
+            for secret in saved {
+                secrets.insert((secret.tenant_id.clone(), secret.name.clone()), secret);
+            }
+        }
+        Ok(Self {
+            secrets: Mutex::new(secrets),
+            cipher,
+            path,
+            ..Self::new()
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.cipher.enabled()
+    }
+
+    pub fn list(&self, tenant_id: &str) -> Vec<SecretInfo> {
+        self.lock()
+            .values()
+            .filter(|secret| secret.tenant_id == tenant_id)
+            .map(|secret| SecretInfo {
+                name: secret.name.clone(),
+                updated_at_ms: secret.updated_at_ms,
+            })
+            .collect()
+    }
+
+    pub fn contains(&self, tenant_id: &str, name: &str) -> bool {
+        self.lock()
+            .contains_key(&(tenant_id.to_string(), name.to_string()))
+    }
+
+    pub fn count(&self, tenant_id: &str) -> usize {
+        self.lock()
+            .keys()
+            .filter(|(tenant, _)| tenant == tenant_id)
+            .count()
+    }
+
+    pub async fn put(
+        &self,
+        tenant_id: &str,
+        name: &str,
+        value: &str,
+    ) -> anyhow::Result<SecretInfo> {
+        let secret = StoredSecret {
+            tenant_id: tenant_id.to_string(),
+            name: name.to_string(),
+            updated_at_ms: now_ms(),
+            sealed: self.cipher.seal(value)?,
+        };
+        let info = SecretInfo {
+            name: secret.name.clone(),
+            updated_at_ms: secret.updated_at_ms,
+        };
+        self.lock()
+            .insert((tenant_id.to_string(), name.to_string()), secret);
+        self.save().await?;
+        Ok(info)
+    }
+
+    pub async fn remove(&self, tenant_id: &str, name: &str) -> anyhow::Result<bool> {
+        let removed = self
+            .lock()
+            .remove(&(tenant_id.to_string(), name.to_string()))
+            .is_some();
+        if removed {
+            self.save().await?;
+        }
+        Ok(removed)
+    }
+
+    // None when the tenant has no secret by that name.
+    pub fn reveal(&self, tenant_id: &str, name: &str) -> anyhow::Result<Option<String>> {
+        let sealed = self
+            .lock()
+            .get(&(tenant_id.to_string(), name.to_string()))
+            .map(|secret| secret.sealed.clone());
+        sealed.map(|sealed| self.cipher.open(&sealed)).transpose()
+    }
+
+    // Written to a temporary file first so a crash never leaves half a file.
+    async fn save(&self) -> anyhow::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let _guard = self.write_lock.lock().await;
+        let snapshot: Vec<StoredSecret> = self.lock().values().cloned().collect();
+        let body = serde_json::to_vec_pretty(&snapshot)?;
+        let temporary = path.with_extension("tmp");
+        tokio::fs::write(&temporary, body)
+            .await
+            .with_context(|| format!("failed to write {}", temporary.display()))?;
+        tokio::fs::rename(&temporary, path)
+            .await
+            .with_context(|| format!("failed to replace {}", path.display()))?;
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<(String, String), StoredSecret>> {
+        self.secrets.lock().expect("secrets poisoned")
+    }
+}
+
+impl Default for SecretStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Replaces every secret value in the captured output, including base64
+// encoded streams, before the result is stored or returned.
+#[derive(Debug, Clone, Default)]
+pub struct Redactor {
+    // Longest first, so a secret containing another is replaced whole.
+    values: Arc<Vec<String>>,
+}
+
+impl Redactor {
+    pub fn new<'a>(values: impl IntoIterator<Item = &'a String>) -> Self {
+        let mut values: Vec<String> = values
+            .into_iter()
+            .filter(|value| !value.is_empty())
+            .cloned()
+            .collect();
+        values.sort_by_key(|value| std::cmp::Reverse(value.len()));
+        values.dedup();
+        Self {
+            values: Arc::new(values),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn text(&self, text: &str) -> String {
+        self.values.iter().fold(text.to_string(), |text, value| {
+            text.replace(value.as_str(), REDACTED)
+        })
+    }
+
+    fn redact(&self, text: &mut String, encoding: FileEncoding) {
+        match encoding {
+            FileEncoding::Utf8 => *text = self.text(text),
+            FileEncoding::Base64 => {
+                if let Ok(bytes) = BASE64_STANDARD.decode(text.as_bytes()) {
+                    *text = BASE64_STANDARD.encode(self.bytes(&bytes));
+                }
+            }
+        }
+    }
+
+    fn bytes(&self, bytes: &[u8]) -> Vec<u8> {
+        self.values.iter().fold(bytes.to_vec(), |bytes, value| {
+            let value = value.as_bytes();
+            let mut out = Vec::with_capacity(bytes.len());
+            let mut rest = &bytes[..];
+            while let Some(at) = rest.windows(value.len()).position(|window| window == value) {
+                out.extend_from_slice(&rest[..at]);
+                out.extend_from_slice(REDACTED.as_bytes());
+                rest = &rest[at + value.len()..];
+            }
+            out.extend_from_slice(rest);
+            out
+        })
+    }
+
+    pub fn output(&self, output: &mut ExecutionOutput) {
+        if self.is_empty() {
+            return;
+        }
+        self.redact(&mut output.stdout, output.stdout_encoding);
+        self.redact(&mut output.stderr, output.stderr_encoding);
+        for stage in output.install.iter_mut().chain(output.compile.iter_mut()) {
+            self.redact(&mut stage.stdout, FileEncoding::Utf8);
+            self.redact(&mut stage.stderr, FileEncoding::Utf8);
+        }
+        for case in &mut output.test_results {
+            self.redact(&mut case.stdout, case.stdout_encoding);
+            self.redact(&mut case.stderr, case.stderr_encoding);
+            for diff in case
+                .stdout_diff
+                .iter_mut()
+                .chain(case.stderr_diff.iter_mut())
+            {
+                self.redact(&mut diff.unified, FileEncoding::Utf8);
+            }
+            for diff in case.files.iter_mut().filter_map(|file| file.diff.as_mut()) {
+                self.redact(&mut diff.unified, FileEncoding::Utf8);
+            }
+        }
+        for case in output
+            .test_report
+            .iter_mut()
+            .flat_map(|report| &mut report.cases)
+        {
+            if let Some(message) = &mut case.message {
+                self.redact(message, FileEncoding::Utf8);
+            }
+        }
+        for diagnostic in &mut output.diagnostics {
+            self.redact(&mut diagnostic.message, FileEncoding::Utf8);
+        }
+        if let Some(formatted) = &mut output.formatted {
+            self.redact(formatted, FileEncoding::Utf8);
+        }
+        for chunk in &mut output.combined {
+            self.redact(&mut chunk.data, chunk.encoding);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{Engine, prelude::BASE64_STANDARD};
+
+    use super::{Redactor, SecretStore};
+    use crate::engine::{config::EngineConfig, encryption::RecordCipher, models::ExecutionOutput};
+
+    #[tokio::test]
+    async fn keeps_secrets_sealed_and_redacts_their_values() {
+        let path = std::env::temp_dir().join(format!("secrets-{}.json", uuid::Uuid::new_v4()));
+        let mut config = EngineConfig::from_env();
+        config.encryption_keys = format!("k1:{}", BASE64_STANDARD.encode([7u8; 32]));
+        let cipher = RecordCipher::from_config(&config).unwrap();
+        let store = SecretStore::load(Some(path.clone()), cipher.clone()).unwrap();
+        store.put("a", "token", "hunter2-token").await.unwrap();
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("hunter2"));
+
+        let reloaded = SecretStore::load(Some(path.clone()), cipher).unwrap();
+        assert_eq!(
+            reloaded.reveal("a", "token").unwrap().as_deref(),
+            Some("hunter2-token")
+        );
+        assert!(reloaded.reveal("b", "token").unwrap().is_none());
+        assert!(!SecretStore::new().enabled());
+
+        let diff = serde_json::json!({
+            "line": 1, "column": 7, "truncated": false,
+            "unified": "-token=\n+token=hunter2-token\n",
+        });
+        let mut output: ExecutionOutput = serde_json::from_value(serde_json::json!({
+            "stdout": "token=hunter2-token\n",
+            "stderr": BASE64_STANDARD.encode(b"\xffhunter2-token"),
+            "stderr_encoding": "base64",
+            "exit_code": 0, "duration_ms": 1, "sandbox_backend": "process",
+            "test_results": [{
+                "stdin": "", "stdout": "", "stderr": "", "passed": false,
+                "exit_code": 0, "duration_ms": 1,
+                "stdout_diff": diff, "stderr_diff": diff,
+                "files": [{
+                    "path": "out.txt", "exists": true, "passed": false, "sha256": null,
+                    "diff": diff,
+                }],
+            }],
+            "test_report": {
+                "framework": "junit", "passed": 0, "failed": 1, "skipped": 0,
+                "cases": [{
+                    "name": "login", "suite": null, "status": "failed", "duration_ms": null,
+                    "message": "expected token but got hunter2-token",
+                }],
+            },
+        }))
+        .unwrap();
+        Redactor::new(&["hunter2-token".to_string()]).output(&mut output);
+        assert_eq!(output.stdout, "token=[REDACTED]\n");
+        assert_eq!(
+            BASE64_STANDARD.decode(&output.stderr).unwrap(),
+            b"\xff[REDACTED]"
+        );
+        let case = &output.test_results[0];
+        for diff in [&case.stdout_diff, &case.stderr_diff, &case.files[0].diff] {
+            assert_eq!(
+                diff.as_ref().unwrap().unified,
+                "-token=\n+token=[REDACTED]\n"
+            );
+        }
+        assert_eq!(
+            output.test_report.unwrap().cases[0].message.as_deref(),
+            Some("expected token but got [REDACTED]")
+        );
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,501 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use uuid::Uuid;
+
+use crate::engine::{
+    config::{EngineConfig, SandboxBackendKind},
+    error::EngineError,
+    models::{EvalResult, ExecutionLimits, Language, SessionInfo},
+    sandbox::{SandboxBackend, SandboxSession, SessionSpec, is_infrastructure_error},
+    store::now_ms,
+};
+
+// A REPL runtime owned by one tenant. Evaluations take turns on `runtime`,
+// which is emptied once the session is closed. A suspended session's runtime
+// is checkpointed and stopped; the next evaluation restores it.
+pub struct Session {
+    pub id: Uuid,
+    pub tenant_id: String,
+    pub language: Language,
+    pub backend: SandboxBackendKind,
+    pub limits: ExecutionLimits,
+    created_at_ms: u64,
+    last_used_at_ms: AtomicU64,
+    evals: AtomicU64,
+    suspended: AtomicBool,
+    runtime: tokio::sync::Mutex<Option<Box<dyn SandboxSession>>>,
+}
+
+impl Session {
+    pub fn info(&self) -> SessionInfo {
+        SessionInfo {
+            id: self.id,
+            tenant_id: self.tenant_id.clone(),
+            language: self.language.clone(),
+            backend: self.backend,
+            limits: self.limits.clone(),
+            created_at_ms: self.created_at_ms,
+            last_used_at_ms: self.last_used_at_ms.load(Ordering::SeqCst),
+            evals: self.evals.load(Ordering::SeqCst),
+            suspended: self.suspended.load(Ordering::SeqCst),
+            variables: None,
+        }
+    }
+
+    fn touch(&self) {
+        self.last_used_at_ms.store(now_ms(), Ordering::SeqCst);
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_millis(self.limits.timeout_ms)
+    }
+
+    fn is_suspended(&self) -> bool {
+        self.suspended.load(Ordering::SeqCst)
+    }
+}
+
+// The open sessions of this node. Sessions idle for longer than
+// `idle_timeout` are closed by the reaper, or suspended when `checkpoint` is
+// on and closed once suspended for `suspended_ttl`. Suspended sessions do
+// not count against `max`, but do against `max_per_tenant`.
+pub struct SessionManager {
+    sessions: Mutex<HashMap<Uuid, Arc<Session>>>,
+    max: usize,
+    max_per_tenant: usize,
+    idle_timeout: Duration,
+    checkpoint: bool,
+    suspended_ttl: Duration,
+}
+
+impl SessionManager {
+    pub fn new(config: &EngineConfig) -> Self {
+        Self {
+            sessions: Mutex::new(HashMap::new()),
+            max: config.session_max,
+            max_per_tenant: config.session_max_per_tenant,
+            idle_timeout: Duration::from_millis(config.session_idle_timeout_ms.max(1)),
+            checkpoint: config.session_checkpoint,
+            suspended_ttl: Duration::from_millis(config.session_suspended_ttl_ms.max(1)),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.max > 0
+    }
+
+    pub async fn open(
+        &self,
+        tenant_id: &str,
+        kind: SandboxBackendKind,
+        backend: Arc<dyn SandboxBackend>,
+        spec: SessionSpec,
+    ) -> Result<Arc<Session>, EngineError> {
+        // Checked again on insert; this only avoids starting a runtime for
+        // nothing.
+        self.admit(&self.lock(), tenant_id)?;
+        let runtime = backend
+            .open_session(spec.clone())
+            .await
+            .map_err(runtime_error)?;
+        let now = now_ms();
+        let session = Arc::new(Session {
+            id: spec.id,
+            tenant_id: tenant_id.to_string(),
+            language: spec.language,
+            backend: kind,
+            limits: spec.limits,
+            created_at_ms: now,
+            last_used_at_ms: AtomicU64::new(now),
+            evals: AtomicU64::new(0),
+            suspended: AtomicBool::new(false),
+            runtime: tokio::sync::Mutex::new(Some(runtime)),
+        });
+        let admitted = {
+            let mut sessions = self.lock();
+            self.admit(&sessions, tenant_id)
+                .map(|_| sessions.insert(session.id, session.clone()))
+        };
+        if let Err(err) = admitted {
+            if let Some(mut runtime) = session.runtime.lock().await.take() {
+                runtime.close().await;
+            }
+            return Err(err);
+        }
+        Ok(session)
+    }
+
+    pub fn get(&self, id: Uuid, tenant_id: &str) -> Result<Arc<Session>, EngineError> {
+        let session = self
+            .lock()
+            .get(&id)
+            .cloned()
+            .ok_or(EngineError::SessionNotFound)?;
+        if session.tenant_id != tenant_id {
+            return Err(EngineError::Forbidden);
+        }
+        Ok(session)
+    }
+
+    // Failures are reported in the result; a timeout or a runtime that went
+    // away closes the session.
+    pub async fn eval(&self, session: &Session, code: &str) -> EvalResult {
+        let mut runtime = session.runtime.lock().await;
+        let started = Instant::now();
+        session.touch();
+        if let Err(err) = self.resume_locked(session, &mut runtime).await {
+            return EvalResult {
+                error: Some(err.to_string()),
+                session_closed: runtime.is_none(),
+                ..Default::default()
+            };
+        }
+        let outcome = match runtime.as_mut() {
+            Some(runtime) => runtime.eval(code, session.timeout()).await,
+            None => Err(anyhow::anyhow!("session is closed")),
+        };
+        let (mut result, healthy) = match outcome {
+            Ok(Some(result)) => (result, true),
+            Ok(None) => (
+                EvalResult {
+                    error: Some(format!(
+                        "evaluation timed out after {} ms",
+                        session.limits.timeout_ms
+                    )),
+                    timed_out: true,
+                    ..Default::default()
+                },
+                false,
+            ),
+            Err(err) => (
+                EvalResult {
+                    error: Some(format!("{err:#}")),
+                    ..Default::default()
+                },
+                false,
+            ),
+        };
+        result.duration_ms = started.elapsed().as_millis() as u64;
+        session.evals.fetch_add(1, Ordering::SeqCst);
+        session.touch();
+        if !healthy {
+            result.session_closed = true;
+            self.lock().remove(&session.id);
+            if let Some(mut runtime) = runtime.take() {
+                runtime.close().await;
+            }
+        }
+        result
+    }
+
+    // The session's info with the names currently defined in it.
+    pub async fn inspect(&self, session: &Session) -> Result<SessionInfo, EngineError> {
+        let mut runtime = session.runtime.lock().await;
+        if session.is_suspended() {
+            return Ok(session.info());
+        }
+        let variables = match runtime.as_mut() {
+            Some(runtime) => runtime.inspect(session.timeout()).await,
+            None => return Err(EngineError::SessionNotFound),
+        };
+        match variables {
+            Ok(Some(variables)) => Ok(SessionInfo {
+                variables: Some(variables),
+                ..session.info()
+            }),
+            failed => {
+                self.lock().remove(&session.id);
+                if let Some(mut runtime) = runtime.take() {
+                    runtime.close().await;
+                }
+                let reason = match failed {
+                    Err(err) => format!("{err:#}"),
+                    _ => "inspection timed out".to_string(),
+                };
+                Err(EngineError::Conflict(format!(
+                    "session closed after its runtime failed: {reason}"
+                )))
+            }
+        }
+    }
+
+    // Checkpoints the session's runtime, freeing its capacity; the next
+    // evaluation (or `resume`) restores it. Waits for a running evaluation.
+    pub async fn suspend(&self, session: &Session) -> Result<SessionInfo, EngineError> {
+        let mut runtime = session.runtime.lock().await;
+        let Some(active) = runtime.as_mut() else {
+            return Err(EngineError::SessionNotFound);
+        };
+        if !session.is_suspended() {
+            active.suspend().await.map_err(runtime_error)?;
+            session.suspended.store(true, Ordering::SeqCst);
+            tracing::info!(session_id = %session.id, tenant_id = %session.tenant_id, "session suspended");
+        }
+        Ok(session.info())
+    }
+
+    pub async fn resume(&self, session: &Session) -> Result<SessionInfo, EngineError> {
+        let mut runtime = session.runtime.lock().await;
+        self.resume_locked(session, &mut runtime).await?;
+        session.touch();
+        Ok(session.info())
+    }
+
+    // Restores a suspended session if there is room for it; one that fails to
+    // restore is closed.
+    async fn resume_locked(
+        &self,
+        session: &Session,
+        runtime: &mut Option<Box<dyn SandboxSession>>,
+    ) -> Result<(), EngineError> {
+        if !session.is_suspended() {
+            return Ok(());
+        }
+        let Some(active) = runtime.as_mut() else {
+            return Err(EngineError::SessionNotFound);
+        };
+        if self.active(&self.lock()) >= self.max {
+            return Err(EngineError::Conflict(
+                "no capacity to resume the session".to_string(),
+            ));
+        }
+        if let Err(err) = active.resume().await {
+            self.lock().remove(&session.id);
+            if let Some(mut runtime) = runtime.take() {
+                runtime.close().await;
+            }
+            return Err(EngineError::Conflict(format!(
+                "session closed after its runtime failed to restore: {err:#}"
+            )));
+        }
+        session.suspended.store(false, Ordering::SeqCst);
+        tracing::info!(session_id = %session.id, tenant_id = %session.tenant_id, "session resumed");
+        Ok(())
+    }
+
+    // Waits for a running evaluation to finish first.
+    pub async fn close(&self, session: &Session) {
+        self.lock().remove(&session.id);
+        if let Some(mut runtime) = session.runtime.lock().await.take() {
+            runtime.close().await;
+        }
+    }
+
+    // Sessions busy evaluating are left alone. With checkpoints on, idle
+    // sessions are suspended first and closed after `suspended_ttl`; those
+    // that cannot be suspended are closed.
+    pub async fn close_idle(&self) {
+        let now = now_ms();
+        let idle_cutoff = now.saturating_sub(self.idle_timeout.as_millis() as u64);
+        let suspended_cutoff = now.saturating_sub(self.suspended_ttl.as_millis() as u64);
+        let idle: Vec<_> = self
+            .lock()
+            .values()
+            .filter(|session| {
+                let cutoff = if session.is_suspended() {
+                    suspended_cutoff
+                } else {
+                    idle_cutoff
+                };
+                session.last_used_at_ms.load(Ordering::SeqCst) < cutoff
+            })
+            .cloned()
+            .collect();
+        for session in idle {
+            let Ok(mut runtime) = session.runtime.try_lock() else {
+                continue;
+            };
+            if self.checkpoint
+                && !session.is_suspended()
+                && let Some(active) = runtime.as_mut()
+            {
+                match active.suspend().await {
+                    Ok(()) => {
+                        session.suspended.store(true, Ordering::SeqCst);
+                        session.touch();
+                        tracing::info!(session_id = %session.id, tenant_id = %session.tenant_id, "suspended idle session");
+                        continue;
+                    }
+                    Err(err) => {
+                        tracing::warn!(session_id = %session.id, error = %format!("{err:#}"), "failed to suspend idle session")
+                    }
+                }
+            }
+            self.lock().remove(&session.id);
+            if let Some(mut runtime) = runtime.take() {
+                tracing::info!(session_id = %session.id, tenant_id = %session.tenant_id, "closing idle session");
+                runtime.close().await;
+            }
+        }
+    }
+
+    fn admit(
+        &self,
+        sessions: &HashMap<Uuid, Arc<Session>>,
+        tenant_id: &str,
+    ) -> Result<(), EngineError> {
+        if self.active(sessions) >= self.max {
+            return Err(EngineError::Conflict(
+                "no capacity for more sessions".to_string(),
+            ));
+        }
+        let owned = sessions
+            .values()
+            .filter(|session| session.tenant_id == tenant_id)
+            .count();
+        if owned >= self.max_per_tenant {
+            return Err(EngineError::Conflict(format!(
+                "tenant already has {owned} open sessions; close one first"
+            )));
+        }
+        Ok(())
+    }
+
+    fn active(&self, sessions: &HashMap<Uuid, Arc<Session>>) -> usize {
+        sessions
+            .values()
+            .filter(|session| !session.is_suspended())
+            .count()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<Uuid, Arc<Session>>> {
+        self.sessions.lock().expect("sessions poisoned")
+    }
+}
+
+pub fn spawn_session_reaper(sessions: Arc<SessionManager>) {
+    let interval =
+        (sessions.idle_timeout / 4).clamp(Duration::from_secs(1), Duration::from_secs(30));
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            sessions.close_idle().await;
+        }
+    });
+}
+
+fn runtime_error(err: anyhow::Error) -> EngineError {
+    if is_infrastructure_error(&err) {
+        EngineError::Internal(format!("{err:#}"))
+    } else {
+        EngineError::InvalidRequest(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+    use async_trait::async_trait;
+    use uuid::Uuid;
+
+    use super::SessionManager;
+    use crate::engine::{
+        config::{EngineConfig, SandboxBackendKind},
+        models::{EvalResult, ExecutionLimits, Language, SessionVariable},
+        sandbox::{RunSpec, SandboxBackend, SandboxResult, SandboxSession, SessionSpec},
+    };
+
+    // Counts evaluations; suspending keeps the count, as a checkpoint would.
+    struct CountingSession {
+        evals: u32,
+        running: bool,
+    }
+
+    #[async_trait]
+    impl SandboxSession for CountingSession {
+        async fn eval(&mut self, _: &str, _: Duration) -> anyhow::Result<Option<EvalResult>> {
+            anyhow::ensure!(self.running, "runtime is stopped");
+            self.evals += 1;
+            Ok(Some(EvalResult {
+                value: Some(self.evals.to_string()),
+                ..Default::default()
+            }))
+        }
+
+        async fn inspect(&mut self, _: Duration) -> anyhow::Result<Option<Vec<SessionVariable>>> {
+            Ok(Some(Vec::new()))
+        }
+
+        async fn close(&mut self) {}
+
+        async fn suspend(&mut self) -> anyhow::Result<()> {
+            self.running = false;
+            Ok(())
+        }
+
+        async fn resume(&mut self) -> anyhow::Result<()> {
+            self.running = true;
+            Ok(())
+        }
+    }
+
+    struct CountingBackend;
+
+    #[async_trait]
+    impl SandboxBackend for CountingBackend {
+        fn name(&self) -> &'static str {
+            "counting"
+        }
+
+        async fn execute(&self, _: RunSpec) -> anyhow::Result<SandboxResult> {
+            anyhow::bail!("not used")
+        }
+
+        async fn open_session(&self, _: SessionSpec) -> anyhow::Result<Box<dyn SandboxSession>> {
+            Ok(Box::new(CountingSession {
+                evals: 0,
+                running: true,
+            }))
+        }
+    }
+
+    fn spec() -> SessionSpec {
+        SessionSpec {
+            id: Uuid::new_v4(),
+            language: Language::Python,
+            limits: ExecutionLimits {
+                cpu_cores: 1.0,
+                memory_mb: 256,
+                timeout_ms: 1000,
+                max_processes: 8,
+                max_file_size_bytes: 1024,
+                max_output_bytes: 1024,
+            },
+            env: BTreeMap::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn suspended_sessions_free_capacity_and_resume_on_eval() {
+        let mut config = EngineConfig::from_env();
+        config.session_max = 1;
+        config.session_max_per_tenant = 2;
+        let sessions = SessionManager::new(&config);
+        let backend: Arc<dyn SandboxBackend> = Arc::new(CountingBackend);
+        let open = || sessions.open("t", SandboxBackendKind::Process, backend.clone(), spec());
+
+        let first = open().await.unwrap();
+        assert_eq!(sessions.eval(&first, "").await.value.as_deref(), Some("1"));
+        assert!(open().await.is_err());
+        assert!(sessions.suspend(&first).await.unwrap().suspended);
+        let second = open().await.unwrap();
+
+        let blocked = sessions.eval(&first, "").await;
+        assert!(blocked.error.unwrap().contains("no capacity"));
+        assert!(!blocked.session_closed);
+
+        sessions.close(&second).await;
+        let resumed = sessions.eval(&first, "").await;
+        assert_eq!(resumed.value.as_deref(), Some("2"));
+        assert!(!first.info().suspended);
+    }
+}
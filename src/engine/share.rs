@@ -0,0 +1,94 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::engine::{api::constant_time_eq, models::ExecutionRecord};
+
+// Shared links name the execution, when they expire and whether they are
+// redacted; the signature binds those to the owning tenant under
+// SHARE_LINK_KEY, so rotating the key revokes every link.
+pub fn sign(key: &str, id: Uuid, tenant_id: &str, expires_ms: u64, redact: bool) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("hmac takes any key");
+    mac.update(format!("{id}\n{tenant_id}\n{expires_ms}\n{redact}").as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+pub fn verify(
+    key: &str,
+    record: &ExecutionRecord,
+    expires_ms: u64,
+    redact: bool,
+    signature: &str,
+    now_ms: u64,
+) -> bool {
+    let expected = sign(key, record.id, &record.tenant_id, expires_ms, redact);
+    constant_time_eq(expected.as_bytes(), signature.as_bytes()) && now_ms < expires_ms
+}
+
+// What a redacted link leaves out: the run's inputs other than the code, and
+// anything that identifies the tenant's setup.
+pub fn redact(record: &mut ExecutionRecord) {
+    let request = &mut record.request;
+    request.stdin.clear();
+    request.args.clear();
+    request.env.clear();
+    request.secrets.clear();
+    request.metadata.clear();
+    request.egress.clear();
+    request.files.clear();
+    request.git = None;
+    request.archive = None;
+    request.dependencies = None;
+    request.filename = None;
+    for case in &mut request.test_cases {
+        case.stdin.clear();
+    }
+    record.tenant_id.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{redact, sign, verify};
+    use crate::engine::models::ExecutionRecord;
+
+    #[test]
+    fn verifies_only_unexpired_links_of_the_signing_tenant() {
+        let mut record: ExecutionRecord = serde_json::from_value(serde_json::json!({
+            "id": uuid::Uuid::new_v4(),
+            "tenant_id": "acme",
+            "status": "succeeded",
+            "request": {
+                "language": "python",
+                "code": "print(input())",
+                "stdin": "hunter2",
+                "env": { "TOKEN": "x" },
+            },
+            "limits": {
+                "cpu_cores": 1.0,
+                "memory_mb": 256,
+                "timeout_ms": 1000,
+                "max_processes": 8,
+                "max_file_size_bytes": 1024,
+                "max_output_bytes": 1024,
+            },
+            "output": null,
+            "error": null,
+            "created_at_ms": 1,
+            "started_at_ms": null,
+            "finished_at_ms": null,
+        }))
+        .unwrap();
+        let signature = sign("key", record.id, "acme", 2_000, true);
+        assert!(verify("key", &record, 2_000, true, &signature, 1_000));
+        assert!(!verify("key", &record, 2_000, true, &signature, 2_000));
+        assert!(!verify("key", &record, 2_000, false, &signature, 1_000));
+        assert!(!verify("key", &record, 3_000, true, &signature, 1_000));
+        assert!(!verify("other", &record, 2_000, true, &signature, 1_000));
+        record.tenant_id = "beta".into();
+        assert!(!verify("key", &record, 2_000, true, &signature, 1_000));
+
+        redact(&mut record);
+        assert!(record.request.stdin.is_empty() && record.request.env.is_empty());
+        assert_eq!(record.request.code, "print(input())");
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    hash::{DefaultHasher, Hasher},
+    path::{Path, PathBuf},
+};
+
+use axum::extract::multipart::Field;
+use tokio::io::AsyncWriteExt;
+
+use crate::engine::{config::EngineConfig, error::EngineError};
+
+// A stdin payload too large for the JSON body, spooled to disk as it
+// arrives so it never sits in memory or in the queue. The sandbox streams
+// the file to the program, and the worker removes it once the execution
+// finishes.
+#[derive(Debug)]
+pub struct StdinUpload {
+    pub path: PathBuf,
+    pub bytes: u64,
+    // Of the content, for the idempotency fingerprint.
+    pub hash: u64,
+}
+
+pub async fn spool(
+    config: &EngineConfig,
+    mut field: Field<'_>,
+) -> Result<StdinUpload, EngineError> {
+    let internal = |err: std::io::Error| EngineError::Internal(format!("stdin upload: {err}"));
+    tokio::fs::create_dir_all(&config.stdin_upload_dir)
+        .await
+        .map_err(internal)?;
+    let path = config
+        .stdin_upload_dir
+        .join(uuid::Uuid::new_v4().simple().to_string());
+    let mut file = tokio::fs::File::create(&path).await.map_err(internal)?;
+    let mut hasher = DefaultHasher::new();
+    let mut bytes = 0u64;
+    let written = async {
+        while let Some(chunk) = field
+            .chunk()
+            .await
+            .map_err(|err| EngineError::InvalidRequest(err.to_string()))?
+        {
+            bytes += chunk.len() as u64;
+            if bytes > config.stdin_upload_max_bytes {
+                return Err(EngineError::InvalidRequest(format!(
+                    "stdin upload too large; max is {} bytes",
+                    config.stdin_upload_max_bytes
+                )));
+            }
+            hasher.write(&chunk);
+            file.write_all(&chunk).await.map_err(internal)?;
+        }
+        file.flush().await.map_err(internal)
+    }
+    .await;
+    if let Err(err) = written {
+        discard(&path).await;
+        return Err(err);
+    }
+    Ok(StdinUpload {
+        path,
+        bytes,
+        hash: hasher.finish(),
+    })
+}
+
+pub async fn discard(path: &Path) {
+    if let Err(err) = tokio::fs::remove_file(path).await
+        && err.kind() != std::io::ErrorKind::NotFound
+    {
+        tracing::warn!(path = %path.display(), error = %err, "failed to remove stdin upload");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        extract::{FromRequest, Multipart},
+        http::Request,
+    };
+
+    use super::spool;
+    use crate::engine::{config::EngineConfig, error::EngineError};
+
+    async fn multipart(content: &str) -> Multipart {
+        let body = format!(
+            "--b\r\nContent-Disposition: form-data; name=\"stdin\"\r\n\r\n{content}\r\n--b--\r\n"
+        );
+        let request = Request::builder()
+            .header("content-type", "multipart/form-data; boundary=b")
+            .body(Body::from(body))
+            .unwrap();
+        Multipart::from_request(request, &()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn spools_to_disk_within_the_limit() {
+        let mut config = EngineConfig::from_env();
+        config.stdin_upload_dir =
+            std::env::temp_dir().join(format!("stdin-{}", uuid::Uuid::new_v4()));
+        config.stdin_upload_max_bytes = 8;
+
+        let mut form = multipart("12345678").await;
+        let upload = spool(&config, form.next_field().await.unwrap().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(upload.bytes, 8);
+        assert_eq!(std::fs::read(&upload.path).unwrap(), b"12345678");
+        let mut form = multipart("12345678").await;
+        let again = spool(&config, form.next_field().await.unwrap().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(again.hash, upload.hash);
+        assert_ne!(again.path, upload.path);
+
+        let mut form = multipart("123456789").await;
+        let err = spool(&config, form.next_field().await.unwrap().unwrap())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, EngineError::InvalidRequest(_)));
+        // Only the two accepted uploads are left.
+        assert_eq!(
+            std::fs::read_dir(&config.stdin_upload_dir).unwrap().count(),
+            2
+        );
+        let _ = std::fs::remove_dir_all(&config.stdin_upload_dir);
+    }
+}
@@ -1,31 +1,104 @@
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
+use anyhow::Context;
 use dashmap::DashMap;
-use tokio::io::AsyncWriteExt;
-use tokio::sync::Mutex;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter};
+use tokio::sync::{Mutex, broadcast};
 use uuid::Uuid;
 
-use crate::engine::models::{
-    ExecutionEvent, ExecutionOutput, ExecutionRecord, ExecutionRequest, ExecutionStatus,
+use crate::engine::{
+    encryption::RecordCipher,
+    models::{
+        ExecutionEvent, ExecutionOutput, ExecutionRecord, ExecutionRequest, ExecutionStatus,
+        OutputChunk, UsageReport,
+    },
+    object_store::ObjectStore,
 };
 
 #[derive(Clone)]
 pub struct ExecutionStore {
     records: Arc<DashMap<Uuid, ExecutionRecord>>,
+    // Ids by (tenant, metadata key, value), so metadata searches do not
+    // scan every record.
+    metadata_index: Arc<DashMap<(String, String, String), BTreeSet<Uuid>>>,
     persistence_path: Option<PathBuf>,
     write_lock: Arc<Mutex<()>>,
+    events: broadcast::Sender<EventUpdate>,
+    cipher: RecordCipher,
+    objects: Option<Arc<ObjectStore>>,
+    offload_bytes: usize,
+}
+
+// What moves to the object store when a run's output is large.
+#[derive(Serialize, Deserialize)]
+struct OffloadedOutput {
+    stdout: String,
+    stderr: String,
+    combined: Vec<OutputChunk>,
+}
+
+// What a compaction pass left in and took out of the persistence file.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Compaction {
+    pub kept: usize,
+    pub dropped: usize,
+    pub evicted: usize,
+}
+
+// An event appended to a record; `seq` is its index in `record.events`.
+// `remote` events arrived with a record replicated from another node.
+#[derive(Debug, Clone)]
+pub struct EventUpdate {
+    pub id: Uuid,
+    pub seq: usize,
+    pub event: ExecutionEvent,
+    pub remote: bool,
 }
 
 impl ExecutionStore {
     pub fn new(persistence_path: Option<PathBuf>) -> Self {
         Self {
             records: Arc::new(DashMap::new()),
+            metadata_index: Arc::new(DashMap::new()),
             persistence_path,
             write_lock: Arc::new(Mutex::new(())),
+            events: broadcast::channel(1024).0,
+            cipher: RecordCipher::disabled(),
+            objects: None,
+            offload_bytes: usize::MAX,
         }
     }
 
+    // Moves output over `offload_bytes` to `objects`, and archives finished
+    // records there instead of the persistence file.
+    pub fn with_object_store(mut self, objects: ObjectStore, offload_bytes: usize) -> Self {
+        self.objects = Some(Arc::new(objects));
+        self.offload_bytes = offload_bytes;
+        self
+    }
+
+    // Seals each line of the persistence file.
+    pub fn with_cipher(mut self, cipher: RecordCipher) -> Self {
+        self.cipher = cipher;
+        self
+    }
+
+    pub fn cipher(&self) -> &RecordCipher {
+        &self.cipher
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<EventUpdate> {
+        self.events.subscribe()
+    }
+
     pub fn insert(&self, record: ExecutionRecord) {
+        self.index(&record);
         self.records.insert(record.id, record);
     }
 
@@ -33,32 +106,237 @@ impl ExecutionStore {
         self.records.get(id).map(|e| e.value().clone())
     }
 
+    // Like `get`, with offloaded output read back, and falling back to the
+    // archived copy of records no longer in memory (e.g. after a restart).
+    pub async fn load(&self, id: &Uuid) -> anyhow::Result<Option<ExecutionRecord>> {
+        let mut record = match self.get(id) {
+            Some(record) => record,
+            None => match self.fetch(&record_key(id)).await? {
+                Some(record) => record,
+                None => return Ok(None),
+            },
+        };
+        if let Some(output) = &mut record.output
+            && let Some(key) = output.offloaded.take()
+        {
+            let offloaded: OffloadedOutput = self
+                .fetch(&key)
+                .await?
+                .with_context(|| format!("offloaded output {key} is missing"))?;
+            output.stdout = offloaded.stdout;
+            output.stderr = offloaded.stderr;
+            output.combined = offloaded.combined;
+        }
+        Ok(Some(record))
+    }
+
     pub fn remove(&self, id: &Uuid) {
-        self.records.remove(id);
+        let Some((_, record)) = self.records.remove(id) else {
+            return;
+        };
+        for (key, value) in &record.request.metadata {
+            let index_key = (record.tenant_id.clone(), key.clone(), value.clone());
+            if let Some(mut ids) = self.metadata_index.get_mut(&index_key) {
+                ids.remove(id);
+            }
+            self.metadata_index
+                .remove_if(&index_key, |_, ids| ids.is_empty());
+        }
+    }
+
+    fn index(&self, record: &ExecutionRecord) {
+        for (key, value) in &record.request.metadata {
+            self.metadata_index
+                .entry((record.tenant_id.clone(), key.clone(), value.clone()))
+                .or_default()
+                .insert(record.id);
+        }
+    }
+
+    // Returns false when the execution was cancelled or has already finished.
+    pub fn mark_running(&self, id: Uuid) -> bool {
+        let Some(mut entry) = self.records.get_mut(&id) else {
+            return false;
+        };
+        // Running when the queue redelivered it after its lease ran out.
+        if !matches!(
+            entry.status,
+            ExecutionStatus::Queued | ExecutionStatus::Running
+        ) {
+            return false;
+        }
+        let now = now_ms();
+        entry.status = ExecutionStatus::Running;
+        entry.started_at_ms = Some(now);
+        self.push_event(&mut entry, now, "running", "worker started execution");
+        true
     }
 
-    pub fn mark_running(&self, id: Uuid) {
+    // Returns a running execution to the queue for another attempt.
+    pub fn mark_retrying(&self, id: Uuid, message: impl Into<String>) {
         if let Some(mut entry) = self.records.get_mut(&id) {
-            let now = now_ms();
-            entry.status = ExecutionStatus::Running;
-            entry.started_at_ms = Some(now);
-            entry.events.push(ExecutionEvent {
-                ts_ms: now,
-                stage: "running".to_string(),
-                message: "worker started execution".to_string(),
-            });
+            entry.status = ExecutionStatus::Queued;
+            entry.started_at_ms = None;
+            self.push_event(&mut entry, now_ms(), "retrying", message);
+        }
+    }
+
+    // Only queued executions can be cancelled; the job stays in the queue and
+    // is dropped when a worker picks it up.
+    pub async fn cancel(&self, id: Uuid) -> bool {
+        let cancelled = self.records.get_mut(&id).is_some_and(|mut entry| {
+            let queued = matches!(entry.status, ExecutionStatus::Queued);
+            if queued {
+                entry.status = ExecutionStatus::Cancelled;
+            }
+            queued
+        });
+        if cancelled {
+            self.mark_finished(
+                id,
+                ExecutionStatus::Cancelled,
+                None,
+                Some("cancelled before it started".to_string()),
+            )
+            .await;
+        }
+        cancelled
+    }
+
+    // Newest first. Records must carry every `metadata` pair given.
+    pub fn list_for_tenant(
+        &self,
+        tenant_id: &str,
+        status: Option<&ExecutionStatus>,
+        metadata: &BTreeMap<String, String>,
+    ) -> Vec<ExecutionRecord> {
+        let matching = |record: &ExecutionRecord| {
+            record.tenant_id == tenant_id && status.is_none_or(|status| &record.status == status)
+        };
+        let ids = metadata
+            .iter()
+            .map(|(key, value)| {
+                self.metadata_index
+                    .get(&(tenant_id.to_string(), key.clone(), value.clone()))
+                    .map(|ids| ids.clone())
+                    .unwrap_or_default()
+            })
+            .reduce(|found, ids| found.intersection(&ids).copied().collect());
+        let mut records: Vec<_> = match ids {
+            Some(ids) => ids
+                .iter()
+                .filter_map(|id| self.get(id))
+                .filter(|record| matching(record))
+                .collect(),
+            None => self
+                .records
+                .iter()
+                .filter(|entry| matching(entry.value()))
+                .map(|entry| entry.value().clone())
+                .collect(),
+        };
+        records.sort_by_key(|record| std::cmp::Reverse(record.created_at_ms));
+        records
+    }
+
+    pub fn queued_by_tenant(&self) -> BTreeMap<String, usize> {
+        let mut queued = BTreeMap::new();
+        for entry in self.records.iter() {
+            if matches!(entry.status, ExecutionStatus::Queued) {
+                *queued.entry(entry.tenant_id.clone()).or_default() += 1;
+            }
+        }
+        queued
+    }
+
+    pub fn usage_report(&self, tenant_id: &str, from_ms: u64, to_ms: u64) -> UsageReport {
+        let mut report = UsageReport {
+            tenant_id: tenant_id.to_string(),
+            from_ms,
+            to_ms,
+            ..UsageReport::default()
+        };
+        for entry in self.records.iter() {
+            if entry.tenant_id != tenant_id || !(from_ms..to_ms).contains(&entry.created_at_ms) {
+                continue;
+            }
+            report.executions += 1;
+            *report
+                .by_status
+                .entry(entry.status.as_str().to_string())
+                .or_default() += 1;
+            report.cost += entry.cost.unwrap_or(0.0);
+            let Some(output) = &entry.output else {
+                continue;
+            };
+            for (usage, duration_ms) in output.runs() {
+                report.cpu_ms += usage.user_cpu_ms.unwrap_or(0) + usage.system_cpu_ms.unwrap_or(0);
+                report.duration_ms += u64::try_from(duration_ms).unwrap_or(u64::MAX);
+                report.stdout_bytes += usage.stdout_bytes;
+                report.stderr_bytes += usage.stderr_bytes;
+            }
+        }
+        report
+    }
+
+    // Set before `mark_finished`, which persists it with the result.
+    pub fn record_cost(&self, id: Uuid, cost: f64) {
+        if let Some(mut entry) = self.records.get_mut(&id) {
+            entry.cost = Some(cost);
         }
     }
 
     pub fn append_event(&self, id: Uuid, stage: impl Into<String>, message: impl Into<String>) {
         if let Some(mut entry) = self.records.get_mut(&id) {
-            let now = now_ms();
-            entry.events.push(ExecutionEvent {
-                ts_ms: now,
-                stage: stage.into(),
-                message: message.into(),
+            self.push_event(&mut entry, now_ms(), stage, message);
+        }
+    }
+
+    fn push_event(
+        &self,
+        record: &mut ExecutionRecord,
+        ts_ms: u64,
+        stage: impl Into<String>,
+        message: impl Into<String>,
+    ) {
+        let event = ExecutionEvent {
+            ts_ms,
+            stage: stage.into(),
+            message: message.into(),
+        };
+        record.events.push(event.clone());
+        // No subscribers is not an error.
+        let _ = self.events.send(EventUpdate {
+            id: record.id,
+            seq: record.events.len() - 1,
+            event,
+            remote: false,
+        });
+    }
+
+    // Takes another node's copy of a record when it has more events than
+    // ours; the new events go out to local subscribers.
+    pub async fn apply(&self, record: ExecutionRecord) {
+        let (seen, was_finished) = match self.records.get(&record.id) {
+            Some(local) if local.events.len() >= record.events.len() => return,
+            Some(local) => (local.events.len(), local.finished_at_ms.is_some()),
+            None => {
+                self.index(&record);
+                (0, false)
+            }
+        };
+        self.records.insert(record.id, record.clone());
+        for (seq, event) in record.events.iter().enumerate().skip(seen) {
+            let _ = self.events.send(EventUpdate {
+                id: record.id,
+                seq,
+                event: event.clone(),
+                remote: true,
             });
         }
+        if !was_finished && record.finished_at_ms.is_some() {
+            self.persist(&record).await;
+        }
     }
 
     pub async fn mark_finished(
@@ -68,37 +346,199 @@ impl ExecutionStore {
         output: Option<ExecutionOutput>,
         error: Option<String>,
     ) {
+        let persisting = Instant::now();
+        let output = match output {
+            Some(output) => Some(self.offload(id, output).await),
+            None => None,
+        };
         let snapshot = if let Some(mut entry) = self.records.get_mut(&id) {
             let now = now_ms();
             entry.status = status;
             entry.output = output;
             entry.error = error;
             entry.finished_at_ms = Some(now);
-            entry.events.push(ExecutionEvent {
-                ts_ms: now,
-                stage: "finished".to_string(),
-                message: "execution finalized".to_string(),
-            });
+            self.push_event(&mut entry, now, "finished", "execution finalized");
             Some(entry.clone())
         } else {
             None
         };
+        if let Some(record) = snapshot {
+            self.persist(&record).await;
+            if let Some(mut entry) = self.records.get_mut(&id)
+                && let Some(output) = &mut entry.output
+            {
+                output.timings.persist_ms = Some(persisting.elapsed().as_millis() as u64);
+            }
+        }
+    }
 
-        if let (Some(path), Some(record)) = (&self.persistence_path, snapshot) {
-            let _guard = self.write_lock.lock().await;
-            let line = match serde_json::to_string(&record) {
-                Ok(line) => line,
-                Err(_) => return,
-            };
-            let mut options = tokio::fs::OpenOptions::new();
-            options.create(true).append(true);
-            if let Ok(mut file) = options.open(path).await {
-                let _ = file.write_all(line.as_bytes()).await;
-                let _ = file.write_all(b"\n").await;
+    // Keeps large output out of memory, leaving only its key behind. The
+    // output stays inline if the upload fails.
+    async fn offload(&self, id: Uuid, mut output: ExecutionOutput) -> ExecutionOutput {
+        let Some(objects) = &self.objects else {
+            return output;
+        };
+        let size = output.stdout.len()
+            + output.stderr.len()
+            + output
+                .combined
+                .iter()
+                .map(|chunk| chunk.data.len())
+                .sum::<usize>();
+        if output.offloaded.is_some() || size <= self.offload_bytes {
+            return output;
+        }
+        let key = format!("outputs/{id}.json");
+        let offloaded = OffloadedOutput {
+            stdout: std::mem::take(&mut output.stdout),
+            stderr: std::mem::take(&mut output.stderr),
+            combined: std::mem::take(&mut output.combined),
+        };
+        let stored = async {
+            let body = self.cipher.seal(&serde_json::to_string(&offloaded)?)?;
+            objects.put(&key, body.into_bytes()).await
+        }
+        .await;
+        match stored {
+            Ok(()) => output.offloaded = Some(key),
+            Err(err) => {
+                tracing::warn!(execution_id = %id, error = %format!("{err:#}"), "failed to offload output");
+                output.stdout = offloaded.stdout;
+                output.stderr = offloaded.stderr;
+                output.combined = offloaded.combined;
+            }
+        }
+        output
+    }
+
+    async fn fetch<T: DeserializeOwned>(&self, key: &str) -> anyhow::Result<Option<T>> {
+        let Some(objects) = &self.objects else {
+            return Ok(None);
+        };
+        let Some(body) = objects.get(key).await? else {
+            return Ok(None);
+        };
+        let body = self.cipher.open(&String::from_utf8(body)?)?;
+        Ok(Some(serde_json::from_str(&body)?))
+    }
+
+    async fn persist(&self, record: &ExecutionRecord) {
+        if let Some(objects) = &self.objects {
+            let archived = async {
+                let body = self.cipher.seal(&serde_json::to_string(record)?)?;
+                objects
+                    .put(&record_key(&record.id), body.into_bytes())
+                    .await
             }
+            .await;
+            if let Err(err) = archived {
+                tracing::error!(execution_id = %record.id, error = %format!("{err:#}"), "failed to archive record");
+            }
+            return;
+        }
+        let Some(path) = &self.persistence_path else {
+            return;
+        };
+        let _guard = self.write_lock.lock().await;
+        let line = match serde_json::to_string(record) {
+            Ok(line) => line,
+            Err(_) => return,
+        };
+        let line = match self.cipher.seal(&line) {
+            Ok(line) => line,
+            Err(err) => {
+                tracing::error!(execution_id = %record.id, error = %format!("{err:#}"), "failed to seal record");
+                return;
+            }
+        };
+        let mut options = tokio::fs::OpenOptions::new();
+        options.create(true).append(true);
+        if let Ok(mut file) = options.open(path).await {
+            let _ = file.write_all(line.as_bytes()).await;
+            let _ = file.write_all(b"\n").await;
+            // Tokio finishes writes in the background; wait for them so a
+            // compaction taking the lock next sees the whole line.
+            let _ = file.flush().await;
         }
     }
 
+    // Forgets finished records older than `retention`, then rewrites the
+    // persistence file with only the lines still retained. The previous file
+    // is rotated to `<path>.1`, shifting older segments up to
+    // `keep_segments`. Lines that can't be read are kept, since their age is
+    // unknown.
+    pub async fn compact(
+        &self,
+        retention: Option<Duration>,
+        keep_segments: usize,
+    ) -> anyhow::Result<Compaction> {
+        let cutoff =
+            retention.map(|retention| now_ms().saturating_sub(retention.as_millis() as u64));
+        let expired = |record: &ExecutionRecord| {
+            cutoff.is_some_and(|cutoff| record.finished_at_ms.is_some_and(|at| at < cutoff))
+        };
+        let mut compaction = Compaction::default();
+        let stale: Vec<Uuid> = self
+            .records
+            .iter()
+            .filter(|entry| expired(entry.value()))
+            .map(|entry| *entry.key())
+            .collect();
+        for id in &stale {
+            self.remove(id);
+        }
+        compaction.evicted = stale.len();
+
+        let Some(path) = &self.persistence_path else {
+            return Ok(compaction);
+        };
+        // Appends wait until the rewritten file is in place.
+        let _guard = self.write_lock.lock().await;
+        let file = match tokio::fs::File::open(path).await {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(compaction),
+            Err(err) => return Err(err.into()),
+        };
+        let snapshot = segment(path, "tmp");
+        let mut out = BufWriter::new(tokio::fs::File::create(&snapshot).await?);
+        let mut lines = BufReader::new(file).lines();
+        while let Some(line) = lines.next_line().await? {
+            if line.is_empty() {
+                continue;
+            }
+            let record = self
+                .cipher
+                .open(&line)
+                .ok()
+                .and_then(|line| serde_json::from_str::<ExecutionRecord>(&line).ok());
+            if record.as_ref().is_some_and(expired) {
+                compaction.dropped += 1;
+                continue;
+            }
+            out.write_all(line.as_bytes()).await?;
+            out.write_all(b"\n").await?;
+            compaction.kept += 1;
+        }
+        out.flush().await?;
+        out.into_inner().sync_all().await?;
+
+        if keep_segments == 0 {
+            tokio::fs::rename(&snapshot, path).await?;
+            return Ok(compaction);
+        }
+        let _ = tokio::fs::remove_file(segment(path, &keep_segments.to_string())).await;
+        for n in (1..keep_segments).rev() {
+            let _ = tokio::fs::rename(
+                segment(path, &n.to_string()),
+                segment(path, &(n + 1).to_string()),
+            )
+            .await;
+        }
+        tokio::fs::rename(path, segment(path, "1")).await?;
+        tokio::fs::rename(&snapshot, path).await?;
+        Ok(compaction)
+    }
+
     pub fn create_record(
         &self,
         id: Uuid,
@@ -111,6 +551,7 @@ impl ExecutionStore {
             id,
             tenant_id,
             status: ExecutionStatus::Queued,
+            deterministic: request.deterministic,
             request,
             limits,
             output: None,
@@ -123,13 +564,378 @@ impl ExecutionStore {
             created_at_ms: now,
             started_at_ms: None,
             finished_at_ms: None,
+            cost: None,
         }
     }
 }
 
-fn now_ms() -> u64 {
+// Compacts every `interval` (PERSIST_COMPACT_INTERVAL_SECS).
+pub fn spawn_compaction(
+    store: Arc<ExecutionStore>,
+    interval: Duration,
+    retention: Option<Duration>,
+    keep_segments: usize,
+) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            match store.compact(retention, keep_segments).await {
+                Ok(compaction) => tracing::info!(
+                    kept = compaction.kept,
+                    dropped = compaction.dropped,
+                    evicted = compaction.evicted,
+                    "compacted execution records"
+                ),
+                Err(err) => {
+                    tracing::error!(error = %format!("{err:#}"), "failed to compact persistence file")
+                }
+            }
+        }
+    });
+}
+
+// `<path>.<suffix>`, next to the persistence file.
+fn segment(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{suffix}"));
+    PathBuf::from(name)
+}
+
+fn record_key(id: &Uuid) -> String {
+    format!("records/{id}.json")
+}
+
+pub(crate) fn now_ms() -> u64 {
     std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
         .map(|d| d.as_millis() as u64)
         .unwrap_or(0)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use uuid::Uuid;
+
+    use super::{Compaction, ExecutionStore};
+    use crate::engine::models::{ExecutionLimits, ExecutionRequest, ExecutionStatus};
+
+    #[tokio::test]
+    async fn usage_report_sums_test_cases_within_window() {
+        let store = ExecutionStore::new(None);
+        let request: ExecutionRequest =
+            serde_json::from_value(serde_json::json!({ "language": "python", "code": "" }))
+                .unwrap();
+        let limits = ExecutionLimits {
+            cpu_cores: 1.0,
+            memory_mb: 256,
+            timeout_ms: 1000,
+            max_processes: 8,
+            max_file_size_bytes: 1024,
+            max_output_bytes: 1024,
+        };
+        let case = serde_json::json!({
+            "stdin": "", "stdout": "", "stderr": "", "passed": true, "exit_code": 0,
+            "duration_ms": 10,
+            "usage": { "user_cpu_ms": 3, "system_cpu_ms": 1, "stdout_bytes": 5, "stderr_bytes": 0 },
+        });
+        let output = serde_json::from_value(serde_json::json!({
+            "stdout": "", "stderr": "", "exit_code": 0, "duration_ms": 10,
+            "sandbox_backend": "process", "test_results": [case.clone(), case],
+        }))
+        .unwrap();
+
+        let mut ids = Vec::new();
+        for tenant in ["a", "a", "b"] {
+            let id = Uuid::new_v4();
+            store.insert(store.create_record(id, tenant.into(), request.clone(), limits.clone()));
+            ids.push(id);
+        }
+        store.record_cost(ids[0], 1.5);
+        store
+            .mark_finished(ids[0], ExecutionStatus::Succeeded, Some(output), None)
+            .await;
+
+        let report = store.usage_report("a", 0, u64::MAX);
+        assert_eq!(report.executions, 2);
+        assert_eq!(report.by_status["succeeded"], 1);
+        assert_eq!(report.by_status["queued"], 1);
+        assert_eq!(report.cpu_ms, 8);
+        assert_eq!(report.duration_ms, 20);
+        assert_eq!(report.stdout_bytes, 10);
+        assert_eq!(report.cost, 1.5);
+        assert_eq!(store.usage_report("a", 0, 1).executions, 0);
+    }
+
+    #[tokio::test]
+    async fn applies_only_newer_remote_copies() {
+        let store = ExecutionStore::new(None);
+        let request: ExecutionRequest =
+            serde_json::from_value(serde_json::json!({ "language": "python", "code": "" }))
+                .unwrap();
+        let limits = ExecutionLimits {
+            cpu_cores: 1.0,
+            memory_mb: 256,
+            timeout_ms: 1000,
+            max_processes: 8,
+            max_file_size_bytes: 1024,
+            max_output_bytes: 1024,
+        };
+        let id = Uuid::new_v4();
+        let queued = store.create_record(id, "t".into(), request, limits);
+        store.insert(queued.clone());
+
+        let remote = ExecutionStore::new(None);
+        remote.insert(queued.clone());
+        remote.mark_running(id);
+        remote
+            .mark_finished(id, ExecutionStatus::Succeeded, None, None)
+            .await;
+
+        let mut updates = store.subscribe();
+        store.apply(remote.get(&id).unwrap()).await;
+        assert!(matches!(
+            store.get(&id).unwrap().status,
+            ExecutionStatus::Succeeded
+        ));
+        let first = updates.recv().await.unwrap();
+        assert_eq!((first.seq, first.remote), (1, true));
+
+        store.apply(queued).await;
+        assert!(matches!(
+            store.get(&id).unwrap().status,
+            ExecutionStatus::Succeeded
+        ));
+    }
+
+    #[test]
+    fn lists_by_indexed_metadata() {
+        let store = ExecutionStore::new(None);
+        let limits = ExecutionLimits {
+            cpu_cores: 1.0,
+            memory_mb: 256,
+            timeout_ms: 1000,
+            max_processes: 8,
+            max_file_size_bytes: 1024,
+            max_output_bytes: 1024,
+        };
+        let mut ids = Vec::new();
+        for (tenant, assignment, user) in [("a", "42", "u1"), ("a", "42", "u2"), ("b", "42", "u1")]
+        {
+            let request: ExecutionRequest = serde_json::from_value(serde_json::json!({
+                "language": "python", "code": "",
+                "metadata": {"assignment_id": assignment, "user_id": user},
+            }))
+            .unwrap();
+            let id = Uuid::new_v4();
+            store.insert(store.create_record(id, tenant.into(), request, limits.clone()));
+            ids.push(id);
+        }
+        let filter = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        };
+        let found = store.list_for_tenant("a", None, &filter(&[("assignment_id", "42")]));
+        assert_eq!(found.len(), 2);
+        let found = store.list_for_tenant(
+            "a",
+            None,
+            &filter(&[("assignment_id", "42"), ("user_id", "u1")]),
+        );
+        assert_eq!(found.iter().map(|r| r.id).collect::<Vec<_>>(), [ids[0]]);
+        assert!(
+            store
+                .list_for_tenant(
+                    "a",
+                    Some(&ExecutionStatus::Running),
+                    &filter(&[("user_id", "u1")])
+                )
+                .is_empty()
+        );
+
+        store.remove(&ids[0]);
+        assert!(
+            store
+                .list_for_tenant("a", None, &filter(&[("user_id", "u1")]))
+                .is_empty()
+        );
+        assert_eq!(store.list_for_tenant("a", None, &filter(&[])).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn compacts_past_retention_and_rotates_segments() {
+        let dir = std::env::temp_dir().join(format!("store-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("results.jsonl");
+        let store = ExecutionStore::new(Some(path.clone()));
+        let request: ExecutionRequest =
+            serde_json::from_value(serde_json::json!({ "language": "python", "code": "" }))
+                .unwrap();
+        let limits: ExecutionLimits = serde_json::from_value(serde_json::json!({
+            "cpu_cores": 1.0, "memory_mb": 256, "timeout_ms": 1000, "max_processes": 8,
+            "max_file_size_bytes": 1024, "max_output_bytes": 1024,
+        }))
+        .unwrap();
+        let (old, recent) = (Uuid::new_v4(), Uuid::new_v4());
+        for id in [old, recent] {
+            store.insert(store.create_record(id, "a".into(), request.clone(), limits.clone()));
+            store
+                .mark_finished(id, ExecutionStatus::Succeeded, None, None)
+                .await;
+        }
+        let mut record = store.get(&old).unwrap();
+        record.finished_at_ms = Some(1);
+        store.insert(record.clone());
+        // The old record's line as an earlier run would have written it.
+        std::fs::write(
+            &path,
+            format!(
+                "{}\n{}not json\n",
+                serde_json::to_string(&record).unwrap(),
+                std::fs::read_to_string(&path)
+                    .unwrap()
+                    .lines()
+                    .nth(1)
+                    .map(|line| format!("{line}\n"))
+                    .unwrap()
+            ),
+        )
+        .unwrap();
+
+        let compaction = store
+            .compact(Some(Duration::from_secs(3600)), 2)
+            .await
+            .unwrap();
+        assert_eq!(
+            compaction,
+            Compaction {
+                kept: 2,
+                dropped: 1,
+                evicted: 1
+            }
+        );
+        assert!(store.get(&old).is_none());
+        assert!(store.get(&recent).is_some());
+        let compacted = std::fs::read_to_string(&path).unwrap();
+        assert!(compacted.contains(&recent.to_string()) && !compacted.contains(&old.to_string()));
+        assert_eq!(
+            std::fs::read_to_string(dir.join("results.jsonl.1"))
+                .unwrap()
+                .lines()
+                .count(),
+            3
+        );
+
+        store.compact(None, 2).await.unwrap();
+        store.compact(None, 2).await.unwrap();
+        assert!(dir.join("results.jsonl.2").exists());
+        assert!(!dir.join("results.jsonl.3").exists());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn offloads_large_output_and_archives_records() {
+        use std::sync::Arc;
+
+        use axum::{
+            Router,
+            body::Bytes,
+            extract::Path,
+            http::{HeaderMap, Method, StatusCode},
+        };
+        use dashmap::DashMap;
+
+        use crate::engine::{config::EngineConfig, object_store::ObjectStore};
+
+        // Just enough of S3 to hold objects for signed requests.
+        let objects = Arc::new(DashMap::<String, Vec<u8>>::new());
+        let bucket = objects.clone();
+        let app = Router::new().route(
+            "/{*path}",
+            axum::routing::any(
+                move |method: Method, Path(path): Path<String>, headers: HeaderMap, body: Bytes| {
+                    let bucket = bucket.clone();
+                    async move {
+                        let signed = headers["authorization"]
+                            .to_str()
+                            .unwrap()
+                            .starts_with("AWS4-HMAC-SHA256 Credential=test/");
+                        if !signed {
+                            return (StatusCode::FORBIDDEN, Vec::new());
+                        }
+                        if method == Method::PUT {
+                            bucket.insert(path, body.to_vec());
+                            return (StatusCode::OK, Vec::new());
+                        }
+                        match bucket.get(&path) {
+                            Some(object) => (StatusCode::OK, object.clone()),
+                            None => (StatusCode::NOT_FOUND, Vec::new()),
+                        }
+                    }
+                },
+            ),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let mut config = EngineConfig::from_env();
+        config.object_store_bucket = Some("results".into());
+        config.object_store_endpoint = Some(format!("http://{addr}"));
+        config.object_store_access_key_id = Some("test".into());
+        config.object_store_secret_access_key = Some("secret".into());
+        let store = ExecutionStore::new(None)
+            .with_object_store(ObjectStore::from_config(&config).unwrap().unwrap(), 16);
+
+        let request: ExecutionRequest =
+            serde_json::from_value(serde_json::json!({ "language": "python", "code": "" }))
+                .unwrap();
+        let limits: ExecutionLimits = serde_json::from_value(serde_json::json!({
+            "cpu_cores": 1.0, "memory_mb": 256, "timeout_ms": 1000, "max_processes": 8,
+            "max_file_size_bytes": 1024, "max_output_bytes": 1024,
+        }))
+        .unwrap();
+        let output = |stdout: &str| {
+            serde_json::from_value(serde_json::json!({
+                "stdout": stdout, "stderr": "", "exit_code": 0, "duration_ms": 10,
+                "sandbox_backend": "process",
+            }))
+            .unwrap()
+        };
+        let (small, large) = (Uuid::new_v4(), Uuid::new_v4());
+        for (id, stdout) in [(small, "ok\n"), (large, "a fairly long line of output\n")] {
+            store.insert(store.create_record(id, "a".into(), request.clone(), limits.clone()));
+            store
+                .mark_finished(id, ExecutionStatus::Succeeded, Some(output(stdout)), None)
+                .await;
+        }
+
+        let inline = store.get(&small).unwrap().output.unwrap();
+        assert_eq!((inline.stdout.as_str(), inline.offloaded), ("ok\n", None));
+        let pointer = store.get(&large).unwrap().output.unwrap();
+        assert_eq!(pointer.stdout, "");
+        assert_eq!(
+            pointer.offloaded.as_deref(),
+            Some(&*format!("outputs/{large}.json"))
+        );
+        let loaded = store.load(&large).await.unwrap().unwrap().output.unwrap();
+        assert_eq!(loaded.stdout, "a fairly long line of output\n");
+        assert_eq!(loaded.offloaded, None);
+
+        // Gone from memory, the record is read back from its archived copy.
+        store.remove(&large);
+        let archived = store.load(&large).await.unwrap().unwrap();
+        assert_eq!(archived.tenant_id, "a");
+        assert_eq!(
+            archived.output.unwrap().stdout,
+            "a fairly long line of output\n"
+        );
+        assert!(store.load(&Uuid::new_v4()).await.unwrap().is_none());
+        assert!(objects.contains_key(&format!("results/records/{small}.json")));
+    }
+}
@@ -0,0 +1,175 @@
+use std::{collections::BTreeMap, path::PathBuf, sync::Mutex};
+
+use anyhow::Context;
+
+use crate::engine::{
+    models::{ExecutionTemplate, TemplateInfo},
+    store::now_ms,
+};
+
+// Named execution templates per tenant. With a path they are loaded at
+// startup and the whole set is rewritten on every change.
+pub struct TemplateStore {
+    templates: Mutex<BTreeMap<(String, String), TemplateInfo>>,
+    path: Option<PathBuf>,
+    write_lock: tokio::sync::Mutex<()>,
+}
+
+impl TemplateStore {
+    // Kept in memory only.
+    pub fn new() -> Self {
+        Self {
+            templates: Mutex::new(BTreeMap::new()),
+            path: None,
+            write_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    pub fn load(path: Option<PathBuf>) -> anyhow::Result<Self> {
+        let mut templates = BTreeMap::new();
+        if let Some(path) = &path
+            && path.exists()
+        {
+            let saved = std::fs::read(path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            let saved: Vec<TemplateInfo> = serde_json::from_slice(&saved)
+                .with_context(|| format!("failed to parse {}", path.display()))?;
+            for info in saved {
+                templates.insert((info.tenant_id.clone(), info.name.clone()), info);
+            }
+        }
+        Ok(Self {
+            templates: Mutex::new(templates),
+            path,
+            ..Self::new()
+        })
+    }
+
+    pub fn get(&self, tenant_id: &str, name: &str) -> Option<TemplateInfo> {
+        self.lock()
+            .get(&(tenant_id.to_string(), name.to_string()))
+            .cloned()
+    }
+
+    pub fn list(&self, tenant_id: &str) -> Vec<TemplateInfo> {
+        self.lock()
+            .values()
+            .filter(|info| info.tenant_id == tenant_id)
+            .cloned()
+            .collect()
+    }
+
+    pub fn count(&self, tenant_id: &str) -> usize {
+        self.lock()
+            .keys()
+            .filter(|(tenant, _)| tenant == tenant_id)
+            .count()
+    }
+
+    pub async fn put(
+        &self,
+        tenant_id: &str,
+        name: &str,
+        template: ExecutionTemplate,
+    ) -> anyhow::Result<TemplateInfo> {
+        let info = TemplateInfo {
+            name: name.to_string(),
+            tenant_id: tenant_id.to_string(),
+            updated_at_ms: now_ms(),
+            template,
+        };
+        self.lock()
+            .insert((tenant_id.to_string(), name.to_string()), info.clone());
+        self.save().await?;
+        Ok(info)
+    }
+
+    pub async fn remove(&self, tenant_id: &str, name: &str) -> anyhow::Result<bool> {
+        let removed = self
+            .lock()
+            .remove(&(tenant_id.to_string(), name.to_string()))
+            .is_some();
+        if removed {
+            self.save().await?;
+        }
+        Ok(removed)
+    }
+
+    // Written to a temporary file first so a crash never leaves half a file.
+    async fn save(&self) -> anyhow::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let _guard = self.write_lock.lock().await;
+        let snapshot: Vec<TemplateInfo> = self.lock().values().cloned().collect();
+        let body = serde_json::to_vec_pretty(&snapshot)?;
+        let temporary = path.with_extension("tmp");
+        tokio::fs::write(&temporary, body)
+            .await
+            .with_context(|| format!("failed to write {}", temporary.display()))?;
+        tokio::fs::rename(&temporary, path)
+            .await
+            .with_context(|| format!("failed to replace {}", path.display()))?;
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<(String, String), TemplateInfo>> {
+        self.templates.lock().expect("templates poisoned")
+    }
+}
+
+impl Default for TemplateStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::TemplateStore;
+    use crate::engine::models::{ExecutionTemplate, TemplateExecutionRequest};
+
+    #[tokio::test]
+    async fn persists_templates_per_tenant() {
+        let path = std::env::temp_dir().join(format!("templates-{}.json", uuid::Uuid::new_v4()));
+        let template: ExecutionTemplate = serde_json::from_value(serde_json::json!({
+            "language": "python",
+            "args": ["--fast"],
+            "files": [{ "name": "data.txt", "content": "1 2" }],
+            "test_cases": [{ "stdin": "1", "expected_stdout": "2" }],
+        }))
+        .unwrap();
+        let store = TemplateStore::load(Some(path.clone())).unwrap();
+        store.put("a", "sum", template.clone()).await.unwrap();
+        store.put("b", "other", template).await.unwrap();
+
+        let reloaded = TemplateStore::load(Some(path.clone())).unwrap();
+        assert_eq!(reloaded.count("a"), 1);
+        assert!(reloaded.get("b", "sum").is_none());
+        let info = reloaded.get("a", "sum").unwrap();
+        let request = info.template.instantiate(TemplateExecutionRequest {
+            code: "print(2)".into(),
+            stdin: String::new(),
+            args: None,
+            files: serde_json::from_value(serde_json::json!([
+                { "name": "extra.txt", "content": "3" }
+            ]))
+            .unwrap(),
+            metadata: BTreeMap::new(),
+            priority: None,
+        });
+        assert_eq!(request.args, ["--fast"]);
+        assert_eq!(request.files.len(), 2);
+        assert_eq!(request.test_cases.len(), 1);
+
+        assert!(reloaded.remove("a", "sum").await.unwrap());
+        assert!(!reloaded.remove("a", "sum").await.unwrap());
+        assert_eq!(
+            TemplateStore::load(Some(path.clone())).unwrap().count("a"),
+            0
+        );
+        let _ = std::fs::remove_file(path);
+    }
+}
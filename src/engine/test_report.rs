@@ -0,0 +1,334 @@
+use std::{collections::BTreeMap, sync::LazyLock};
+
+use regex::Regex;
+
+use crate::engine::models::{TestReport, TestReportCase, TestReportStatus};
+
+const MAX_CASES: usize = 1000;
+const MAX_MESSAGE_BYTES: usize = 4 * 1024;
+
+// `test path::name ... ok|FAILED|ignored` from libtest (cargo test).
+static LIBTEST_RESULT: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^test (?P<name>\S+) \.\.\. (?P<status>ok|FAILED|ignored)").unwrap()
+});
+static LIBTEST_FAILURE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^---- (?P<name>\S+) stdout ----$").unwrap());
+static XML_ATTRIBUTE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?P<key>[\w:-]+)\s*=\s*"(?P<value>[^"]*)""#).unwrap());
+
+// A `test` execution's per-test results, read from whatever the framework
+// wrote to stdout: JUnit XML (pytest, deno, jest-junit), jest `--json`,
+// `go test -json` or libtest's text. None when nothing was recognised.
+pub fn parse(stdout: &str) -> Option<TestReport> {
+    let trimmed = stdout.trim_start();
+    let (framework, mut cases) = if trimmed.starts_with('{')
+        && let Some(cases) = parse_jest(trimmed)
+    {
+        ("jest", cases)
+    } else if stdout.contains("<testcase") {
+        ("junit", parse_junit(stdout))
+    } else if trimmed.starts_with('{')
+        && let Some(cases) = parse_go(stdout)
+    {
+        ("go", cases)
+    } else {
+        ("libtest", parse_libtest(stdout))
+    };
+    if cases.is_empty() {
+        return None;
+    }
+    cases.truncate(MAX_CASES);
+    for case in &mut cases {
+        if let Some(message) = &mut case.message {
+            truncate(message, MAX_MESSAGE_BYTES);
+        }
+    }
+    let count = |status| cases.iter().filter(|case| case.status == status).count();
+    Some(TestReport {
+        framework: framework.to_string(),
+        passed: count(TestReportStatus::Passed),
+        failed: count(TestReportStatus::Failed) + count(TestReportStatus::Error),
+        skipped: count(TestReportStatus::Skipped),
+        cases,
+    })
+}
+
+fn parse_junit(xml: &str) -> Vec<TestReportCase> {
+    let mut cases = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find("<testcase") {
+        rest = &rest[start + "<testcase".len()..];
+        let Some(tag_end) = rest.find('>') else {
+            break;
+        };
+        let tag = &rest[..tag_end];
+        let attributes = attributes(tag);
+        let body = if tag.ends_with('/') {
+            ""
+        } else {
+            let end = rest.find("</testcase>").unwrap_or(rest.len());
+            &rest[tag_end + 1..end]
+        };
+        let (status, element) = if body.contains("<failure") {
+            (TestReportStatus::Failed, Some("failure"))
+        } else if body.contains("<error") {
+            (TestReportStatus::Error, Some("error"))
+        } else if body.contains("<skipped") {
+            (TestReportStatus::Skipped, Some("skipped"))
+        } else {
+            (TestReportStatus::Passed, None)
+        };
+        cases.push(TestReportCase {
+            name: attributes.get("name").cloned().unwrap_or_default(),
+            suite: attributes.get("classname").cloned(),
+            status,
+            duration_ms: attributes
+                .get("time")
+                .and_then(|time| time.parse::<f64>().ok())
+                .map(|seconds| (seconds * 1000.0).round() as u64),
+            message: element.and_then(|element| junit_message(body, element)),
+        });
+    }
+    cases
+}
+
+// The element's `message` attribute, else its text.
+fn junit_message(body: &str, element: &str) -> Option<String> {
+    let start = body.find(&format!("<{element}"))?;
+    let rest = &body[start + element.len() + 1..];
+    let tag_end = rest.find('>')?;
+    let tag = &rest[..tag_end];
+    if let Some(message) = attributes(tag).remove("message") {
+        return Some(message);
+    }
+    if tag.ends_with('/') {
+        return None;
+    }
+    let text = &rest[tag_end + 1..];
+    let text = &text[..text.find(&format!("</{element}>")).unwrap_or(text.len())];
+    let text = text
+        .trim()
+        .trim_start_matches("<![CDATA[")
+        .trim_end_matches("]]>");
+    (!text.is_empty()).then(|| unescape(text))
+}
+
+fn attributes(tag: &str) -> BTreeMap<String, String> {
+    XML_ATTRIBUTE
+        .captures_iter(tag)
+        .map(|caps| (caps["key"].to_string(), unescape(&caps["value"])))
+        .collect()
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&#10;", "\n")
+        .replace("&amp;", "&")
+}
+
+fn parse_jest(json: &str) -> Option<Vec<TestReportCase>> {
+    let report: serde_json::Value = serde_json::from_str(json).ok()?;
+    let suites = report.get("testResults")?.as_array()?;
+    let mut cases = Vec::new();
+    for suite in suites {
+        let file = suite.get("name").and_then(|name| name.as_str());
+        for assertion in suite
+            .get("assertionResults")
+            .and_then(|results| results.as_array())
+            .into_iter()
+            .flatten()
+        {
+            let status = match assertion.get("status").and_then(|s| s.as_str()) {
+                Some("passed") => TestReportStatus::Passed,
+                Some("failed") => TestReportStatus::Failed,
+                _ => TestReportStatus::Skipped,
+            };
+            let message = assertion
+                .get("failureMessages")
+                .and_then(|messages| messages.as_array())
+                .map(|messages| {
+                    messages
+                        .iter()
+                        .filter_map(|message| message.as_str())
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                })
+                .filter(|message| !message.is_empty());
+            cases.push(TestReportCase {
+                name: assertion
+                    .get("fullName")
+                    .or_else(|| assertion.get("title"))
+                    .and_then(|name| name.as_str())
+                    .unwrap_or_default()
+                    .to_string(),
+                suite: file.map(str::to_string),
+                status,
+                duration_ms: assertion.get("duration").and_then(|d| d.as_u64()),
+                message,
+            });
+        }
+    }
+    Some(cases)
+}
+
+// test2json events, one per line; a test's output lines become the message
+// of a failure.
+fn parse_go(stdout: &str) -> Option<Vec<TestReportCase>> {
+    let mut cases: Vec<TestReportCase> = Vec::new();
+    let mut output: BTreeMap<(String, String), String> = BTreeMap::new();
+    let mut recognised = false;
+    for line in stdout.lines() {
+        let Ok(event) = serde_json::from_str::<serde_json::Value>(line) else {
+            continue;
+        };
+        let (Some(action), Some(test)) = (
+            event.get("Action").and_then(|a| a.as_str()),
+            event.get("Test").and_then(|t| t.as_str()),
+        ) else {
+            recognised |= event.get("Action").is_some();
+            continue;
+        };
+        recognised = true;
+        let package = event
+            .get("Package")
+            .and_then(|p| p.as_str())
+            .unwrap_or_default()
+            .to_string();
+        let key = (package.clone(), test.to_string());
+        let status = match action {
+            "output" => {
+                if let Some(text) = event.get("Output").and_then(|o| o.as_str()) {
+                    output.entry(key).or_default().push_str(text);
+                }
+                continue;
+            }
+            "pass" => TestReportStatus::Passed,
+            "fail" => TestReportStatus::Failed,
+            "skip" => TestReportStatus::Skipped,
+            _ => continue,
+        };
+        let message = (status != TestReportStatus::Passed)
+            .then(|| output.remove(&key))
+            .flatten()
+            .map(|text| text.trim().to_string());
+        cases.push(TestReportCase {
+            name: test.to_string(),
+            suite: (!package.is_empty()).then_some(package),
+            status,
+            duration_ms: event
+                .get("Elapsed")
+                .and_then(|e| e.as_f64())
+                .map(|seconds| (seconds * 1000.0).round() as u64),
+            message,
+        });
+    }
+    recognised.then_some(cases)
+}
+
+fn parse_libtest(stdout: &str) -> Vec<TestReportCase> {
+    let mut cases = Vec::new();
+    let mut failures: BTreeMap<String, String> = BTreeMap::new();
+    let mut current: Option<String> = None;
+    for line in stdout.lines() {
+        if let Some(caps) = LIBTEST_RESULT.captures(line) {
+            cases.push(TestReportCase {
+                name: caps["name"].to_string(),
+                suite: None,
+                status: match &caps["status"] {
+                    "ok" => TestReportStatus::Passed,
+                    "FAILED" => TestReportStatus::Failed,
+                    _ => TestReportStatus::Skipped,
+                },
+                duration_ms: None,
+                message: None,
+            });
+            continue;
+        }
+        if let Some(caps) = LIBTEST_FAILURE.captures(line) {
+            current = Some(caps["name"].to_string());
+            continue;
+        }
+        if line.starts_with("failures:") || line.starts_with("test result:") {
+            current = None;
+        }
+        if let Some(name) = &current {
+            let text = failures.entry(name.clone()).or_default();
+            text.push_str(line);
+            text.push('\n');
+        }
+    }
+    for case in &mut cases {
+        case.message = failures
+            .remove(&case.name)
+            .map(|text| text.trim().to_string());
+    }
+    cases
+}
+
+fn truncate(text: &mut String, max: usize) {
+    if text.len() > max {
+        let mut end = max;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use crate::engine::models::TestReportStatus;
+
+    #[test]
+    fn parses_each_framework_format() {
+        let junit = r#"<?xml version="1.0"?><testsuites><testsuite name="pytest">
+<testcase classname="test_app" name="test_ok" time="0.012" />
+<testcase classname="test_app" name="test_bad" time="0.100"><failure message="assert 1 == 2">trace</failure></testcase>
+<testcase classname="test_app" name="test_skip"><skipped message="later" /></testcase>
+</testsuite></testsuites>"#;
+        let report = parse(junit).unwrap();
+        assert_eq!(report.framework, "junit");
+        assert_eq!((report.passed, report.failed, report.skipped), (1, 1, 1));
+        assert_eq!(report.cases[0].duration_ms, Some(12));
+        assert_eq!(report.cases[1].message.as_deref(), Some("assert 1 == 2"));
+        assert_eq!(report.cases[1].suite.as_deref(), Some("test_app"));
+
+        let jest = r#"{"numTotalTests":2,"testResults":[{"name":"/w/sum.test.js","assertionResults":[
+{"fullName":"sum adds","status":"passed","duration":3,"failureMessages":[]},
+{"fullName":"sum fails","status":"failed","failureMessages":["Expected: 3"]}]}]}"#;
+        let report = parse(jest).unwrap();
+        assert_eq!(report.framework, "jest");
+        assert_eq!(report.cases[1].status, TestReportStatus::Failed);
+        assert_eq!(report.cases[1].message.as_deref(), Some("Expected: 3"));
+
+        let go = concat!(
+            r#"{"Action":"run","Package":"sandbox","Test":"TestAdd"}"#,
+            "\n",
+            r#"{"Action":"output","Package":"sandbox","Test":"TestAdd","Output":"    add_test.go:9: got 3\n"}"#,
+            "\n",
+            r#"{"Action":"fail","Package":"sandbox","Test":"TestAdd","Elapsed":0.01}"#,
+            "\n",
+            r#"{"Action":"fail","Package":"sandbox","Elapsed":0.02}"#,
+            "\n",
+        );
+        let report = parse(go).unwrap();
+        assert_eq!(report.framework, "go");
+        assert_eq!(report.failed, 1);
+        assert_eq!(
+            report.cases[0].message.as_deref(),
+            Some("add_test.go:9: got 3")
+        );
+
+        let libtest = "running 2 tests\ntest tests::adds ... ok\ntest tests::fails ... FAILED\n\nfailures:\n\n---- tests::fails stdout ----\nassertion failed\n\nfailures:\n    tests::fails\n\ntest result: FAILED. 1 passed; 1 failed\n";
+        let report = parse(libtest).unwrap();
+        assert_eq!(report.framework, "libtest");
+        assert_eq!((report.passed, report.failed), (1, 1));
+        assert_eq!(report.cases[1].message.as_deref(), Some("assertion failed"));
+
+        assert!(parse("hello\n").is_none());
+    }
+}
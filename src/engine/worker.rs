@@ -1,144 +1,798 @@
-use std::sync::Arc;
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
 
-use tokio::sync::{Mutex, mpsc::Receiver};
+use anyhow::Context;
+use futures_util::{StreamExt, stream};
+use tokio::sync::Semaphore;
+use tracing::{Instrument, Span};
 // worker pools
 
 use crate::engine::{
+    admin::{PoolSize, WorkerControl},
+    benchmark,
+    capacity::{Capacity, Demand, TenantSlots},
+    config::CostWeights,
+    diagnostics,
+    git::{self, GitLimits},
+    grading,
     metrics::MetricsRegistry,
-    models::{ExecutionStatus, TestCaseResult},
-    sandbox::{RunSpec, SandboxBackend, SandboxResult},
+    models::{
+        BenchmarkReport, ExecutionMode, ExecutionOutput, ExecutionStatus, FileEncoding,
+        ResourceUsage, StageTimings, TestCaseResult, TestScore,
+    },
+    queue::{ASSUMED_RUN_TIME, JobQueue, Scheduler},
+    result_cache::ResultCache,
+    sandbox::{
+        EventSink, LiveStdin, RunSpec, SandboxBackend, SandboxRegistry, SandboxResult,
+        is_infrastructure_error, signal_name,
+    },
+    secrets::Redactor,
+    stdin_upload,
     store::ExecutionStore,
+    test_report,
 };
 
-pub fn spawn_worker_pool(
-    workers: usize,
-    receiver: Arc<Mutex<Receiver<crate::engine::queue::QueuedJob>>>,
-    store: Arc<ExecutionStore>,
-    metrics: Arc<MetricsRegistry>,
-    sandbox: Arc<dyn SandboxBackend>,
-) {
-    for worker_id in 0..workers {
-        let receiver = receiver.clone();
-        let store = store.clone();
-        let metrics = metrics.clone();
-        let sandbox = sandbox.clone();
+// Everything a worker shares with the rest of the engine. `run_slots` is
+// per pool, everything else is shared.
+#[derive(Clone)]
+pub struct WorkerContext {
+    pub pool: String,
+    pub store: Arc<ExecutionStore>,
+    pub metrics: Arc<MetricsRegistry>,
+    pub sandboxes: SandboxRegistry,
+    pub run_slots: Arc<Semaphore>,
+    pub test_case_parallelism: usize,
+    pub tenant_test_case_slots: TenantSlots,
+    pub control: Arc<WorkerControl>,
+    pub scheduler: Scheduler,
+    pub infra_retry_limit: u32,
+    pub result_cache: ResultCache,
+    pub cost_weights: CostWeights,
+    pub git: GitLimits,
+    // Jobs are accepted by separate API nodes, so their records only exist
+    // here once the job is dequeued.
+    pub remote_submissions: bool,
+    pub heartbeat_interval: Duration,
+    // Jobs wait here for their CPU and memory before they start.
+    pub capacity: Option<Capacity>,
+}
+
+// Pool size bounds; the autoscaler only runs when `max > min`. It aims to
+// drain the queue within `target_wait`.
+#[derive(Debug, Clone, Copy)]
+pub struct Scaling {
+    pub min: usize,
+    pub max: usize,
+    pub target_wait: Duration,
+    pub interval: Duration,
+}
+
+// Starts the workers for `context.pool`, taking jobs from that pool's queue.
+pub fn spawn_worker_pool(scaling: Scaling, context: WorkerContext) {
+    let pool = WorkerPool {
+        queue: context.scheduler.queue(&context.pool),
+        size: context.control.pool(&context.pool),
+        context,
+    };
+    pool.size.set_target(scaling.min);
+    for _ in 0..scaling.min {
+        pool.spawn_worker();
+    }
+    if scaling.max > scaling.min {
+        tokio::spawn(autoscale(scaling, pool));
+    }
+}
+
+#[derive(Clone)]
+struct WorkerPool {
+    queue: Arc<dyn JobQueue>,
+    size: Arc<PoolSize>,
+    context: WorkerContext,
+}
+
+impl WorkerPool {
+    // A worker that panics is replaced; its job goes back out once the
+    // lease runs out.
+    fn spawn_worker(&self) {
+        let worker_id = self.context.control.next_worker_id();
+        self.size.joined();
+        let worker = tokio::spawn(worker_loop(
+            worker_id,
+            self.queue.clone(),
+            self.size.clone(),
+            self.context.clone(),
+        ));
+        let pool = self.clone();
         tokio::spawn(async move {
-            worker_loop(worker_id, receiver, store, metrics, sandbox).await;
+            if let Err(err) = worker.await
+                && err.is_panic()
+            {
+                tracing::error!(worker_id, pool = %pool.context.pool, "worker panicked, replacing it");
+                pool.context.control.finished(worker_id);
+                pool.size.left();
+                pool.spawn_worker();
+            }
         });
     }
 }
 
+// Grows straight to the size the backlog needs, but shrinks one worker per
+// tick so a brief lull does not tear the pool down.
+async fn autoscale(scaling: Scaling, pool: WorkerPool) {
+    let WorkerContext {
+        pool: name,
+        control,
+        scheduler,
+        metrics,
+        ..
+    } = pool.context.clone();
+    let size = pool.size.clone();
+    let mut ticker = tokio::time::interval(scaling.interval);
+    loop {
+        ticker.tick().await;
+        if control.is_paused() {
+            continue;
+        }
+        let average = metrics
+            .average_execution_duration()
+            .unwrap_or(ASSUMED_RUN_TIME);
+        let desired = desired_workers(
+            scaling,
+            scheduler.depth(&name).await,
+            control.busy(&name),
+            average,
+        );
+        let target = size.target();
+        if desired > target {
+            tracing::info!(pool = %name, from = target, to = desired, "scaling worker pool up");
+            size.set_target(desired);
+            for _ in size.live()..desired {
+                pool.spawn_worker();
+            }
+        } else if desired < target {
+            tracing::info!(pool = %name, from = target, to = target - 1, "scaling worker pool down");
+            size.set_target(target - 1);
+        } else if size.live() > target {
+            // A retire notice can miss a worker that was between checks.
+            size.set_target(target);
+        }
+    }
+}
+
+// Busy workers plus enough idle ones to run the backlog within the target wait.
+fn desired_workers(scaling: Scaling, depth: usize, busy: usize, average: Duration) -> usize {
+    let backlog = average.as_secs_f64() * depth as f64;
+    let extra = (backlog / scaling.target_wait.as_secs_f64().max(0.001)).ceil() as usize;
+    (busy + extra).clamp(scaling.min, scaling.max)
+}
+
 async fn worker_loop(
     worker_id: usize,
-    receiver: Arc<Mutex<Receiver<crate::engine::queue::QueuedJob>>>,
-    store: Arc<ExecutionStore>,
-    metrics: Arc<MetricsRegistry>,
-    sandbox: Arc<dyn SandboxBackend>,
+    queue: Arc<dyn JobQueue>,
+    size: Arc<PoolSize>,
+    context: WorkerContext,
 ) {
+    let WorkerContext {
+        pool,
+        store,
+        metrics,
+        sandboxes,
+        run_slots,
+        test_case_parallelism,
+        tenant_test_case_slots,
+        control,
+        scheduler,
+        infra_retry_limit,
+        result_cache,
+        cost_weights,
+        git,
+        remote_submissions,
+        heartbeat_interval,
+        capacity,
+    } = context;
     loop {
-        let job = {
-            let mut locked = receiver.lock().await;
-            locked.recv().await
+        if size.try_retire() {
+            tracing::info!(worker_id, "worker retiring");
+            break;
+        }
+        control.wait_until_resumed().await;
+        let job = tokio::select! {
+            job = queue.pop() => job,
+            () = size.retire_requested() => continue,
         };
-        let Some(job) = job else {
+        let Some(mut job) = job else {
             tracing::info!(worker_id, "job queue closed, worker exiting");
             break;
         };
+        // Dequeueing may have been paused while this worker waited on the queue;
+        // the job stays queued until it resumes.
+        control.wait_until_resumed().await;
+        // A retry goes to a different worker when one exists; the job is only
+        // handed off once so it cannot bounce around the queue.
+        if size.live() > 1 && job.last_worker.take() == Some(worker_id) {
+            match scheduler.hand_off(job, queue.as_ref()).await {
+                None => {
+                    // Let an idle worker get to the queue first.
+                    tokio::task::yield_now().await;
+                    continue;
+                }
+                Some(returned) => job = returned,
+            }
+        }
+        job.queue_wait = Span::none();
+        let receipt = job.receipt.clone();
 
+        // Otherwise redelivered by a durable queue after the engine that
+        // accepted it went away, taking the record with it.
+        if store.get(&job.id).is_none() {
+            store.insert(store.create_record(
+                job.id,
+                job.tenant_id.clone(),
+                job.request.clone(),
+                job.limits.clone(),
+            ));
+            if !remote_submissions {
+                store.append_event(job.id, "recovered", "execution redelivered by the queue");
+            }
+        }
+        // Its lease ran out while another worker had it, most likely one that
+        // crashed.
+        if store
+            .get(&job.id)
+            .is_some_and(|record| record.status == ExecutionStatus::Running)
+        {
+            tracing::warn!(worker_id, execution_id = %job.id, "running execution redelivered after its lease expired");
+            store.append_event(job.id, "redelivered", "job lease expired; running it again");
+        }
+        let demand = Demand::of(&job.request, &job.limits, test_case_parallelism);
+        let _reservation = match &capacity {
+            Some(capacity) => Some(match capacity.try_reserve(demand) {
+                Some(reservation) => reservation,
+                None => {
+                    store.append_event(
+                        job.id,
+                        "queued",
+                        format!(
+                            "waiting for {} cpu cores and {} MB of memory on worker-{worker_id}",
+                            demand.cpu_cores, demand.memory_mb
+                        ),
+                    );
+                    // The job is still the queue's, so its lease is kept up.
+                    let reserve = capacity.reserve(demand);
+                    tokio::pin!(reserve);
+                    let mut ticker = tokio::time::interval(heartbeat_interval);
+                    let reserved = loop {
+                        tokio::select! {
+                            reservation = &mut reserve => break Some(reservation),
+                            _ = ticker.tick() => {
+                                if let Some(receipt) = &receipt
+                                    && !queue.renew(receipt).await
+                                {
+                                    break None;
+                                }
+                            }
+                        }
+                    };
+                    // Another worker has the job now.
+                    let Some(reservation) = reserved else {
+                        tracing::warn!(worker_id, execution_id = %job.id, "queue lease lost while waiting for capacity");
+                        continue;
+                    };
+                    reservation
+                }
+            }),
+            None => None,
+        };
+        if !store.mark_running(job.id) {
+            tracing::info!(worker_id, execution_id = %job.id, "skipping cancelled execution");
+            metrics.cancelled();
+            if let Some(path) = &job.stdin_file {
+                stdin_upload::discard(path).await;
+            }
+            if let Some(receipt) = &receipt {
+                queue.ack(receipt).await;
+            }
+            continue;
+        }
         tracing::info!(worker_id, execution_id = %job.id, "starting execution");
         metrics.started();
-        store.mark_running(job.id);
+        let queue_wait = job.enqueued_at.elapsed();
+        metrics.queue_wait(queue_wait);
         store.append_event(job.id, "worker", format!("worker-{worker_id} claimed job"));
 
         let job_id = job.id;
-        let request = job.request.clone();
+        // Results of runs with secrets are never shared through the cache, nor
+        // are those of git sources, whose ref may move, or of uploaded stdin,
+        // which is not part of the key.
+        let cache_result = job.request.cache
+            && job.stdin_file.is_none()
+            && job.secrets.is_empty()
+            && job.request.git.is_none()
+            && job.request.mode != Some(ExecutionMode::Benchmark);
+        let redactor = Redactor::new(job.secrets.values());
+        let interactive = job.request.interactive;
+        let language = job.request.language.as_str();
+        let tenant_id = job.tenant_id.clone();
+        let started = Instant::now();
+        let chain = sandboxes.chain_for(&job.request);
+        let span = tracing::info_span!(parent: &job.span, "execution.worker", worker_id);
+        let killed = control.started(worker_id, &pool, &job);
+        let mode = job.request.mode.clone();
         let limits = job.limits.clone();
-        let base_spec = RunSpec::from(job);
+        let mut base_spec = RunSpec::from(job.clone());
+        let attempt = base_spec.attempt;
+        let event_store = store.clone();
+        let event_redactor = redactor.clone();
+        // When the program of a single run started, to split the sandbox's
+        // time into setup, run and collect.
+        let program_started = Arc::new(std::sync::Mutex::new(None));
+        let started_at = program_started.clone();
+        base_spec.events = EventSink::new(move |stage, message| {
+            if stage == "running" {
+                *started_at.lock().unwrap_or_else(|e| e.into_inner()) = Some(Instant::now());
+            }
+            event_store.append_event(job_id, stage, event_redactor.text(&message))
+        });
+        if job.request.interactive {
+            base_spec.stdin = LiveStdin::new(control.open_stdin(job_id));
+        }
 
-        let result = if request.test_cases.is_empty() {
-            sandbox
-                .execute(base_spec)
-                .await
-                .map(|single| (single, Vec::new()))
-        } else {
-            execute_test_cases(job_id, request, limits, sandbox.clone()).await
+        let stdin_file = job.stdin_file.clone();
+        let multi_run =
+            !job.request.test_cases.is_empty() || mode == Some(ExecutionMode::Benchmark);
+        let sandbox_started = Instant::now();
+        let backends = chain.clone();
+        let run = async {
+            // Missing when the upload went to a directory this worker does
+            // not share with the API node.
+            if let Some(path) = &stdin_file {
+                tokio::fs::metadata(path)
+                    .await
+                    .with_context(|| format!("stdin upload {} is unavailable", path.display()))?;
+            }
+            if base_spec.request.git.is_some() {
+                base_spec.events.emit("fetching", "fetching code from git");
+                git::checkout(&mut base_spec.request, job_id, git).await?;
+            }
+            // An infrastructure failure moves the job down the failover
+            // chain; only the last backend's failure is retried through the
+            // queue.
+            let mut chain = chain.into_iter().peekable();
+            loop {
+                let (kind, sandbox) = chain.next().expect("backend chain is never empty");
+                let next = chain.peek().map(|(next, _)| *next);
+                let mut spec = base_spec.clone();
+                spec.request.backend = Some(kind);
+                let result = run_on(
+                    sandbox.clone(),
+                    spec,
+                    &run_slots,
+                    test_case_parallelism,
+                    &tenant_test_case_slots,
+                )
+                .await;
+                match result {
+                    Err(err) if is_infrastructure_error(&err) && next.is_some() => {
+                        let next = next.expect("checked above");
+                        tracing::warn!(worker_id, execution_id = %job_id, from = kind.as_str(), to = next.as_str(), error = %format!("{err:#}"), "sandbox backend failed, failing over");
+                        metrics.failover();
+                        base_spec.events.emit(
+                            "failover",
+                            format!(
+                                "{} backend failed: {err:#}; falling back to {}",
+                                kind.as_str(),
+                                next.as_str()
+                            ),
+                        );
+                    }
+                    result => break result.map(|outcome| (sandbox.name(), outcome)),
+                }
+            }
+        }
+        .instrument(span.clone());
+        // Ends when the lease is lost: the job was redelivered and another
+        // worker runs it.
+        let heartbeat = async {
+            let mut ticker = tokio::time::interval(heartbeat_interval);
+            loop {
+                ticker.tick().await;
+                control.heartbeat(worker_id);
+                if let Some(receipt) = &receipt
+                    && !queue.renew(receipt).await
+                {
+                    return;
+                }
+            }
         };
+        let mut stuck = false;
+        let result = tokio::select! {
+            result = run => result,
+            () = killed.notified() => {
+                stuck = true;
+                for (_, sandbox) in &backends {
+                    sandbox.kill(attempt).await;
+                }
+                Err(anyhow::anyhow!("execution stuck past its deadline; killed by the watchdog"))
+            }
+            () = heartbeat => {
+                // Its result and ack belong to the new owner, which may be this
+                // engine running the same execution, so only this attempt dies.
+                tracing::warn!(worker_id, execution_id = %job_id, "queue lease lost, abandoning execution");
+                for (_, sandbox) in &backends {
+                    sandbox.kill(attempt).await;
+                }
+                store.append_event(
+                    job_id,
+                    "lease_lost",
+                    format!("worker-{worker_id} lost the queue lease and stopped its run"),
+                );
+                control.finished(worker_id);
+                continue;
+            }
+        };
+        let sandbox_finished = Instant::now();
+        // A stuck run is not retried; it would likely hang again.
+        let result = match result {
+            Err(err)
+                if is_infrastructure_error(&err) && !stuck && job.attempts < infra_retry_limit =>
+            {
+                job.attempts += 1;
+                let message = redactor.text(&format!(
+                    "attempt {} of {} failed: {err:#}",
+                    job.attempts,
+                    infra_retry_limit + 1
+                ));
+                tracing::warn!(worker_id, execution_id = %job_id, error = %format!("{err:#}"), "infrastructure failure, retrying");
+                // Back to queued first so whichever worker dequeues it can claim it.
+                store.mark_retrying(job_id, message);
+                job.last_worker = Some(worker_id);
+                job.enqueued_at = Instant::now();
+                job.queue_wait = tracing::info_span!(parent: &job.span, "execution.queue_wait");
+                if scheduler.retry(job, queue.as_ref()).await.is_none() {
+                    control.finished(worker_id);
+                    continue;
+                }
+                Err(err.context("queue full, retry dropped"))
+            }
+            other => other,
+        };
+        let persist = tracing::info_span!(parent: &span, "execution.persist");
 
         match result {
-            Ok((result, test_results)) => {
+            Ok((backend_name, (result, test_results, score, benchmark))) => {
                 let status = if result.timed_out {
                     metrics.timed_out();
                     ExecutionStatus::TimedOut
+                } else if result.oom_killed {
+                    metrics.oom_killed();
+                    metrics.failed(&tenant_id);
+                    ExecutionStatus::OutOfMemory
                 } else if result.exit_code == 0 {
                     ExecutionStatus::Succeeded
                 } else {
-                    metrics.failed();
+                    metrics.failed(&tenant_id);
                     ExecutionStatus::Failed
                 };
 
                 metrics.completed();
+                metrics.finished(language, &tenant_id, status.as_str(), started.elapsed());
+                let termination_reason = result.termination_reason();
+                let timings = stage_timings(
+                    &result,
+                    queue_wait,
+                    sandbox_started..sandbox_finished,
+                    (!multi_run)
+                        .then(|| *program_started.lock().unwrap_or_else(|e| e.into_inner()))
+                        .flatten(),
+                );
+                let mut output = ExecutionOutput {
+                    stdout: result.stdout,
+                    stderr: result.stderr,
+                    exit_code: result.exit_code,
+                    duration_ms: result.duration_ms,
+                    sandbox_backend: backend_name.to_string(),
+                    usage: result.usage,
+                    oom_killed: result.oom_killed,
+                    signal: result.signal.map(signal_name),
+                    termination_reason,
+                    stdout_truncated: result.stdout_truncated,
+                    stderr_truncated: result.stderr_truncated,
+                    stdout_encoding: result.stdout_encoding,
+                    stderr_encoding: result.stderr_encoding,
+                    install: result.install,
+                    compile: result.compile,
+                    test_results,
+                    score,
+                    failure: None,
+                    diagnostics: Vec::new(),
+                    formatted: None,
+                    test_report: None,
+                    benchmark,
+                    combined: result.combined,
+                    offloaded: None,
+                    timings,
+                };
+                match mode {
+                    Some(ExecutionMode::AgentOptimized) => {
+                        diagnostics::apply_agent_view(&mut output)
+                    }
+                    Some(ExecutionMode::Check) => diagnostics::apply_check_view(&mut output),
+                    Some(ExecutionMode::Lint) => diagnostics::apply_lint_view(&mut output),
+                    Some(ExecutionMode::Format) if output.exit_code == 0 => {
+                        output.formatted = Some(output.stdout.clone());
+                    }
+                    Some(ExecutionMode::Test) => {
+                        output.test_report = test_report::parse(&output.stdout)
+                    }
+                    _ => {}
+                }
+                redactor.output(&mut output);
+                let cost = cost_weights.cost(&output, &limits);
+                store.record_cost(job_id, cost);
+                metrics.cost(&tenant_id, cost);
                 store
-                    .mark_finished(
-                        job_id,
-                        status,
-                        Some(crate::engine::models::ExecutionOutput {
-                            stdout: result.stdout,
-                            stderr: result.stderr,
-                            exit_code: result.exit_code,
-                            duration_ms: result.duration_ms,
-                            sandbox_backend: sandbox.name().to_string(),
-                            test_results,
-                        }),
-                        None,
-                    )
+                    .mark_finished(job_id, status, Some(output), None)
+                    .instrument(persist)
                     .await;
+                if cache_result
+                    && !interactive
+                    && let Some(record) = store.get(&job_id)
+                {
+                    result_cache.remember(&record);
+                }
             }
             Err(err) => {
-                store.append_event(job_id, "sandbox_error", err.to_string());
-                metrics.failed();
+                let err = redactor.text(&err.to_string());
+                store.append_event(job_id, "sandbox_error", err.clone());
+                metrics.failed(&tenant_id);
+                metrics.finished(
+                    language,
+                    &tenant_id,
+                    ExecutionStatus::Failed.as_str(),
+                    started.elapsed(),
+                );
                 store
-                    .mark_finished(job_id, ExecutionStatus::Failed, None, Some(err.to_string()))
+                    .mark_finished(job_id, ExecutionStatus::Failed, None, Some(err))
+                    .instrument(persist)
                     .await;
             }
         }
+        if let Some(path) = &stdin_file {
+            stdin_upload::discard(path).await;
+        }
+        if let Some(receipt) = &receipt {
+            queue.ack(receipt).await;
+        }
+        control.finished(worker_id);
+    }
+}
+
+// Kills executions still running `grace` past their deadline (the timeout
+// of every run they make), or whose worker stopped heartbeating for as long,
+// e.g. behind a hung container wait. Their worker gives up on the run, tears
+// down what is left of it and fails the record.
+pub fn spawn_watchdog(
+    control: Arc<WorkerControl>,
+    store: Arc<ExecutionStore>,
+    metrics: Arc<MetricsRegistry>,
+    interval: Duration,
+    grace: Duration,
+) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            for execution in control.overdue(grace) {
+                tracing::error!(
+                    worker_id = execution.worker_id,
+                    execution_id = %execution.execution_id,
+                    started_at_ms = execution.started_at_ms,
+                    heartbeat_ms = execution.heartbeat_ms,
+                    "execution stuck in running, killing it"
+                );
+                metrics.stuck();
+                store.append_event(
+                    execution.execution_id,
+                    "watchdog",
+                    format!(
+                        "worker-{} stuck past the execution's deadline; killing it",
+                        execution.worker_id
+                    ),
+                );
+                control.kill(execution.worker_id);
+            }
+        }
+    });
+}
+
+// `program` is when a single run's program started; unset for test cases
+// and benchmarks, and when the run ended before its program started (a
+// failed install or compile, or a check).
+fn stage_timings(
+    result: &SandboxResult,
+    queue_wait: Duration,
+    sandbox: std::ops::Range<Instant>,
+    program: Option<Instant>,
+) -> StageTimings {
+    let ms = |duration: Duration| duration.as_millis() as u64;
+    let install_ms = result
+        .install
+        .as_ref()
+        .map(|install| install.duration_ms as u64);
+    let compile_ms = result
+        .compile
+        .as_ref()
+        .map(|compile| compile.duration_ms as u64);
+    let total = ms(sandbox.end - sandbox.start);
+    let build = install_ms.unwrap_or(0) + compile_ms.unwrap_or(0);
+    let mut timings = StageTimings {
+        queue_wait_ms: Some(ms(queue_wait)),
+        install_ms,
+        compile_ms,
+        ..StageTimings::default()
+    };
+    match program {
+        Some(program) => {
+            let run_ms = result.duration_ms as u64;
+            timings.setup_ms = Some(ms(program - sandbox.start).saturating_sub(build));
+            timings.run_ms = Some(run_ms);
+            timings.collect_ms = Some(ms(sandbox.end - program).saturating_sub(run_ms));
+        }
+        None if result.duration_ms > 0 => timings.run_ms = Some(total),
+        None => timings.setup_ms = Some(total.saturating_sub(build)),
     }
+    timings
 }
 
+type RunOutcome = (
+    SandboxResult,
+    Vec<TestCaseResult>,
+    Option<TestScore>,
+    Option<BenchmarkReport>,
+);
+
+// One attempt at the job on `sandbox`: a benchmark, the test cases, or a
+// single run.
+async fn run_on(
+    sandbox: Arc<dyn SandboxBackend>,
+    spec: RunSpec,
+    run_slots: &Arc<Semaphore>,
+    test_case_parallelism: usize,
+    tenant_slots: &TenantSlots,
+) -> anyhow::Result<RunOutcome> {
+    if spec.request.mode == Some(ExecutionMode::Benchmark) {
+        benchmark::execute(spec, sandbox, run_slots.clone())
+            .await
+            .map(|(single, report)| (single, Vec::new(), None, Some(report)))
+    } else if spec.request.test_cases.is_empty() {
+        let _slot = run_slots.acquire().await.expect("run slots closed");
+        sandbox
+            .execute(spec)
+            .await
+            .map(|single| (single, Vec::new(), None, None))
+    } else {
+        execute_test_cases(
+            spec,
+            sandbox,
+            run_slots.clone(),
+            tenant_slots.clone(),
+            test_case_parallelism,
+        )
+        .await
+        .map(|(single, test_results, score)| (single, test_results, score, None))
+    }
+}
+
+// Cases run up to `parallelism` at a time, each holding one of the tenant's
+// test-case slots and one of the engine-wide run slots. Results keep request
+// order; after a case times out or errors no further cases are started, and
+// cases that were skipped are left out of the results (but still count
+// against the score). Each finished case is reported as a `test_case` event
+// in place of the per-run `running` event.
 async fn execute_test_cases(
-    id: uuid::Uuid,
-    request: crate::engine::models::ExecutionRequest,
-    limits: crate::engine::models::ExecutionLimits,
+    base: RunSpec,
     sandbox: Arc<dyn SandboxBackend>,
-) -> anyhow::Result<(SandboxResult, Vec<TestCaseResult>)> {
-    let test_cases = request.test_cases.clone();
-    let mut test_results = Vec::with_capacity(test_cases.len());
-    let mut final_result = None;
+    run_slots: Arc<Semaphore>,
+    tenant_slots: TenantSlots,
+    parallelism: usize,
+) -> anyhow::Result<(SandboxResult, Vec<TestCaseResult>, Option<TestScore>)> {
+    let test_cases = base.request.test_cases.clone();
+    let total = test_cases.len();
+    let stop = Arc::new(AtomicBool::new(false));
+    let finished = Arc::new(AtomicUsize::new(0));
+    let case_events = {
+        let events = base.events.clone();
+        EventSink::new(move |stage, message| {
+            if stage != "running" {
+                events.emit(stage, message);
+            }
+        })
+    };
 
-    for case in test_cases {
-        let mut request_for_case = request.clone();
-        request_for_case.stdin = case.stdin.clone();
-        request_for_case.test_cases.clear();
-        let spec = RunSpec {
-            request: request_for_case,
-            limits: limits.clone(),
-            id,
+    let outcomes: Vec<_> = stream::iter(test_cases.iter().cloned().enumerate())
+        .map(|(index, case)| {
+            let mut spec = base.clone();
+            spec.request.stdin = case.stdin;
+            spec.request.test_cases.clear();
+            spec.collect = case
+                .expected_files
+                .iter()
+                .map(|file| file.path.clone())
+                .collect();
+            spec.events = case_events.clone();
+            let sandbox = sandbox.clone();
+            let run_slots = run_slots.clone();
+            let tenant_slots = tenant_slots.clone();
+            let stop = stop.clone();
+            let finished = finished.clone();
+            let events = base.events.clone();
+            let span = tracing::info_span!("execution.test_case", index);
+            async move {
+                if stop.load(Ordering::SeqCst) {
+                    return Ok(None);
+                }
+                // The tenant's slot first, so a tenant at its cap does not
+                // sit on engine-wide slots others could use.
+                let _tenant_slot = tenant_slots.acquire(&spec.tenant_id).await;
+                let _slot = run_slots.acquire_owned().await?;
+                if stop.load(Ordering::SeqCst) {
+                    return Ok(None);
+                }
+                let out = sandbox.execute(spec).await;
+                if out.as_ref().map_or(true, |out| out.timed_out) {
+                    stop.store(true, Ordering::SeqCst);
+                }
+                let done = finished.fetch_add(1, Ordering::SeqCst) + 1;
+                events.emit(
+                    "test_case",
+                    format!("case {} finished ({done}/{total})", index + 1),
+                );
+                out.map(Some)
+            }
+            .instrument(span)
+        })
+        .buffered(parallelism.max(1))
+        .collect()
+        .await;
+
+    let mut test_results = Vec::with_capacity(test_cases.len());
+    let mut verdicts = Vec::with_capacity(test_cases.len());
+    let mut final_result: Option<SandboxResult> = None;
+    for (case, outcome) in test_cases.iter().zip(outcomes) {
+        let Some(out) = outcome? else {
+            verdicts.push(None);
+            continue;
         };
-        let out = sandbox.execute(spec).await?;
-        let passed = case
-            .expected_stdout
-            .as_ref()
-            .map(|expected| out.stdout.trim() == expected.trim());
+        let files = grading::check_files(case, &out.files);
+        let passed = grading::evaluate(case, &out.stdout, &out.stderr, out.exit_code, &files);
+        verdicts.push(passed);
         test_results.push(TestCaseResult {
-            stdin: case.stdin,
+            stdin: case.stdin.clone(),
             stdout: out.stdout.clone(),
             stderr: out.stderr.clone(),
             passed,
             exit_code: out.exit_code,
             duration_ms: out.duration_ms,
+            usage: out.usage.clone(),
+            oom_killed: out.oom_killed,
+            signal: out.signal.map(signal_name),
+            termination_reason: out.termination_reason(),
+            stdout_truncated: out.stdout_truncated,
+            stderr_truncated: out.stderr_truncated,
+            stdout_encoding: out.stdout_encoding,
+            stderr_encoding: out.stderr_encoding,
+            stdout_diff: grading::output_diff(case, case.expected_stdout.as_deref(), &out.stdout),
+            stderr_diff: grading::output_diff(case, case.expected_stderr.as_deref(), &out.stderr),
+            files,
         });
-        final_result = Some(out.clone());
-        if out.timed_out {
-            break;
+        // A timed-out case decides the overall status.
+        if !final_result.as_ref().is_some_and(|last| last.timed_out) {
+            final_result = Some(out);
         }
     }
 
@@ -148,7 +802,493 @@ async fn execute_test_cases(
         exit_code: 0,
         duration_ms: 0,
         timed_out: false,
+        compile: None,
+        install: None,
+        usage: ResourceUsage::default(),
+        oom_killed: false,
+        signal: None,
+        stdout_truncated: false,
+        stderr_truncated: false,
+        stdout_encoding: FileEncoding::Utf8,
+        stderr_encoding: FileEncoding::Utf8,
+        combined: Vec::new(),
+        files: Vec::new(),
+    };
+
+    let score = grading::score(&test_cases, &verdicts);
+    Ok((final_result.unwrap_or(fallback), test_results, score))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            Arc, Mutex,
+            atomic::{AtomicUsize, Ordering},
+        },
+        time::{Duration, Instant},
     };
 
-    Ok((final_result.unwrap_or(fallback), test_results))
+    use tokio::sync::Semaphore;
+    use tracing::Span;
+
+    use super::{
+        Scaling, WorkerContext, desired_workers, execute_test_cases, spawn_watchdog,
+        spawn_worker_pool, stage_timings,
+    };
+    use crate::engine::{
+        admin::WorkerControl,
+        capacity::TenantSlots,
+        config::{CostWeights, SandboxBackendKind},
+        git::GitLimits,
+        metrics::MetricsRegistry,
+        models::{
+            CompileOutput, ExecutionLimits, ExecutionRecord, ExecutionRequest, ExecutionStatus,
+            FileEncoding, ResourceUsage,
+        },
+        queue::{Admission, DEFAULT_POOL, QueueBackend, QueuedJob, Scheduler},
+        result_cache::ResultCache,
+        sandbox::{EventSink, RunSpec, SandboxBackend, SandboxRegistry, SandboxResult},
+        store::ExecutionStore,
+    };
+
+    // Echoes stdin after a delay that makes earlier cases finish last.
+    #[derive(Default)]
+    struct EchoSandbox {
+        running: AtomicUsize,
+        peak: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl SandboxBackend for EchoSandbox {
+        fn name(&self) -> &'static str {
+            "echo"
+        }
+
+        async fn execute(&self, spec: RunSpec) -> anyhow::Result<SandboxResult> {
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.peak.fetch_max(running, Ordering::SeqCst);
+            let delay: u64 = spec.request.stdin.parse()?;
+            tokio::time::sleep(Duration::from_millis(100 - delay * 10)).await;
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            Ok(SandboxResult {
+                stdout: spec.request.stdin,
+                stderr: String::new(),
+                exit_code: 0,
+                duration_ms: 0,
+                timed_out: false,
+                compile: None,
+                install: None,
+                usage: ResourceUsage::default(),
+                oom_killed: false,
+                signal: None,
+                stdout_truncated: false,
+                stderr_truncated: false,
+                stdout_encoding: FileEncoding::Utf8,
+                stderr_encoding: FileEncoding::Utf8,
+                combined: Vec::new(),
+                files: Vec::new(),
+            })
+        }
+    }
+
+    // Fails with an infrastructure error until `failures` runs out.
+    #[derive(Default)]
+    struct FlakySandbox {
+        failures: AtomicUsize,
+        inner: EchoSandbox,
+    }
+
+    #[async_trait::async_trait]
+    impl SandboxBackend for FlakySandbox {
+        fn name(&self) -> &'static str {
+            "flaky"
+        }
+
+        async fn execute(&self, spec: RunSpec) -> anyhow::Result<SandboxResult> {
+            let remaining = self.failures.load(Ordering::SeqCst);
+            if remaining > 0 {
+                self.failures.store(remaining - 1, Ordering::SeqCst);
+                anyhow::bail!("container runtime unreachable");
+            }
+            self.inner.execute(spec).await
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_infrastructure_failures_on_another_worker() {
+        let store = Arc::new(ExecutionStore::new(None));
+        let metrics = Arc::new(MetricsRegistry::new());
+        let admission = Admission {
+            max_depth: 8,
+            max_wait: None,
+        };
+        let scheduler = Scheduler::new(
+            QueueBackend::Memory {
+                capacity: 8,
+                lease: Duration::from_secs(60),
+            },
+            admission,
+            metrics.clone(),
+        )
+        .with_pool(DEFAULT_POOL, 2);
+        let sandbox = Arc::new(FlakySandbox {
+            failures: AtomicUsize::new(1),
+            ..FlakySandbox::default()
+        });
+        spawn_worker_pool(
+            Scaling {
+                min: 2,
+                max: 2,
+                target_wait: Duration::from_secs(1),
+                interval: Duration::from_secs(1),
+            },
+            test_context(
+                store.clone(),
+                metrics,
+                scheduler.clone(),
+                sandbox,
+                Arc::new(WorkerControl::new()),
+            ),
+        );
+
+        let request: ExecutionRequest = serde_json::from_value(serde_json::json!({
+            "language": "python",
+            "code": "print(input())",
+            "stdin": "9",
+        }))
+        .unwrap();
+        let limits = ExecutionLimits {
+            cpu_cores: 1.0,
+            memory_mb: 256,
+            timeout_ms: 1000,
+            max_processes: 8,
+            max_file_size_bytes: 1024,
+            max_output_bytes: 1024,
+        };
+        let id = submit(&store, &scheduler, request, limits).await;
+        let record = finished(&store, id).await;
+        assert_eq!(record.status, ExecutionStatus::Succeeded);
+        let claims: Vec<_> = record
+            .events
+            .iter()
+            .filter(|event| event.stage == "worker")
+            .map(|event| event.message.as_str())
+            .collect();
+        assert_eq!(claims.len(), 2);
+        assert_ne!(claims[0], claims[1]);
+        assert!(record.events.iter().any(|event| event.stage == "retrying"));
+    }
+
+    // Never returns, as on a hung container wait.
+    #[derive(Default)]
+    struct HangingSandbox {
+        killed: AtomicUsize,
+        started: Mutex<Vec<uuid::Uuid>>,
+        killed_attempts: Mutex<Vec<uuid::Uuid>>,
+    }
+
+    #[async_trait::async_trait]
+    impl SandboxBackend for HangingSandbox {
+        fn name(&self) -> &'static str {
+            "hanging"
+        }
+
+        async fn execute(&self, spec: RunSpec) -> anyhow::Result<SandboxResult> {
+            self.started.lock().unwrap().push(spec.attempt);
+            std::future::pending().await
+        }
+
+        async fn kill(&self, attempt: uuid::Uuid) {
+            self.killed.fetch_add(1, Ordering::SeqCst);
+            self.killed_attempts.lock().unwrap().push(attempt);
+        }
+    }
+
+    fn test_context(
+        store: Arc<ExecutionStore>,
+        metrics: Arc<MetricsRegistry>,
+        scheduler: Scheduler,
+        sandbox: Arc<dyn SandboxBackend>,
+        control: Arc<WorkerControl>,
+    ) -> WorkerContext {
+        WorkerContext {
+            pool: DEFAULT_POOL.to_string(),
+            store,
+            metrics,
+            sandboxes: SandboxRegistry::new(SandboxBackendKind::Process, sandbox),
+            run_slots: Arc::new(Semaphore::new(2)),
+            test_case_parallelism: 1,
+            tenant_test_case_slots: TenantSlots::new(0),
+            control,
+            scheduler,
+            infra_retry_limit: 1,
+            result_cache: ResultCache::new(Duration::ZERO),
+            cost_weights: CostWeights {
+                cpu_second: 1.0,
+                memory_gb_second: 1.0,
+                duration_second: 1.0,
+            },
+            git: GitLimits {
+                timeout: Duration::from_secs(1),
+                max_bytes: 1024,
+                max_files: 8,
+            },
+            remote_submissions: false,
+            heartbeat_interval: Duration::from_millis(10),
+            capacity: None,
+        }
+    }
+
+    async fn submit(
+        store: &ExecutionStore,
+        scheduler: &Scheduler,
+        request: ExecutionRequest,
+        limits: ExecutionLimits,
+    ) -> uuid::Uuid {
+        let id = uuid::Uuid::new_v4();
+        store.insert(store.create_record(id, "t".into(), request.clone(), limits.clone()));
+        scheduler
+            .submit(QueuedJob {
+                id,
+                tenant_id: "t".into(),
+                request,
+                limits,
+                secrets: Default::default(),
+                stdin_file: None,
+                enqueued_at: Instant::now(),
+                attempts: 0,
+                last_worker: None,
+                receipt: None,
+                span: Span::none(),
+                queue_wait: Span::none(),
+            })
+            .await
+            .unwrap();
+        id
+    }
+
+    async fn finished(store: &ExecutionStore, id: uuid::Uuid) -> ExecutionRecord {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                match store.get(&id) {
+                    Some(record) if record.finished_at_ms.is_some() => break record,
+                    _ => tokio::time::sleep(Duration::from_millis(10)).await,
+                }
+            }
+        })
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn watchdog_kills_executions_stuck_past_their_deadline() {
+        let store = Arc::new(ExecutionStore::new(None));
+        let metrics = Arc::new(MetricsRegistry::new());
+        let scheduler = Scheduler::new(
+            QueueBackend::Memory {
+                capacity: 8,
+                lease: Duration::from_secs(60),
+            },
+            Admission {
+                max_depth: 8,
+                max_wait: None,
+            },
+            metrics.clone(),
+        )
+        .with_pool(DEFAULT_POOL, 1);
+        let sandbox = Arc::new(HangingSandbox::default());
+        let control = Arc::new(WorkerControl::new());
+        spawn_worker_pool(
+            Scaling {
+                min: 1,
+                max: 1,
+                target_wait: Duration::from_secs(1),
+                interval: Duration::from_secs(1),
+            },
+            test_context(
+                store.clone(),
+                metrics.clone(),
+                scheduler.clone(),
+                sandbox.clone(),
+                control.clone(),
+            ),
+        );
+        spawn_watchdog(
+            control.clone(),
+            store.clone(),
+            metrics.clone(),
+            Duration::from_millis(10),
+            Duration::from_millis(100),
+        );
+
+        let request: ExecutionRequest = serde_json::from_value(serde_json::json!({
+            "language": "python",
+            "code": "while True: pass",
+        }))
+        .unwrap();
+        let limits = ExecutionLimits {
+            cpu_cores: 1.0,
+            memory_mb: 256,
+            timeout_ms: 50,
+            max_processes: 8,
+            max_file_size_bytes: 1024,
+            max_output_bytes: 1024,
+        };
+        let id = submit(&store, &scheduler, request, limits).await;
+        let record = finished(&store, id).await;
+        assert_eq!(record.status, ExecutionStatus::Failed);
+        assert!(record.error.unwrap().contains("watchdog"));
+        assert!(record.events.iter().any(|event| event.stage == "watchdog"));
+        assert_eq!(sandbox.killed.load(Ordering::SeqCst), 1);
+        // The kill names the attempt that ran, not the execution.
+        assert_eq!(
+            *sandbox.killed_attempts.lock().unwrap(),
+            *sandbox.started.lock().unwrap()
+        );
+        assert_ne!(sandbox.killed_attempts.lock().unwrap()[0], id);
+        assert!(
+            metrics
+                .render_prometheus()
+                .contains("execution_stuck_total 1")
+        );
+        assert!(control.running().is_empty());
+    }
+
+    #[tokio::test]
+    async fn runs_cases_in_parallel_and_keeps_order() {
+        let cases: Vec<_> = (0..8)
+            .map(
+                |i| serde_json::json!({ "stdin": i.to_string(), "expected_stdout": i.to_string() }),
+            )
+            .collect();
+        let request: ExecutionRequest = serde_json::from_value(serde_json::json!({
+            "language": "python",
+            "code": "print(input())",
+            "test_cases": cases,
+        }))
+        .unwrap();
+        let limits = ExecutionLimits {
+            cpu_cores: 1.0,
+            memory_mb: 256,
+            timeout_ms: 1000,
+            max_processes: 8,
+            max_file_size_bytes: 1024,
+            max_output_bytes: 1024,
+        };
+        let sandbox = Arc::new(EchoSandbox::default());
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        let spec = RunSpec {
+            request,
+            limits,
+            id: uuid::Uuid::new_v4(),
+            attempt: uuid::Uuid::new_v4(),
+            tenant_id: "t".into(),
+            events: EventSink::new(move |stage, _| {
+                recorded.lock().unwrap().push(stage.to_string())
+            }),
+            stdin: Default::default(),
+            stdin_file: None,
+            collect: Vec::new(),
+        };
+        let (_, results, score) = execute_test_cases(
+            spec,
+            sandbox.clone(),
+            Arc::new(Semaphore::new(3)),
+            TenantSlots::new(0),
+            4,
+        )
+        .await
+        .unwrap();
+
+        let stdout: Vec<_> = results.iter().map(|r| r.stdout.as_str()).collect();
+        assert_eq!(stdout, ["0", "1", "2", "3", "4", "5", "6", "7"]);
+        assert!(results.iter().all(|r| r.passed == Some(true)));
+        assert_eq!(score.unwrap().passed, 8);
+        assert_eq!(sandbox.peak.load(Ordering::SeqCst), 3);
+        assert_eq!(*events.lock().unwrap(), vec!["test_case"; 8]);
+    }
+
+    #[tokio::test]
+    async fn caps_cases_per_tenant() {
+        let request: ExecutionRequest = serde_json::from_value(serde_json::json!({
+            "language": "python",
+            "code": "print(input())",
+            "test_cases": (0..6).map(|i| serde_json::json!({ "stdin": i.to_string() })).collect::<Vec<_>>(),
+        }))
+        .unwrap();
+        let sandbox = Arc::new(EchoSandbox::default());
+        let spec = RunSpec {
+            request,
+            limits: ExecutionLimits::MAX,
+            id: uuid::Uuid::new_v4(),
+            attempt: uuid::Uuid::new_v4(),
+            tenant_id: "t".into(),
+            events: EventSink::default(),
+            stdin: Default::default(),
+            stdin_file: None,
+            collect: Vec::new(),
+        };
+        let slots = TenantSlots::new(2);
+        let (_, results, _) =
+            execute_test_cases(spec, sandbox.clone(), Arc::new(Semaphore::new(8)), slots, 6)
+                .await
+                .unwrap();
+        assert_eq!(results.len(), 6);
+        assert_eq!(sandbox.peak.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn splits_sandbox_time_into_stages() {
+        let compile = CompileOutput {
+            success: true,
+            exit_code: 0,
+            stdout: String::new(),
+            stderr: String::new(),
+            duration_ms: 300,
+        };
+        let start = Instant::now();
+        let sandbox = start..start + Duration::from_millis(1000);
+        let mut result = SandboxResult::checked(None, Some(compile));
+        result.duration_ms = 500;
+
+        let timings = stage_timings(
+            &result,
+            Duration::from_millis(40),
+            sandbox.clone(),
+            Some(start + Duration::from_millis(400)),
+        );
+        assert_eq!(timings.queue_wait_ms, Some(40));
+        assert_eq!(timings.compile_ms, Some(300));
+        assert_eq!(timings.install_ms, None);
+        assert_eq!(
+            (timings.setup_ms, timings.run_ms, timings.collect_ms),
+            (Some(100), Some(500), Some(100))
+        );
+
+        // Test cases: every run, setup included, counts as running.
+        let timings = stage_timings(&result, Duration::ZERO, sandbox.clone(), None);
+        assert_eq!((timings.setup_ms, timings.run_ms), (None, Some(1000)));
+
+        // Stopped before the program: everything but the build is setup.
+        result.duration_ms = 0;
+        let timings = stage_timings(&result, Duration::ZERO, sandbox, None);
+        assert_eq!((timings.setup_ms, timings.run_ms), (Some(700), None));
+    }
+
+    #[test]
+    fn sizes_pool_to_drain_backlog_within_target_wait() {
+        let scaling = Scaling {
+            min: 2,
+            max: 10,
+            target_wait: Duration::from_secs(2),
+            interval: Duration::from_secs(1),
+        };
+        let average = Duration::from_millis(500);
+        assert_eq!(desired_workers(scaling, 0, 0, average), 2);
+        // 12 queued jobs of 0.5s are 6s of work: 3 more workers drain it in 2s.
+        assert_eq!(desired_workers(scaling, 12, 4, average), 7);
+        assert_eq!(desired_workers(scaling, 1000, 4, average), 10);
+    }
 }
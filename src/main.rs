@@ -1,6 +1,11 @@
+mod cli;
 mod engine;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("run") {
+        std::process::exit(cli::run(&args[1..]).await);
+    }
     engine::run().await
 }